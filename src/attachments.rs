@@ -0,0 +1,447 @@
+//! Export of attachments only, see [`run`].
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{ensure, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use futures::{future::ready, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tatutanatata_core::{
+    client::Client,
+    constants::VERSION_STRING,
+    failure::Failure,
+    folders::{self, Folder},
+    mails::{AttachmentFilter, DownloadOptions, DownloadedMail, Mail},
+    retry::RetryPolicy,
+    session::Session,
+};
+use tracing::{debug, info};
+
+use crate::{
+    file_output::{escape_file_string, sanitize_file_name, write_to_file, FilenameCharset},
+    filename_template::DATE_FORMAT,
+    lock::LockCLIConfig,
+    timezone::Timezone,
+    CONCURRENT_FOLDERS,
+};
+
+/// File name of the attachment manifest within the output directory.
+const ATTACHMENT_MANIFEST_FILE: &str = "attachments.json";
+
+/// Options of the `download-attachments` command.
+#[derive(Debug, Parser)]
+pub(crate) struct AttachmentsCLIConfig {
+    /// Folder name.
+    ///
+    /// Can be repeated. Nested folders are NOT included automatically. Defaults to all accessible folders.
+    #[clap(long, action)]
+    folder: Vec<String>,
+
+    /// Target directory.
+    #[clap(long, action)]
+    path: PathBuf,
+
+    /// Maximum number of concurrent downloads.
+    #[clap(long, action, default_value_t = 5)]
+    concurrent_downloads: usize,
+
+    /// Characters that are kept in folder names.
+    #[clap(long, action, value_enum, default_value_t = FilenameCharset::Ascii)]
+    filename_charset: FilenameCharset,
+
+    /// Ignore new mails that cannot be decrypted (yet).
+    #[clap(long, action)]
+    ignore_new_mails: bool,
+
+    /// Only export attachments up to the given size in bytes.
+    #[clap(long, action)]
+    max_attachment_size: Option<u64>,
+
+    /// Only export attachments of the given MIME types, e.g. `image/*,application/pdf`.
+    ///
+    /// Can be repeated.
+    #[clap(long, action, value_delimiter = ',')]
+    attachment_type: Vec<String>,
+
+    /// Maximum size of a decompressed mail body or header block in bytes.
+    #[clap(long, action, default_value_t = crate::DEFAULT_MAX_DECOMPRESSED_SIZE)]
+    max_decompressed_size: usize,
+
+    /// Run lock config.
+    #[clap(flatten)]
+    lock_cfg: LockCLIConfig,
+}
+
+impl AttachmentsCLIConfig {
+    /// Lock config and target path, see [`RunLock`](crate::lock::RunLock).
+    pub(crate) fn lock_target(&self) -> (&LockCLIConfig, &Path) {
+        (&self.lock_cfg, &self.path)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttachmentManifest {
+    /// Version of the tool that wrote the manifest.
+    tool_version: String,
+
+    /// Exported attachments, keyed by output file name relative to the output directory.
+    attachments: BTreeMap<String, AttachmentEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttachmentEntry {
+    mail_id: String,
+    folder_id: String,
+    date: DateTime<Utc>,
+    sender: String,
+    subject: String,
+
+    /// Original attachment file name.
+    name: String,
+    mime_type: String,
+    size: u64,
+}
+
+impl AttachmentManifest {
+    async fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(ATTACHMENT_MANIFEST_FILE);
+        if !tokio::fs::try_exists(&path)
+            .await
+            .context("check attachment manifest existence")?
+        {
+            return Ok(Self::default());
+        }
+
+        let data = tokio::fs::read(&path)
+            .await
+            .context("read attachment manifest")?;
+        serde_json::from_slice(&data).context("parse attachment manifest")
+    }
+
+    async fn write(&mut self, dir: &Path, retry: &RetryPolicy) -> Result<()> {
+        VERSION_STRING.clone_into(&mut self.tool_version);
+        let s = serde_json::to_string_pretty(self).context("serialize attachment manifest")?;
+
+        write_to_file(
+            s.as_bytes(),
+            &dir.join(ATTACHMENT_MANIFEST_FILE),
+            None,
+            retry,
+        )
+        .await
+        .context("write attachment manifest")
+    }
+}
+
+/// Export the attachments of all mails in the selected folders.
+///
+/// Attachments are written into a directory hierarchy that mirrors the folders. Mails that are listed in the manifest
+/// already are skipped.
+pub(crate) async fn run(
+    client: &Client,
+    session: &Session,
+    group: Option<&str>,
+    cfg: AttachmentsCLIConfig,
+) -> Result<()> {
+    let folders = Folder::list(client, session, group)
+        .await
+        .context("get folders")?
+        .try_collect::<Vec<_>>()
+        .await
+        .context("poll folder")?;
+    let dirs = folder_dirs(&folders, cfg.filename_charset);
+
+    let selected = if cfg.folder.is_empty() {
+        folders.iter().filter(|f| f.accessible).collect::<Vec<_>>()
+    } else {
+        let mut selected = Vec::with_capacity(cfg.folder.len());
+        for name in &cfg.folder {
+            let folder = folders
+                .iter()
+                .find(|f| &f.name == name)
+                .context(Failure::FolderNotFound)
+                .with_context(|| format!("find folder `{name}`"))?;
+            ensure!(
+                folder.accessible,
+                "folder `{name}` is inaccessible: no key for its owner group"
+            );
+            selected.push(folder);
+        }
+        selected
+    };
+
+    tokio::fs::create_dir_all(&cfg.path)
+        .await
+        .with_context(|| format!("create output dir: `{}`", cfg.path.display()))?;
+    let mut manifest = AttachmentManifest::load(&cfg.path).await?;
+
+    let res = export_folders(client, session, &cfg, &selected, &dirs, &mut manifest).await;
+
+    // keep the progress of an aborted run
+    manifest.write(&cfg.path, client.retry_policy()).await?;
+    res
+}
+
+/// Export the attachments of the given folders.
+///
+/// Several folders are listed at the same time, the downloads of all folders share `--concurrent-downloads`.
+async fn export_folders(
+    client: &Client,
+    session: &Session,
+    cfg: &AttachmentsCLIConfig,
+    folders: &[&Folder],
+    dirs: &HashMap<String, String>,
+    manifest: &mut AttachmentManifest,
+) -> Result<()> {
+    let exported = manifest
+        .attachments
+        .values()
+        .map(|entry| entry.mail_id.clone())
+        .collect::<HashSet<_>>();
+    let exported = &exported;
+    let attachment_filter = AttachmentFilter {
+        skip_all: false,
+        max_size: cfg.max_attachment_size,
+        mime_types: cfg.attachment_type.clone(),
+    };
+    let attachment_filter = &attachment_filter;
+
+    // the streams are boxed up front, so that the closure does not end up in the type of the future, which would
+    // prevent it from being `Send`
+    let streams = folders
+        .iter()
+        .map(|folder| {
+            let dir = dirs[&folder.id].clone();
+            info!(folder = folder.name.as_str(), dir, "export attachments");
+            Mail::list(client, session, folder, cfg.ignore_new_mails, None)
+                .try_filter(move |mail| {
+                    ready(!mail.attachments.is_empty() && !exported.contains(&mail.mail_id))
+                })
+                .map_ok(move |mail| (dir.clone(), mail))
+                .boxed()
+        })
+        .collect::<Vec<_>>();
+    let mails = futures::stream::iter(streams)
+        .flatten_unordered(CONCURRENT_FOLDERS)
+        .map_ok(|(dir, mail)| async move {
+            debug!(mail_id = mail.mail_id.as_str(), "download mail");
+            let mail = Arc::clone(&mail)
+                .download(
+                    client,
+                    session,
+                    None,
+                    DownloadOptions {
+                        max_decompressed_size: cfg.max_decompressed_size,
+                        memory_budget: None,
+                        attachment_filter,
+                    },
+                )
+                .await
+                .with_context(|| format!("download mail: `{}`", mail.ui_url(client)))?;
+            Ok((dir, mail))
+        })
+        .try_buffered(cfg.concurrent_downloads);
+    let mut mails = std::pin::pin!(mails);
+    while let Some((dir, mail)) = mails.try_next().await? {
+        write_attachments(cfg, mail, &dir, manifest, client.retry_policy()).await?;
+    }
+
+    Ok(())
+}
+
+/// Write all attachments of the given mail and record them in the manifest.
+async fn write_attachments(
+    cfg: &AttachmentsCLIConfig,
+    mail: DownloadedMail,
+    dir: &str,
+    manifest: &mut AttachmentManifest,
+    retry: &RetryPolicy,
+) -> Result<()> {
+    let date = Timezone::Utc.format(mail.mail.date, DATE_FORMAT);
+    for attachment in &mail.attachments {
+        let name = sanitize_file_name(&escape_file_string(
+            &attachment.name,
+            FilenameCharset::Unicode,
+        ));
+        let file_name =
+            unique_file_name(&cfg.path, dir, &format!("{date}-{name}"), manifest).await?;
+        let path = cfg.path.join(&file_name);
+
+        info!(
+            mail_id = mail.mail.mail_id.as_str(),
+            target_file = %path.display(),
+            "write attachment",
+        );
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("create output dir: `{}`", parent.display()))?;
+        }
+        write_to_file(
+            attachment.data.reader().context("read attachment")?,
+            &path,
+            None,
+            retry,
+        )
+        .await
+        .with_context(|| format!("write attachment: `{}`", path.display()))?;
+
+        manifest.attachments.insert(
+            file_name,
+            AttachmentEntry {
+                mail_id: mail.mail.mail_id.clone(),
+                folder_id: mail.mail.folder_id.clone(),
+                date: mail.mail.date,
+                sender: mail.mail.sender.mail.clone(),
+                subject: mail.mail.subject.clone(),
+                name: attachment.name.clone(),
+                mime_type: attachment.mime_type.clone(),
+                size: attachment.data.len(),
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// File name within `dir` that is neither used by the manifest nor exists on disk.
+///
+/// A counter is added before the extension if the name is taken, e.g. `invoice (2).pdf`.
+async fn unique_file_name(
+    root: &Path,
+    dir: &str,
+    name: &str,
+    manifest: &AttachmentManifest,
+) -> Result<String> {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (name, String::new()),
+    };
+
+    for n in 1.. {
+        let file_name = if n == 1 {
+            format!("{dir}{name}")
+        } else {
+            format!("{dir}{stem} ({n}){ext}")
+        };
+        if !manifest.attachments.contains_key(&file_name)
+            && !tokio::fs::try_exists(root.join(&file_name))
+                .await
+                .context("check attachment existence")?
+        {
+            return Ok(file_name);
+        }
+    }
+    unreachable!("counter exhausted")
+}
+
+/// Output directory of each folder, keyed by folder ID.
+///
+/// Nested folders are placed in the directory of their parent. Directories end with a `/`.
+fn folder_dirs(folders: &[Folder], charset: FilenameCharset) -> HashMap<String, String> {
+    let mut dirs = HashMap::<String, String>::with_capacity(folders.len());
+    // parents come before their children
+    for (_depth, idx) in folders::tree(folders) {
+        let folder = &folders[idx];
+        let parent = folder
+            .parent
+            .as_ref()
+            .and_then(|p| dirs.get(p))
+            .cloned()
+            .unwrap_or_default();
+        let name = sanitize_file_name(&escape_file_string(&folder.name, charset));
+        dirs.insert(folder.id.clone(), format!("{parent}{name}/"));
+    }
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use tatutanatata_core::proto::enums::MailFolderType;
+
+    use super::*;
+
+    fn folder(id: &str, name: &str, parent: Option<&str>) -> Folder {
+        Folder {
+            name: name.to_owned(),
+            mails: format!("{id}Mails"),
+            id: id.to_owned(),
+            folder_type: MailFolderType::Custom,
+            accessible: true,
+            parent: parent.map(ToOwned::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_folder_dirs() {
+        let folders = [
+            folder("nested", "Invoices 2024", Some("custom")),
+            folder("inbox", "Inbox", None),
+            folder("custom", "Work: Projects", None),
+            folder("orphan", "..", Some("unknown")),
+        ];
+
+        let dirs = folder_dirs(&folders, FilenameCharset::Unicode);
+        assert_eq!(dirs["inbox"], "Inbox/");
+        assert_eq!(dirs["custom"], "Work Projects/");
+        assert_eq!(dirs["nested"], "Work Projects/Invoices 2024/");
+        assert_eq!(dirs["orphan"], "_/");
+    }
+
+    #[tokio::test]
+    async fn test_unique_file_name() {
+        let root = tempfile::TempDir::new().unwrap();
+        tokio::fs::create_dir(root.path().join("Inbox"))
+            .await
+            .unwrap();
+        tokio::fs::write(root.path().join("Inbox/a.pdf"), b"")
+            .await
+            .unwrap();
+        let mut manifest = AttachmentManifest::default();
+
+        assert_eq!(
+            unique_file_name(root.path(), "Inbox/", "b.pdf", &manifest)
+                .await
+                .unwrap(),
+            "Inbox/b.pdf",
+        );
+        assert_eq!(
+            unique_file_name(root.path(), "Inbox/", "a.pdf", &manifest)
+                .await
+                .unwrap(),
+            "Inbox/a (2).pdf",
+        );
+
+        manifest.attachments.insert(
+            "Inbox/a (2).pdf".to_owned(),
+            AttachmentEntry {
+                mail_id: "mail".to_owned(),
+                folder_id: "inbox".to_owned(),
+                date: DateTime::UNIX_EPOCH,
+                sender: "alice@example.com".to_owned(),
+                subject: "Hello".to_owned(),
+                name: "a.pdf".to_owned(),
+                mime_type: "application/pdf".to_owned(),
+                size: 0,
+            },
+        );
+        assert_eq!(
+            unique_file_name(root.path(), "Inbox/", "a.pdf", &manifest)
+                .await
+                .unwrap(),
+            "Inbox/a (3).pdf",
+        );
+        assert_eq!(
+            unique_file_name(root.path(), "Inbox/", "README", &manifest)
+                .await
+                .unwrap(),
+            "Inbox/README",
+        );
+    }
+}
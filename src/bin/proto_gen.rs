@@ -0,0 +1,462 @@
+//! Generate Rust structs for `tatutanatata-core/src/proto/messages.rs` from Tuta's entity model definitions.
+//!
+//! The input is the JSON object of a `TypeModels` file of the Tuta client, either keyed by type name (older versions)
+//! or by type ID (newer versions). Wrapping code like `export const typeModels = { ... } as const` is stripped. The
+//! output is printed to stdout and is meant to be compared with, or copied into, the hand-maintained structs.
+#![allow(unused_crate_dependencies)]
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+
+/// Generate protocol structs from Tuta's entity model definitions.
+#[derive(Debug, Parser)]
+#[clap(version)]
+struct Args {
+    /// Model definition file, e.g. `TypeModels.js` of the `tutanota` app.
+    #[clap(action)]
+    model: PathBuf,
+
+    /// Only generate the given type and the types that it aggregates.
+    ///
+    /// Can be repeated. All types are generated by default.
+    #[clap(long = "type", action)]
+    types: Vec<String>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let s = std::fs::read_to_string(&args.model)
+        .with_context(|| format!("read model: `{}`", args.model.display()))?;
+    let model = Model::parse(&s)?;
+    print!("{}", model.generate(&args.types)?);
+
+    Ok(())
+}
+
+/// Type definition.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TypeModel {
+    name: String,
+    id: u64,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    version: Option<serde_json::Value>,
+    #[serde(default)]
+    values: BTreeMap<String, ValueModel>,
+    #[serde(default)]
+    associations: BTreeMap<String, AssociationModel>,
+}
+
+/// Value of a [`TypeModel`].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ValueModel {
+    name: String,
+    id: u64,
+    #[serde(rename = "type")]
+    kind: String,
+    cardinality: String,
+    #[serde(default)]
+    encrypted: bool,
+}
+
+/// Association of a [`TypeModel`].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AssociationModel {
+    name: String,
+    id: u64,
+    #[serde(rename = "type")]
+    kind: String,
+    cardinality: String,
+
+    /// Referenced type by name, used by older versions.
+    #[serde(default)]
+    ref_type: Option<String>,
+
+    /// Referenced type by ID, used by newer versions.
+    #[serde(default)]
+    ref_type_id: Option<u64>,
+}
+
+/// Field of a generated struct.
+#[derive(Debug)]
+struct Field {
+    name: String,
+    id: u64,
+    rust_type: String,
+    encrypted: bool,
+}
+
+#[derive(Debug)]
+struct Model {
+    /// Types by name.
+    types: BTreeMap<String, TypeModel>,
+}
+
+impl Model {
+    fn parse(s: &str) -> Result<Self> {
+        let json = match (s.find('{'), s.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &s[start..=end],
+            _ => bail!("model does not contain a JSON object"),
+        };
+        let types: BTreeMap<String, TypeModel> =
+            serde_json::from_str(json).context("parse model")?;
+
+        Ok(Self {
+            types: types.into_values().map(|t| (t.name.clone(), t)).collect(),
+        })
+    }
+
+    /// Generate structs for the given types, or all types if none are given.
+    fn generate(&self, types: &[String]) -> Result<String> {
+        let mut selected = BTreeSet::new();
+        let mut to_visit = if types.is_empty() {
+            self.types.keys().cloned().collect::<Vec<_>>()
+        } else {
+            types.to_vec()
+        };
+        while let Some(name) = to_visit.pop() {
+            let t = self
+                .types
+                .get(&name)
+                .with_context(|| format!("unknown type: `{name}`"))?;
+            if !selected.insert(name) {
+                continue;
+            }
+            for a in t.associations.values() {
+                if a.kind == "AGGREGATION" {
+                    to_visit.push(self.ref_type(a)?.to_owned());
+                }
+            }
+        }
+
+        let mut out = String::new();
+        if let Some(version) = self.types.values().find_map(|t| t.version.as_ref()) {
+            let version = match version {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            writeln!(
+                out,
+                "// generated by `proto-gen` from model version {version}"
+            )
+            .expect("write to string");
+        } else {
+            writeln!(out, "// generated by `proto-gen`").expect("write to string");
+        }
+        for name in selected {
+            out.push('\n');
+            self.generate_struct(&self.types[&name], &mut out)?;
+        }
+        Ok(out)
+    }
+
+    fn generate_struct(&self, t: &TypeModel, out: &mut String) -> Result<()> {
+        let mut fields = t
+            .values
+            .values()
+            .map(|v| {
+                Ok(Field {
+                    name: v.name.clone(),
+                    id: v.id,
+                    rust_type: cardinality(&v.cardinality, value_type(t, v))?,
+                    encrypted: v.encrypted,
+                })
+            })
+            .chain(t.associations.values().map(|a| {
+                Ok(Field {
+                    name: a.name.clone(),
+                    id: a.id,
+                    rust_type: cardinality(&a.cardinality, self.association_type(a)?)?,
+                    encrypted: false,
+                })
+            }))
+            .collect::<Result<Vec<_>>>()?;
+        fields.sort_by_key(|f| f.id);
+
+        writeln!(out, "/// `{}` ({}, type ID {}).", t.name, t.kind, t.id).expect("write to string");
+        writeln!(out, "#[derive(Debug, Serialize, Deserialize)]").expect("write to string");
+        writeln!(out, "#[serde(rename_all = \"camelCase\")]").expect("write to string");
+        writeln!(out, "pub(crate) struct {} {{", t.name).expect("write to string");
+        for (idx, f) in fields.iter().enumerate() {
+            if idx > 0 {
+                out.push('\n');
+            }
+            if f.encrypted {
+                writeln!(out, "    /// Encrypted with the session key.").expect("write to string");
+            }
+            let rust_name = snake_case(&f.name);
+            if f.name.starts_with('_') || to_camel_case(&rust_name) != f.name {
+                writeln!(out, "    #[serde(rename = \"{}\")]", f.name).expect("write to string");
+            }
+            writeln!(
+                out,
+                "    pub(crate) {}: {},",
+                rust_field_name(&rust_name),
+                f.rust_type
+            )
+            .expect("write to string");
+        }
+        writeln!(out, "}}").expect("write to string");
+
+        Ok(())
+    }
+
+    fn ref_type<'a>(&'a self, a: &'a AssociationModel) -> Result<&'a str> {
+        match (&a.ref_type, a.ref_type_id) {
+            (Some(name), _) => Ok(name),
+            (None, Some(id)) => self
+                .types
+                .values()
+                .find(|t| t.id == id)
+                .map(|t| t.name.as_str())
+                .with_context(|| format!("unknown type ID of `{}`: {id}", a.name)),
+            (None, None) => bail!("association `{}` has no type", a.name),
+        }
+    }
+
+    fn association_type(&self, a: &AssociationModel) -> Result<String> {
+        let t = match a.kind.as_str() {
+            "AGGREGATION" => self.ref_type(a)?.to_owned(),
+            "ELEMENT_ASSOCIATION" | "LIST_ASSOCIATION" => "String".to_owned(),
+            "LIST_ELEMENT_ASSOCIATION"
+            | "LIST_ELEMENT_ASSOCIATION_GENERATED"
+            | "LIST_ELEMENT_ASSOCIATION_CUSTOM"
+            | "BLOB_ELEMENT_ASSOCIATION" => "[String; 2]".to_owned(),
+            other => bail!("unknown association type of `{}`: {other}", a.name),
+        };
+        Ok(t)
+    }
+}
+
+fn value_type(t: &TypeModel, v: &ValueModel) -> &'static str {
+    match (v.name.as_str(), v.kind.as_str()) {
+        ("_format", _) => "Format<0>",
+        ("_id", _) if matches!(t.kind.as_str(), "LIST_ELEMENT_TYPE" | "BLOB_ELEMENT_TYPE") => {
+            "[String; 2]"
+        }
+        (name, "Bytes") if is_encrypted_key(name) => "EncryptedKey",
+        (_, _) if v.encrypted => "Base64String",
+        (_, "String" | "GeneratedId" | "CustomId") => "String",
+        (_, "Number") => "Number",
+        (_, "Boolean") => "Boolean",
+        (_, "Date") => "UnixDate",
+        (_, _) => "Base64String",
+    }
+}
+
+/// Keys that are encrypted with another key, e.g. `_ownerEncSessionKey` or `symEncGKey`.
+fn is_encrypted_key(name: &str) -> bool {
+    name.contains("Enc") && name.ends_with("Key")
+}
+
+fn cardinality(cardinality: &str, t: impl Into<String>) -> Result<String> {
+    let t = t.into();
+    match cardinality {
+        "One" => Ok(t),
+        "ZeroOrOne" => Ok(format!("Option<{t}>")),
+        "Any" => Ok(format!("Vec<{t}>")),
+        other => bail!("unknown cardinality: {other}"),
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 4);
+    for c in name.trim_start_matches('_').chars() {
+        if c.is_ascii_uppercase() {
+            if !out.is_empty() && !out.ends_with('_') {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Inverse of [`snake_case`], as applied by `#[serde(rename_all = "camelCase")]`.
+fn to_camel_case(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            out.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn rust_field_name(name: &str) -> String {
+    match name {
+        "type" | "ref" | "match" | "mod" | "move" | "static" | "struct" | "trait" | "use" => {
+            format!("r#{name}")
+        }
+        _ => name.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        // newer format, keyed by IDs
+        let model = Model::parse(
+            r#"export const typeModels = {
+                "92": {
+                    "name": "MailAddress", "id": 92, "type": "AGGREGATED_TYPE", "version": 77,
+                    "values": {
+                        "93": {"name": "_id", "id": 93, "type": "CustomId", "cardinality": "One", "encrypted": false},
+                        "95": {"name": "name", "id": 95, "type": "String", "cardinality": "One", "encrypted": true},
+                        "96": {"name": "address", "id": 96, "type": "String", "cardinality": "One", "encrypted": false}
+                    },
+                    "associations": {}
+                },
+                "97": {
+                    "name": "Mail", "id": 97, "type": "LIST_ELEMENT_TYPE", "version": 77,
+                    "values": {
+                        "99": {"name": "_id", "id": 99, "type": "GeneratedId", "cardinality": "One", "encrypted": false},
+                        "101": {"name": "_format", "id": 101, "type": "Number", "cardinality": "One", "encrypted": false},
+                        "102": {"name": "_ownerEncSessionKey", "id": 102, "type": "Bytes", "cardinality": "ZeroOrOne", "encrypted": false},
+                        "105": {"name": "subject", "id": 105, "type": "String", "cardinality": "One", "encrypted": true},
+                        "107": {"name": "receivedDate", "id": 107, "type": "Date", "cardinality": "One", "encrypted": false},
+                        "108": {"name": "unread", "id": 108, "type": "Boolean", "cardinality": "One", "encrypted": false}
+                    },
+                    "associations": {
+                        "111": {"name": "sender", "id": 111, "type": "AGGREGATION", "cardinality": "One", "refTypeId": 92},
+                        "115": {"name": "attachments", "id": 115, "type": "LIST_ELEMENT_ASSOCIATION_GENERATED", "cardinality": "Any", "refTypeId": 13},
+                        "1308": {"name": "mailDetails", "id": 1308, "type": "BLOB_ELEMENT_ASSOCIATION", "cardinality": "ZeroOrOne", "refTypeId": 1305}
+                    }
+                },
+                "1": {
+                    "name": "Unrelated", "id": 1, "type": "ELEMENT_TYPE",
+                    "values": {},
+                    "associations": {}
+                }
+            } as const"#,
+        )
+        .unwrap();
+
+        insta::assert_snapshot!(model.generate(&["Mail".to_owned()]).unwrap(), @r###"
+        // generated by `proto-gen` from model version 77
+
+        /// `Mail` (LIST_ELEMENT_TYPE, type ID 97).
+        #[derive(Debug, Serialize, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        pub(crate) struct Mail {
+            #[serde(rename = "_id")]
+            pub(crate) id: [String; 2],
+
+            #[serde(rename = "_format")]
+            pub(crate) format: Format<0>,
+
+            #[serde(rename = "_ownerEncSessionKey")]
+            pub(crate) owner_enc_session_key: Option<EncryptedKey>,
+
+            /// Encrypted with the session key.
+            pub(crate) subject: Base64String,
+
+            pub(crate) received_date: UnixDate,
+
+            pub(crate) unread: Boolean,
+
+            pub(crate) sender: MailAddress,
+
+            pub(crate) attachments: Vec<[String; 2]>,
+
+            pub(crate) mail_details: Option<[String; 2]>,
+        }
+
+        /// `MailAddress` (AGGREGATED_TYPE, type ID 92).
+        #[derive(Debug, Serialize, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        pub(crate) struct MailAddress {
+            #[serde(rename = "_id")]
+            pub(crate) id: String,
+
+            /// Encrypted with the session key.
+            pub(crate) name: Base64String,
+
+            pub(crate) address: String,
+        }
+        "###);
+    }
+
+    #[test]
+    fn test_generate_by_name() {
+        // older format, keyed by names
+        let model = Model::parse(
+            r#"{
+                "MailBox": {
+                    "name": "MailBox", "id": 125, "type": "ELEMENT_TYPE", "version": "60",
+                    "values": {
+                        "_id": {"name": "_id", "id": 129, "type": "GeneratedId", "cardinality": "One", "encrypted": false}
+                    },
+                    "associations": {
+                        "folders": {"name": "folders", "id": 443, "type": "AGGREGATION", "cardinality": "ZeroOrOne", "refType": "MailFolderRef"}
+                    }
+                },
+                "MailFolderRef": {
+                    "name": "MailFolderRef", "id": 440, "type": "AGGREGATED_TYPE", "version": "60",
+                    "values": {},
+                    "associations": {
+                        "folders": {"name": "folders", "id": 442, "type": "LIST_ASSOCIATION", "cardinality": "One", "refType": "MailFolder"}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        insta::assert_snapshot!(model.generate(&[]).unwrap(), @r###"
+        // generated by `proto-gen` from model version 60
+
+        /// `MailBox` (ELEMENT_TYPE, type ID 125).
+        #[derive(Debug, Serialize, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        pub(crate) struct MailBox {
+            #[serde(rename = "_id")]
+            pub(crate) id: String,
+
+            pub(crate) folders: Option<MailFolderRef>,
+        }
+
+        /// `MailFolderRef` (AGGREGATED_TYPE, type ID 440).
+        #[derive(Debug, Serialize, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        pub(crate) struct MailFolderRef {
+            pub(crate) folders: String,
+        }
+        "###);
+        assert_eq!(
+            model.generate(&["Foo".to_owned()]).unwrap_err().to_string(),
+            "unknown type: `Foo`",
+        );
+    }
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!(snake_case("_ownerEncSessionKey"), "owner_enc_session_key");
+        assert_eq!(snake_case("symEncGKey"), "sym_enc_g_key");
+        assert_eq!(to_camel_case("sym_enc_g_key"), "symEncGKey");
+        assert_eq!(snake_case("type"), "type");
+        assert_eq!(rust_field_name("type"), "r#type");
+    }
+}
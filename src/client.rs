@@ -1,316 +1,117 @@
-use std::{future::Future, path::PathBuf, sync::Arc};
+//! HTTP client options of the CLI, see [`ClientCLIConfig`].
+use std::path::PathBuf;
 
-use anyhow::{Context, Result};
-use futures::Stream;
-use reqwest::{Method, Response, StatusCode};
-use serde::de::DeserializeOwned;
-use tokio::{
-    sync::mpsc::{channel, Receiver},
-    task::JoinSet,
+use clap::Parser;
+use tatutanatata_core::{
+    client::{ClientConfig, DEFAULT_HOST},
+    tls::CertFingerprint,
 };
-use tracing::debug;
-use uuid::Uuid;
 
-use crate::{
-    constants::APP_USER_AGENT,
-    proto::{binary::Base64Url, messages::Entity},
-};
-
-const STREAM_BATCH_SIZE: u64 = 1000;
-const STREAM_BUFFER_SIZE: u64 = 4 * STREAM_BATCH_SIZE;
-pub(crate) const DEFAULT_HOST: &str = "https://app.tuta.com";
-
-#[derive(Debug, Clone)]
-pub(crate) struct Client {
-    inner: reqwest::Client,
+/// HTTP client CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct ClientCLIConfig {
+    /// Server URL.
+    ///
+    /// Use this for self-hosted or whitelabel deployments.
+    #[clap(long, env = "TUTANOTA_CLI_HOST", default_value = DEFAULT_HOST)]
+    host: String,
+
+    /// Proxy URL, e.g. `http://proxy:3128` or `socks5://proxy:1080`.
+    ///
+    /// If not set, the standard `HTTPS_PROXY`/`ALL_PROXY` environment variables are used.
+    #[clap(long, env = "TUTANOTA_CLI_PROXY")]
+    proxy: Option<String>,
+
+    /// Use HTTP/1.1 instead of HTTP/2.
+    ///
+    /// Some middleboxes and older proxies do not support HTTP/2.
+    #[clap(long)]
+    http1: bool,
+
+    /// Additionally trust the CA certificates in the given PEM file.
+    ///
+    /// This is required behind TLS-intercepting proxies.
+    #[clap(long, env = "TUTANOTA_CLI_CA_CERT")]
+    ca_cert: Option<PathBuf>,
+
+    /// Require a certificate with the given SHA-256 fingerprint in the server certificate chain.
+    ///
+    /// The fingerprint is hex-encoded and may contain colons. Since the API and blob servers use different leaf
+    /// certificates, you likely want to pin an intermediate certificate. Can be repeated.
+    #[clap(long)]
+    pin_cert: Vec<CertFingerprint>,
+
+    /// Allow requests that modify data on the server.
+    ///
+    /// By default, the client is read-only and refuses to issue any request that could modify the mailbox (logging in
+    /// and out is always permitted).
+    #[clap(long)]
+    allow_writes: bool,
+
+    /// Dump JSON responses of server to given folder.
+    ///
+    /// This is useful for development and debugging. Binary responses (e.g. blobs) are dumped as well, so the folder
+    /// can later be used with `--replay-from`. The `index.jsonl` file records the request method, path, query, and body
+    /// of each response along with a sequence number. Access tokens are redacted.
+    #[clap(long)]
     debug_dump_json_to: Option<PathBuf>,
-}
-
-impl Client {
-    pub(crate) async fn try_new(debug_dump_json_to: Option<PathBuf>) -> Result<Self> {
-        let inner = reqwest::Client::builder()
-            .hickory_dns(true)
-            .http2_adaptive_window(true)
-            .http2_prior_knowledge()
-            .https_only(true)
-            .min_tls_version(reqwest::tls::Version::TLS_1_3)
-            .user_agent(APP_USER_AGENT)
-            .build()
-            .context("set up HTTPs client")?;
-
-        if let Some(path) = &debug_dump_json_to {
-            tokio::fs::create_dir_all(path)
-                .await
-                .context("creating directories to dump JSON data")?;
-        }
-
-        Ok(Self {
-            inner,
-            debug_dump_json_to,
-        })
-    }
-
-    pub(crate) fn stream<Resp>(
-        &self,
-        path: &str,
-        access_token: Option<&Base64Url>,
-    ) -> impl Stream<Item = Result<Resp>>
-    where
-        Resp: DeserializeOwned + Entity + Send + 'static,
-    {
-        let (tx, rx) = channel(STREAM_BUFFER_SIZE as usize);
-
-        let path = Arc::new(path.to_owned());
-        let access_token = Arc::new(access_token.cloned());
-        let this = self.clone();
-        let mut fetch_task = JoinSet::new();
-        fetch_task.spawn(async move {
-            let mut next_start = "------------".to_owned();
-
-            loop {
-                debug!(
-                    path = path.as_str(),
-                    start = next_start.as_str(),
-                    "fetch new page",
-                );
-
-                let res = this
-                    .do_json::<(), Vec<Resp>>(Request {
-                        method: Method::GET,
-                        host: DEFAULT_HOST,
-                        prefix: Prefix::Tutanota,
-                        path: &path,
-                        data: &(),
-                        access_token: access_token.as_ref().as_ref(),
-                        query: &[
-                            ("start", &next_start),
-                            ("count", &STREAM_BATCH_SIZE.to_string()),
-                            ("reverse", "false"),
-                        ],
-                    })
-                    .await
-                    .context("fetch next page");
-
-                match res {
-                    Ok(elements) => {
-                        match elements.last() {
-                            None => {
-                                // reached end
-                                return;
-                            }
-                            Some(o) => {
-                                o.id().to_owned().clone_into(&mut next_start);
-                            }
-                        }
-
-                        for o in elements {
-                            if tx.send(Ok(o)).await.is_err() {
-                                // receiver gone
-                                return;
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        // it's OK if the receiver is gone, we exit anyways
-                        tx.send(Err(e)).await.ok();
-                        return;
-                    }
-                }
-            }
-        });
-        let state = StreamState { rx, fetch_task };
-
-        futures::stream::unfold(state, move |mut state: StreamState<Resp>| async move {
-            state.rx.recv().await.map(|next| (next, state))
-        })
-    }
-
-    pub(crate) async fn do_json<Req, Resp>(&self, r: Request<'_, Req>) -> Result<Resp>
-    where
-        Req: serde::Serialize + Sync,
-        Resp: DeserializeOwned,
-    {
-        let s = retry(|| async { self.do_request(r.clone()).await?.text().await }).await?;
-
-        let json_path = match &self.debug_dump_json_to {
-            Some(path) => {
-                let uuid = Uuid::new_v4();
-                let path = path.join(format!("{uuid}.json"));
-                debug!(%uuid, path=%path.display(), "dumping debug JSON");
-                tokio::fs::write(&path, &s)
-                    .await
-                    .context("dumping debug JSON")?;
-                Some(path)
-            }
-            None => None,
-        };
-
-        let jd = &mut serde_json::Deserializer::from_str(&s);
-        let res: Result<Resp, _> = serde_path_to_error::deserialize(jd);
-
-        res.with_context(|| {
-            let type_name = std::any::type_name::<Resp>();
-            match json_path {
-                Some(json_path) => {
-                    format!(
-                        "deserialize JSON for `{}`, data dumped to `{}`",
-                        type_name,
-                        json_path.display(),
-                    )
-                }
-                None => {
-                    format!(
-                        "deserialize JSON for `{}`, consider passing `--debug-dump-json-to=some/path` to dump the data",
-                        type_name,
-                    )
-                }
-            }
-        })
-    }
-
-    pub(crate) async fn do_bytes<Req>(&self, r: Request<'_, Req>) -> Result<Vec<u8>>
-    where
-        Req: serde::Serialize + Sync,
-    {
-        let b = retry(|| async { self.do_request(r.clone()).await?.bytes().await }).await?;
-
-        Ok(b.to_vec())
-    }
-
-    pub(crate) async fn do_no_response<Req>(&self, r: Request<'_, Req>) -> Result<()>
-    where
-        Req: serde::Serialize + Sync,
-    {
-        retry(|| async { self.do_request(r.clone()).await }).await?;
 
-        Ok(())
-    }
-
-    async fn do_request<Req>(&self, r: Request<'_, Req>) -> Result<Response, reqwest::Error>
-    where
-        Req: serde::Serialize + Sync,
-    {
-        let Request {
-            method,
-            host,
-            prefix,
-            path,
-            data,
-            access_token,
-            query,
-        } = r;
-        debug!(%method, prefix=prefix.str(), path, "service request",);
-
-        let mut req = self
-            .inner
-            .request(method, format!("{}/rest/{}/{}", host, prefix.str(), path));
-
-        if let Some(access_token) = access_token {
-            req = req.header("accessToken", access_token.to_string());
-        }
-
-        let resp = req
-            .json(data)
-            .query(query)
-            .send()
-            .await?
-            .error_for_status()?;
-
-        Ok(resp)
-    }
-}
-
-struct StreamState<T> {
-    rx: Receiver<Result<T>>,
-    #[allow(dead_code)]
-    fetch_task: JoinSet<()>,
-}
-
-#[derive(Debug, Clone, Copy)]
-pub(crate) enum Prefix {
-    Tutanota,
-    Storage,
-    Sys,
-}
-
-impl Prefix {
-    fn str(&self) -> &'static str {
-        match self {
-            Self::Tutanota => "tutanota",
-            Self::Storage => "storage",
-            Self::Sys => "sys",
-        }
-    }
+    /// Serve responses from a folder that was written by `--debug-dump-json-to` instead of contacting the server.
+    ///
+    /// Requests are matched by method, path, query and body, ignoring session-specific access tokens. Requests
+    /// without response (e.g. logout) always succeed. This is useful for debugging parsing and decryption issues
+    /// offline.
+    #[clap(long, conflicts_with = "debug_dump_json_to")]
+    replay_from: Option<PathBuf>,
+
+    /// Log fields of server responses that are NOT part of the protocol model.
+    ///
+    /// Tuta changes its API frequently, so this gives early warning about new or renamed fields. Every field is only
+    /// reported once per response type. The model skips some fields on purpose because they are NOT needed, so a few
+    /// reports are expected.
+    #[clap(long)]
+    strict_proto: bool,
+
+    /// Number of list pages that are fetched ahead while the current page is processed.
+    #[clap(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..))]
+    prefetch_pages: u64,
+
+    /// Fetch list pages from both ends concurrently until they meet.
+    ///
+    /// This roughly halves the time to list large folders. Elements are still listed in order, so the upper half is
+    /// buffered in memory until both ends meet.
+    #[clap(long)]
+    parallel_pagination: bool,
 }
 
-pub(crate) struct Request<'a, Req>
-where
-    Req: serde::Serialize + Sync,
-{
-    pub(crate) method: Method,
-    pub(crate) host: &'a str,
-    pub(crate) prefix: Prefix,
-    pub(crate) path: &'a str,
-    pub(crate) data: &'a Req,
-    pub(crate) access_token: Option<&'a Base64Url>,
-    pub(crate) query: &'a [(&'a str, &'a str)],
-}
-
-impl<'a, Req> Request<'a, Req>
-where
-    Req: serde::Serialize + Sync,
-{
-    pub(crate) fn new(prefix: Prefix, path: &'a str, data: &'a Req) -> Self {
+impl From<ClientCLIConfig> for ClientConfig {
+    fn from(cfg: ClientCLIConfig) -> Self {
         Self {
-            method: Method::GET,
-            host: DEFAULT_HOST,
-            prefix,
-            path,
-            data,
-            access_token: None,
-            query: &[],
+            host: cfg.host,
+            proxy: cfg.proxy,
+            http1: cfg.http1,
+            ca_cert: cfg.ca_cert,
+            pin_cert: cfg.pin_cert,
+            allow_writes: cfg.allow_writes,
+            debug_dump_json_to: cfg.debug_dump_json_to,
+            replay_from: cfg.replay_from,
+            strict_proto: cfg.strict_proto,
+            prefetch_pages: cfg.prefetch_pages,
+            parallel_pagination: cfg.parallel_pagination,
         }
     }
 }
 
-impl<Req> Clone for Request<'_, Req>
-where
-    Req: serde::Serialize + Sync,
-{
-    fn clone(&self) -> Self {
-        Self {
-            method: self.method.clone(),
-            host: self.host,
-            prefix: self.prefix,
-            path: self.path,
-            data: self.data,
-            access_token: self.access_token,
-            query: self.query,
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_config_defaults() {
+        let parsed = ClientCLIConfig::try_parse_from(["test", "--host", DEFAULT_HOST]).unwrap();
+        assert_eq!(
+            format!("{:?}", ClientConfig::default()),
+            format!("{:?}", ClientConfig::from(parsed)),
+        );
     }
 }
-
-async fn retry<F, Fut, T>(action: F) -> Result<T>
-where
-    F: Fn() -> Fut + Send,
-    Fut: Future<Output = Result<T, reqwest::Error>> + Send,
-    T: Send,
-{
-    crate::retry::retry("REST client", action, |e| {
-        if e.is_connect() || e.is_timeout() {
-            return true;
-        }
-
-        if let Some(status) = e.status() {
-            if status.is_server_error()
-                || (status == StatusCode::REQUEST_TIMEOUT)
-                || (status == StatusCode::TOO_MANY_REQUESTS)
-            {
-                return true;
-            }
-        }
-
-        false
-    })
-    .await
-}
@@ -0,0 +1,150 @@
+//! Detect mails whose file names collide, see [`FilenameTemplate::render_unique`](crate::filename_template::FilenameTemplate::render_unique).
+//!
+//! All mails that share a file name get a unique one, so the assignment does NOT depend on the listing order or on
+//! which of the mails are exported in a run.
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Decides for the mails of a listing whether their file name collides with the one of another mail.
+///
+/// Mails must be pushed by ascending ID. Each mail is held back until the listing passed the ID after which no mail
+/// with the same file name can follow.
+#[derive(Debug)]
+pub(crate) struct Collisions<T> {
+    pending: VecDeque<Pending<T>>,
+
+    /// Number of pending mails per file name.
+    counts: HashMap<String, usize>,
+
+    /// File names of released mails that collided, so that pending mails with the same name are unique as well.
+    colliding: HashSet<String>,
+}
+
+#[derive(Debug)]
+struct Pending<T> {
+    mail: T,
+    file_name: String,
+
+    /// ID after which no mail with the same file name follows, or [`None`] if this is only known at the end.
+    decided_by: Option<String>,
+}
+
+impl<T> Default for Collisions<T> {
+    fn default() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            counts: HashMap::new(),
+            colliding: HashSet::new(),
+        }
+    }
+}
+
+impl<T> Collisions<T> {
+    /// Add next mail of the listing.
+    ///
+    /// Returns the mails that are decided now, in listing order, paired with whether their file name collides.
+    pub(crate) fn push(
+        &mut self,
+        mail: T,
+        mail_id: &str,
+        file_name: String,
+        decided_by: Option<String>,
+    ) -> Vec<(T, bool)> {
+        let released = self.release(|decided_by| decided_by.is_some_and(|id| id <= mail_id));
+
+        *self.counts.entry(file_name.clone()).or_default() += 1;
+        self.pending.push_back(Pending {
+            mail,
+            file_name,
+            decided_by,
+        });
+
+        released
+    }
+
+    /// Release all remaining mails at the end of the listing.
+    pub(crate) fn finish(mut self) -> Vec<(T, bool)> {
+        self.release(|_| true)
+    }
+
+    fn release(&mut self, decided: impl Fn(Option<&str>) -> bool) -> Vec<(T, bool)> {
+        let mut released = vec![];
+        while let Some(front) = self.pending.front() {
+            if !decided(front.decided_by.as_deref()) {
+                break;
+            }
+            let Pending {
+                mail, file_name, ..
+            } = self.pending.pop_front().expect("just checked");
+
+            let count = self.counts.get_mut(&file_name).expect("counted on push");
+            let collides = *count > 1 || self.colliding.contains(&file_name);
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&file_name);
+            }
+            if collides {
+                self.colliding.insert(file_name);
+            }
+
+            released.push((mail, collides));
+        }
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collisions() {
+        let mut collisions = Collisions::default();
+        let mut released = vec![];
+        for (id, file_name, decided_by) in [
+            ("a", "x", Some("d")),
+            ("b", "y", Some("d")),
+            ("c", "x", Some("f")),
+            ("d", "z", Some("g")),
+            ("e", "w", None),
+            ("f", "z", None),
+        ] {
+            released.extend(collisions.push(
+                id,
+                id,
+                file_name.to_owned(),
+                decided_by.map(str::to_owned),
+            ));
+        }
+        released.extend(collisions.finish());
+
+        assert_eq!(
+            released,
+            [
+                ("a", true),
+                ("b", false),
+                ("c", true),
+                ("d", true),
+                ("e", false),
+                ("f", true),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_collisions_hold_back() {
+        let mut collisions = Collisions::default();
+        assert_eq!(
+            collisions.push("a", "a", "x".to_owned(), Some("c".to_owned())),
+            [],
+        );
+        assert_eq!(
+            collisions.push("b", "b", "y".to_owned(), Some("c".to_owned())),
+            [],
+        );
+        assert_eq!(
+            collisions.push("c", "c", "z".to_owned(), Some("d".to_owned())),
+            [("a", false), ("b", false)],
+        );
+        assert_eq!(collisions.finish(), [("c", false)]);
+    }
+}
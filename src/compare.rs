@@ -0,0 +1,392 @@
+//! Comparison of a local export with the server, see [`run`].
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use futures::TryStreamExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tatutanatata_core::{client::Client, folders::Folder, mails::Mail, session::Session};
+use tracing::debug;
+
+use crate::{
+    db::Database,
+    file_output::FileCompression,
+    manifest::{Manifest, MANIFEST_FILE},
+    OutputFormat,
+};
+
+/// Suffixes of encrypted files, which cannot be read back.
+const ENCRYPTED_SUFFIXES: &[&str] = &[".age", ".gpg"];
+
+/// Options of the `compare` command.
+#[derive(Debug, Parser)]
+pub(crate) struct CompareCLIConfig {
+    /// Folder name.
+    #[clap(long, action)]
+    folder: String,
+
+    /// Directory of the export.
+    ///
+    /// The `manifest.json` in this directory is used to find the exported mails, see `download --manifest`.
+    #[clap(long, action)]
+    path: PathBuf,
+
+    /// Database of the export, see `download --db`.
+    ///
+    /// Exported files are only checked for size and hash mismatches if the database is passed.
+    #[clap(long, action)]
+    db: Option<PathBuf>,
+
+    /// Ignore new mails that cannot be decrypted (yet).
+    #[clap(long, action)]
+    ignore_new_mails: bool,
+}
+
+/// Exported mail, see [`local_mails`].
+#[derive(Debug)]
+pub(crate) struct LocalMail {
+    /// File name within the export directory.
+    pub(crate) file_name: String,
+
+    /// Size and hash of the uncompressed data, if recorded in the database.
+    recorded: Option<(u64, String)>,
+}
+
+/// Differences between the export and the server.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CompareReport {
+    server_mails: usize,
+    local_mails: usize,
+
+    /// Mails on the server that were NOT exported or whose file is gone.
+    missing: Vec<MissingMail>,
+
+    /// Exported mails that are NOT on the server anymore.
+    deleted: Vec<ExportedFile>,
+
+    /// Exported files that differ from the recorded size or hash.
+    mismatched: Vec<Mismatch>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MissingMail {
+    mail_id: String,
+    date: DateTime<Utc>,
+    subject: String,
+
+    /// File that was exported before, if any.
+    file_name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedFile {
+    mail_id: String,
+    file_name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Mismatch {
+    mail_id: String,
+    file_name: String,
+    reason: String,
+}
+
+/// Compare the export of a folder with the mails on the server and print the differences.
+///
+/// Fails if mails are missing locally or if exported files do NOT match. Mails that were deleted on the server are
+/// only reported.
+pub(crate) async fn run(
+    client: &Client,
+    session: &Session,
+    group: Option<&str>,
+    output: OutputFormat,
+    cfg: CompareCLIConfig,
+) -> Result<()> {
+    let folder = Folder::find(client, session, group, &cfg.folder).await?;
+    let local = local_mails(&cfg.path, cfg.db.as_deref(), &folder).await?;
+
+    let server = Mail::list(client, session, &folder, cfg.ignore_new_mails, None)
+        .map_ok(|mail| (mail.mail_id.clone(), mail))
+        .try_collect::<BTreeMap<_, _>>()
+        .await
+        .context("list mail")?;
+
+    let report = compare(&cfg.path, &server, &local)?;
+    match output {
+        OutputFormat::Text => {
+            for m in &report.missing {
+                println!(
+                    "missing\t{}\t{}\t{}",
+                    m.mail_id,
+                    m.date.to_rfc3339(),
+                    m.subject
+                );
+            }
+            for f in &report.deleted {
+                println!("deleted\t{}\t{}", f.mail_id, f.file_name);
+            }
+            for m in &report.mismatched {
+                println!("mismatch\t{}\t{}\t{}", m.mail_id, m.file_name, m.reason);
+            }
+        }
+        OutputFormat::Json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).context("format JSON")?
+            );
+        }
+    }
+    eprintln!(
+        "{} mails on server, {} exported: {} missing, {} deleted on server, {} mismatched",
+        report.server_mails,
+        report.local_mails,
+        report.missing.len(),
+        report.deleted.len(),
+        report.mismatched.len(),
+    );
+
+    ensure!(
+        report.missing.is_empty() && report.mismatched.is_empty(),
+        "export is incomplete: {} mail(s) missing, {} file(s) mismatched",
+        report.missing.len(),
+        report.mismatched.len(),
+    );
+    Ok(())
+}
+
+/// Exported mails of the given folder, keyed by mail ID.
+///
+/// Database records take precedence over manifest entries.
+pub(crate) async fn local_mails(
+    dir: &Path,
+    db: Option<&Path>,
+    folder: &Folder,
+) -> Result<BTreeMap<String, LocalMail>> {
+    let mut local = BTreeMap::new();
+
+    let manifest_path = dir.join(MANIFEST_FILE);
+    let has_manifest = tokio::fs::try_exists(&manifest_path)
+        .await
+        .context("check manifest existence")?;
+    if has_manifest {
+        let manifest = Manifest::load(dir).await.context("load manifest")?;
+        for (file_name, mail_id) in manifest.files_of_folder(&folder.id) {
+            local.insert(
+                mail_id,
+                LocalMail {
+                    file_name,
+                    recorded: None,
+                },
+            );
+        }
+    }
+
+    if let Some(path) = db {
+        ensure!(
+            tokio::fs::try_exists(path)
+                .await
+                .context("check database existence")?,
+            "database not found: `{}`",
+            path.display()
+        );
+        let db = Database::open(path).context("open database")?;
+        for record in db.exported(&folder.id)? {
+            local.insert(
+                record.mail_id,
+                LocalMail {
+                    file_name: record.file_name,
+                    recorded: Some((record.size, record.sha256)),
+                },
+            );
+        }
+    } else if !has_manifest {
+        bail!(
+            "neither `{}` nor `--db` found, export with `--manifest` or `--db` first",
+            manifest_path.display()
+        );
+    }
+
+    Ok(local)
+}
+
+fn compare(
+    root: &Path,
+    server: &BTreeMap<String, Arc<Mail>>,
+    local: &BTreeMap<String, LocalMail>,
+) -> Result<CompareReport> {
+    let mut report = CompareReport {
+        server_mails: server.len(),
+        local_mails: local.len(),
+        ..Default::default()
+    };
+
+    for (mail_id, mail) in server {
+        let Some(local) = local.get(mail_id) else {
+            report.missing.push(MissingMail {
+                mail_id: mail_id.clone(),
+                date: mail.date,
+                subject: mail.subject.clone(),
+                file_name: None,
+            });
+            continue;
+        };
+
+        let path = root.join(&local.file_name);
+        match check_file(&path, local.recorded.as_ref())? {
+            FileCheck::Ok => {}
+            FileCheck::Missing => report.missing.push(MissingMail {
+                mail_id: mail_id.clone(),
+                date: mail.date,
+                subject: mail.subject.clone(),
+                file_name: Some(local.file_name.clone()),
+            }),
+            FileCheck::Mismatch(reason) => report.mismatched.push(Mismatch {
+                mail_id: mail_id.clone(),
+                file_name: local.file_name.clone(),
+                reason,
+            }),
+        }
+    }
+
+    for (mail_id, local) in local {
+        if !server.contains_key(mail_id) {
+            report.deleted.push(ExportedFile {
+                mail_id: mail_id.clone(),
+                file_name: local.file_name.clone(),
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+/// Result of [`check_file`].
+#[derive(Debug, PartialEq, Eq)]
+enum FileCheck {
+    Ok,
+    Missing,
+    Mismatch(String),
+}
+
+/// Check that the exported file exists and matches the recorded size and hash, if any.
+///
+/// Compressed files are decompressed first. Encrypted files are only checked for existence.
+fn check_file(path: &Path, recorded: Option<&(u64, String)>) -> Result<FileCheck> {
+    tokio::task::block_in_place(|| {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(FileCheck::Missing),
+            Err(e) => {
+                return Err(e).with_context(|| format!("open exported file: `{}`", path.display()))
+            }
+        };
+
+        let Some((size, sha256)) = recorded else {
+            return Ok(FileCheck::Ok);
+        };
+        let name = path.to_string_lossy();
+        if ENCRYPTED_SUFFIXES
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+        {
+            debug!(path = %path.display(), "cannot check encrypted file");
+            return Ok(FileCheck::Ok);
+        }
+
+        let compression = FileCompression::value_variants()
+            .iter()
+            .find(|c| name.ends_with(c.suffix()));
+        let mut reader: Box<dyn Read + Send> = match compression {
+            Some(compression) => compression.decompress(file)?,
+            None => Box::new(file),
+        };
+        let mut hasher = Sha256::new();
+        let actual_size = match std::io::copy(&mut reader, &mut hasher) {
+            Ok(n) => n,
+            Err(e) if compression.is_some() => {
+                return Ok(FileCheck::Mismatch(format!("cannot decompress: {e}")));
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("read exported file: `{}`", path.display()))
+            }
+        };
+        let actual_sha256 = format!("{:x}", hasher.finalize());
+
+        Ok(if actual_size != *size {
+            FileCheck::Mismatch(format!(
+                "size differs: expected {size} bytes, found {actual_size}"
+            ))
+        } else if actual_sha256 != *sha256 {
+            FileCheck::Mismatch("hash differs".to_owned())
+        } else {
+            FileCheck::Ok
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_check_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let recorded = (
+            3,
+            "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae".to_owned(),
+        );
+
+        let plain = dir.path().join("a.eml");
+        std::fs::write(&plain, b"foo").unwrap();
+        assert_eq!(check_file(&plain, Some(&recorded)).unwrap(), FileCheck::Ok);
+        assert_eq!(check_file(&plain, None).unwrap(), FileCheck::Ok);
+
+        let missing = dir.path().join("missing.eml");
+        assert_eq!(check_file(&missing, None).unwrap(), FileCheck::Missing);
+
+        let changed = dir.path().join("b.eml");
+        std::fs::write(&changed, b"bar").unwrap();
+        assert_eq!(
+            check_file(&changed, Some(&recorded)).unwrap(),
+            FileCheck::Mismatch("hash differs".to_owned()),
+        );
+        std::fs::write(&changed, b"fooo").unwrap();
+        assert_eq!(
+            check_file(&changed, Some(&recorded)).unwrap(),
+            FileCheck::Mismatch("size differs: expected 3 bytes, found 4".to_owned()),
+        );
+
+        let compressed = dir.path().join("c.eml.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&compressed).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(b"foo").unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(
+            check_file(&compressed, Some(&recorded)).unwrap(),
+            FileCheck::Ok
+        );
+
+        // encrypted files cannot be read back
+        let encrypted = dir.path().join("d.eml.age");
+        std::fs::write(&encrypted, b"garbage").unwrap();
+        assert_eq!(
+            check_file(&encrypted, Some(&recorded)).unwrap(),
+            FileCheck::Ok
+        );
+    }
+}
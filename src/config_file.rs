@@ -0,0 +1,399 @@
+//! Config file support.
+//!
+//! The config file uses TOML. Top-level keys map to global CLI options and tables named after a subcommand map to the
+//! options of that subcommand. Keys use the long option names, e.g.:
+//!
+//! ```toml
+//! username = "foo@tutanota.de"
+//! verbose = 1
+//!
+//! [download]
+//! folder = "MyFolder"
+//! path = "./output"
+//! concurrent-downloads = 10
+//! ```
+//!
+//! Options passed via the command line take precedence over the config file. Flags accept an explicit value on the
+//! command line, e.g. `--http1=false`, so that they can be switched off even if the config file switches them on.
+use std::{ffi::OsString, path::PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::{parser::ValueSource, Arg, ArgAction, ArgMatches, Command};
+use toml::{Table, Value};
+
+/// Long name of the CLI option that points to the config file.
+pub(crate) const CONFIG_ARG: &str = "config";
+
+/// Environment variable that points to the config file.
+pub(crate) const CONFIG_ENV: &str = "TUTANOTA_CLI_CONFIG";
+
+/// Let flags take an optional value, e.g. `--flag=false`, see [`args_with_config`].
+pub(crate) fn with_flag_values(cmd: Command) -> Command {
+    let subcommands = cmd
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_owned())
+        .collect::<Vec<_>>();
+    subcommands
+        .into_iter()
+        .fold(cmd.mut_args(flag_with_value), |cmd, name| {
+            cmd.mut_subcommand(name, with_flag_values)
+        })
+}
+
+fn flag_with_value(arg: Arg) -> Arg {
+    if !matches!(arg.get_action(), ArgAction::SetTrue) {
+        return arg;
+    }
+
+    arg.action(ArgAction::Set)
+        .num_args(0..=1)
+        .require_equals(true)
+        .value_name("true|false")
+        .value_parser(clap::value_parser!(bool))
+        .default_missing_value("true")
+        .default_value("false")
+        .hide_default_value(true)
+}
+
+/// Extend CLI arguments with the values from the config file, if one is provided.
+///
+/// Options that are passed on the command line replace the respective config entries, so that list options and counts
+/// do NOT accumulate. Flags must be prepared via [`with_flag_values`] to be switched off on the command line.
+pub(crate) fn args_with_config(cmd: &Command, args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let Some(path) = find_config_path(&args) else {
+        return Ok(args);
+    };
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("read config file: `{}`", path.display()))?;
+    let config: Table = toml::from_str(&content)
+        .with_context(|| format!("parse config file: `{}`", path.display()))?;
+
+    merge_args(cmd, args, &config)
+}
+
+fn find_config_path(args: &[OsString]) -> Option<PathBuf> {
+    let flag = format!("--{CONFIG_ARG}");
+    let prefix = format!("{flag}=");
+
+    let mut it = args.iter().skip(1);
+    while let Some(arg) = it.next() {
+        let Some(arg) = arg.to_str() else {
+            continue;
+        };
+        if arg == "--" {
+            break;
+        } else if arg == flag {
+            return it.next().map(PathBuf::from);
+        } else if let Some(path) = arg.strip_prefix(&prefix) {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    std::env::var_os(CONFIG_ENV).map(PathBuf::from)
+}
+
+fn merge_args(cmd: &Command, args: Vec<OsString>, config: &Table) -> Result<Vec<OsString>> {
+    let subcommand = find_subcommand(cmd, &args);
+
+    // Only used to tell which options are set on the command line, so missing required options are fine. Parse errors
+    // like `--help` are reported by the actual parse.
+    let cli = cmd
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+        .ok();
+
+    let mut global_args = vec![];
+    let mut subcommand_args = vec![];
+    for (key, value) in config {
+        match value {
+            Value::Table(table) => {
+                let section = key;
+                let sub = cmd
+                    .find_subcommand(section)
+                    .with_context(|| format!("unknown config section: `{section}`"))?;
+                if subcommand.map(|(_idx, name)| name) == Some(sub.get_name()) {
+                    let cli = cli
+                        .as_ref()
+                        .and_then(|m| m.subcommand_matches(sub.get_name()));
+                    for (key, value) in table {
+                        subcommand_args.extend(
+                            entry_to_args(sub, cli, key, value)
+                                .with_context(|| format!("config section `{section}`"))?,
+                        );
+                    }
+                }
+            }
+            value => {
+                global_args.extend(entry_to_args(cmd, cli.as_ref(), key, value)?);
+            }
+        }
+    }
+
+    let mut args = args.into_iter();
+    let mut out = Vec::with_capacity(args.len() + global_args.len() + subcommand_args.len());
+    out.extend(args.next());
+    out.extend(global_args);
+    if let Some((idx, _name)) = subcommand {
+        out.extend(args.by_ref().take(idx));
+        out.extend(subcommand_args);
+    }
+    out.extend(args);
+
+    Ok(out)
+}
+
+/// Find position and name of the subcommand within the raw CLI arguments.
+fn find_subcommand<'a>(cmd: &'a Command, args: &[OsString]) -> Option<(usize, &'a str)> {
+    let mut skip_next = false;
+    for (idx, arg) in args.iter().enumerate().skip(1) {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+
+        let arg = arg.to_str()?;
+        if arg == "--" {
+            return None;
+        } else if let Some(long) = arg.strip_prefix("--") {
+            if !long.contains('=') {
+                skip_next = cmd
+                    .get_arguments()
+                    .find(|a| a.get_long() == Some(long))
+                    .map(|a| a.get_action().takes_values())
+                    .unwrap_or_default();
+            }
+        } else if let Some(short) = arg.strip_prefix('-') {
+            let mut chars = short.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                skip_next = cmd
+                    .get_arguments()
+                    .find(|a| a.get_short() == Some(c))
+                    .map(|a| a.get_action().takes_values())
+                    .unwrap_or_default();
+            }
+        } else if let Some(sub) = cmd.find_subcommand(arg) {
+            return Some((idx, sub.get_name()));
+        }
+    }
+
+    None
+}
+
+/// Convert config entry to CLI arguments, which are empty if the option is already set on the command line.
+fn entry_to_args(
+    cmd: &Command,
+    cli: Option<&ArgMatches>,
+    key: &str,
+    value: &Value,
+) -> Result<Vec<OsString>> {
+    let key = key.replace('_', "-");
+    ensure!(
+        key != CONFIG_ARG,
+        "config file cannot point to another config file"
+    );
+
+    let arg = cmd
+        .get_arguments()
+        .find(|a| a.get_long() == Some(&key))
+        .with_context(|| format!("unknown config option: `{key}`"))?;
+    if cli.and_then(|m| m.value_source(arg.get_id().as_str())) == Some(ValueSource::CommandLine) {
+        return Ok(vec![]);
+    }
+    let flag = format!("--{key}");
+
+    match (arg.get_action(), value) {
+        (ArgAction::SetTrue, Value::Boolean(b)) => Ok(if *b { vec![flag.into()] } else { vec![] }),
+        (ArgAction::Count, Value::Integer(n)) => {
+            let n = usize::try_from(*n).with_context(|| format!("invalid count for `{key}`"))?;
+            Ok(vec![flag.into(); n])
+        }
+        (_, Value::Array(values)) => values
+            .iter()
+            .map(|v| Ok(format!("{flag}={}", scalar(v)?).into()))
+            .collect(),
+        (_, v) => Ok(vec![format!("{flag}={}", scalar(v)?).into()]),
+    }
+}
+
+fn scalar(v: &Value) -> Result<String> {
+    match v {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        Value::Datetime(d) => Ok(d.to_string()),
+        Value::Array(_) | Value::Table(_) => bail!("unsupported value: {v}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Arg;
+
+    use super::*;
+
+    #[test]
+    fn test_merge_args() {
+        let cmd = test_cmd();
+
+        assert_eq!(merge(&cmd, &["bin", "sub"], ""), ["bin", "sub"]);
+        assert_eq!(
+            merge(
+                &cmd,
+                &["bin", "--global", "g", "sub", "--opt", "o"],
+                r#"
+                global = "cfg_g"
+                verbose = 2
+                flag = true
+                off = false
+
+                [sub]
+                opt = "cfg_o"
+                list = ["a", "b"]
+                "#
+            ),
+            [
+                "bin",
+                "--flag",
+                "--verbose",
+                "--verbose",
+                "--global",
+                "g",
+                "sub",
+                "--list=a",
+                "--list=b",
+                "--opt",
+                "o",
+            ],
+        );
+        assert_eq!(
+            merge(
+                &cmd,
+                &["bin", "-v", "other"],
+                r#"
+                [sub]
+                opt = "cfg_o"
+                "#
+            ),
+            ["bin", "-v", "other"],
+        );
+    }
+
+    #[test]
+    fn test_merge_args_cli_overrides() {
+        let cmd = with_flag_values(test_cmd());
+        let config = r#"
+            verbose = 2
+            flag = true
+
+            [sub]
+            list = ["a", "b"]
+            "#;
+
+        let matches = cmd
+            .clone()
+            .try_get_matches_from(merge(&cmd, &["bin", "sub"], config))
+            .unwrap();
+        assert_eq!(matches.get_count("verbose"), 2);
+        assert!(matches.get_flag("flag"));
+        assert_eq!(list(&matches), ["a", "b"]);
+
+        let matches = cmd
+            .clone()
+            .try_get_matches_from(merge(
+                &cmd,
+                &["bin", "-v", "--flag=false", "sub", "--list=c"],
+                config,
+            ))
+            .unwrap();
+        assert_eq!(matches.get_count("verbose"), 1);
+        assert!(!matches.get_flag("flag"));
+        assert_eq!(list(&matches), ["c"]);
+
+        // flags without value still work
+        let matches = cmd
+            .clone()
+            .try_get_matches_from(["bin", "--off", "sub"])
+            .unwrap();
+        assert!(matches.get_flag("off"));
+        assert!(!matches.get_flag("flag"));
+    }
+
+    #[test]
+    fn test_merge_args_errors() {
+        let cmd = test_cmd();
+
+        assert_eq!(merge_err(&cmd, "foo = 1"), "unknown config option: `foo`");
+        assert_eq!(
+            merge_err(&cmd, "[foo]\nbar = 1"),
+            "unknown config section: `foo`"
+        );
+        assert_eq!(
+            merge_err(&cmd, "config = \"x.toml\""),
+            "config file cannot point to another config file"
+        );
+    }
+
+    #[test]
+    fn test_find_config_path() {
+        assert_eq!(
+            find_config_path(&os(&["bin", "--config", "a.toml", "sub"])),
+            Some(PathBuf::from("a.toml")),
+        );
+        assert_eq!(
+            find_config_path(&os(&["bin", "--config=b.toml", "sub"])),
+            Some(PathBuf::from("b.toml")),
+        );
+    }
+
+    fn test_cmd() -> Command {
+        Command::new("bin")
+            .args_override_self(true)
+            .arg(Arg::new("config").long("config"))
+            .arg(Arg::new("global").long("global"))
+            .arg(
+                Arg::new("verbose")
+                    .short('v')
+                    .long("verbose")
+                    .action(ArgAction::Count),
+            )
+            .arg(Arg::new("flag").long("flag").action(ArgAction::SetTrue))
+            .arg(Arg::new("off").long("off").action(ArgAction::SetTrue))
+            .subcommand(
+                Command::new("sub")
+                    .arg(Arg::new("opt").long("opt"))
+                    .arg(Arg::new("list").long("list").action(ArgAction::Append)),
+            )
+            .subcommand(Command::new("other"))
+    }
+
+    fn merge(cmd: &Command, args: &[&str], config: &str) -> Vec<String> {
+        merge_args(cmd, os(args), &toml::from_str(config).unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|s| s.into_string().unwrap())
+            .collect()
+    }
+
+    fn merge_err(cmd: &Command, config: &str) -> String {
+        merge_args(cmd, os(&["bin", "sub"]), &toml::from_str(config).unwrap())
+            .unwrap_err()
+            .to_string()
+    }
+
+    fn list(matches: &ArgMatches) -> Vec<&str> {
+        matches
+            .subcommand_matches("sub")
+            .unwrap()
+            .get_many::<String>("list")
+            .unwrap()
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn os(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+}
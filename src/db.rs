@@ -0,0 +1,236 @@
+//! SQLite database that records exported mails.
+use std::{path::Path, sync::Mutex};
+
+use anyhow::{ensure, Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use tatutanatata_core::spool::Spool;
+
+/// Current schema version, stored as `user_version`.
+const SCHEMA_VERSION: u32 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS mails (
+    folder_id TEXT NOT NULL,
+    mail_id TEXT NOT NULL,
+    message_id TEXT,
+    received_at TEXT NOT NULL,
+    exported_at TEXT NOT NULL,
+    output TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    sha256 TEXT NOT NULL,
+    PRIMARY KEY (folder_id, mail_id)
+);
+";
+
+/// Exported mail.
+#[derive(Debug)]
+pub(crate) struct DbEntry<'a> {
+    pub(crate) folder_id: &'a str,
+    pub(crate) mail_id: &'a str,
+    pub(crate) message_id: Option<&'a str>,
+    pub(crate) received_at: DateTime<Utc>,
+
+    /// Output location as passed via `--path`.
+    pub(crate) output: &'a str,
+
+    /// File name within [`output`](Self::output).
+    pub(crate) file_name: &'a str,
+
+    /// Uncompressed EML data.
+    pub(crate) data: &'a Spool,
+}
+
+/// Recorded export of a mail, see [`Database::exported`].
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct DbRecord {
+    pub(crate) mail_id: String,
+
+    /// File name within the output location.
+    pub(crate) file_name: String,
+
+    /// Size of the uncompressed data.
+    pub(crate) size: u64,
+
+    /// Hex-encoded SHA-256 hash of the uncompressed data.
+    pub(crate) sha256: String,
+}
+
+/// Database that is shared between concurrent downloads.
+#[derive(Debug)]
+pub(crate) struct Database {
+    conn: Mutex<Connection>,
+}
+
+impl Database {
+    /// Open database, creating it if it does not exist.
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("open database: `{}`", path.display()))?;
+
+        let version: u32 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .context("get schema version")?;
+        ensure!(
+            version <= SCHEMA_VERSION,
+            "database was created by a newer version (schema {version})"
+        );
+        conn.execute_batch(SCHEMA).context("create schema")?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)
+            .context("set schema version")?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Record exported mail, replacing any earlier record of the same mail.
+    pub(crate) fn record(&self, entry: &DbEntry<'_>) -> Result<()> {
+        // the spool hashed the data while it was written, so this does NOT read it again
+        let sha256 = hex_encode(&entry.data.sha256());
+
+        tokio::task::block_in_place(|| {
+            let conn = self.conn.lock().expect("not poisoned");
+            conn.execute(
+                "INSERT OR REPLACE INTO mails
+                    (folder_id, mail_id, message_id, received_at, exported_at, output, file_name, size, sha256)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    entry.folder_id,
+                    entry.mail_id,
+                    entry.message_id,
+                    entry.received_at.to_rfc3339(),
+                    Utc::now().to_rfc3339(),
+                    entry.output,
+                    entry.file_name,
+                    entry.data.len(),
+                    sha256,
+                ],
+            )
+            .context("insert row")
+        })
+        .with_context(|| format!("record mail: `{}`", entry.mail_id))?;
+
+        Ok(())
+    }
+
+    /// Remove record of the given mail.
+    pub(crate) fn remove(&self, folder_id: &str, mail_id: &str) -> Result<()> {
+        let conn = self.conn.lock().expect("not poisoned");
+
+        tokio::task::block_in_place(|| {
+            conn.execute(
+                "DELETE FROM mails WHERE folder_id = ?1 AND mail_id = ?2",
+                params![folder_id, mail_id],
+            )
+            .context("delete row")
+        })
+        .with_context(|| format!("remove mail: `{mail_id}`"))?;
+
+        Ok(())
+    }
+
+    /// Recorded exports of the given folder, ordered by mail ID.
+    pub(crate) fn exported(&self, folder_id: &str) -> Result<Vec<DbRecord>> {
+        let conn = self.conn.lock().expect("not poisoned");
+
+        tokio::task::block_in_place(|| {
+            conn.prepare(
+                "SELECT mail_id, file_name, size, sha256 FROM mails WHERE folder_id = ?1 ORDER BY mail_id",
+            )
+            .context("prepare query")?
+            .query_map(params![folder_id], |row| {
+                Ok(DbRecord {
+                    mail_id: row.get(0)?,
+                    file_name: row.get(1)?,
+                    size: row.get(2)?,
+                    sha256: row.get(3)?,
+                })
+            })
+            .context("query rows")?
+            .collect::<Result<Vec<_>, _>>()
+            .context("read row")
+        })
+        .with_context(|| format!("list exported mails of folder: `{folder_id}`"))
+    }
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_record() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("export.sqlite");
+
+        let entry = DbEntry {
+            folder_id: "f",
+            mail_id: "m",
+            message_id: Some("<foo@example.com>"),
+            received_at: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+            output: "out",
+            file_name: "a.eml",
+            data: &Spool::from(b"foo".to_vec()),
+        };
+        let db = Database::open(&path).unwrap();
+        db.record(&entry).unwrap();
+        drop(db);
+
+        // reopening keeps data, recording again replaces the row
+        let db = Database::open(&path).unwrap();
+        db.record(&DbEntry {
+            file_name: "b.eml",
+            ..entry
+        })
+        .unwrap();
+
+        assert_eq!(
+            db.exported("f").unwrap(),
+            [DbRecord {
+                mail_id: "m".to_owned(),
+                file_name: "b.eml".to_owned(),
+                size: 3,
+                sha256: "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+                    .to_owned(),
+            }],
+        );
+        assert!(db.exported("other").unwrap().is_empty());
+
+        let conn = db.conn.lock().unwrap();
+        let rows = conn
+            .prepare("SELECT file_name, size, sha256, received_at FROM mails")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [(
+                "b.eml".to_owned(),
+                3,
+                "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae".to_owned(),
+                "2024-01-02T03:04:05+00:00".to_owned(),
+            )],
+        );
+        drop(conn);
+
+        db.remove("f", "m").unwrap();
+        assert!(db.exported("f").unwrap().is_empty());
+    }
+}
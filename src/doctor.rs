@@ -0,0 +1,258 @@
+//! Diagnostics for the environment the tool runs in, see [`run`].
+use std::{
+    fmt::Display,
+    future::Future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use tatutanatata_core::{
+    client::Client,
+    session::{check_account, LoginConfig},
+};
+
+use crate::s3::is_s3_path;
+
+/// Maximum time per check, so that an unreachable server does not wait for the whole retry deadline.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Clock skew that is reported as a warning.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Options of the `doctor` command.
+#[derive(Debug, Parser)]
+pub(crate) struct DoctorCLIConfig {
+    /// Also check that the given output directory is writable.
+    #[clap(long, action)]
+    path: Option<PathBuf>,
+}
+
+/// Outcome of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+    Skip,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Ok => "ok",
+            Self::Warn => "WARN",
+            Self::Fail => "FAIL",
+            Self::Skip => "skip",
+        };
+        f.pad(s)
+    }
+}
+
+/// Result of a single check.
+#[derive(Debug)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<4}  {:<11}  {}", self.status, self.name, self.detail)
+    }
+}
+
+/// Check DNS, TLS, clock skew, credentials, and the output directory and print a report.
+///
+/// All checks are run even if earlier ones failed. Fails if any check failed.
+pub(crate) async fn run(
+    client: &Client,
+    login_cfg: &LoginConfig,
+    cfg: &DoctorCLIConfig,
+) -> Result<()> {
+    let mut checks = vec![check_dns(client.host()).await];
+
+    let server_date = timeout(client.server_date()).await;
+    checks.push(match &server_date {
+        Ok(_) => Check {
+            name: "connection",
+            status: Status::Ok,
+            detail: format!("TLS connection to {} established", client.host()),
+        },
+        Err(e) => Check {
+            name: "connection",
+            status: Status::Fail,
+            detail: format!("{e:#}"),
+        },
+    });
+    checks.push(match server_date {
+        Ok(Some(date)) => check_clock(date, Utc::now()),
+        Ok(None) => Check {
+            name: "clock",
+            status: Status::Skip,
+            detail: "server did not send its time".to_owned(),
+        },
+        Err(_) => Check {
+            name: "clock",
+            status: Status::Skip,
+            detail: "server is unreachable".to_owned(),
+        },
+    });
+
+    checks.push(match timeout(check_account(login_cfg, client)).await {
+        Ok(()) => Check {
+            name: "credentials",
+            status: Status::Ok,
+            detail: "account exists".to_owned(),
+        },
+        Err(e) => Check {
+            name: "credentials",
+            status: Status::Fail,
+            detail: format!("{e:#}"),
+        },
+    });
+
+    if let Some(path) = &cfg.path {
+        checks.push(check_output(path));
+    }
+
+    for check in &checks {
+        println!("{check}");
+    }
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        bail!("{failed} check(s) failed");
+    }
+    Ok(())
+}
+
+async fn timeout<T>(fut: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(CHECK_TIMEOUT, fut)
+        .await
+        .context("timeout")?
+}
+
+async fn check_dns(host: &str) -> Check {
+    let res = async {
+        let url = reqwest::Url::parse(host).context("parse server URL")?;
+        let name = url.host_str().context("server URL has no host")?;
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addrs = timeout(async {
+            tokio::net::lookup_host((name, port))
+                .await
+                .with_context(|| format!("resolve `{name}`"))
+        })
+        .await?
+        .map(|addr| addr.ip().to_string())
+        .collect::<Vec<_>>();
+        Ok::<_, anyhow::Error>(format!("{name} resolves to {}", addrs.join(", ")))
+    }
+    .await;
+
+    match res {
+        Ok(detail) => Check {
+            name: "dns",
+            status: Status::Ok,
+            detail,
+        },
+        Err(e) => Check {
+            name: "dns",
+            status: Status::Fail,
+            detail: format!("{e:#}"),
+        },
+    }
+}
+
+/// Compare local clock with the server time.
+///
+/// The `Date` header only has a resolution of one second, so small differences are expected.
+fn check_clock(server: DateTime<Utc>, local: DateTime<Utc>) -> Check {
+    let skew = (local - server).abs().to_std().unwrap_or_default();
+    Check {
+        name: "clock",
+        status: if skew > MAX_CLOCK_SKEW {
+            Status::Warn
+        } else {
+            Status::Ok
+        },
+        detail: format!(
+            "local clock is {}s {} the server",
+            skew.as_secs(),
+            if local >= server {
+                "ahead of"
+            } else {
+                "behind"
+            }
+        ),
+    }
+}
+
+/// Check that a file can be created in the output directory.
+fn check_output(path: &Path) -> Check {
+    if is_s3_path(path) {
+        return Check {
+            name: "output",
+            status: Status::Skip,
+            detail: "S3 outputs are not checked".to_owned(),
+        };
+    }
+
+    // the directory is created by the export, so its closest existing ancestor must be writable
+    let dir = path
+        .ancestors()
+        .find(|p| p.is_dir())
+        .unwrap_or_else(|| Path::new("."));
+    match tempfile::tempfile_in(dir) {
+        Ok(_) => Check {
+            name: "output",
+            status: Status::Ok,
+            detail: format!("`{}` is writable", dir.display()),
+        },
+        Err(e) => Check {
+            name: "output",
+            status: Status::Fail,
+            detail: format!("cannot write to `{}`: {e}", dir.display()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_clock() {
+        let server = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .to_utc();
+
+        insta::assert_snapshot!(
+            check_clock(server, server + chrono::Duration::seconds(2)),
+            @"ok    clock        local clock is 2s ahead of the server"
+        );
+        insta::assert_snapshot!(
+            check_clock(server, server - chrono::Duration::minutes(5)),
+            @"WARN  clock        local clock is 300s behind the server"
+        );
+    }
+
+    #[test]
+    fn test_check_output() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let check = check_output(&dir.path().join("new").join("export"));
+        assert_eq!(check.status, Status::Ok);
+        assert_eq!(
+            check.detail,
+            format!("`{}` is writable", dir.path().display())
+        );
+
+        assert_eq!(
+            check_output(Path::new("s3://bucket/prefix")).status,
+            Status::Skip
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Exit codes for classes of failures, so that wrapping tools can tell "retry later" from "fix the setup".
+use tatutanatata_core::failure::Failure;
+
+/// Exit code of failures that are NOT classified.
+const GENERIC_EXIT_CODE: u8 = 1;
+
+/// Process exit code of the given failure class.
+///
+/// `2` is used by the argument parser for usage errors.
+fn exit_code(failure: Failure) -> u8 {
+    match failure {
+        Failure::Auth => 3,
+        Failure::FolderNotFound => 4,
+        Failure::PartialExport => 5,
+        Failure::Network => 6,
+        Failure::Filesystem => 7,
+    }
+}
+
+/// Process exit code of the given error.
+pub(crate) fn exit_code_of(e: &anyhow::Error) -> u8 {
+    Failure::of(e).map(exit_code).unwrap_or(GENERIC_EXIT_CODE)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    #[test]
+    fn test_exit_code_of() {
+        assert_eq!(exit_code_of(&anyhow!("foo")), GENERIC_EXIT_CODE);
+
+        let e = anyhow!("foo").context(Failure::Auth).context("bar");
+        assert_eq!(exit_code_of(&e), 3);
+
+        let e = anyhow::Error::new(std::io::Error::other("foo")).context("write file");
+        assert_eq!(exit_code_of(&e), 7);
+    }
+}
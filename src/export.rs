@@ -0,0 +1,752 @@
+//! Export of mails into the output, see [`download`].
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, ensure, Context, Result};
+use futures::{Stream, StreamExt, TryStreamExt};
+use tatutanatata_core::{
+    blob::get_mail_blobs,
+    client::Client,
+    eml::{message_id, write_eml, EmlOptions},
+    failure::Failure,
+    folders::Folder,
+    html::{write_html, write_text},
+    mails::{DownloadOptions, DownloadedMail, Mail},
+    proto::messages::MailDetails,
+    session::Session,
+    spool::Spool,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    collisions::Collisions,
+    db::DbEntry,
+    export_events::{Event, EventKind},
+    export_state::ExportState,
+    file_output::link_file,
+    json_output::write_json,
+    manifest::ManifestEntry,
+    mbox::write_mbox,
+    notmuch::notmuch_tags,
+    output::is_stdout_path,
+    s3::is_s3_path,
+    summary::{ProgressTracker, RunStats},
+    timezone::Timezone,
+    verify::{existing_boundaries, verify_file},
+    zip_output::is_zip_path,
+    Dedup, DownloadCLIConfig, ExportFormat, LINK_INDEX_DIR,
+};
+
+/// Number of mails whose details are fetched with a single request.
+const MAIL_DETAILS_BATCH_SIZE: usize = 100;
+
+/// Export mails of the given folder.
+pub(crate) async fn download(
+    client: &Client,
+    session: &Session,
+    mut cfg: DownloadCLIConfig,
+    folder: &Folder,
+) -> Result<()> {
+    ensure!(
+        cfg.metrics_addr.is_none(),
+        "`--metrics-addr` is only supported by `watch`"
+    );
+
+    let target = cfg.path.clone();
+    let staging = staging_dir(&target).await?;
+    if let Some(staging) = &staging {
+        debug!(staging = %staging.display(), "use staging directory");
+        cfg.path.clone_from(staging);
+    }
+
+    prepare_output(&cfg).await?;
+    let state = ExportState::try_new(&cfg, folder, &target, client.retry_policy().clone()).await?;
+    let client = &client
+        .clone()
+        .with_concurrency_limit(Arc::clone(&state.concurrency_limit));
+
+    let res = tokio::select! {
+        res = download_folder(client, session, &cfg, folder, &state) => res,
+        never = report_progress(&cfg, &state.stats) => match never {},
+    };
+    state.write_manifest().await?;
+    state.write_notmuch_dump().await?;
+    state.write_failure_report().await?;
+
+    let summary = state.stats.summary();
+    eprintln!("{summary}");
+    summary.record();
+    if let Some(path) = &cfg.summary {
+        summary.write(path, &state.retry).await?;
+    }
+    res?;
+
+    let n_mismatches = state.verify_mismatches.load(Ordering::SeqCst);
+    ensure!(
+        n_mismatches == 0,
+        "{n_mismatches} existing file(s) do not match the server data"
+    );
+
+    let n_failed = state.stats.failed.load(Ordering::SeqCst);
+    state.finish().await?;
+
+    if let Some(staging) = staging {
+        tokio::fs::rename(&staging, &target)
+            .await
+            .with_context(|| format!("move staging directory to `{}`", target.display()))?;
+    }
+
+    // the export is usable nevertheless, a re-run only exports the missing mails
+    if n_failed > 0 {
+        return Err(anyhow!("{n_failed} mail(s) failed to export").context(Failure::PartialExport));
+    }
+
+    Ok(())
+}
+
+/// Log the progress periodically, see [`DownloadCLIConfig::progress_interval_secs`].
+///
+/// This never returns, so it is meant to be raced against the export.
+async fn report_progress(cfg: &DownloadCLIConfig, stats: &RunStats) -> Infallible {
+    if cfg.progress_interval_secs == 0 {
+        return std::future::pending().await;
+    }
+
+    let interval = Duration::from_secs(cfg.progress_interval_secs);
+    let mut tracker = ProgressTracker::new(stats);
+    loop {
+        tokio::time::sleep(interval).await;
+        let progress = tracker.update(stats, stats.total.get().copied());
+        info!(%progress, "progress");
+    }
+}
+
+/// Staging directory for an export into a new directory.
+///
+/// The export is written to the staging directory which is only moved to `path` once the export completed, so an
+/// aborted export does not leave a seemingly complete directory behind. A staging directory of an aborted export is
+/// reused.
+///
+/// Returns [`None`] if `path` is not a directory target or exists already, e.g. for incremental exports.
+async fn staging_dir(path: &Path) -> Result<Option<PathBuf>> {
+    if is_stdout_path(path) || is_s3_path(path) || is_zip_path(path) {
+        return Ok(None);
+    }
+    if tokio::fs::try_exists(path)
+        .await
+        .context("check output dir existence")?
+    {
+        return Ok(None);
+    }
+
+    let mut name = path
+        .file_name()
+        .context("output path has no file name")?
+        .to_owned();
+    name.push(".partial");
+    Ok(Some(path.with_file_name(name)))
+}
+
+/// Ensure that output directories exist.
+pub(crate) async fn prepare_output(cfg: &DownloadCLIConfig) -> Result<()> {
+    ensure!(
+        cfg.timestamp_url.is_none()
+            || cfg.manifest
+            || cfg.archive.is_some()
+            || is_zip_path(&cfg.path),
+        "`--timestamp-url` requires `--manifest`"
+    );
+    ensure!(
+        !cfg.verify || cfg.encrypt_to.is_empty(),
+        "`--verify` is not supported for encrypted files"
+    );
+
+    ensure!(
+        cfg.archive.is_none() || is_stdout_path(&cfg.path),
+        "`--archive` requires `--path -`"
+    );
+
+    if is_stdout_path(&cfg.path) {
+        ensure!(
+            cfg.mail_id.len() == 1 || cfg.archive.is_some() || cfg.format == ExportFormat::Mbox,
+            "`--path -` requires a single `--mail-id`, `--archive`, or `--format mbox`"
+        );
+        ensure!(
+            !cfg.link_index,
+            "`--link-index` is not supported for stdout"
+        );
+        ensure!(!cfg.verify, "`--verify` is not supported for stdout");
+        ensure!(
+            !cfg.manifest || cfg.archive.is_some(),
+            "`--manifest` is not supported for stdout, archives contain one anyway"
+        );
+        ensure!(
+            cfg.events.is_none(),
+            "`--events` is not supported for stdout"
+        );
+        return Ok(());
+    }
+
+    if is_s3_path(&cfg.path) {
+        ensure!(!cfg.link_index, "`--link-index` is not supported for S3");
+        ensure!(!cfg.verify, "`--verify` is not supported for S3");
+        return Ok(());
+    }
+
+    if is_zip_path(&cfg.path) {
+        ensure!(
+            !cfg.link_index,
+            "`--link-index` is not supported for ZIP archives"
+        );
+        ensure!(!cfg.verify, "`--verify` is not supported for ZIP archives");
+        ensure!(
+            cfg.compress.is_none(),
+            "`--compress` is not supported for ZIP archives, they are compressed already"
+        );
+        if let Some(parent) = cfg.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("create output dir")?;
+        }
+        return Ok(());
+    }
+
+    tokio::fs::create_dir_all(&cfg.path)
+        .await
+        .context("create output dir")?;
+    if cfg.link_index {
+        tokio::fs::create_dir_all(cfg.path.join(LINK_INDEX_DIR))
+            .await
+            .context("create link index dir")?;
+    }
+
+    Ok(())
+}
+
+/// Export all mails of the given folder.
+pub(crate) async fn download_folder(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    folder: &Folder,
+    state: &ExportState,
+) -> Result<()> {
+    if !cfg.mail_id.is_empty() {
+        let _ = state.stats.total.set(cfg.mail_id.len() as u64);
+        let mut mails = Vec::with_capacity(cfg.mail_id.len());
+        for mail_id in &cfg.mail_id {
+            let mail = Mail::get(client, session, folder, mail_id)
+                .await?
+                .with_context(|| {
+                    format!("cannot decode mail `{mail_id}` yet, view it in the official app")
+                })?;
+            mails.push(mail);
+        }
+        let mails = find_collisions(client, session, cfg, folder, mails, state).await?;
+        return export_mails(client, session, cfg, mails, state).await;
+    }
+
+    let mails = Mail::list(client, session, folder, cfg.ignore_new_mails, cfg.since);
+    export_listing(client, session, cfg, mails, state).await
+}
+
+/// Export the mails of a folder listing, which must be ordered by ascending ID.
+pub(crate) async fn export_listing(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    mails: impl Stream<Item = Result<Arc<Mail>>>,
+    state: &ExportState,
+) -> Result<()> {
+    let mut mails = std::pin::pin!(mails);
+    let mut collisions = Collisions::default();
+    let mut batch = Vec::with_capacity(MAIL_DETAILS_BATCH_SIZE);
+    let mut listed = 0;
+    while let Some(mail) = mails.try_next().await.context("list mail")? {
+        listed += 1;
+        if cfg.filename_template.contains_mail_id() {
+            batch.push((mail, false));
+        } else {
+            let mail_id = mail.mail_id.clone();
+            let file_name = render_file_name(cfg, state, &mail, &cfg.timestamp_timezone, false);
+            // without the date, any other mail of the folder may have the same name
+            let decided_by = cfg
+                .filename_template
+                .contains_date()
+                .then(|| mail.same_second_id_bound());
+            batch.extend(collisions.push(mail, &mail_id, file_name, decided_by));
+        }
+
+        while batch.len() >= MAIL_DETAILS_BATCH_SIZE {
+            let rest = batch.split_off(MAIL_DETAILS_BATCH_SIZE);
+            export_mails(
+                client,
+                session,
+                cfg,
+                std::mem::replace(&mut batch, rest),
+                state,
+            )
+            .await?;
+        }
+    }
+
+    // the total is only used for the progress of `download`, later listings of `watch` do not change it
+    let _ = state.stats.total.set(listed);
+
+    batch.extend(collisions.finish());
+    for chunk in batch.chunks(MAIL_DETAILS_BATCH_SIZE) {
+        export_mails(client, session, cfg, chunk.to_vec(), state).await?;
+    }
+    Ok(())
+}
+
+/// Check if the file names of the given mails collide with the ones of other mails in the folder.
+///
+/// This is the counterpart of [`Collisions`] for mails that are exported individually, e.g. via `--mail-id`. Only
+/// the mails received in the same second are listed, unless the file name template lacks the date.
+pub(crate) async fn find_collisions(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    folder: &Folder,
+    mails: Vec<Arc<Mail>>,
+    state: &ExportState,
+) -> Result<Vec<(Arc<Mail>, bool)>> {
+    // fresh outputs only contain files of this run, which are claimed in `queue_mails`
+    if cfg.filename_template.contains_mail_id() || !state.output.keeps_files() {
+        return Ok(mails.into_iter().map(|mail| (mail, false)).collect());
+    }
+
+    let mut out = Vec::with_capacity(mails.len());
+    for mail in mails {
+        let file_name = render_file_name(cfg, state, &mail, &cfg.timestamp_timezone, false);
+        let others = if cfg.filename_template.contains_date() {
+            mail.list_same_second(client, session, folder).boxed()
+        } else {
+            Mail::list(client, session, folder, true, None).boxed()
+        };
+        let collides = others
+            .try_any(|other| {
+                let collides = other.mail_id != mail.mail_id
+                    && render_file_name(cfg, state, &other, &cfg.timestamp_timezone, false)
+                        == file_name;
+                async move { collides }
+            })
+            .await
+            .context("list mails with the same file name")?;
+        out.push((mail, collides));
+    }
+    Ok(out)
+}
+
+/// Export the given mails concurrently.
+///
+/// Each mail is paired with whether its file name collides with the one of another mail, see [`Collisions`].
+async fn export_mails(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    mails: Vec<(Arc<Mail>, bool)>,
+    state: &ExportState,
+) -> Result<()> {
+    futures::stream::iter(queue_mails(client, session, cfg, mails, state).await?)
+        .map(|queued| async move {
+            let _permit = state.concurrency_limit.acquire().await;
+            export_mail(client, session, cfg, queued, state).await
+        })
+        .buffer_unordered(cfg.concurrent_downloads)
+        .try_collect::<()>()
+        .await
+}
+
+/// Mail that is ready to be passed to [`export_mail`].
+#[derive(Debug)]
+pub(crate) struct QueuedMail {
+    mail: Arc<Mail>,
+    file_name: String,
+    exists: bool,
+
+    /// Prefetched mail details, see [`queue_mails`].
+    details: Option<MailDetails>,
+}
+
+/// Output file name of the given mail, see [`DownloadCLIConfig::filename_template`].
+///
+/// The name includes the directory of the [layout](DownloadCLIConfig::layout). If `unique` is set, the mail ID is
+/// added, see [`FilenameTemplate::render_unique`](crate::filename_template::FilenameTemplate::render_unique).
+fn render_file_name(
+    cfg: &DownloadCLIConfig,
+    state: &ExportState,
+    mail: &Mail,
+    timezone: &Timezone,
+    unique: bool,
+) -> String {
+    let name = if unique {
+        cfg.filename_template
+            .render_unique(mail, timezone, cfg.filename_charset)
+    } else {
+        cfg.filename_template
+            .render(mail, timezone, cfg.filename_charset)
+    };
+    state.file_name(
+        format!(
+            "{}{}",
+            cfg.layout.directory(mail, timezone),
+            cfg.format.apply_extension(name)
+        ),
+        cfg.compress,
+    )
+}
+
+/// Check which mails already exist in the output and fetch the details of the remaining ones.
+///
+/// Details are requested in batches per archive instead of once per mail. Drafts are stored differently and are
+/// still fetched individually by [`Mail::download`].
+pub(crate) async fn queue_mails(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    mails: Vec<(Arc<Mail>, bool)>,
+    state: &ExportState,
+) -> Result<Vec<QueuedMail>> {
+    let mut queued = futures::stream::iter(mails)
+        .map(|(mail, unique)| {
+            let mut file_name =
+                render_file_name(cfg, state, &mail, &cfg.timestamp_timezone, unique);
+            // collisions were detected already, this only catches names that are still taken, e.g. by mails that
+            // were moved to the folder since the last run
+            if !state.claim_file_name(&file_name, &mail.mail_id) {
+                let unique_file_name =
+                    render_file_name(cfg, state, &mail, &cfg.timestamp_timezone, true);
+                info!(
+                    mail_id = mail.mail_id.as_str(),
+                    file_name = file_name.as_str(),
+                    unique_file_name = unique_file_name.as_str(),
+                    "file name belongs to another mail",
+                );
+                state.claim_file_name(&unique_file_name, &mail.mail_id);
+                file_name = unique_file_name;
+            }
+
+            async move {
+                let mut exists = state.output.exists(&file_name).await?;
+
+                // earlier exports may have used the default time zone, keep them instead of exporting the mail again
+                if !exists && cfg.timestamp_timezone != Timezone::Utc {
+                    let utc_file_name = render_file_name(cfg, state, &mail, &Timezone::Utc, unique);
+                    if utc_file_name != file_name && state.output.exists(&utc_file_name).await? {
+                        file_name = utc_file_name;
+                        exists = true;
+                    }
+                }
+                if exists && cfg.refresh_drafts && mail.is_draft {
+                    debug!(mail_id = mail.mail_id.as_str(), "refresh draft");
+                    exists = false;
+                }
+                state.emit(Event::new(EventKind::Discovered, &mail).with_file_name(&file_name))?;
+                state.stats.listed.fetch_add(1, Ordering::SeqCst);
+
+                Result::<_>::Ok(QueuedMail {
+                    mail,
+                    file_name,
+                    exists,
+                    details: None,
+                })
+            }
+        })
+        .buffered(cfg.concurrent_downloads)
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let mut by_archive = HashMap::<String, Vec<String>>::new();
+    for q in &queued {
+        if (!q.exists || cfg.verify) && !q.mail.is_draft {
+            by_archive
+                .entry(q.mail.archive_id.clone())
+                .or_default()
+                .push(q.mail.blob_id.clone());
+        }
+    }
+
+    let mut details = HashMap::new();
+    for (archive_id, blob_ids) in by_archive {
+        for chunk in blob_ids.chunks(MAIL_DETAILS_BATCH_SIZE) {
+            debug!(
+                archive_id = archive_id.as_str(),
+                n = chunk.len(),
+                "fetch mail details"
+            );
+            let blobs = get_mail_blobs(client, session, &archive_id, chunk)
+                .await
+                .context("download mail details")?;
+            details.extend(blobs.into_iter().map(|blob| {
+                let [_, blob_id] = blob.id;
+                (blob_id, blob.details)
+            }));
+        }
+    }
+
+    for q in &mut queued {
+        q.details = details.remove(&q.mail.blob_id);
+    }
+
+    Ok(queued)
+}
+
+/// Export single mail, unless it exists already.
+///
+/// Existing files are verified instead if requested.
+pub(crate) async fn export_mail(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    queued: QueuedMail,
+    state: &ExportState,
+) -> Result<()> {
+    let mail = Arc::clone(&queued.mail);
+    let res = export_mail_inner(client, session, cfg, queued, state).await;
+    state.stats.completed.fetch_add(1, Ordering::SeqCst);
+    if let Some(report) = &state.failure_report {
+        match &res {
+            Ok(()) => report.resolve(&mail),
+            Err(e) => report.fail(&mail, e),
+        }
+    }
+    if let Err(e) = &res {
+        state.emit(Event::new(EventKind::Failed, &mail).with_reason(format!("{e:#}")))?;
+
+        if cfg.keep_going {
+            warn!(
+                folder_id = mail.folder_id.as_str(),
+                mail_id = mail.mail_id.as_str(),
+                ui_url = mail.ui_url(client).as_str(),
+                error = format!("{e:#}"),
+                "export failed, continue with remaining mails",
+            );
+            state.stats.failed.fetch_add(1, Ordering::SeqCst);
+            return Ok(());
+        }
+    }
+    res
+}
+
+async fn export_mail_inner(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    queued: QueuedMail,
+    state: &ExportState,
+) -> Result<()> {
+    let QueuedMail {
+        mail,
+        file_name,
+        exists,
+        details,
+    } = queued;
+    let target_file = cfg.path.join(&file_name);
+
+    if exists && !cfg.verify {
+        info!(
+            folder_id = mail.folder_id.as_str(),
+            mail_id = mail.mail_id.as_str(),
+            target_file = state.output.describe(&file_name),
+            ui_url = mail.ui_url(client).as_str(),
+            "already exists",
+        );
+        state.emit(
+            Event::new(EventKind::Skipped, &mail)
+                .with_file_name(&file_name)
+                .with_reason("exists"),
+        )?;
+        state.stats.skipped_existing.fetch_add(1, Ordering::SeqCst);
+
+        if let Some(manifest) = &state.manifest {
+            manifest.insert(file_name, ManifestEntry::new(&mail, None));
+        }
+    } else {
+        info!(
+            folder_id = mail.folder_id.as_str(),
+            mail_id = mail.mail_id.as_str(),
+            target_file = state.output.describe(&file_name),
+            ui_url = mail.ui_url(client).as_str(),
+            "{}",
+            if exists { "verify" } else { "download" },
+        );
+
+        let mail = Arc::clone(&mail)
+            .download(
+                client,
+                session,
+                details,
+                DownloadOptions {
+                    max_decompressed_size: cfg.max_decompressed_size,
+                    memory_budget: state.memory_budget.as_ref(),
+                    attachment_filter: &state.attachment_filter,
+                },
+            )
+            .await
+            .with_context(|| format!("download mail: `{}`", mail.ui_url(client)))?;
+        state.emit(Event::new(EventKind::Downloaded, &mail.mail))?;
+        state.stats.downloaded.fetch_add(1, Ordering::SeqCst);
+
+        if cfg.dedup == Some(Dedup::MessageId) {
+            if let Some(message_id) = mail.headers.as_deref().and_then(message_id) {
+                if state.is_duplicate(&message_id) {
+                    info!(
+                        folder_id = mail.mail.folder_id.as_str(),
+                        mail_id = mail.mail.mail_id.as_str(),
+                        message_id = message_id.as_str(),
+                        "skip duplicate",
+                    );
+                    state.emit(
+                        Event::new(EventKind::Skipped, &mail.mail)
+                            .with_file_name(&file_name)
+                            .with_reason("duplicate"),
+                    )?;
+                    state.stats.skipped_duplicate.fetch_add(1, Ordering::SeqCst);
+                    return Ok(());
+                }
+            }
+        }
+
+        let ui_url = mail.mail.ui_url(client);
+        let (mail, data) = match cfg.format {
+            ExportFormat::Eml => {
+                let eml_options = EmlOptions {
+                    body_encoding: cfg.body_encoding,
+                    text_alternative: cfg.text_alternative,
+                    preserve_content_type: cfg.preserve_content_type,
+                    state_headers: cfg.state_headers,
+                };
+                // older versions used random boundaries, so reuse the existing ones for verification
+                let boundaries = if exists {
+                    existing_boundaries(&target_file, cfg.compress)
+                } else {
+                    vec![]
+                };
+                encode(mail, move |mail, data| {
+                    write_eml(mail, eml_options, boundaries, data)
+                })
+                .await
+                .with_context(|| format!("emit eml: `{ui_url}`"))?
+            }
+            ExportFormat::Mbox => {
+                let eml_options = EmlOptions {
+                    body_encoding: cfg.body_encoding,
+                    text_alternative: cfg.text_alternative,
+                    preserve_content_type: cfg.preserve_content_type,
+                    state_headers: cfg.state_headers,
+                };
+                encode(mail, move |mail, data| write_mbox(mail, eml_options, data))
+                    .await
+                    .with_context(|| format!("emit mbox: `{ui_url}`"))?
+            }
+            ExportFormat::Html => encode(mail, write_html)
+                .await
+                .with_context(|| format!("emit html: `{ui_url}`"))?,
+            ExportFormat::Txt => encode(mail, write_text)
+                .await
+                .with_context(|| format!("emit text: `{ui_url}`"))?,
+            ExportFormat::Json => {
+                let include_attachment_data = cfg.json_attachment_data;
+                encode(mail, move |mail, data| {
+                    write_json(mail, include_attachment_data, data)
+                })
+                .await
+                .with_context(|| format!("emit json: `{ui_url}`"))?
+            }
+        };
+        if exists {
+            // only reachable for directories, see `prepare_output`
+            if !verify_file(&data, &target_file, cfg.compress)? {
+                state.verify_mismatches.fetch_add(1, Ordering::SeqCst);
+            }
+        } else if !state
+            .write_file(&file_name, data.reader()?, cfg.compress)
+            .await?
+        {
+            info!(
+                mail_id = mail.mail.mail_id.as_str(),
+                file_name = file_name.as_str(),
+                "already exists in output",
+            );
+            state.emit(
+                Event::new(EventKind::Skipped, &mail.mail)
+                    .with_file_name(&file_name)
+                    .with_reason("exists"),
+            )?;
+            state.stats.skipped_existing.fetch_add(1, Ordering::SeqCst);
+            return Ok(());
+        } else {
+            state.emit(Event::new(EventKind::Written, &mail.mail).with_file_name(&file_name))?;
+            state.stats.bytes.fetch_add(data.len(), Ordering::SeqCst);
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            state.stats.last_written.store(now, Ordering::SeqCst);
+        }
+
+        if let Some(db) = &state.db {
+            db.record(&DbEntry {
+                folder_id: &mail.mail.folder_id,
+                mail_id: &mail.mail.mail_id,
+                message_id: mail.headers.as_deref().and_then(message_id).as_deref(),
+                received_at: mail.mail.date,
+                output: &state.target,
+                file_name: &file_name,
+                data: &data,
+            })?;
+        }
+
+        if let Some(notmuch_dump) = &state.notmuch_dump {
+            if let Some(message_id) = mail.headers.as_deref().and_then(message_id) {
+                notmuch_dump.insert(&message_id, notmuch_tags(&mail.mail));
+            }
+        }
+
+        if cfg.refresh_drafts && mail.mail.is_draft {
+            state
+                .remove_stale_drafts(&mail.mail.mail_id, &file_name)
+                .await?;
+        }
+        if let Some(manifest) = &state.manifest {
+            let attachments = mail.attachments.iter().map(|a| a.name.clone()).collect();
+            manifest.insert(file_name, ManifestEntry::new(&mail.mail, Some(attachments)));
+        }
+    }
+
+    if cfg.link_index {
+        let link = cfg.path.join(LINK_INDEX_DIR).join(state.file_name(
+            format!("{}{}", mail.mail_id, cfg.format.extension()),
+            cfg.compress,
+        ));
+        link_file(&target_file, &link)
+            .await
+            .with_context(|| format!("link index file: `{}`", link.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Encode mail on the blocking thread pool.
+///
+/// Encoding large attachments is CPU-heavy and would otherwise stall the downloads on the async workers. The mail is
+/// returned along with the encoded data.
+async fn encode<F>(mail: DownloadedMail, f: F) -> Result<(DownloadedMail, Spool)>
+where
+    F: FnOnce(&DownloadedMail, &mut Spool) -> Result<()> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let mut data = Spool::new();
+        f(&mail, &mut data)?;
+        Ok((mail, data))
+    })
+    .await
+    .context("join encoding task")?
+}
@@ -0,0 +1,163 @@
+//! Machine-readable stream of export lifecycle events.
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tatutanatata_core::mails::Mail;
+
+/// Format of the event stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum EventsFormat {
+    /// One JSON object per line.
+    Jsonl,
+}
+
+/// Lifecycle stage of a mail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum EventKind {
+    /// Mail was listed and is considered for export.
+    Discovered,
+
+    /// Mail is NOT exported, see [`Event::reason`].
+    Skipped,
+
+    /// Mail was downloaded and decrypted.
+    Downloaded,
+
+    /// Exported file was written.
+    Written,
+
+    /// Export failed, see [`Event::reason`].
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Event<'a> {
+    event: EventKind,
+    folder_id: &'a str,
+    mail_id: &'a str,
+
+    /// Output file name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_name: Option<&'a str>,
+
+    /// Why the mail was skipped or failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl<'a> Event<'a> {
+    pub(crate) fn new(event: EventKind, mail: &'a Mail) -> Self {
+        Self {
+            event,
+            folder_id: &mail.folder_id,
+            mail_id: &mail.mail_id,
+            file_name: None,
+            reason: None,
+        }
+    }
+
+    pub(crate) fn with_file_name(self, file_name: &'a str) -> Self {
+        Self {
+            file_name: Some(file_name),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_reason(self, reason: impl Into<String>) -> Self {
+        Self {
+            reason: Some(reason.into()),
+            ..self
+        }
+    }
+}
+
+/// Sink that writes events to stdout.
+#[derive(Debug)]
+pub(crate) struct EventSink {
+    format: EventsFormat,
+}
+
+impl EventSink {
+    pub(crate) fn new(format: EventsFormat) -> Self {
+        Self { format }
+    }
+
+    /// Write single event.
+    ///
+    /// Every event is flushed immediately, so wrapping tools see it as soon as it happens.
+    pub(crate) fn emit(&self, event: Event<'_>) -> Result<()> {
+        let line = self.format_event(&event)?;
+
+        let mut stdout = std::io::stdout().lock();
+        stdout
+            .write_all(line.as_bytes())
+            .and_then(|()| stdout.flush())
+            .context("write event")
+    }
+
+    fn format_event(&self, event: &Event<'_>) -> Result<String> {
+        match self.format {
+            EventsFormat::Jsonl => {
+                let mut line = serde_json::to_string(event).context("serialize event")?;
+                line.push('\n');
+                Ok(line)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tatutanatata_core::{
+        mails::Address,
+        proto::{
+            enums::{MailState, ReplyType},
+            keys::Key,
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_format_event() {
+        let mail = Mail {
+            folder_id: "folder_id".to_owned(),
+            mail_id: "mail_id".to_owned(),
+            archive_id: "archive_id".to_owned(),
+            blob_id: "blob_id".to_owned(),
+            is_draft: false,
+            unread: false,
+            state: MailState::Received,
+            reply_type: ReplyType::NoReply,
+            conversation_id: None,
+            session_key: Key::Aes256([0; 32]),
+            bucket_session_keys: Default::default(),
+            date: Default::default(),
+            subject: "".to_owned(),
+            sender: Address {
+                mail: "foo@example.com".to_owned(),
+                name: "".to_owned(),
+            },
+            attachments: vec![],
+        };
+        let sink = EventSink::new(EventsFormat::Jsonl);
+
+        insta::assert_snapshot!(
+            sink.format_event(&Event::new(EventKind::Discovered, &mail).with_file_name("a.eml"))
+                .unwrap(),
+            @r###"
+            {"event":"discovered","folderId":"folder_id","mailId":"mail_id","fileName":"a.eml"}
+            "###
+        );
+        insta::assert_snapshot!(
+            sink.format_event(&Event::new(EventKind::Failed, &mail).with_reason("foo: bar"))
+                .unwrap(),
+            @r###"
+            {"event":"failed","folderId":"folder_id","mailId":"mail_id","reason":"foo: bar"}
+            "###
+        );
+    }
+}
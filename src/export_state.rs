@@ -0,0 +1,306 @@
+//! State that is shared between the exports of a single run, see [`ExportState`].
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    io::Read,
+    path::Path,
+    sync::{atomic::AtomicUsize, Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use tatutanatata_core::{
+    concurrency::AdaptiveLimit, folders::Folder, mails::AttachmentFilter, memory::MemoryBudget,
+    retry::RetryPolicy,
+};
+use tracing::info;
+
+use crate::{
+    db::Database,
+    export_events::{Event, EventSink},
+    failure_report::FailureReport,
+    file_encryption::FileEncryption,
+    file_output::{maybe_compress, FileCompression},
+    manifest::{Manifest, MANIFEST_FILE},
+    notmuch::NotmuchDump,
+    output::Output,
+    prune,
+    summary::RunStats,
+    timestamp::{self, TIMESTAMP_FILE},
+    DownloadCLIConfig,
+};
+
+/// State that is shared between the exports of a single run.
+#[derive(Debug)]
+pub(crate) struct ExportState {
+    pub(crate) manifest: Option<Manifest>,
+
+    /// Target of the exported files.
+    pub(crate) output: Output,
+
+    /// Database of exported mails, see [`DownloadCLIConfig::db`].
+    pub(crate) db: Option<Database>,
+
+    /// Tags of the downloaded mails, see [`DownloadCLIConfig::notmuch_dump`].
+    pub(crate) notmuch_dump: Option<NotmuchDump>,
+
+    /// Lifecycle events, see [`DownloadCLIConfig::events`].
+    events: Option<EventSink>,
+
+    /// Failed mails, see [`DownloadCLIConfig::failures`].
+    pub(crate) failure_report: Option<FailureReport>,
+
+    /// Budget for buffered mail data, see [`DownloadCLIConfig::memory_limit`].
+    pub(crate) memory_budget: Option<MemoryBudget>,
+
+    /// Attachments that are downloaded, see [`DownloadCLIConfig::attachment_type`].
+    pub(crate) attachment_filter: AttachmentFilter,
+
+    /// Limit for concurrent downloads, see [`DownloadCLIConfig::concurrent_downloads`].
+    pub(crate) concurrency_limit: Arc<AdaptiveLimit>,
+
+    /// Final output location, which differs from [`DownloadCLIConfig::path`] while a staging directory is used.
+    pub(crate) target: String,
+
+    /// Encryption of the written files, see [`DownloadCLIConfig::encrypt_to`].
+    encryption: Option<FileEncryption>,
+
+    /// Time-stamping authority for the final manifest, see [`DownloadCLIConfig::timestamp_url`].
+    timestamp_url: Option<reqwest::Url>,
+
+    /// Mail IDs by output file name, see [`claim_file_name`](Self::claim_file_name).
+    file_names: Mutex<HashMap<String, String>>,
+
+    /// `Message-ID`s of the mails exported so far, see [`Dedup::MessageId`](crate::Dedup::MessageId).
+    message_ids: Mutex<HashSet<String>>,
+
+    /// Number of existing files that failed verification.
+    pub(crate) verify_mismatches: AtomicUsize,
+
+    /// Counters for the summary, including the failed mails, see [`DownloadCLIConfig::keep_going`].
+    pub(crate) stats: Arc<RunStats>,
+
+    /// Retries of file writes, shared with the client.
+    pub(crate) retry: RetryPolicy,
+}
+
+impl ExportState {
+    pub(crate) async fn try_new(
+        cfg: &DownloadCLIConfig,
+        folder: &Folder,
+        target: &Path,
+        retry: RetryPolicy,
+    ) -> Result<Self> {
+        let output = Output::try_new(&cfg.path, &cfg.s3_cfg, cfg.archive, retry.clone())?;
+        let encryption = FileEncryption::try_new(&cfg.encrypt_to).context("set up encryption")?;
+        let manifest = match &output {
+            // archives always contain a manifest
+            Output::Zip(_) | Output::Tar(_) => Some(Manifest::new()),
+            // encrypted manifests cannot be read back, existing files are re-added to the new one instead
+            Output::Dir(..) | Output::S3(_) if cfg.manifest && encryption.is_some() => {
+                Some(Manifest::new())
+            }
+            Output::Dir(dir, _) if cfg.manifest => {
+                Some(Manifest::load(dir).await.context("load manifest")?)
+            }
+            Output::S3(s3) if cfg.manifest => match s3.get(MANIFEST_FILE).await? {
+                Some(data) => Some(Manifest::from_json(&data)?),
+                None => Some(Manifest::new()),
+            },
+            Output::Dir(..) | Output::S3(_) | Output::Stdout => None,
+        };
+
+        let db = cfg
+            .db
+            .as_deref()
+            .map(Database::open)
+            .transpose()
+            .context("open database")?;
+
+        Ok(Self {
+            manifest,
+            output,
+            db,
+            notmuch_dump: cfg
+                .notmuch_dump
+                .clone()
+                .map(|path| NotmuchDump::new(path, &folder.name)),
+            events: cfg.events.map(EventSink::new),
+            failure_report: cfg.failures.clone().map(FailureReport::new),
+            memory_budget: cfg.memory_limit.map(MemoryBudget::new),
+            attachment_filter: AttachmentFilter {
+                skip_all: cfg.skip_attachments,
+                max_size: cfg.max_attachment_size,
+                mime_types: cfg.attachment_type.clone(),
+            },
+            concurrency_limit: Arc::new(AdaptiveLimit::new(cfg.concurrent_downloads)),
+            target: target.display().to_string(),
+            encryption,
+            timestamp_url: cfg.timestamp_url.clone(),
+            file_names: Mutex::default(),
+            message_ids: Mutex::default(),
+            verify_mismatches: AtomicUsize::new(0),
+            stats: Arc::new(RunStats::new()),
+            retry,
+        })
+    }
+
+    /// Record `Message-ID` and return `true` if it was seen before.
+    pub(crate) fn is_duplicate(&self, message_id: &str) -> bool {
+        !self
+            .message_ids
+            .lock()
+            .expect("not poisoned")
+            .insert(message_id.to_owned())
+    }
+
+    /// Emit lifecycle event if requested.
+    pub(crate) fn emit(&self, event: Event<'_>) -> Result<()> {
+        match &self.events {
+            Some(events) => events.emit(event),
+            None => Ok(()),
+        }
+    }
+
+    /// Claim output file name for the given mail.
+    ///
+    /// Returns `false` if the name belongs to a different mail, either from this run or according to the manifest.
+    pub(crate) fn claim_file_name(&self, file_name: &str, mail_id: &str) -> bool {
+        if let Some(manifest) = &self.manifest {
+            if manifest
+                .mail_id(file_name)
+                .is_some_and(|other| other != mail_id)
+            {
+                return false;
+            }
+        }
+
+        match self
+            .file_names
+            .lock()
+            .expect("not poisoned")
+            .entry(file_name.to_owned())
+        {
+            Entry::Occupied(o) => o.get() == mail_id,
+            Entry::Vacant(v) => {
+                v.insert(mail_id.to_owned());
+                true
+            }
+        }
+    }
+
+    /// Remove earlier exports of the given draft under other file names, see [`DownloadCLIConfig::refresh_drafts`].
+    ///
+    /// The file names are taken from the manifest, so this only works for directories.
+    pub(crate) async fn remove_stale_drafts(&self, mail_id: &str, file_name: &str) -> Result<()> {
+        let (Some(manifest), Output::Dir(dir, _)) = (&self.manifest, &self.output) else {
+            return Ok(());
+        };
+
+        for stale in manifest.files_of_mail(mail_id) {
+            if stale != file_name {
+                info!(mail_id, file_name = stale.as_str(), "remove stale draft");
+                prune::remove_file(dir, &dir.join(&stale)).await?;
+                manifest.remove(&stale);
+            }
+        }
+        Ok(())
+    }
+
+    /// Name of the given file after compression and encryption.
+    pub(crate) fn file_name(
+        &self,
+        mut name: String,
+        compression: Option<FileCompression>,
+    ) -> String {
+        if let Some(compression) = compression {
+            name.push_str(compression.suffix());
+        }
+        if let Some(encryption) = &self.encryption {
+            name.push_str(encryption.suffix());
+        }
+        name
+    }
+
+    /// Write file to output, compressing and encrypting it if requested.
+    ///
+    /// `name` must already contain all suffixes, see [`file_name`](Self::file_name).
+    pub(crate) async fn write_file(
+        &self,
+        name: &str,
+        data: impl Read + Send,
+        compression: Option<FileCompression>,
+    ) -> Result<bool> {
+        match &self.encryption {
+            Some(encryption) => {
+                let encrypted = encryption
+                    .encrypt(maybe_compress(data, compression)?)
+                    .with_context(|| format!("encrypt `{name}`"))?;
+                let reader = encrypted.reader()?;
+                self.output.write(name, reader, None).await
+            }
+            None => self.output.write(name, data, compression).await,
+        }
+    }
+
+    /// Write manifest to output directory.
+    ///
+    /// For archives, the manifest is only written by [`finish`](Self::finish).
+    pub(crate) async fn write_manifest(&self) -> Result<()> {
+        match (&self.manifest, &self.output) {
+            (Some(manifest), Output::Dir(..) | Output::S3(_)) if self.encryption.is_some() => {
+                let name = self.file_name(MANIFEST_FILE.to_owned(), None);
+                self.write_file(&name, manifest.to_json()?.as_bytes(), None)
+                    .await
+                    .context("write manifest")?;
+                Ok(())
+            }
+            (Some(manifest), Output::Dir(dir, retry)) => manifest.write(dir, retry).await,
+            (Some(manifest), Output::S3(s3)) => s3
+                .put(MANIFEST_FILE, manifest.to_json()?.as_bytes())
+                .await
+                .context("upload manifest"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Write `notmuch dump` file, see [`DownloadCLIConfig::notmuch_dump`].
+    pub(crate) async fn write_notmuch_dump(&self) -> Result<()> {
+        match &self.notmuch_dump {
+            Some(dump) => dump.write(&self.retry).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Write failure report, see [`DownloadCLIConfig::failures`].
+    pub(crate) async fn write_failure_report(&self) -> Result<()> {
+        match &self.failure_report {
+            Some(report) => report.write(&self.retry).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Write manifest, timestamp it if requested, and finish output.
+    pub(crate) async fn finish(self) -> Result<()> {
+        self.write_manifest().await?;
+
+        if let (Some(manifest), Output::Zip(_) | Output::Tar(_)) = (&self.manifest, &self.output) {
+            let name = self.file_name(MANIFEST_FILE.to_owned(), None);
+            self.write_file(&name, manifest.to_json()?.as_bytes(), None)
+                .await
+                .context("add manifest to archive")?;
+        }
+
+        if let (Some(manifest), Some(url)) = (&self.manifest, &self.timestamp_url) {
+            // the manifest does not change anymore, so this is the content that was written above
+            let (token, time) = timestamp::timestamp(url, manifest.to_json()?.as_bytes())
+                .await
+                .context("timestamp manifest")?;
+            info!(time = %time.to_date_time(), "timestamped manifest");
+            let name = self.file_name(TIMESTAMP_FILE.to_owned(), None);
+            self.write_file(&name, token.as_slice(), None)
+                .await
+                .context("write manifest timestamp")?;
+        }
+
+        self.output.finish()
+    }
+}
@@ -0,0 +1,202 @@
+//! Report of mails that failed to export, so that they can be retried later.
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tatutanatata_core::{constants::VERSION_STRING, mails::Mail, retry::RetryPolicy};
+
+use crate::file_output::write_to_file;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportData {
+    /// Version of the tool that wrote the report.
+    tool_version: String,
+
+    /// Failed mails, in the order they failed.
+    mails: Vec<FailedMail>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FailedMail {
+    pub(crate) folder_id: String,
+    pub(crate) mail_id: String,
+
+    /// Error of the last attempt.
+    error: String,
+}
+
+impl FailedMail {
+    fn key(&self) -> (&str, &str) {
+        (&self.folder_id, &self.mail_id)
+    }
+}
+
+/// Failed mails of a run, written to a JSON file.
+///
+/// The file may be shared by several runs, e.g. for different folders. Entries of earlier runs are kept unless the
+/// respective mail was exported or failed again.
+#[derive(Debug)]
+pub(crate) struct FailureReport {
+    path: PathBuf,
+    failed: Mutex<Vec<FailedMail>>,
+
+    /// Folder and mail IDs that were exported successfully.
+    resolved: Mutex<HashSet<(String, String)>>,
+}
+
+impl FailureReport {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            failed: Mutex::default(),
+            resolved: Mutex::default(),
+        }
+    }
+
+    /// Load failed mails from a report file, grouped by folder ID.
+    pub(crate) async fn load(path: &Path) -> Result<BTreeMap<String, Vec<String>>> {
+        let data = read(path)
+            .await?
+            .with_context(|| format!("failure report does not exist: `{}`", path.display()))?;
+
+        let mut by_folder = BTreeMap::<String, Vec<String>>::new();
+        for mail in data.mails {
+            let mail_ids = by_folder.entry(mail.folder_id).or_default();
+            if !mail_ids.contains(&mail.mail_id) {
+                mail_ids.push(mail.mail_id);
+            }
+        }
+        Ok(by_folder)
+    }
+
+    /// Record failed mail.
+    pub(crate) fn fail(&self, mail: &Mail, e: &anyhow::Error) {
+        self.failed.lock().expect("not poisoned").push(FailedMail {
+            folder_id: mail.folder_id.clone(),
+            mail_id: mail.mail_id.clone(),
+            error: format!("{e:#}"),
+        });
+    }
+
+    /// Record successfully exported mail.
+    pub(crate) fn resolve(&self, mail: &Mail) {
+        self.resolved
+            .lock()
+            .expect("not poisoned")
+            .insert((mail.folder_id.clone(), mail.mail_id.clone()));
+    }
+
+    /// Merge the mails of this run into the report file.
+    pub(crate) async fn write(&self, retry: &RetryPolicy) -> Result<()> {
+        let existing = read(&self.path).await?.unwrap_or_default();
+
+        let data = {
+            let failed = self.failed.lock().expect("not poisoned");
+            let resolved = self.resolved.lock().expect("not poisoned");
+            ReportData {
+                tool_version: VERSION_STRING.to_owned(),
+                mails: merge(existing.mails, &failed, &resolved),
+            }
+        };
+        let s = serde_json::to_string_pretty(&data).context("serialize failure report")?;
+
+        write_to_file(s.as_bytes(), &self.path, None, retry)
+            .await
+            .context("write failure report")
+    }
+}
+
+/// Read report file, or [`None`] if it does not exist.
+async fn read(path: &Path) -> Result<Option<ReportData>> {
+    if !tokio::fs::try_exists(path)
+        .await
+        .context("check failure report existence")?
+    {
+        return Ok(None);
+    }
+
+    let data = tokio::fs::read(path).await.context("read failure report")?;
+    let data = serde_json::from_slice(&data).context("parse failure report")?;
+    Ok(Some(data))
+}
+
+/// Replace entries of mails that were exported or failed again.
+fn merge(
+    existing: Vec<FailedMail>,
+    failed: &[FailedMail],
+    resolved: &HashSet<(String, String)>,
+) -> Vec<FailedMail> {
+    let failed_keys = failed.iter().map(FailedMail::key).collect::<HashSet<_>>();
+
+    existing
+        .into_iter()
+        .filter(|mail| {
+            !failed_keys.contains(&mail.key())
+                && !resolved.contains(&(mail.folder_id.clone(), mail.mail_id.clone()))
+        })
+        .chain(failed.iter().cloned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed(folder_id: &str, mail_id: &str, error: &str) -> FailedMail {
+        FailedMail {
+            folder_id: folder_id.to_owned(),
+            mail_id: mail_id.to_owned(),
+            error: error.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let existing = vec![
+            failed("f1", "m1", "old"),
+            failed("f1", "m2", "old"),
+            failed("f1", "m3", "old"),
+            failed("f2", "m1", "old"),
+        ];
+        let resolved = HashSet::from([("f1".to_owned(), "m1".to_owned())]);
+
+        assert_eq!(
+            merge(existing, &[failed("f1", "m2", "new")], &resolved),
+            [
+                failed("f1", "m3", "old"),
+                failed("f2", "m1", "old"),
+                failed("f1", "m2", "new"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("failures.json");
+        assert!(FailureReport::load(&path).await.is_err());
+
+        let report = FailureReport::new(path.clone());
+        report.failed.lock().unwrap().extend([
+            failed("f2", "m1", "foo"),
+            failed("f1", "m2", "foo"),
+            failed("f1", "m1", "foo"),
+            failed("f1", "m2", "bar"),
+        ]);
+        report.write(&RetryPolicy::default()).await.unwrap();
+
+        assert_eq!(
+            FailureReport::load(&path).await.unwrap(),
+            BTreeMap::from([
+                ("f1".to_owned(), vec!["m2".to_owned(), "m1".to_owned()]),
+                ("f2".to_owned(), vec!["m1".to_owned()]),
+            ])
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Encrypt exported files at rest.
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use tatutanatata_core::spool::Spool;
+
+/// Prefix of age X25519 recipients.
+const AGE_PREFIX: &str = "age1";
+
+/// Recipient that exported files are encrypted to.
+#[derive(Debug, Clone)]
+pub(crate) enum Recipient {
+    /// [age](https://age-encryption.org/) X25519 public key.
+    Age(age::x25519::Recipient),
+
+    /// GPG key ID, fingerprint, or user ID.
+    Gpg(String),
+}
+
+impl FromStr for Recipient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            Err("recipient must not be empty".to_owned())
+        } else if s.starts_with(AGE_PREFIX) {
+            s.parse()
+                .map(Self::Age)
+                .map_err(|e| format!("invalid age recipient: {e}"))
+        } else {
+            Ok(Self::Gpg(s.to_owned()))
+        }
+    }
+}
+
+/// Encryption that is applied to every written file.
+#[derive(Debug)]
+pub(crate) enum FileEncryption {
+    /// Encrypt with the `age` library.
+    Age(Vec<age::x25519::Recipient>),
+
+    /// Encrypt by piping the data through `gpg`.
+    Gpg(Vec<String>),
+}
+
+impl FileEncryption {
+    /// Set up encryption for the given recipients.
+    ///
+    /// Returns [`None`] if there are no recipients.
+    pub(crate) fn try_new(recipients: &[Recipient]) -> Result<Option<Self>> {
+        let Some(first) = recipients.first() else {
+            return Ok(None);
+        };
+
+        let this = match first {
+            Recipient::Age(_) => Self::Age(
+                recipients
+                    .iter()
+                    .map(|r| match r {
+                        Recipient::Age(r) => Ok(r.clone()),
+                        Recipient::Gpg(_) => bail!("cannot mix age and GPG recipients"),
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            Recipient::Gpg(_) => Self::Gpg(
+                recipients
+                    .iter()
+                    .map(|r| match r {
+                        Recipient::Gpg(r) => Ok(r.clone()),
+                        Recipient::Age(_) => bail!("cannot mix age and GPG recipients"),
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+        };
+        Ok(Some(this))
+    }
+
+    /// File name suffix, including the leading dot.
+    pub(crate) fn suffix(&self) -> &'static str {
+        match self {
+            Self::Age(_) => ".age",
+            Self::Gpg(_) => ".gpg",
+        }
+    }
+
+    /// Encrypt data.
+    ///
+    /// The result is [spooled](Spool) since the encrypted output is produced by a writer.
+    pub(crate) fn encrypt(&self, mut data: impl Read + Send) -> Result<Spool> {
+        let mut out = Spool::new();
+
+        tokio::task::block_in_place(|| match self {
+            Self::Age(recipients) => {
+                let encryptor = age::Encryptor::with_recipients(
+                    recipients.iter().map(|r| r as &dyn age::Recipient),
+                )
+                .context("set up age encryption")?;
+                let mut writer = encryptor
+                    .wrap_output(&mut out)
+                    .context("write age header")?;
+                std::io::copy(&mut data, &mut writer).context("encrypt data")?;
+                writer.finish().context("finish age encryption")?;
+                Ok(())
+            }
+            Self::Gpg(recipients) => gpg_encrypt(recipients, &mut data, &mut out),
+        })?;
+
+        Ok(out)
+    }
+}
+
+fn gpg_encrypt(
+    recipients: &[String],
+    data: &mut (impl Read + Send),
+    out: &mut Spool,
+) -> Result<()> {
+    let mut cmd = Command::new("gpg");
+    cmd.args([
+        "--batch",
+        "--quiet",
+        "--no-tty",
+        "--encrypt",
+        "--output",
+        "-",
+    ]);
+    for r in recipients {
+        cmd.arg("--recipient").arg(r);
+    }
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("spawn `gpg`")?;
+    let mut stdin = child.stdin.take().expect("piped");
+    let mut stdout = child.stdout.take().expect("piped");
+
+    // feed stdin concurrently, otherwise both pipes may fill up
+    let copied = std::thread::scope(|s| {
+        let writer = s.spawn(move || -> Result<()> {
+            std::io::copy(data, &mut stdin).context("write to `gpg`")?;
+            stdin.flush().context("flush `gpg` input")
+        });
+        let read = std::io::copy(&mut stdout, out).context("read from `gpg`");
+        let written = writer.join().expect("writer thread panicked");
+        read.and(written)
+    });
+
+    let output = child.wait_with_output().context("wait for `gpg`")?;
+    ensure!(
+        output.status.success(),
+        "`gpg` failed ({}): {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim(),
+    );
+    copied
+}
+
+#[cfg(test)]
+mod tests {
+    use age::secrecy::ExposeSecret;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_recipient() {
+        let identity = age::x25519::Identity::generate();
+        let recipient = identity.to_public().to_string();
+        assert!(matches!(recipient.parse(), Ok(Recipient::Age(_))));
+
+        assert!(matches!(
+            "foo@example.com".parse(),
+            Ok(Recipient::Gpg(r)) if r == "foo@example.com"
+        ));
+        assert!("age1foo".parse::<Recipient>().is_err());
+        assert!("".parse::<Recipient>().is_err());
+
+        // secret keys are NOT recipients
+        assert!(matches!(
+            identity.to_string().expose_secret().parse(),
+            Ok(Recipient::Gpg(_))
+        ));
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(FileEncryption::try_new(&[]).unwrap().is_none());
+
+        let age = Recipient::Age(age::x25519::Identity::generate().to_public());
+        let gpg = Recipient::Gpg("foo@example.com".to_owned());
+        assert_eq!(
+            FileEncryption::try_new(&[age.clone(), age.clone()])
+                .unwrap()
+                .unwrap()
+                .suffix(),
+            ".age",
+        );
+        assert_eq!(
+            FileEncryption::try_new(std::slice::from_ref(&gpg))
+                .unwrap()
+                .unwrap()
+                .suffix(),
+            ".gpg",
+        );
+        assert_eq!(
+            FileEncryption::try_new(&[age, gpg])
+                .unwrap_err()
+                .to_string(),
+            "cannot mix age and GPG recipients",
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_encrypt_age() {
+        let identity = age::x25519::Identity::generate();
+        let encryption = FileEncryption::Age(vec![identity.to_public()]);
+
+        let encrypted = encryption.encrypt(b"foo".as_slice()).unwrap();
+        let decryptor = age::Decryptor::new(encrypted.reader().unwrap()).unwrap();
+        let mut decrypted = vec![];
+        decryptor
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .unwrap()
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, b"foo");
+    }
+}
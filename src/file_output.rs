@@ -1,11 +1,84 @@
-use std::path::Path;
+use std::{
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
 
 use anyhow::{Context, Result};
+use tatutanatata_core::retry::{ErrorClass, Retry, RetryPolicy};
 use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use tracing::debug;
 
-use crate::retry::retry;
+/// Chunk size used to copy data into files.
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Compression for output files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum FileCompression {
+    /// gzip, adds `.gz` to the file name.
+    Gzip,
+
+    /// Zstandard, adds `.zst` to the file name.
+    Zstd,
+}
+
+impl FileCompression {
+    /// File name suffix, including the leading dot.
+    pub(crate) fn suffix(&self) -> &'static str {
+        match self {
+            Self::Gzip => ".gz",
+            Self::Zstd => ".zst",
+        }
+    }
+
+    /// Wrap reader so that it yields compressed data.
+    fn compress<'a>(&self, data: impl Read + Send + 'a) -> Result<Box<dyn Read + Send + 'a>> {
+        match self {
+            Self::Gzip => Ok(Box::new(flate2::read::GzEncoder::new(
+                data,
+                flate2::Compression::default(),
+            ))),
+            Self::Zstd => Ok(Box::new(
+                zstd::stream::read::Encoder::new(data, 0).context("zstd compress")?,
+            )),
+        }
+    }
+
+    /// Wrap reader so that it yields decompressed data.
+    pub(crate) fn decompress<'a>(
+        &self,
+        data: impl Read + Send + 'a,
+    ) -> Result<Box<dyn Read + Send + 'a>> {
+        match self {
+            Self::Gzip => Ok(Box::new(flate2::read::GzDecoder::new(data))),
+            Self::Zstd => Ok(Box::new(
+                zstd::stream::read::Decoder::new(data).context("zstd decompress")?,
+            )),
+        }
+    }
+}
+
+/// Wrap reader so that it yields compressed data if requested.
+pub(crate) fn maybe_compress<'a>(
+    content: impl Read + Send + 'a,
+    compression: Option<FileCompression>,
+) -> Result<Box<dyn Read + Send + 'a>> {
+    match compression {
+        Some(compression) => compression.compress(content),
+        None => Ok(Box::new(content)),
+    }
+}
+
+/// Write file atomically, optionally compressing the content.
+///
+/// `path` is used as is, i.e. the caller is responsible for adding the [suffix](FileCompression::suffix).
+pub(crate) async fn write_to_file(
+    content: impl Read + Send,
+    path: &Path,
+    compression: Option<FileCompression>,
+    retry: &RetryPolicy,
+) -> Result<()> {
+    let mut content = maybe_compress(content, compression)?;
 
-pub(crate) async fn write_to_file(content: &[u8], path: &Path) -> Result<()> {
     let tmp_path = path.with_extension(".part");
     let mut f = OpenOptions::new()
         .write(true)
@@ -15,39 +88,265 @@ pub(crate) async fn write_to_file(content: &[u8], path: &Path) -> Result<()> {
         .await
         .context("open temp file")?;
 
-    f.write_all(content).await.context("write to temp file")?;
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let n = content.read(&mut buf).context("read content")?;
+        if n == 0 {
+            break;
+        }
+        f.write_all(&buf[..n]).await.context("write to temp file")?;
+    }
     f.shutdown().await.context("close temp file")?;
 
-    rename(&tmp_path, path).await.context("rename")?;
+    rename(&tmp_path, path, retry).await.context("rename")?;
 
     Ok(())
 }
 
-async fn rename(old: &Path, new: &Path) -> Result<()> {
+async fn rename(old: &Path, new: &Path, retry: &RetryPolicy) -> Result<()> {
     // some file systems like SMB may not sync immediately and return "not found" shortly after file
     // creation
-    retry(
-        "rename file",
-        || async move { tokio::fs::rename(old, new).await },
-        |e| e.kind() == std::io::ErrorKind::NotFound,
-    )
-    .await
+    retry
+        .retry(
+            "rename file",
+            || async move { tokio::fs::rename(old, new).await },
+            |e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    Retry::Backoff(ErrorClass::Filesystem)
+                } else {
+                    Retry::No
+                }
+            },
+        )
+        .await
 }
 
-pub(crate) fn escape_file_string(s: &str) -> String {
-    s.chars()
-        .filter(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | ' '))
+/// Create `link` that points to `target`.
+///
+/// Uses a relative symlink if possible. Falls back to a hardlink and finally to a copy for file systems that support
+/// neither. An existing `link` is replaced.
+pub(crate) async fn link_file(target: &Path, link: &Path) -> Result<()> {
+    if tokio::fs::symlink_metadata(link).await.is_ok() {
+        tokio::fs::remove_file(link)
+            .await
+            .context("remove existing link")?;
+    }
+
+    let link_dir = link.parent().context("link has no parent directory")?;
+    match symlink(&relative_path(link_dir, target), link).await {
+        Ok(()) => return Ok(()),
+        Err(e) => {
+            debug!(%e, link=%link.display(), "cannot create symlink, try hardlink");
+        }
+    }
+
+    match tokio::fs::hard_link(target, link).await {
+        Ok(()) => return Ok(()),
+        Err(e) => {
+            debug!(%e, link=%link.display(), "cannot create hardlink, copy file");
+        }
+    }
+
+    tokio::fs::copy(target, link).await.context("copy file")?;
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    tokio::fs::symlink(target, link).await
+}
+
+#[cfg(windows)]
+async fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    tokio::fs::symlink_file(target, link).await
+}
+
+/// Express `target` relative to directory `base`.
+///
+/// Both paths must either be absolute or relative to the same directory.
+fn relative_path(base: &Path, target: &Path) -> PathBuf {
+    let base = base.components().collect::<Vec<_>>();
+    let target = target.components().collect::<Vec<_>>();
+    let common = base.iter().zip(&target).take_while(|(a, b)| a == b).count();
+
+    base[common..]
+        .iter()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .map(|_| Component::ParentDir)
+        .chain(target[common..].iter().copied())
         .collect()
 }
 
+/// Characters that are kept in file names, see [`escape_file_string`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum FilenameCharset {
+    /// ASCII letters, digits, and spaces.
+    ///
+    /// This matches the naming of earlier versions but drops non-Latin text entirely.
+    #[default]
+    Ascii,
+
+    /// All printable characters, except those that are invalid on common file systems.
+    Unicode,
+}
+
+/// Characters that are invalid in file names on Windows, and in the case of `/` everywhere.
+const INVALID_FILE_NAME_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Device names that Windows reserves, regardless of the extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Escape string so that it can be used as part of a file name.
+pub(crate) fn escape_file_string(s: &str, charset: FilenameCharset) -> String {
+    match charset {
+        FilenameCharset::Ascii => s
+            .chars()
+            .filter(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | ' '))
+            .collect(),
+        FilenameCharset::Unicode => s
+            .chars()
+            .map(|c| if c.is_whitespace() { ' ' } else { c })
+            .filter(|c| !c.is_control() && !INVALID_FILE_NAME_CHARS.contains(c))
+            .collect(),
+    }
+}
+
+/// Make complete file name safe to use on all platforms.
+///
+/// This removes trailing dots and spaces as well as leading dots, and avoids names that Windows reserves for devices.
+pub(crate) fn sanitize_file_name(name: &str) -> String {
+    let mut name = name
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .to_owned();
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        name.insert(stem.len(), '_');
+    }
+
+    if name.is_empty() {
+        name.push('_');
+    }
+    name
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_compression_roundtrip() {
+        let data = b"hello hello hello hello".repeat(100);
+        for compression in [FileCompression::Gzip, FileCompression::Zstd] {
+            let mut compressed = vec![];
+            compression
+                .compress(data.as_slice())
+                .unwrap()
+                .read_to_end(&mut compressed)
+                .unwrap();
+            assert!(compressed.len() < data.len());
+
+            let mut decompressed = vec![];
+            compression
+                .decompress(compressed.as_slice())
+                .unwrap()
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_to_file_compressed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("a.eml.gz");
+
+        write_to_file(
+            b"foo".as_slice(),
+            &path,
+            Some(FileCompression::Gzip),
+            &RetryPolicy::default(),
+        )
+        .await
+        .unwrap();
+        let mut content = vec![];
+        FileCompression::Gzip
+            .decompress(std::fs::File::open(&path).unwrap())
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, b"foo");
+    }
+
     #[test]
     fn test_escape_file_string() {
-        assert_eq!(escape_file_string(""), "");
-        assert_eq!(escape_file_string("azaZ09 "), "azaZ09 ");
-        assert_eq!(escape_file_string("fOo1!@/\\bar19"), "fOo1bar19");
+        let ascii = FilenameCharset::Ascii;
+        assert_eq!(escape_file_string("", ascii), "");
+        assert_eq!(escape_file_string("azaZ09 ", ascii), "azaZ09 ");
+        assert_eq!(escape_file_string("fOo1!@/\\bar19", ascii), "fOo1bar19");
+        assert_eq!(escape_file_string("Grüße 你好", ascii), "Gre ");
+
+        let unicode = FilenameCharset::Unicode;
+        assert_eq!(escape_file_string("fOo1!@/\\bar19", unicode), "fOo1!@bar19");
+        assert_eq!(escape_file_string("Grüße 你好", unicode), "Grüße 你好");
+        assert_eq!(
+            escape_file_string("Re: a<b>\"c\"|d?*\te\r\nf\0", unicode),
+            "Re abcd e  f",
+        );
+    }
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("foo.eml"), "foo.eml");
+        assert_eq!(sanitize_file_name("..foo. . "), "foo");
+        assert_eq!(sanitize_file_name("CON"), "CON_");
+        assert_eq!(sanitize_file_name("con.eml"), "con_.eml");
+        assert_eq!(sanitize_file_name("Lpt1 .tar.gz"), "Lpt1_ .tar.gz");
+        assert_eq!(sanitize_file_name("CONSOLE.eml"), "CONSOLE.eml");
+        assert_eq!(sanitize_file_name("..."), "_");
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("out/by-id"), Path::new("out/a.eml")),
+            PathBuf::from("../a.eml"),
+        );
+        assert_eq!(
+            relative_path(Path::new("/out/by-id"), Path::new("/out/a.eml")),
+            PathBuf::from("../a.eml"),
+        );
+        assert_eq!(
+            relative_path(Path::new("out"), Path::new("out/a.eml")),
+            PathBuf::from("a.eml"),
+        );
+        assert_eq!(
+            relative_path(Path::new("x/y"), Path::new("z/a.eml")),
+            PathBuf::from("../../z/a.eml"),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let target = dir.path().join("a.eml");
+        let link_dir = dir.path().join("by-id");
+        let link = link_dir.join("id.eml");
+        std::fs::create_dir(&link_dir).unwrap();
+
+        std::fs::write(&target, b"foo").unwrap();
+        link_file(&target, &link).await.unwrap();
+        assert_eq!(std::fs::read(&link).unwrap(), b"foo");
+
+        // replace existing link
+        link_file(&target, &link).await.unwrap();
+        assert_eq!(std::fs::read(&link).unwrap(), b"foo");
     }
 }
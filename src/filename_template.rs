@@ -0,0 +1,360 @@
+//! Templates for output file names.
+use std::str::FromStr;
+
+use tatutanatata_core::mails::Mail;
+
+use crate::{
+    file_output::{escape_file_string, sanitize_file_name, FilenameCharset},
+    timezone::Timezone,
+};
+
+/// Default template, matches the naming of earlier versions.
+const DEFAULT_TEMPLATE: &str = "{date}-{subject}.eml";
+
+/// Format of the `{date}` placeholder.
+pub(crate) const DATE_FORMAT: &str = "%Y-%m-%d-%Hh%Mm%Ss";
+
+/// Maximum number of characters of the `{subject}` placeholder.
+const MAX_SUBJECT_CHARS: usize = 64;
+
+/// Maximum number of bytes of the `{subject}` placeholder.
+///
+/// Non-ASCII characters take up to 4 bytes, and most file systems limit names to 255 bytes.
+const MAX_SUBJECT_BYTES: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Date,
+    From,
+    FromName,
+    Subject,
+    MailId,
+    FolderId,
+}
+
+impl Placeholder {
+    const ALL: &[Self] = &[
+        Self::Date,
+        Self::From,
+        Self::FromName,
+        Self::Subject,
+        Self::MailId,
+        Self::FolderId,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Date => "date",
+            Self::From => "from",
+            Self::FromName => "from_name",
+            Self::Subject => "subject",
+            Self::MailId => "mail_id",
+            Self::FolderId => "folder_id",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// File name template like `{date}-{from}-{subject}.eml`.
+///
+/// Supported placeholders:
+///
+/// - `{date}`: date the mail was received, e.g. `2024-01-02-03h04m05s`
+/// - `{from}`: sender address
+/// - `{from_name}`: sender name
+/// - `{subject}`: subject, truncated to 64 characters
+/// - `{mail_id}`: mail ID
+/// - `{folder_id}`: folder ID
+///
+/// Use `{{` and `}}` for literal braces. Placeholder values are escaped so that they are safe to use in file names, see
+/// [`FilenameCharset`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FilenameTemplate {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl FilenameTemplate {
+    /// Render file name for given mail.
+    pub(crate) fn render(
+        &self,
+        mail: &Mail,
+        timezone: &Timezone,
+        charset: FilenameCharset,
+    ) -> String {
+        let mut out = String::new();
+
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Placeholder(p) => {
+                    let value = match p {
+                        Placeholder::Date => timezone.format(mail.date, DATE_FORMAT),
+                        Placeholder::From => escape_file_string(&mail.sender.mail, charset),
+                        Placeholder::FromName => escape_file_string(&mail.sender.name, charset),
+                        Placeholder::Subject => {
+                            let mut len = 0;
+                            escape_file_string(&mail.subject, charset)
+                                .chars()
+                                .take(MAX_SUBJECT_CHARS)
+                                .take_while(|c| {
+                                    len += c.len_utf8();
+                                    len <= MAX_SUBJECT_BYTES
+                                })
+                                .collect()
+                        }
+                        Placeholder::MailId => escape_file_string(&mail.mail_id, charset),
+                        Placeholder::FolderId => escape_file_string(&mail.folder_id, charset),
+                    };
+                    out.push_str(&value);
+                }
+            }
+        }
+
+        sanitize_file_name(&out)
+    }
+
+    /// The template contains the given placeholder.
+    fn contains(&self, placeholder: Placeholder) -> bool {
+        self.parts.contains(&Part::Placeholder(placeholder))
+    }
+
+    /// Names of mails that were received in different seconds never collide.
+    pub(crate) fn contains_date(&self) -> bool {
+        self.contains(Placeholder::Date)
+    }
+
+    /// Names of different mails never collide.
+    pub(crate) fn contains_mail_id(&self) -> bool {
+        self.contains(Placeholder::MailId)
+    }
+
+    /// Render file name that is unique per mail, used if [`render`](Self::render) yields the name of another mail.
+    ///
+    /// The mail ID is inserted before the extension of the template.
+    pub(crate) fn render_unique(
+        &self,
+        mail: &Mail,
+        timezone: &Timezone,
+        charset: FilenameCharset,
+    ) -> String {
+        let mut out = self.render(mail, timezone, charset);
+
+        let extension_len = match self.parts.last() {
+            Some(Part::Literal(s)) => s.rfind('.').map(|pos| s.len() - pos).unwrap_or_default(),
+            _ => 0,
+        };
+        out.insert_str(
+            out.len() - extension_len,
+            &format!("-{}", escape_file_string(&mail.mail_id, charset)),
+        );
+
+        out
+    }
+}
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        DEFAULT_TEMPLATE.parse().expect("default template is valid")
+    }
+}
+
+impl std::fmt::Display for FilenameTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed placeholder: `{{{name}`")),
+                        }
+                    }
+                    let placeholder = Placeholder::ALL
+                        .iter()
+                        .find(|p| p.name() == name)
+                        .ok_or_else(|| format!("unknown placeholder: `{{{name}}}`"))?;
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(*placeholder));
+                }
+                '}' => return Err("unmatched `}`, use `}}` for a literal brace".to_owned()),
+                '/' | '\\' | '\0' => {
+                    return Err(format!(
+                        "invalid character in file name: `{}`",
+                        c.escape_default()
+                    ))
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        if parts.is_empty() {
+            return Err("template must not be empty".to_owned());
+        }
+
+        Ok(Self {
+            source: s.to_owned(),
+            parts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use tatutanatata_core::{
+        mails::Address,
+        proto::{
+            enums::{MailState, ReplyType},
+            keys::Key,
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mail = Mail {
+            folder_id: "folder".to_owned(),
+            mail_id: "mail-1".to_owned(),
+            archive_id: String::new(),
+            blob_id: String::new(),
+            is_draft: false,
+            unread: false,
+            state: MailState::Received,
+            reply_type: ReplyType::NoReply,
+            conversation_id: None,
+            session_key: Key::Aes128([0; 16]),
+            bucket_session_keys: Default::default(),
+            date: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+            subject: format!("Re: {}", "x".repeat(100)),
+            sender: Address {
+                mail: "foo@example.com".to_owned(),
+                name: "Foo Bar".to_owned(),
+            },
+            attachments: vec![],
+        };
+
+        assert_eq!(
+            FilenameTemplate::default().render(&mail, &Timezone::Utc, FilenameCharset::Ascii),
+            format!("2024-01-02-03h04m05s-Re {}.eml", "x".repeat(61)),
+        );
+        assert_eq!(
+            FilenameTemplate::from_str("{date}-{from}-{from_name}-{mail_id}-{folder_id}.eml")
+                .unwrap()
+                .render(&mail, &Timezone::Utc, FilenameCharset::Ascii),
+            "2024-01-02-03h04m05s-fooexamplecom-Foo Bar-mail1-folder.eml",
+        );
+        assert_eq!(
+            FilenameTemplate::from_str("{{{mail_id}}}").unwrap().render(
+                &mail,
+                &Timezone::Utc,
+                FilenameCharset::Ascii
+            ),
+            "{mail1}",
+        );
+
+        assert_eq!(
+            FilenameTemplate::from_str("{date}.x.eml")
+                .unwrap()
+                .render_unique(&mail, &Timezone::Utc, FilenameCharset::Ascii),
+            "2024-01-02-03h04m05s.x-mail1.eml",
+        );
+        assert_eq!(
+            FilenameTemplate::from_str("{date}-{subject}")
+                .unwrap()
+                .render_unique(&mail, &Timezone::Utc, FilenameCharset::Ascii),
+            format!("2024-01-02-03h04m05s-Re {}-mail1", "x".repeat(61)),
+        );
+
+        let mail = Mail {
+            subject: "Re: Grüße aus 東京".to_owned(),
+            ..mail
+        };
+        assert_eq!(
+            FilenameTemplate::default().render(&mail, &Timezone::Utc, FilenameCharset::Unicode),
+            "2024-01-02-03h04m05s-Re Grüße aus 東京.eml",
+        );
+        let mail = Mail {
+            subject: "東".repeat(100),
+            ..mail
+        };
+        assert_eq!(
+            FilenameTemplate::default().render(&mail, &Timezone::Utc, FilenameCharset::Unicode),
+            format!("2024-01-02-03h04m05s-{}.eml", "東".repeat(42)),
+        );
+        assert_eq!(
+            FilenameTemplate::from_str("{subject}").unwrap().render(
+                &mail,
+                &Timezone::Utc,
+                FilenameCharset::Ascii
+            ),
+            "_",
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            FilenameTemplate::from_str("{foo}.eml").unwrap_err(),
+            "unknown placeholder: `{foo}`",
+        );
+        assert_eq!(
+            FilenameTemplate::from_str("{date").unwrap_err(),
+            "unclosed placeholder: `{date`",
+        );
+        assert_eq!(
+            FilenameTemplate::from_str("a}b").unwrap_err(),
+            "unmatched `}`, use `}}` for a literal brace",
+        );
+        assert_eq!(
+            FilenameTemplate::from_str("a/{date}").unwrap_err(),
+            "invalid character in file name: `/`",
+        );
+        assert_eq!(
+            FilenameTemplate::from_str("").unwrap_err(),
+            "template must not be empty",
+        );
+    }
+
+    #[test]
+    fn test_display_roundtrip() {
+        let t = FilenameTemplate::from_str("{{{date}}}-x.eml").unwrap();
+        assert_eq!(t.to_string(), "{{{date}}}-x.eml");
+        assert_eq!(FilenameTemplate::from_str(&t.to_string()).unwrap(), t);
+    }
+}
@@ -0,0 +1,224 @@
+//! JSON representation of downloaded mails.
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tatutanatata_core::mails::{Address, Attachment, DownloadedMail};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MailJson<'a> {
+    mail_id: &'a str,
+    folder_id: &'a str,
+    date: DateTime<Utc>,
+    subject: &'a str,
+    unread: bool,
+    state: &'static str,
+    reply_type: &'static str,
+    conversation_id: Option<&'a str>,
+    sender: AddressJson<'a>,
+    to: Vec<AddressJson<'a>>,
+    cc: Vec<AddressJson<'a>>,
+    bcc: Vec<AddressJson<'a>>,
+
+    /// Raw headers, [`None`] if the server did not provide any.
+    headers: Option<&'a str>,
+
+    /// HTML body.
+    body: String,
+
+    attachments: Vec<AttachmentJson<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AddressJson<'a> {
+    name: &'a str,
+    mail: &'a str,
+}
+
+impl<'a> From<&'a Address> for AddressJson<'a> {
+    fn from(addr: &'a Address) -> Self {
+        Self {
+            name: &addr.name,
+            mail: &addr.mail,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AttachmentJson<'a> {
+    name: &'a str,
+    mime_type: &'a str,
+    cid: Option<&'a str>,
+    size: u64,
+
+    /// Base64-encoded content, only included if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
+
+impl<'a> AttachmentJson<'a> {
+    fn new(attachment: &'a Attachment, include_data: bool) -> Result<Self> {
+        let data = if include_data {
+            let mut buf = Vec::with_capacity(attachment.data.len() as usize);
+            attachment
+                .data
+                .reader()?
+                .read_to_end(&mut buf)
+                .with_context(|| format!("read attachment `{}`", attachment.name))?;
+            Some(BASE64_STANDARD.encode(buf))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name: &attachment.name,
+            mime_type: &attachment.mime_type,
+            cid: attachment.cid.as_deref(),
+            size: attachment.data.len(),
+            data,
+        })
+    }
+}
+
+/// Write mail as JSON document.
+///
+/// Attachment data is only included if requested, since the whole document is built in memory.
+pub(crate) fn write_json(
+    mail: &DownloadedMail,
+    include_attachment_data: bool,
+    w: &mut impl Write,
+) -> Result<()> {
+    let json = MailJson {
+        mail_id: &mail.mail.mail_id,
+        folder_id: &mail.mail.folder_id,
+        date: mail.mail.date,
+        subject: &mail.mail.subject,
+        unread: mail.mail.unread,
+        state: mail.mail.state.name(),
+        reply_type: mail.mail.reply_type.name(),
+        conversation_id: mail.mail.conversation_id.as_deref(),
+        sender: (&mail.mail.sender).into(),
+        to: mail.to.iter().map(Into::into).collect(),
+        cc: mail.cc.iter().map(Into::into).collect(),
+        bcc: mail.bcc.iter().map(Into::into).collect(),
+        headers: mail.headers.as_deref(),
+        body: String::from_utf8_lossy(&mail.body).into_owned(),
+        attachments: mail
+            .attachments
+            .iter()
+            .map(|a| AttachmentJson::new(a, include_attachment_data))
+            .collect::<Result<_>>()?,
+    };
+
+    serde_json::to_writer_pretty(&mut *w, &json).context("serialize mail")?;
+    writeln!(w)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::DateTime;
+
+    use tatutanatata_core::{
+        mails::Mail,
+        proto::{
+            enums::{MailState, ReplyType},
+            keys::Key,
+        },
+    };
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_write_json() {
+        let mail = DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some("From: foo@example.com".to_owned()),
+            body: b"<p>hi</p>".to_vec(),
+            attachments: vec![Attachment {
+                cid: Some("img1".to_owned()),
+                mime_type: "image/png".to_owned(),
+                name: "a.png".to_owned(),
+                data: b"foo".to_vec().into(),
+            }],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![Address {
+                mail: "bar@example.com".to_owned(),
+                name: "".to_owned(),
+            }],
+            omitted_attachments: vec![],
+            memory: None,
+        };
+
+        let mut buf = vec![];
+        write_json(&mail, false, &mut buf).unwrap();
+        insta::assert_snapshot!(String::from_utf8(buf).unwrap(), @r###"
+        {
+          "mailId": "mail_id",
+          "folderId": "folder_id",
+          "date": "2020-03-04T11:22:33Z",
+          "subject": "Hällö",
+          "unread": false,
+          "state": "Received",
+          "replyType": "NoReply",
+          "conversationId": null,
+          "sender": {
+            "name": "Me",
+            "mail": "foo@example.com"
+          },
+          "to": [
+            {
+              "name": "",
+              "mail": "bar@example.com"
+            }
+          ],
+          "cc": [],
+          "bcc": [],
+          "headers": "From: foo@example.com",
+          "body": "<p>hi</p>",
+          "attachments": [
+            {
+              "name": "a.png",
+              "mimeType": "image/png",
+              "cid": "img1",
+              "size": 3
+            }
+          ]
+        }
+        "###);
+
+        let mut buf = vec![];
+        write_json(&mail, true, &mut buf).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(json["attachments"][0]["data"], "Zm9v");
+    }
+}
@@ -0,0 +1,195 @@
+//! Advisory lock that keeps overlapping runs from writing to the same output, see [`RunLock`].
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use tracing::{debug, info};
+
+use crate::{output::is_stdout_path, s3::is_s3_path};
+
+/// Suffix of the default lock file, which is placed next to the output.
+const LOCK_SUFFIX: &str = ".lock";
+
+/// Run lock config.
+#[derive(Debug, Clone, Parser)]
+pub(crate) struct LockCLIConfig {
+    /// Lock file that prevents overlapping runs, e.g. of cron jobs.
+    ///
+    /// Defaults to `<path>.lock` next to the output directory or archive. Outputs on stdout and S3 are only locked if
+    /// this is set.
+    #[clap(long, action)]
+    lock_file: Option<PathBuf>,
+
+    /// Wait until a running instance releases the lock instead of failing.
+    #[clap(long, action)]
+    wait: bool,
+}
+
+/// Lock that is held until it is dropped.
+///
+/// The lock is advisory, i.e. it only protects against other instances of this tool. The lock file is NOT removed
+/// afterwards, since waiting instances may hold it open already.
+#[derive(Debug)]
+pub(crate) struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Acquire lock for the given output path.
+    ///
+    /// Returns [`None`] if the output is not locked, see [`LockCLIConfig::lock_file`].
+    pub(crate) async fn acquire(cfg: &LockCLIConfig, output: &Path) -> Result<Option<Self>> {
+        let Some(path) = lock_path(cfg, output) else {
+            debug!(output = %output.display(), "no lock file");
+            return Ok(None);
+        };
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("create lock dir: `{}`", parent.display()))?;
+        }
+
+        let wait = cfg.wait;
+        tokio::task::spawn_blocking(move || Self::acquire_blocking(&path, wait))
+            .await
+            .context("join lock task")?
+            .map(Some)
+    }
+
+    fn acquire_blocking(path: &Path, wait: bool) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("open lock file: `{}`", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) if wait => {
+                info!(
+                    path = %path.display(),
+                    holder = holder(&mut file).as_deref().unwrap_or("unknown"),
+                    "wait for lock",
+                );
+                file.lock()
+                    .with_context(|| format!("lock: `{}`", path.display()))?;
+            }
+            Err(TryLockError::WouldBlock) => {
+                bail!(
+                    "another run (PID {}) holds the lock `{}`, pass `--wait` to queue behind it",
+                    holder(&mut file).as_deref().unwrap_or("unknown"),
+                    path.display(),
+                );
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("lock: `{}`", path.display()));
+            }
+        }
+        debug!(path = %path.display(), "acquired lock");
+
+        // only informational, see `holder`
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| writeln!(file, "{}", std::process::id()))
+            .with_context(|| format!("write lock file: `{}`", path.display()))?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// Lock file of the given output, see [`LockCLIConfig::lock_file`].
+fn lock_path(cfg: &LockCLIConfig, output: &Path) -> Option<PathBuf> {
+    if let Some(path) = &cfg.lock_file {
+        return Some(path.clone());
+    }
+    if is_stdout_path(output) || is_s3_path(output) {
+        return None;
+    }
+
+    let mut name = output.file_name()?.to_owned();
+    name.push(LOCK_SUFFIX);
+    Some(output.with_file_name(name))
+}
+
+/// PID of the process that holds the lock, as written by [`RunLock::acquire`].
+///
+/// This may fail on platforms with mandatory locks.
+fn holder(file: &mut File) -> Option<String> {
+    let mut s = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut s).ok()?;
+    let s = s.trim();
+    (!s.is_empty()).then(|| s.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_lock_path() {
+        let cfg = LockCLIConfig {
+            lock_file: None,
+            wait: false,
+        };
+        assert_eq!(
+            lock_path(&cfg, Path::new("backup/out/")),
+            Some(PathBuf::from("backup/out.lock")),
+        );
+        assert_eq!(
+            lock_path(&cfg, Path::new("out.zip")),
+            Some(PathBuf::from("out.zip.lock")),
+        );
+        assert_eq!(lock_path(&cfg, Path::new("-")), None);
+        assert_eq!(lock_path(&cfg, Path::new("s3://bucket/prefix")), None);
+
+        let cfg = LockCLIConfig {
+            lock_file: Some(PathBuf::from("/run/export.lock")),
+            wait: false,
+        };
+        assert_eq!(
+            lock_path(&cfg, Path::new("-")),
+            Some(PathBuf::from("/run/export.lock")),
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_acquire() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let output = dir.path().join("out");
+        let fail = LockCLIConfig {
+            lock_file: None,
+            wait: false,
+        };
+        let wait = LockCLIConfig {
+            lock_file: None,
+            wait: true,
+        };
+
+        let lock = RunLock::acquire(&fail, &output).await.unwrap().unwrap();
+        let err = RunLock::acquire(&fail, &output).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "another run (PID {}) holds the lock `{}`, pass `--wait` to queue behind it",
+                std::process::id(),
+                dir.path().join("out.lock").display(),
+            ),
+        );
+
+        let waiting = tokio::spawn(async move { RunLock::acquire(&wait, &output).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiting.is_finished());
+        drop(lock);
+        waiting.await.unwrap().unwrap().unwrap();
+    }
+}
@@ -1,10 +1,14 @@
 //! Logging setup.
-use std::io::IsTerminal;
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_log::LogTracer;
-use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Layer, Registry};
 
 /// Logging CLI config.
 #[derive(Debug, Parser)]
@@ -27,14 +31,70 @@ pub(crate) struct LoggingCLIConfig {
         action = clap::ArgAction::Count,
     )]
     log_verbose_count: u8,
+
+    /// Log format.
+    #[clap(long, action, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Also write logs to the given file, in addition to stderr.
+    ///
+    /// The file uses the same format as stderr. Unless rotation is disabled, the file name gets a date suffix, e.g.
+    /// `export.log.2024-01-31`.
+    #[clap(long, action)]
+    log_file: Option<PathBuf>,
+
+    /// Start a new log file per period, see `--log-file`.
+    #[clap(long, action, value_enum, default_value_t = LogRotation::Daily)]
+    log_rotation: LogRotation,
+
+    /// Maximum number of rotated log files that are kept, see `--log-file`.
+    ///
+    /// Older files are deleted. All files are kept by default.
+    #[clap(long, action)]
+    log_max_files: Option<usize>,
+}
+
+/// Log format, see [`LoggingCLIConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum LogFormat {
+    /// Human-readable text.
+    Text,
+
+    /// One JSON object per line with timestamp, level, target, message, and fields, e.g. for log aggregators.
+    Json,
+}
+
+/// Rotation of the log file, see [`LoggingCLIConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum LogRotation {
+    /// Single file without date suffix.
+    Never,
+
+    /// New file every hour.
+    Hourly,
+
+    /// New file every day.
+    Daily,
+}
+
+impl LogRotation {
+    fn rotation(self) -> Rotation {
+        match self {
+            Self::Never => Rotation::NEVER,
+            Self::Hourly => Rotation::HOURLY,
+            Self::Daily => Rotation::DAILY,
+        }
+    }
 }
 
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
 /// Setup process-wide logging.
-pub(crate) fn setup_logging(config: LoggingCLIConfig) -> Result<()> {
+pub(crate) fn setup_logging(config: &LoggingCLIConfig) -> Result<()> {
     LogTracer::init()?;
 
-    let filter = match config.log_filter {
-        Some(filter) => filter,
+    let filter = match &config.log_filter {
+        Some(filter) => filter.clone(),
         None => match config.log_verbose_count {
             0 => "warn".to_owned(),
             1 => "info".to_owned(),
@@ -45,14 +105,111 @@ pub(crate) fn setup_logging(config: LoggingCLIConfig) -> Result<()> {
     };
     let filter = EnvFilter::try_new(filter)?;
 
-    let writer = std::io::stderr;
-    let subscriber = FmtSubscriber::builder()
-        .with_ansi(writer().is_terminal())
-        .with_env_filter(filter)
-        .with_writer(writer)
-        .finish();
+    let mut layers = vec![fmt_layer(
+        config.log_format,
+        std::io::stderr,
+        std::io::stderr().is_terminal(),
+    )];
+    if let Some(path) = &config.log_file {
+        let file_name = path
+            .file_name()
+            .context("log file path has no file name")?
+            .to_str()
+            .context("log file name is not UTF-8")?;
+        let dir = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+
+        let mut builder = RollingFileAppender::builder()
+            .rotation(config.log_rotation.rotation())
+            .filename_prefix(file_name);
+        if let Some(n) = config.log_max_files {
+            builder = builder.max_log_files(n);
+        }
+        let appender = builder
+            .build(dir)
+            .with_context(|| format!("open log file: `{}`", path.display()))?;
 
+        layers.push(fmt_layer(config.log_format, appender, false));
+    }
+
+    let subscriber = tracing_subscriber::registry().with(layers).with(filter);
     tracing::subscriber::set_global_default(subscriber)?;
 
     Ok(())
 }
+
+/// Formatting layer that writes to the given writer.
+///
+/// For [`LogFormat::Json`], fields are placed next to the message instead of being nested, so they can be queried
+/// directly.
+fn fmt_layer<W>(format: LogFormat, writer: W, ansi: bool) -> BoxedLayer
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_ansi(ansi)
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let buf = Arc::new(Mutex::new(vec![]));
+        let writer = {
+            let buf = Arc::clone(&buf);
+            move || SharedBuf(Arc::clone(&buf))
+        };
+
+        let subscriber = tracing_subscriber::registry()
+            .with(vec![fmt_layer(LogFormat::Json, writer, false)])
+            .with(EnvFilter::new("info"));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(mail_id = "m", folder_id = "f", "download");
+            tracing::debug!("filtered");
+        });
+
+        let buf = buf.lock().unwrap();
+        let lines = std::str::from_utf8(&buf)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+
+        let mut event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        let timestamp = event.as_object_mut().unwrap().remove("timestamp").unwrap();
+        assert!(timestamp.is_string());
+        assert_eq!(
+            event,
+            serde_json::json!({
+                "level": "INFO",
+                "target": "tatutanatata::logging::tests",
+                "message": "download",
+                "mail_id": "m",
+                "folder_id": "f",
+            })
+        );
+    }
+
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
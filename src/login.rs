@@ -0,0 +1,64 @@
+//! Login options of the CLI, see [`LoginCLIConfig`].
+use anyhow::{bail, Result};
+use clap::{builder::NonEmptyStringValueParser, Parser};
+use tatutanatata_core::session::LoginConfig;
+
+/// Login CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct LoginCLIConfig {
+    /// Username
+    #[clap(long, env = "TUTANOTA_CLI_USERNAME", value_parser = NonEmptyStringValueParser::new())]
+    username: String,
+
+    /// Password
+    #[clap(
+        long,
+        env = "TUTANOTA_CLI_PASSWORD",
+        required_unless_present = "recovery_code",
+        value_parser = NonEmptyStringValueParser::new()
+    )]
+    password: Option<String>,
+
+    /// Account recovery code.
+    ///
+    /// Can be used instead of the password, e.g. if the second factor was lost. Takes precedence over the password.
+    #[clap(long, env = "TUTANOTA_CLI_RECOVERY_CODE", value_parser = NonEmptyStringValueParser::new())]
+    recovery_code: Option<String>,
+}
+
+impl TryFrom<&LoginCLIConfig> for LoginConfig {
+    type Error = anyhow::Error;
+
+    fn try_from(cfg: &LoginCLIConfig) -> Result<Self> {
+        match (&cfg.password, &cfg.recovery_code) {
+            (_, Some(recovery_code)) => Self::with_recovery_code(&cfg.username, recovery_code),
+            (Some(password), None) => Self::new(&cfg.username, password),
+            (None, None) => bail!("either password or recovery code must be provided"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_config() {
+        let cfg = LoginCLIConfig::try_parse_from([
+            "test",
+            "--username",
+            "foo@example.com",
+            "--password",
+            "secret",
+            "--recovery-code",
+            "code",
+        ])
+        .unwrap();
+        let login_cfg = LoginConfig::try_from(&cfg).unwrap();
+        assert!(format!("{login_cfg:?}").contains(r#"password: None"#));
+
+        let e = LoginCLIConfig::try_parse_from(["test", "--username", "", "--password", "secret"])
+            .unwrap_err();
+        assert_eq!(e.kind(), clap::error::ErrorKind::InvalidValue);
+    }
+}
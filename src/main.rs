@@ -1,20 +1,56 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Instant,
+};
 
 use crate::{
-    client::Client,
-    eml::emit_eml,
-    file_output::{escape_file_string, write_to_file},
-    mails::Mail,
-    session::{LoginCLIConfig, Session},
+    attachments::AttachmentsCLIConfig,
+    client::ClientCLIConfig,
+    compare::CompareCLIConfig,
+    doctor::DoctorCLIConfig,
+    exit_code::exit_code_of,
+    export_events::EventsFormat,
+    failure_report::FailureReport,
+    file_encryption::Recipient,
+    file_output::{FileCompression, FilenameCharset},
+    filename_template::FilenameTemplate,
+    lock::{LockCLIConfig, RunLock},
+    login::LoginCLIConfig,
+    output::is_stdout_path,
+    prune::PruneCLIConfig,
+    retry::RetryCLIConfig,
+    s3::{is_s3_path, S3CLIConfig},
+    summary::Summary,
+    tar_output::ArchiveFormat,
+    webhook::RunReport,
+    zip_output::is_zip_path,
 };
-use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use constants::VERSION_STRING;
-use folders::Folder;
-use futures::{StreamExt, TryStreamExt};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Utc};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use futures::{future::ready, StreamExt, TryStreamExt};
+use itertools::Itertools;
 use logging::{setup_logging, LoggingCLIConfig};
+use serde::Serialize;
 use signal::FutureSignalExt;
-use tracing::{debug, info};
+use tatutanatata_core::{
+    client::{Client, ClientConfig, Prefix, Request},
+    constants::VERSION_STRING,
+    eml::BodyEncoding,
+    failure::Failure,
+    folders::{self, mail_groups, Folder},
+    html::write_text,
+    mails::{AttachmentFilter, DownloadOptions, Mail, MailRef},
+    retry::{RetryConfig, RetryPolicy},
+    session::{LoginConfig, Session},
+};
+use timezone::Timezone;
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
 // Workaround for "unused crate" lint false positives.
 #[cfg(test)]
@@ -23,54 +59,108 @@ use assert_cmd as _;
 use predicates as _;
 #[cfg(test)]
 use similar_asserts as _;
-#[cfg(test)]
-use tempfile as _;
 
-mod blob;
+mod attachments;
 mod client;
-mod compression;
-mod constants;
-mod crypto;
-mod eml;
+mod collisions;
+mod compare;
+mod config_file;
+mod db;
+mod doctor;
+mod exit_code;
+mod export;
+mod export_events;
+mod export_state;
+mod failure_report;
+mod file_encryption;
 mod file_output;
-mod folders;
+mod filename_template;
+mod json_output;
+mod lock;
 mod logging;
-mod mails;
-mod non_empty_string;
-mod proto;
+mod login;
+mod manifest;
+mod mbox;
+mod metrics;
+mod notmuch;
+mod output;
+mod prune;
 mod retry;
-mod session;
+mod s3;
 mod signal;
+mod summary;
+mod systemd;
+mod tar_output;
+mod timestamp;
+mod timezone;
+mod verify;
+mod watch;
+mod webhook;
+mod zip_output;
 
 /// CLI args.
 #[derive(Debug, Parser)]
 #[command(
     about = "CLI (Command Line Interface) for Tutanota/Tuta, mostly meant for mass export.",
     version = VERSION_STRING,
+    args_override_self = true,
 )]
 struct Args {
+    /// Read options from TOML config file.
+    ///
+    /// Options passed on the command line take precedence.
+    #[clap(long = config_file::CONFIG_ARG, env = config_file::CONFIG_ENV)]
+    config: Option<PathBuf>,
+
     /// Logging config.
     #[clap(flatten)]
     logging_cfg: LoggingCLIConfig,
 
-    /// Dump JSON responses of server to given folder.
-    ///
-    /// This is useful for development and debugging.
-    #[clap(long)]
-    debug_dump_json_to: Option<PathBuf>,
+    /// HTTP client config.
+    #[clap(flatten)]
+    client_cfg: ClientCLIConfig,
+
+    /// Retry config.
+    #[clap(flatten)]
+    retry_cfg: RetryCLIConfig,
 
     /// Login config.
     #[clap(flatten)]
     login_cfg: LoginCLIConfig,
 
+    /// Do not log out after the command finished.
+    ///
+    /// The session stays valid until it expires. This is useful for debugging.
+    #[clap(long)]
+    no_logout: bool,
+
+    /// Output format of listing commands.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// ID of the mail group whose mailbox is used, e.g. for shared mailboxes.
+    ///
+    /// Required if the account has more than one mail group. Use `list-groups` to find the IDs.
+    #[clap(long)]
+    group: Option<String>,
+
+    /// POST a JSON report to the given URL when the command finished, e.g. a Slack or ntfy webhook.
+    ///
+    /// The report states whether the command succeeded along with the error, exit code, duration, and the summaries of
+    /// the exports. Its `text` field contains a human-readable version.
+    #[clap(long, env = "TUTANOTA_CLI_NOTIFY_URL", hide_env_values = true)]
+    notify_url: Option<reqwest::Url>,
+
     /// Command
     #[clap(subcommand)]
     command: Command,
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 struct DownloadCLIConfig {
-    /// Concurrent downloads.
+    /// Maximum number of concurrent downloads.
+    ///
+    /// Fewer downloads are run while the server signals overload, e.g. via `429 Too Many Requests`.
     #[clap(long, action, default_value_t = 5)]
     concurrent_downloads: usize,
 
@@ -79,142 +169,1094 @@ struct DownloadCLIConfig {
     folder: String,
 
     /// Target path.
+    ///
+    /// If this ends with `.zip`, all files and a manifest are written into a single ZIP archive instead. Use
+    /// `s3://bucket/prefix` to upload the files to S3-compatible object storage. Use `-` together with `--mail-id`
+    /// to write a single mail to stdout, or together with `--archive` to stream the whole export to stdout.
     #[clap(long, action)]
     path: PathBuf,
 
+    /// Stream all files and a manifest as an archive of the given format to stdout.
+    ///
+    /// Requires `--path -`. Use `--format` to select the format of the individual mails.
+    #[clap(long, action, value_enum)]
+    archive: Option<ArchiveFormat>,
+
+    /// S3 config, only used for `s3://` paths.
+    #[clap(flatten)]
+    s3_cfg: S3CLIConfig,
+
+    /// Export the folder of every mail group, e.g. the personal and all shared mailboxes.
+    ///
+    /// Each mailbox is written into a sub-directory of `--path` that is named after the group ID, see `list-groups`.
+    #[clap(long, action)]
+    all_mailboxes: bool,
+
+    /// Only export the mail with the given ID.
+    ///
+    /// Can be repeated.
+    #[clap(long, action)]
+    mail_id: Vec<String>,
+
+    /// Only export mails received at or after the given date.
+    ///
+    /// Either a date like `2024-01-31`, which is interpreted as midnight UTC, or an RFC 3339 timestamp like
+    /// `2024-01-31T12:00:00+01:00`. Older mails are skipped by the server, so this also speeds up incremental exports
+    /// of large folders.
+    #[clap(long, action, value_parser = parse_since)]
+    since: Option<DateTime<Utc>>,
+
     /// Ignore new mails that cannot be decrypted (yet).
     ///
     /// Use the official app to view and respective folder. This will convert the mail data to a
     /// format that we can read.
     #[clap(long, action)]
     ignore_new_mails: bool,
+
+    /// Maintain a `by-id/<mail_id>.<ext>` index that links to the exported files.
+    ///
+    /// Uses symlinks if possible, otherwise hardlinks or copies.
+    #[clap(long, action)]
+    link_index: bool,
+
+    /// Template for output file names.
+    ///
+    /// Supported placeholders are `{date}`, `{from}`, `{from_name}`, `{subject}`, `{mail_id}`, and `{folder_id}`.
+    /// Use `{{` and `}}` for literal braces.
+    ///
+    /// If several mails of a folder yield the same name, the mail ID is added to each of them. Without `{date}`, this
+    /// requires listing the whole folder before the first mail is exported.
+    #[clap(long, action, default_value_t = FilenameTemplate::default())]
+    filename_template: FilenameTemplate,
+
+    /// Characters that are kept in the placeholder values of file names.
+    ///
+    /// Changing this renames files, so existing exports are NOT detected anymore.
+    #[clap(long, action, value_enum, default_value_t = FilenameCharset::Ascii)]
+    filename_charset: FilenameCharset,
+
+    /// Directory structure of the exported files.
+    #[clap(long, action, value_enum, default_value_t = Layout::Flat)]
+    layout: Layout,
+
+    /// Time zone used for the timestamps in file names.
+    ///
+    /// Either `utc`, `local`, or an IANA time zone name like `Europe/Berlin`. Files of earlier exports that were named
+    /// in UTC are still detected as existing.
+    #[clap(long, action, alias = "filename-timezone", default_value_t = Timezone::Utc)]
+    timestamp_timezone: Timezone,
+
+    /// Maximum size of a decompressed mail body or header block in bytes.
+    ///
+    /// Mails exceeding this limit fail to download. This protects against decompression bombs.
+    #[clap(long, action, default_value_t = DEFAULT_MAX_DECOMPRESSED_SIZE)]
+    max_decompressed_size: usize,
+
+    /// Only export headers and body, NOT the attachment content.
+    ///
+    /// Each omitted attachment is listed in a stub header of the EML file.
+    #[clap(long, action)]
+    skip_attachments: bool,
+
+    /// Only download attachments up to the given size in bytes.
+    ///
+    /// Larger attachments are listed in a stub header like with `--skip-attachments`.
+    #[clap(long, action)]
+    max_attachment_size: Option<u64>,
+
+    /// Only download attachments of the given MIME types, e.g. `image/*,application/pdf`.
+    ///
+    /// Can be repeated. Other attachments are listed in a stub header like with `--skip-attachments`.
+    #[clap(long, action, value_delimiter = ',')]
+    attachment_type: Vec<String>,
+
+    /// Maintain a `manifest.json` that maps the exported files to mail metadata.
+    #[clap(long, action)]
+    manifest: bool,
+
+    /// Request an RFC 3161 timestamp of the final manifest from the given time-stamping authority (TSA), e.g. to
+    /// prove when the export was made.
+    ///
+    /// The response of the TSA is written next to the manifest as `manifest.json.tsr`. Verify it with `openssl ts
+    /// -verify -in manifest.json.tsr -data manifest.json -CAfile <TSA certificate>`. Requires `--manifest` or an
+    /// archive.
+    #[clap(long, action, value_name = "URL")]
+    timestamp_url: Option<reqwest::Url>,
+
+    /// Skip mails that are duplicates of mails exported earlier in the same run.
+    #[clap(long, action, value_enum)]
+    dedup: Option<Dedup>,
+
+    /// Continue with the remaining mails if a mail fails to export.
+    ///
+    /// The command still fails at the end, with a distinct exit code for partial exports.
+    #[clap(long, action)]
+    keep_going: bool,
+
+    /// Write the mails that failed to export to the given JSON file.
+    ///
+    /// Pass it to `retry-failed` to re-attempt exactly these mails. An existing file is updated: mails that were
+    /// exported successfully are removed, other entries are kept.
+    #[clap(long, action)]
+    failures: Option<PathBuf>,
+
+    /// Also write the summary that is printed at the end of the run as JSON to the given file.
+    #[clap(long, action)]
+    summary: Option<PathBuf>,
+
+    /// Log the progress with throughput and ETA every given number of seconds, `0` disables it.
+    ///
+    /// The ETA requires the number of mails to export, which is known once the folder was listed completely. Mails are
+    /// listed while they are exported, so no ETA is logged before.
+    #[clap(long, action, default_value_t = 60)]
+    progress_interval_secs: u64,
+
+    /// Serve Prometheus metrics at `http://<ADDR>/metrics`, e.g. `127.0.0.1:9090`.
+    ///
+    /// Only supported by `watch`. Use `last_written_timestamp_seconds` and the failure and retry counters to alert on
+    /// stalled exports.
+    #[clap(long, action)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Check that existing files match the mail data on the server instead of skipping them.
+    ///
+    /// This downloads all mails again. Files that differ are reported but NOT modified. The command fails if any
+    /// file differs.
+    #[clap(long, action)]
+    verify: bool,
+
+    /// Export drafts again even if they exist, since they may have changed.
+    ///
+    /// With `--manifest`, files of earlier versions whose name differs, e.g. because the subject changed, are removed.
+    #[clap(long, action)]
+    refresh_drafts: bool,
+
+    /// Approximate limit in bytes for the mail data that is buffered in memory by all concurrent downloads.
+    ///
+    /// Downloads wait until enough memory is available, before anything is decrypted. Large attachments are buffered on
+    /// disk and only partially count towards the limit. Compressed bodies count with their worst-case size, which is
+    /// capped by `--max-decompressed-size`.
+    #[clap(long, action)]
+    memory_limit: Option<u64>,
+
+    /// Record exported mails in an SQLite database at the given path.
+    ///
+    /// The database is created if it does not exist.
+    #[clap(long, action)]
+    db: Option<PathBuf>,
+
+    /// Write tags of the downloaded mails to the given file in the format of `notmuch dump`.
+    ///
+    /// Apply them with `notmuch restore --accumulate --input=<FILE>` after indexing the export. Mails are tagged with
+    /// the lowercase folder name as well as `unread`, `replied`, and `passed`. Only mails with a `Message-ID` that were
+    /// downloaded in this run are included.
+    #[clap(long, action)]
+    notmuch_dump: Option<PathBuf>,
+
+    /// File format of the exported mails.
+    ///
+    /// A `.eml` extension of the file name template is replaced by the extension of the format.
+    #[clap(long, action, value_enum, default_value_t = ExportFormat::Eml)]
+    format: ExportFormat,
+
+    /// Include base64-encoded attachment content in `--format json` exports.
+    #[clap(long, action)]
+    json_attachment_data: bool,
+
+    /// Compress each written file.
+    ///
+    /// The respective suffix is added to the file names.
+    #[clap(long, action, value_enum)]
+    compress: Option<FileCompression>,
+
+    /// Transfer encoding of the mail body.
+    ///
+    /// `quoted-printable` and `8bit` keep the exported mails searchable with text tools like `grep`.
+    #[clap(long, action, value_enum, default_value_t = BodyEncoding::Base64)]
+    body_encoding: BodyEncoding,
+
+    /// Add a plain-text rendering of the HTML body for text-mode mail clients and indexers.
+    #[clap(long, action)]
+    text_alternative: bool,
+
+    /// Keep the original content type of single-part plain-text and HTML mails.
+    ///
+    /// By default, all mails are written as `multipart/related` with an HTML body. This option is only applied if the
+    /// stored body actually matches the original content type and there are no attachments. It takes precedence over
+    /// `--text-alternative`.
+    #[clap(long, action)]
+    preserve_content_type: bool,
+
+    /// Add `X-Tuta-Unread`, `X-Tuta-State`, and `X-Tuta-Reply-Type` headers with the read and reply state.
+    ///
+    /// Headers of the same name that are part of the original mail are removed.
+    #[clap(long, action)]
+    state_headers: bool,
+
+    /// Encrypt each written file, including the manifest, to the given recipient.
+    ///
+    /// Recipients starting with `age1` are age public keys, everything else is passed to `gpg --recipient`. Can be
+    /// repeated to encrypt to multiple recipients, but age and GPG recipients cannot be mixed. The respective suffix
+    /// is added to the file names.
+    #[clap(long, action, value_name = "RECIPIENT")]
+    encrypt_to: Vec<Recipient>,
+
+    /// Emit an event on stdout whenever a mail is discovered, skipped, downloaded, written, or failed.
+    ///
+    /// Logs are still written to stderr.
+    #[clap(long, action, value_enum)]
+    events: Option<EventsFormat>,
+
+    /// Run lock config.
+    #[clap(flatten)]
+    lock_cfg: LockCLIConfig,
+}
+
+/// Options of the `download-mail` command.
+///
+/// `--folder` is only required for plain mail IDs, UI URLs contain the folder already.
+#[derive(Debug, Parser)]
+#[command(mut_arg("folder", |a| {
+    a.required(false)
+        .default_value("")
+        .hide_default_value(true)
+        .help("Folder name, only required for plain mail IDs")
+}))]
+struct DownloadMailCLIConfig {
+    /// Mail ID or UI URL of the form `<host>/mail/<folder_id>/<mail_id>`, as printed in the logs.
+    mail: MailRef,
+
+    #[clap(flatten)]
+    download: DownloadCLIConfig,
+}
+
+/// Options of the `retry-failed` command.
+///
+/// The folders are taken from the report, so `--folder` is not used.
+#[derive(Debug, Parser)]
+#[command(mut_arg("folder", |a| {
+    a.required(false)
+        .default_value("")
+        .hide(true)
+}))]
+struct RetryFailedCLIConfig {
+    /// Failure report of a previous run, see `--failures`.
+    report: PathBuf,
+
+    #[clap(flatten)]
+    download: DownloadCLIConfig,
+}
+
+/// Deduplication strategy, see [`DownloadCLIConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Dedup {
+    /// Compare the `Message-ID` header.
+    ///
+    /// Mails without `Message-ID` are never considered duplicates.
+    MessageId,
+}
+
+/// Directory structure of exported files, see [`DownloadCLIConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Layout {
+    /// All files in a single directory.
+    Flat,
+
+    /// `YYYY/MM/` sub-directories by the date the mail was received, in the time zone of the file names.
+    ByDate,
+}
+
+impl Layout {
+    /// Directory of the given mail, including the trailing slash.
+    fn directory(&self, mail: &Mail, timezone: &Timezone) -> String {
+        match self {
+            Self::Flat => String::new(),
+            Self::ByDate => timezone.format(mail.date, "%Y/%m/"),
+        }
+    }
+}
+
+/// File format of exported mails, see [`DownloadCLIConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ExportFormat {
+    /// EML file that can be imported into most mail clients.
+    Eml,
+
+    /// Self-contained HTML page that can be viewed in any web browser.
+    ///
+    /// Inline images and attachments are embedded, remote content is blocked.
+    Html,
+
+    /// Plain text with the most important headers, e.g. for `grep`.
+    ///
+    /// Attachments are NOT included.
+    Txt,
+
+    /// JSON document with metadata, headers, body, and attachment metadata, e.g. for indexing pipelines.
+    ///
+    /// Use `--json-attachment-data` to also include the attachment content.
+    Json,
+
+    /// EML wrapped as mbox message with the read and reply state for Thunderbird.
+    ///
+    /// Messages can be concatenated, so `--path -` writes a whole folder that can be dropped into a Thunderbird
+    /// profile.
+    Mbox,
+}
+
+impl ExportFormat {
+    /// File extension, including the leading dot.
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Eml => ".eml",
+            Self::Html => ".html",
+            Self::Txt => ".txt",
+            Self::Json => ".json",
+            Self::Mbox => ".mbox",
+        }
+    }
+
+    /// Apply extension to a file name that was rendered from a template.
+    fn apply_extension(&self, file_name: String) -> String {
+        let stem = file_name
+            .strip_suffix(Self::Eml.extension())
+            .unwrap_or(&file_name);
+        format!("{stem}{}", self.extension())
+    }
+}
+
+/// Sub-directory of the output path that holds the mail ID index.
+const LINK_INDEX_DIR: &str = "by-id";
+
+/// Number of folders or mailboxes that are listed at the same time, e.g. for `--all-mailboxes`.
+const CONCURRENT_FOLDERS: usize = 4;
+
+/// Default of [`DownloadCLIConfig::max_decompressed_size`].
+const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Parser)]
+struct ApiGetCLIConfig {
+    /// API prefix.
+    #[clap(action, value_enum)]
+    prefix: Prefix,
+
+    /// Entity path, e.g. `mailbox/<id>`.
+    #[clap(action)]
+    path: String,
+
+    /// Additional query parameters in the form `key=value`.
+    ///
+    /// Can be repeated.
+    #[clap(long, action, value_parser = parse_key_value)]
+    query: Vec<(String, String)>,
+}
+
+#[derive(Debug, Parser)]
+struct ListFoldersCLIConfig {
+    /// Also print the total and unread number of mails per folder.
+    ///
+    /// This pages through the metadata of all mails, which takes a while for large mailboxes. Several folders are
+    /// counted at the same time.
+    #[clap(long, action)]
+    counts: bool,
+
+    /// Print nested folders indented below their parent, along with the folder type and ID.
+    #[clap(long, action)]
+    tree: bool,
+}
+
+#[derive(Debug, Parser)]
+struct StatsCLIConfig {
+    /// Folder name.
+    #[clap(long, action)]
+    folder: String,
+
+    /// Group mails.
+    #[clap(long, action, value_enum)]
+    group_by: Option<GroupBy>,
+
+    /// Ignore new mails that cannot be decrypted (yet).
+    #[clap(long, action)]
+    ignore_new_mails: bool,
+}
+
+#[derive(Debug, Parser)]
+struct CatCLIConfig {
+    /// Folder name.
+    #[clap(long, action)]
+    folder: String,
+
+    /// Mail ID.
+    #[clap(long, action)]
+    mail_id: String,
+}
+
+#[cfg(feature = "test-harness")]
+#[derive(Debug, Parser)]
+struct FixtureCLIConfig {
+    /// Directory that the responses are written to, pass it to `--replay-from` afterwards.
+    #[clap(long)]
+    path: PathBuf,
+}
+
+/// Grouping for [`StatsCLIConfig`].
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum GroupBy {
+    /// Sender address.
+    Sender,
+
+    /// Domain of the sender address.
+    Domain,
+
+    /// Year the mail was received.
+    Year,
+}
+
+/// Output format, see [`Args`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// Human-readable text.
+    Text,
+
+    /// JSON.
+    Json,
+}
+
+/// JSON output of the `list-folders` command.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FolderOutput {
+    id: String,
+    name: String,
+    #[serde(rename = "type")]
+    folder_type: &'static str,
+    mails: String,
+    accessible: bool,
+    parent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unread: Option<u64>,
+}
+
+/// JSON output of the `list-groups` command.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupOutput {
+    id: String,
+    #[serde(rename = "type")]
+    group_type: &'static str,
+    accessible: bool,
 }
 
 /// Command
 #[derive(Debug, Subcommand)]
 enum Command {
     /// List folders.
-    ListFolders,
+    ListFolders(ListFoldersCLIConfig),
+
+    /// List group memberships of the user and whether their keys could be decrypted.
+    ///
+    /// This helps to debug errors like "group key not found".
+    ListGroups,
 
     /// Download emails for given folder.
     Download(DownloadCLIConfig),
+
+    /// Download only the attachments of the given folders, or of all folders.
+    ///
+    /// The files are written into directories that mirror the folder hierarchy, along with an `attachments.json`
+    /// manifest that maps each file to its mail. Mails that are listed in the manifest are skipped.
+    DownloadAttachments(AttachmentsCLIConfig),
+
+    /// Download a single mail, e.g. to retry one that failed in a larger run.
+    DownloadMail(DownloadMailCLIConfig),
+
+    /// Download the mails of a failure report of a previous run again, see `--failures`.
+    ///
+    /// Pass the same report to `--failures` to update it, so that repeated runs converge.
+    RetryFailed(RetryFailedCLIConfig),
+
+    /// Download emails for given folder and keep exporting new mails as they arrive.
+    ///
+    /// This listens to the server event channel and runs until it is terminated. Network and server errors are logged
+    /// and the watch reconnects, only authentication and config errors end it.
+    ///
+    /// With `--manifest`, the last exported mail is recorded in the manifest, so that a restart only lists the mails
+    /// after it instead of the whole folder. Mails that are moved into the folder while `watch` is stopped are NOT
+    /// picked up then, use `sync` or `download` for a full pass.
+    Watch(DownloadCLIConfig),
+
+    /// Make the output directory an exact mirror of the folder, e.g. for nightly backups.
+    ///
+    /// This exports new mails, exports changed drafts again, and removes mails that no longer exist on the server, see
+    /// `prune`. It implies `--manifest` and `--refresh-drafts`.
+    Sync(DownloadCLIConfig),
+
+    /// Compare the export of a folder with the server.
+    ///
+    /// Reports mails that are missing locally, mails that were deleted on the server, and exported files whose size
+    /// or hash differs from the state database. Fails if the export is incomplete.
+    Compare(CompareCLIConfig),
+
+    /// Delete exported mails that no longer exist in the folder on the server, e.g. because they were deleted or
+    /// moved.
+    ///
+    /// This keeps the export an exact mirror of the folder. Pass `--dry-run` to only print the affected files.
+    Prune(PruneCLIConfig),
+
+    /// Print a single mail as plain text.
+    ///
+    /// This prints the most important headers followed by a text rendering of the body.
+    Cat(CatCLIConfig),
+
+    /// Print mail statistics for given folder.
+    ///
+    /// This only uses mail metadata and does NOT download mail bodies.
+    Stats(StatsCLIConfig),
+
+    /// Fetch arbitrary API entity and print the raw JSON.
+    ///
+    /// This is meant for development, e.g. to prototype support for new entity types.
+    ApiGet(ApiGetCLIConfig),
+
+    /// Check DNS, connectivity, TLS, clock skew, credentials, and output directory and print a report.
+    ///
+    /// This does NOT log in, so it also works if the login fails.
+    Doctor(DoctorCLIConfig),
+
+    /// Write responses of a synthetic account for `--replay-from`, using the given username and password.
+    ///
+    /// This is meant for tests that should run without real credentials.
+    #[cfg(feature = "test-harness")]
+    #[clap(hide = true)]
+    GenerateFixture(FixtureCLIConfig),
+}
+
+impl Command {
+    /// Lock config and output path of commands that write to an export, see [`RunLock`].
+    fn lock_target(&self) -> Option<(&LockCLIConfig, &Path)> {
+        match self {
+            Self::Download(cfg) | Self::Watch(cfg) | Self::Sync(cfg) => {
+                Some((&cfg.lock_cfg, &cfg.path))
+            }
+            Self::DownloadMail(DownloadMailCLIConfig { download, .. })
+            | Self::RetryFailed(RetryFailedCLIConfig { download, .. }) => {
+                Some((&download.lock_cfg, &download.path))
+            }
+            Self::DownloadAttachments(cfg) => Some(cfg.lock_target()),
+            Self::Prune(cfg) => Some(cfg.lock_target()),
+            _ => None,
+        }
+    }
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    let (k, v) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid `key=value` pair: `{s}`"))?;
+    Ok((k.to_owned(), v.to_owned()))
+}
+
+fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|dt| dt.to_utc())
+        .map_err(|_| format!("neither a date like `2024-01-31` nor an RFC 3339 timestamp: `{s}`"))
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(exit_code_of(&e))
+        }
+    }
+}
+
+async fn run() -> Result<()> {
     dotenvy::dotenv().ok();
-    let args = Args::parse();
-    setup_logging(args.logging_cfg).context("logging setup")?;
+    let cmd = config_file::with_flag_values(Args::command());
+    let matches = cmd.clone().get_matches_from(
+        config_file::args_with_config(&cmd, std::env::args_os().collect())
+            .context("load config file")?,
+    );
+    let command = matches.subcommand_name().unwrap_or_default().to_owned();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    setup_logging(&args.logging_cfg).context("logging setup")?;
+
+    #[cfg(feature = "test-harness")]
+    if let Command::GenerateFixture(cfg) = &args.command {
+        return tatutanatata_core::fixture::generate(
+            &LoginConfig::try_from(&args.login_cfg)?,
+            &cfg.path,
+        )
+        .await;
+    }
 
-    let client = Client::try_new(args.debug_dump_json_to)
+    let notify_url = args.notify_url.take();
+    let started = Instant::now();
+    let res = run_command(args).await;
+    if let Some(url) = &notify_url {
+        RunReport::new(&command, &res, started.elapsed(), Summary::recorded())
+            .send(url)
+            .await;
+    }
+    res
+}
+
+async fn run_command(args: Args) -> Result<()> {
+    let login_cfg = LoginConfig::try_from(&args.login_cfg)?;
+    let client = Client::try_new(ClientConfig::from(args.client_cfg))
         .await
-        .context("set up client")?;
+        .context("set up client")?
+        .with_retry_policy(RetryPolicy::new(RetryConfig::from(&args.retry_cfg)));
+
+    if let Command::Doctor(cfg) = &args.command {
+        return doctor::run(&client, &login_cfg, cfg).await;
+    }
+
+    // held until the command finished
+    let _lock = match args.command.lock_target() {
+        Some((cfg, path)) => RunLock::acquire(cfg, path).await?,
+        None => None,
+    };
 
-    let session = Session::login(args.login_cfg, &client)
+    let session = Session::login(login_cfg, &client)
         .await
+        .map_err(|e| match Failure::of(&e) {
+            // the server may just be unreachable
+            Some(Failure::Network) => e,
+            _ => e.context(Failure::Auth),
+        })
         .context("perform login")?;
 
-    let cmd_res = exec_cmd(&client, &session, args.command)
-        .cancel_on_signal()
-        .await
-        .context("execute command");
-    let logout_res = session.logout(&client).await.context("logout");
+    let cmd_res = exec_cmd(
+        &client,
+        &session,
+        args.command,
+        args.output,
+        args.group.as_deref(),
+    )
+    .cancel_on_signal()
+    .await
+    .context("execute command");
+    // report the original error first, a hung or failing logout must not hide it
+    if let Err(e) = &cmd_res {
+        error!(error = format!("{e:#}"), "command failed");
+    }
+    let logout_res = if args.no_logout {
+        debug!("skip logout");
+        Ok(())
+    } else {
+        session.logout(&client).await.context("logout")
+    };
 
     match (cmd_res, logout_res) {
-        (Err(e), _) => Err(e),
-        (_, Err(e)) => Err(e),
+        (Err(e), Err(logout_e)) => {
+            warn!(
+                error = format!("{logout_e:#}"),
+                "logout failed after command failure"
+            );
+            Err(e)
+        }
+        (Err(e), Ok(())) => Err(e),
+        (Ok(()), Err(e)) => Err(e),
         (Ok(()), Ok(())) => Ok(()),
     }
 }
 
-async fn exec_cmd(client: &Client, session: &Session, cmd: Command) -> Result<()> {
+async fn exec_cmd(
+    client: &Client,
+    session: &Session,
+    cmd: Command,
+    output: OutputFormat,
+    group: Option<&str>,
+) -> Result<()> {
     match cmd {
-        Command::ListFolders => {
-            let folders = Folder::list(client, session).await.context("get folders")?;
-            let mut folders = std::pin::pin!(folders);
+        Command::ListFolders(cfg) => {
+            let folders = Folder::list(client, session, group)
+                .await
+                .context("get folders")?
+                .try_collect::<Vec<_>>()
+                .await
+                .context("poll folder")?;
+            // counting lists every mail of the folder, so several folders are counted at the same time
+            let counts = folders
+                .iter()
+                .map(|f| async move {
+                    if cfg.counts {
+                        Mail::count(client, session, f).await.map(Some)
+                    } else {
+                        Ok(None)
+                    }
+                })
+                .collect::<Vec<_>>();
+            let counts = futures::stream::iter(counts)
+                .buffered(CONCURRENT_FOLDERS)
+                .try_collect::<Vec<_>>()
+                .await?;
+
+            let order = if cfg.tree {
+                folders::tree(&folders)
+            } else {
+                (0..folders.len()).map(|idx| (0, idx)).collect()
+            };
+
+            let mut json_out = vec![];
+            for (depth, idx) in order {
+                let f = &folders[idx];
+                let counts = counts[idx];
+                match output {
+                    OutputFormat::Text => {
+                        let mut line = format!("{}{}", "  ".repeat(depth), f.name);
+                        if cfg.tree {
+                            line.push_str(&format!(" [{}, {}]", f.folder_type.name(), f.id));
+                        }
+
+                        let mut notes = vec![];
+                        if !f.accessible {
+                            notes.push("inaccessible: no key".to_owned());
+                        }
+                        if let Some(counts) = counts {
+                            notes.push(format!("{} mails", counts.total));
+                            notes.push(format!("{} unread", counts.unread));
+                        }
+                        if !notes.is_empty() {
+                            line.push_str(&format!(" ({})", notes.join(", ")));
+                        }
 
-            while let Some(f) = folders.try_next().await.context("poll folder")? {
-                println!("{}", f.name);
+                        println!("{line}");
+                    }
+                    OutputFormat::Json => {
+                        json_out.push(FolderOutput {
+                            id: f.id.clone(),
+                            name: f.name.clone(),
+                            folder_type: f.folder_type.name(),
+                            mails: f.mails.clone(),
+                            accessible: f.accessible,
+                            parent: f.parent.clone(),
+                            total: counts.map(|c| c.total),
+                            unread: counts.map(|c| c.unread),
+                        });
+                    }
+                }
+            }
+
+            if output == OutputFormat::Json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&json_out).context("format JSON")?
+                );
             }
 
             Ok(())
         }
-        Command::Download(cfg) => {
-            // ensure output exists
-            tokio::fs::create_dir_all(&cfg.path)
-                .await
-                .context("create output dir")?;
+        Command::ListGroups => {
+            let mut json_out = vec![];
+            for m in session.memberships() {
+                let accessible = m.accessible;
+                match output {
+                    OutputFormat::Text if accessible => {
+                        println!("{}\t{}", m.group_type.name(), m.group);
+                    }
+                    OutputFormat::Text => {
+                        println!(
+                            "{}\t{} (inaccessible: no key)",
+                            m.group_type.name(),
+                            m.group
+                        );
+                    }
+                    OutputFormat::Json => {
+                        json_out.push(GroupOutput {
+                            id: m.group,
+                            group_type: m.group_type.name(),
+                            accessible,
+                        });
+                    }
+                }
+            }
 
-            // find folder
-            let folders = Folder::list(client, session)
-                .await
-                .context("get folders")?
-                .try_filter(|f| futures::future::ready(f.name == cfg.folder));
-            let mut folders = std::pin::pin!(folders);
-            let folder = folders
-                .try_next()
-                .await
-                .context("search folder")?
-                .context("folder not found")?;
-            debug!(mails = folder.mails.as_str(), "download mails from folder");
+            if output == OutputFormat::Json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&json_out).context("format JSON")?
+                );
+            }
 
-            Mail::list(client, session, &folder, cfg.ignore_new_mails)
-                .map(|mail| {
-                    let cfg = &cfg;
+            Ok(())
+        }
+        Command::Download(cfg) if cfg.all_mailboxes => {
+            ensure!(
+                group.is_none(),
+                "`--all-mailboxes` cannot be combined with `--group`"
+            );
+            ensure!(
+                !is_zip_path(&cfg.path) && !is_stdout_path(&cfg.path),
+                "`--all-mailboxes` cannot write into a single ZIP archive or stdout"
+            );
+            ensure!(
+                cfg.mail_id.is_empty(),
+                "`--all-mailboxes` does not support `--mail-id`"
+            );
+            ensure!(
+                cfg.summary.is_none() && cfg.notmuch_dump.is_none(),
+                "`--all-mailboxes` does not support `--summary` and `--notmuch-dump`"
+            );
 
+            // mailboxes are exported into separate directories, so they do not interfere
+            let exports = mail_groups(session)
+                .into_iter()
+                .map(|group| {
+                    let cfg = DownloadCLIConfig {
+                        path: cfg.path.join(group),
+                        ..cfg.clone()
+                    };
                     async move {
-                        let mail = mail.context("list mail")?;
-
-                        let target_file = cfg.path.join(format!(
-                            "{}-{}.eml",
-                            mail.date.format("%Y-%m-%d-%Hh%Mm%Ss"),
-                            escape_file_string(&mail.subject)
-                                .chars()
-                                .take(64)
-                                .collect::<String>(),
-                        ));
-
-                        if tokio::fs::try_exists(&target_file)
-                            .await
-                            .context("check file existence")?
-                        {
-                            info!(
-                                folder_id = mail.folder_id.as_str(),
-                                mail_id = mail.mail_id.as_str(),
-                                target_file = %target_file.display(),
-                                ui_url = mail.ui_url().as_str(),
-                                "already exists",
-                            );
-                        } else {
-                            info!(
-                                folder_id = mail.folder_id.as_str(),
-                                mail_id = mail.mail_id.as_str(),
-                                target_file = %target_file.display(),
-                                ui_url = mail.ui_url().as_str(),
-                                "download",
-                            );
-
-                            let mail = Arc::clone(&mail)
-                                .download(client, session)
-                                .await
-                                .with_context(|| format!("download mail: `{}`", mail.ui_url()))?;
-
-                            let eml = emit_eml(&mail)
-                                .with_context(|| format!("emit eml: `{}`", mail.mail.ui_url()))?;
-                            write_to_file(eml.as_bytes(), &target_file)
-                                .await
-                                .with_context(|| {
-                                    format!("write output file: `{}`", target_file.display())
-                                })?;
+                        info!("export mailbox");
+                        let folder =
+                            Folder::find(client, session, Some(group), &cfg.folder).await?;
+                        match export::download(client, session, cfg, &folder).await {
+                            Ok(()) => Ok(0),
+                            // continue with the other mailboxes, see `--keep-going`
+                            Err(e) if Failure::of(&e) == Some(Failure::PartialExport) => {
+                                warn!(error = format!("{e:#}"), "some mails failed");
+                                Ok(1)
+                            }
+                            Err(e) => Err(e),
                         }
-
-                        Ok(()) as Result<()>
                     }
+                    .instrument(info_span!("mailbox", group))
                 })
-                .buffer_unordered(cfg.concurrent_downloads)
-                .try_collect::<()>()
+                .collect::<Vec<_>>();
+            let n_partial = futures::stream::iter(exports)
+                .buffer_unordered(CONCURRENT_FOLDERS)
+                .try_fold(0, |n, partial| ready(Ok(n + partial)))
                 .await?;
 
+            if n_partial > 0 {
+                return Err(anyhow!("mails of {n_partial} mailbox(es) failed")
+                    .context(Failure::PartialExport));
+            }
+            Ok(())
+        }
+        Command::Download(cfg) => {
+            let folder = Folder::find(client, session, group, &cfg.folder).await?;
+            export::download(client, session, cfg, &folder).await
+        }
+        Command::DownloadAttachments(cfg) => attachments::run(client, session, group, cfg).await,
+        Command::Compare(cfg) => compare::run(client, session, group, output, cfg).await,
+        Command::Prune(cfg) => prune::run(client, session, group, cfg).await,
+        Command::DownloadMail(DownloadMailCLIConfig { mail, mut download }) => {
+            ensure!(
+                download.mail_id.is_empty(),
+                "`download-mail` does not support `--mail-id`, pass the mail as argument instead"
+            );
+            ensure!(
+                !download.all_mailboxes,
+                "`download-mail` does not support `--all-mailboxes`"
+            );
+            let folder = match &mail.folder_id {
+                Some(folder_id) => Folder::find_by_id(client, session, group, folder_id).await?,
+                None => {
+                    ensure!(
+                        !download.folder.is_empty(),
+                        "`--folder` is required unless the mail is given as URL"
+                    );
+                    Folder::find(client, session, group, &download.folder).await?
+                }
+            };
+            download.mail_id = vec![mail.mail_id];
+            export::download(client, session, download, &folder).await
+        }
+        Command::RetryFailed(RetryFailedCLIConfig { report, download }) => {
+            ensure!(
+                download.folder.is_empty(),
+                "`retry-failed` does not support `--folder`, the folders are taken from the report"
+            );
+            ensure!(
+                download.mail_id.is_empty(),
+                "`retry-failed` does not support `--mail-id`, the mails are taken from the report"
+            );
+            ensure!(
+                !download.all_mailboxes,
+                "`retry-failed` does not support `--all-mailboxes`"
+            );
+
+            let by_folder = FailureReport::load(&report).await?;
+            if by_folder.is_empty() {
+                info!(report = %report.display(), "no failed mails");
+                return Ok(());
+            }
+
+            let mut n_partial = 0;
+            for (folder_id, mail_ids) in by_folder {
+                info!(
+                    folder_id = folder_id.as_str(),
+                    n = mail_ids.len(),
+                    "retry failed mails"
+                );
+                let folder = Folder::find_by_id(client, session, group, &folder_id).await?;
+                let cfg = DownloadCLIConfig {
+                    mail_id: mail_ids,
+                    ..download.clone()
+                };
+                match export::download(client, session, cfg, &folder).await {
+                    Ok(()) => {}
+                    // continue with the other folders, see `--keep-going`
+                    Err(e) if Failure::of(&e) == Some(Failure::PartialExport) => {
+                        warn!(
+                            folder_id = folder_id.as_str(),
+                            error = format!("{e:#}"),
+                            "some mails failed again",
+                        );
+                        n_partial += 1;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if n_partial > 0 {
+                return Err(anyhow!("mails of {n_partial} folder(s) failed again")
+                    .context(Failure::PartialExport));
+            }
+            Ok(())
+        }
+        Command::Sync(mut cfg) => {
+            ensure!(
+                !is_stdout_path(&cfg.path) && !is_s3_path(&cfg.path) && !is_zip_path(&cfg.path),
+                "`sync` only supports directories"
+            );
+            ensure!(
+                cfg.mail_id.is_empty(),
+                "`sync` does not support `--mail-id`"
+            );
+            ensure!(
+                !cfg.all_mailboxes,
+                "`sync` does not support `--all-mailboxes`"
+            );
+            ensure!(
+                cfg.since.is_none(),
+                "`sync` does not support `--since`, older mails would be pruned"
+            );
+            ensure!(
+                cfg.encrypt_to.is_empty(),
+                "`sync` does not support `--encrypt-to`, the manifest must be readable"
+            );
+            cfg.manifest = true;
+            cfg.refresh_drafts = true;
+
+            let folder = Folder::find(client, session, group, &cfg.folder).await?;
+            let path = cfg.path.clone();
+            let db = cfg.db.clone();
+            let res = export::download(client, session, cfg, &folder).await;
+            // failed mails are retried by the next run, but the rest of the mirror is up to date nevertheless
+            if res
+                .as_ref()
+                .is_err_and(|e| Failure::of(e) != Some(Failure::PartialExport))
+            {
+                return res;
+            }
+
+            let pruned =
+                prune::prune(client, session, &folder, &path, db.as_deref(), false).await?;
+            eprintln!("{} exported mails pruned", pruned.len());
+            res
+        }
+        Command::Watch(cfg) => watch::run(client, session, group, cfg).await,
+        Command::Cat(cfg) => {
+            let folder = Folder::find(client, session, group, &cfg.folder).await?;
+            let mail = Mail::get(client, session, &folder, &cfg.mail_id)
+                .await?
+                .with_context(|| {
+                    format!(
+                        "cannot decode mail `{}` yet, view it in the official app",
+                        cfg.mail_id
+                    )
+                })?;
+            let mail = Arc::clone(&mail)
+                .download(
+                    client,
+                    session,
+                    None,
+                    DownloadOptions {
+                        max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+                        memory_budget: None,
+                        attachment_filter: &AttachmentFilter::default(),
+                    },
+                )
+                .await
+                .with_context(|| format!("download mail: `{}`", mail.ui_url(client)))?;
+
+            tokio::task::block_in_place(|| {
+                let mut stdout = std::io::stdout().lock();
+                write_text(&mail, &mut stdout)?;
+                stdout.flush()?;
+                Ok(())
+            })
+        }
+        Command::Stats(cfg) => {
+            let folder = Folder::find(client, session, group, &cfg.folder).await?;
+
+            let mut counts = HashMap::<String, u64>::new();
+            let mails = Mail::list(client, session, &folder, cfg.ignore_new_mails, None);
+            let mut mails = std::pin::pin!(mails);
+            while let Some(mail) = mails.try_next().await.context("list mail")? {
+                let key = match cfg.group_by {
+                    None => String::new(),
+                    Some(GroupBy::Sender) => mail.sender.mail.to_lowercase(),
+                    Some(GroupBy::Domain) => mail
+                        .sender
+                        .mail
+                        .rsplit_once('@')
+                        .map(|(_user, domain)| domain)
+                        .unwrap_or_default()
+                        .to_lowercase(),
+                    Some(GroupBy::Year) => mail.date.year().to_string(),
+                };
+                *counts.entry(key).or_default() += 1;
+            }
+
+            if cfg.group_by.is_some() {
+                let counts = counts
+                    .into_iter()
+                    .sorted_by(|(k1, c1), (k2, c2)| c2.cmp(c1).then_with(|| k1.cmp(k2)));
+                for (key, count) in counts {
+                    println!("{count}\t{key}");
+                }
+            } else {
+                println!("{}", counts.values().sum::<u64>());
+            }
+
+            Ok(())
+        }
+        Command::Doctor(_) => bail!("`doctor` runs without a session"),
+        #[cfg(feature = "test-harness")]
+        Command::GenerateFixture(_) => bail!("`generate-fixture` runs without a session"),
+        Command::ApiGet(cfg) => {
+            let query = cfg
+                .query
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect::<Vec<_>>();
+            let resp: serde_json::Value = client
+                .do_json(Request {
+                    access_token: Some(&session.access_token),
+                    query: &query,
+                    ..Request::new(cfg.prefix, &cfg.path, &())
+                })
+                .await
+                .context("API request")?;
+
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&resp).context("format JSON")?
+            );
+
             Ok(())
         }
     }
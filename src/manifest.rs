@@ -0,0 +1,302 @@
+//! Machine-readable index of exported mails.
+use std::{collections::BTreeMap, path::Path, sync::Mutex};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tatutanatata_core::{constants::VERSION_STRING, mails::Mail, retry::RetryPolicy};
+
+use crate::file_output::write_to_file;
+
+/// File name of the manifest within the output directory.
+pub(crate) const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestData {
+    /// Version of the tool that wrote the manifest.
+    tool_version: String,
+
+    /// Exported mails, keyed by output file name.
+    mails: BTreeMap<String, ManifestEntry>,
+
+    /// Last mail ID per folder up to which `watch` exported all mails, see [`Manifest::watch_progress`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    watch_progress: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ManifestEntry {
+    mail_id: String,
+    folder_id: String,
+    date: DateTime<Utc>,
+    sender: String,
+    subject: String,
+
+    /// Attachment file names.
+    ///
+    /// This is [`None`] for files that were exported by a previous run without manifest.
+    attachments: Option<Vec<String>>,
+
+    /// Read and reply state at the time of the export.
+    ///
+    /// These are [`None`] for entries that were written by an older version.
+    #[serde(default)]
+    unread: Option<bool>,
+    #[serde(default)]
+    state: Option<String>,
+    #[serde(default)]
+    reply_type: Option<String>,
+
+    /// ID that is shared by all mails of the same conversation.
+    #[serde(default)]
+    conversation_id: Option<String>,
+}
+
+impl ManifestEntry {
+    pub(crate) fn new(mail: &Mail, attachments: Option<Vec<String>>) -> Self {
+        Self {
+            mail_id: mail.mail_id.clone(),
+            folder_id: mail.folder_id.clone(),
+            date: mail.date,
+            sender: mail.sender.mail.clone(),
+            subject: mail.subject.clone(),
+            attachments,
+            unread: Some(mail.unread),
+            state: Some(mail.state.name().to_owned()),
+            reply_type: Some(mail.reply_type.name().to_owned()),
+            conversation_id: mail.conversation_id.clone(),
+        }
+    }
+}
+
+/// Manifest that is shared between concurrent downloads.
+#[derive(Debug)]
+pub(crate) struct Manifest {
+    data: Mutex<ManifestData>,
+}
+
+impl Manifest {
+    /// Start empty manifest.
+    pub(crate) fn new() -> Self {
+        Self {
+            data: Mutex::default(),
+        }
+    }
+
+    /// Load manifest from output directory, or start a new one if it does not exist.
+    pub(crate) async fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        if !tokio::fs::try_exists(&path)
+            .await
+            .context("check manifest existence")?
+        {
+            return Ok(Self::new());
+        }
+
+        let data = tokio::fs::read(&path).await.context("read manifest")?;
+        Self::from_json(&data)
+    }
+
+    /// Parse serialized manifest, see [`to_json`](Self::to_json).
+    pub(crate) fn from_json(data: &[u8]) -> Result<Self> {
+        let data = serde_json::from_slice(data).context("parse manifest")?;
+
+        Ok(Self {
+            data: Mutex::new(data),
+        })
+    }
+
+    /// Add entry for the given output file.
+    ///
+    /// Attachments of existing entries are kept if the new one does not know them. Everything else is replaced, since
+    /// the state of a mail may change between runs.
+    pub(crate) fn insert(&self, file_name: String, mut entry: ManifestEntry) {
+        let mut data = self.data.lock().expect("not poisoned");
+        if let Some(existing) = data.mails.get_mut(&file_name) {
+            if entry.attachments.is_none() {
+                entry.attachments = existing.attachments.take();
+            }
+        }
+        data.mails.insert(file_name, entry);
+    }
+
+    /// ID of the mail that the given output file belongs to.
+    pub(crate) fn mail_id(&self, file_name: &str) -> Option<String> {
+        let data = self.data.lock().expect("not poisoned");
+        data.mails.get(file_name).map(|entry| entry.mail_id.clone())
+    }
+
+    /// Remove entry of the given output file.
+    pub(crate) fn remove(&self, file_name: &str) {
+        let mut data = self.data.lock().expect("not poisoned");
+        data.mails.remove(file_name);
+    }
+
+    /// Output file names of the given mail.
+    ///
+    /// There may be more than one if the name changed between runs, e.g. for drafts.
+    pub(crate) fn files_of_mail(&self, mail_id: &str) -> Vec<String> {
+        let data = self.data.lock().expect("not poisoned");
+        data.mails
+            .iter()
+            .filter(|(_file_name, entry)| entry.mail_id == mail_id)
+            .map(|(file_name, _entry)| file_name.clone())
+            .collect()
+    }
+
+    /// Output file names and mail IDs of the given folder.
+    pub(crate) fn files_of_folder(&self, folder_id: &str) -> Vec<(String, String)> {
+        let data = self.data.lock().expect("not poisoned");
+        data.mails
+            .iter()
+            .filter(|(_file_name, entry)| entry.folder_id == folder_id)
+            .map(|(file_name, entry)| (file_name.clone(), entry.mail_id.clone()))
+            .collect()
+    }
+
+    /// Mail ID of the given folder up to which all mails were exported by `watch`.
+    ///
+    /// A restarted watch only lists the mails after it.
+    pub(crate) fn watch_progress(&self, folder_id: &str) -> Option<String> {
+        let data = self.data.lock().expect("not poisoned");
+        data.watch_progress.get(folder_id).cloned()
+    }
+
+    /// Record that all mails of the given folder up to `mail_id` were exported, see
+    /// [`watch_progress`](Self::watch_progress).
+    ///
+    /// The progress never moves backwards.
+    pub(crate) fn set_watch_progress(&self, folder_id: &str, mail_id: &str) {
+        let mut data = self.data.lock().expect("not poisoned");
+        let progress = data.watch_progress.entry(folder_id.to_owned()).or_default();
+        if progress.as_str() < mail_id {
+            mail_id.clone_into(progress);
+        }
+    }
+
+    /// Serialize manifest.
+    pub(crate) fn to_json(&self) -> Result<String> {
+        let mut data = self.data.lock().expect("not poisoned");
+        VERSION_STRING.clone_into(&mut data.tool_version);
+        serde_json::to_string_pretty(&*data).context("serialize manifest")
+    }
+
+    /// Write manifest to output directory.
+    pub(crate) async fn write(&self, dir: &Path, retry: &RetryPolicy) -> Result<()> {
+        let s = self.to_json()?;
+
+        write_to_file(s.as_bytes(), &dir.join(MANIFEST_FILE), None, retry)
+            .await
+            .context("write manifest")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let manifest = Manifest::load(dir.path()).await.unwrap();
+        manifest.insert(
+            "a.eml".to_owned(),
+            entry("a", Some(vec!["x.pdf".to_owned()])),
+        );
+        manifest.insert("b.eml".to_owned(), entry("b", None));
+        manifest
+            .write(dir.path(), &RetryPolicy::default())
+            .await
+            .unwrap();
+
+        let manifest = Manifest::load(dir.path()).await.unwrap();
+        // does NOT lose information
+        manifest.insert("a.eml".to_owned(), entry("a", None));
+        manifest.insert("b.eml".to_owned(), entry("b", Some(vec![])));
+        manifest
+            .write(dir.path(), &RetryPolicy::default())
+            .await
+            .unwrap();
+
+        let data: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join(MANIFEST_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(data["toolVersion"], VERSION_STRING);
+        assert_eq!(
+            data["mails"]["a.eml"],
+            serde_json::json!({
+                "mailId": "a",
+                "folderId": "f",
+                "date": "2024-01-02T03:04:05Z",
+                "sender": "foo@example.com",
+                "subject": "hello",
+                "attachments": ["x.pdf"],
+                "unread": true,
+                "state": "Received",
+                "replyType": "NoReply",
+                "conversationId": "c",
+            }),
+        );
+        assert_eq!(data["mails"]["b.eml"]["attachments"], serde_json::json!([]));
+
+        assert_eq!(
+            manifest.files_of_folder("f"),
+            [
+                ("a.eml".to_owned(), "a".to_owned()),
+                ("b.eml".to_owned(), "b".to_owned()),
+            ],
+        );
+        assert!(manifest.files_of_folder("other").is_empty());
+
+        manifest.insert("c.eml".to_owned(), entry("b", None));
+        assert_eq!(manifest.files_of_mail("b"), ["b.eml", "c.eml"]);
+        manifest.remove("c.eml");
+
+        manifest.remove("a.eml");
+        assert_eq!(
+            manifest.files_of_folder("f"),
+            [("b.eml".to_owned(), "b".to_owned())],
+        );
+        assert!(data.get("watchProgress").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_watch_progress() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let manifest = Manifest::load(dir.path()).await.unwrap();
+        assert_eq!(manifest.watch_progress("f"), None);
+        manifest.set_watch_progress("f", "b");
+        manifest.set_watch_progress("f", "a");
+        manifest.set_watch_progress("g", "c");
+        manifest
+            .write(dir.path(), &RetryPolicy::default())
+            .await
+            .unwrap();
+
+        let manifest = Manifest::load(dir.path()).await.unwrap();
+        assert_eq!(manifest.watch_progress("f").as_deref(), Some("b"));
+        assert_eq!(manifest.watch_progress("g").as_deref(), Some("c"));
+        assert_eq!(manifest.watch_progress("h"), None);
+    }
+
+    fn entry(mail_id: &str, attachments: Option<Vec<String>>) -> ManifestEntry {
+        ManifestEntry {
+            mail_id: mail_id.to_owned(),
+            folder_id: "f".to_owned(),
+            date: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+            sender: "foo@example.com".to_owned(),
+            subject: "hello".to_owned(),
+            attachments,
+            unread: Some(true),
+            state: Some("Received".to_owned()),
+            reply_type: Some("NoReply".to_owned()),
+            conversation_id: Some("c".to_owned()),
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! mbox messages that can be dropped into a Thunderbird profile, see `--format mbox`.
+use std::io::{BufRead, Write};
+
+use anyhow::{Context, Result};
+use tatutanatata_core::{
+    eml::{write_eml, EmlOptions},
+    mails::{DownloadedMail, Mail},
+    proto::enums::ReplyType,
+    spool::Spool,
+};
+
+/// `X-Mozilla-Status` flag of read mails.
+const MOZILLA_READ: u16 = 0x0001;
+
+/// `X-Mozilla-Status` flag of mails that were replied to.
+const MOZILLA_REPLIED: u16 = 0x0002;
+
+/// `X-Mozilla-Status` flag of forwarded mails.
+const MOZILLA_FORWARDED: u16 = 0x1000;
+
+/// Thunderbird rewrites the keywords in place, so the header is padded with spaces.
+const MOZILLA_KEYS_PADDING: usize = 80;
+
+/// Headers that carry the state, the ones of the original mail are replaced.
+const STATE_HEADERS: &[&str] = &[
+    "status",
+    "x-mozilla-status",
+    "x-mozilla-status2",
+    "x-mozilla-keys",
+];
+
+/// Write mail as a single mbox message.
+///
+/// The EML is preceded by a `From_` line as well as `Status` and `X-Mozilla-*` headers with the read and reply state.
+/// Lines that start with `From `, optionally quoted via `>`, are escaped like mboxrd does, so that messages can be
+/// concatenated into one file, e.g. via `--path -`.
+pub(crate) fn write_mbox(
+    mail: &DownloadedMail,
+    options: EmlOptions,
+    w: &mut impl Write,
+) -> Result<()> {
+    let mut eml = Spool::new();
+    write_eml(mail, options, vec![], &mut eml)?;
+
+    let sender = mail.mail.sender.mail.as_str();
+    let sender = if sender.is_empty() || sender.contains(char::is_whitespace) {
+        "MAILER-DAEMON"
+    } else {
+        sender
+    };
+    write!(
+        w,
+        "From {sender} {}\r\n",
+        mail.mail.date.format("%a %b %e %H:%M:%S %Y")
+    )
+    .context("write From_ line")?;
+    for line in state_headers(&mail.mail) {
+        write!(w, "{line}\r\n").context("write state header")?;
+    }
+
+    let mut reader = std::io::BufReader::new(eml.reader().context("read EML")?);
+    let mut line = vec![];
+    let mut in_headers = true;
+    let mut skip_continuation = false;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).context("read EML")? == 0 {
+            break;
+        }
+
+        if in_headers {
+            if line == b"\r\n" || line == b"\n" {
+                in_headers = false;
+            } else if line.starts_with(b" ") || line.starts_with(b"\t") {
+                if skip_continuation {
+                    continue;
+                }
+            } else {
+                skip_continuation = is_state_header(&line);
+                if skip_continuation {
+                    continue;
+                }
+            }
+        }
+
+        if line.iter().skip_while(|b| **b == b'>').take(5).eq(b"From ") {
+            w.write_all(b">").context("write mbox")?;
+        }
+        w.write_all(&line).context("write mbox")?;
+    }
+
+    // messages are separated by an empty line
+    if !line.is_empty() && !line.ends_with(b"\n") {
+        w.write_all(b"\r\n").context("write mbox")?;
+    }
+    w.write_all(b"\r\n").context("write mbox")?;
+
+    Ok(())
+}
+
+/// Headers that Thunderbird reads the state of the mail from.
+fn state_headers(mail: &Mail) -> [String; 4] {
+    let mut flags = 0;
+    if !mail.unread {
+        flags |= MOZILLA_READ;
+    }
+    if matches!(mail.reply_type, ReplyType::Reply | ReplyType::ReplyForward) {
+        flags |= MOZILLA_REPLIED;
+    }
+    if matches!(
+        mail.reply_type,
+        ReplyType::Forward | ReplyType::ReplyForward
+    ) {
+        flags |= MOZILLA_FORWARDED;
+    }
+
+    [
+        format!("Status: {}", if mail.unread { "O" } else { "RO" }),
+        format!("X-Mozilla-Status: {flags:04x}"),
+        "X-Mozilla-Status2: 00000000".to_owned(),
+        format!("X-Mozilla-Keys: {}", " ".repeat(MOZILLA_KEYS_PADDING)),
+    ]
+}
+
+fn is_state_header(line: &[u8]) -> bool {
+    let Some(pos) = line.iter().position(|b| *b == b':') else {
+        return false;
+    };
+    let name = String::from_utf8_lossy(&line[..pos]);
+    STATE_HEADERS
+        .iter()
+        .any(|header| name.trim_end().eq_ignore_ascii_case(header))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::DateTime;
+    use tatutanatata_core::{
+        eml::BodyEncoding,
+        mails::Address,
+        proto::{enums::MailState, keys::Key},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_write_mbox() {
+        let mail = DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::ReplyForward,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hi".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some(
+                "From: foo@example.com\r\nX-Mozilla-Status: 0000\r\nStatus: U\r\n x\r\nSubject: Hi"
+                    .to_owned(),
+            ),
+            body: b"From here\r\n>From there\r\nFrom: not a header".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        };
+        let options = EmlOptions {
+            body_encoding: BodyEncoding::EightBit,
+            text_alternative: false,
+            preserve_content_type: false,
+            state_headers: false,
+        };
+
+        let mut buf = vec![];
+        write_mbox(&mail, options, &mut buf).unwrap();
+        let out = String::from_utf8(buf)
+            .unwrap()
+            .replace("\r\n", "\n")
+            .replace(
+                &format!("Keys: {}", " ".repeat(MOZILLA_KEYS_PADDING)),
+                "Keys: <padding>",
+            );
+        insta::assert_snapshot!(out, @r###"
+        From foo@example.com Wed Mar  4 11:22:33 2020
+        Status: RO
+        X-Mozilla-Status: 1003
+        X-Mozilla-Status2: 00000000
+        X-Mozilla-Keys: <padding>
+        From: foo@example.com
+        Subject: Hi
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: 8bit
+
+        >From here
+        >>From there
+        From: not a header
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_state_headers() {
+        let mut mail = Mail {
+            folder_id: "folder_id".to_owned(),
+            mail_id: "mail_id".to_owned(),
+            archive_id: "archive_id".to_owned(),
+            blob_id: "blob_id".to_owned(),
+            is_draft: false,
+            unread: true,
+            state: MailState::Received,
+            reply_type: ReplyType::NoReply,
+            conversation_id: None,
+            session_key: Key::Aes256([0; 32]),
+            bucket_session_keys: Default::default(),
+            date: Default::default(),
+            subject: "".to_owned(),
+            sender: Address {
+                mail: "".to_owned(),
+                name: "".to_owned(),
+            },
+            attachments: vec![],
+        };
+        assert_eq!(
+            state_headers(&mail)[..2],
+            ["Status: O", "X-Mozilla-Status: 0000"]
+        );
+
+        mail.unread = false;
+        mail.reply_type = ReplyType::Reply;
+        assert_eq!(
+            state_headers(&mail)[..2],
+            ["Status: RO", "X-Mozilla-Status: 0003"]
+        );
+
+        mail.reply_type = ReplyType::Forward;
+        assert_eq!(
+            state_headers(&mail)[..2],
+            ["Status: RO", "X-Mozilla-Status: 1001"]
+        );
+    }
+}
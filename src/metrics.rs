@@ -0,0 +1,306 @@
+//! Prometheus metrics endpoint for long-running exports.
+use std::{
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{Context, Result};
+use tatutanatata_core::{
+    concurrency::AdaptiveLimit,
+    retry::{ErrorClass, RetryPolicy},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tracing::{debug, info};
+
+use crate::summary::RunStats;
+
+/// Prefix of all metric names.
+const PREFIX: &str = "tatutanatata";
+
+/// Maximum size of a request head, larger requests are rejected.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// HTTP server that serves the metrics at `/metrics`.
+///
+/// The server is stopped on drop.
+#[derive(Debug)]
+pub(crate) struct MetricsServer {
+    task: JoinHandle<()>,
+}
+
+impl MetricsServer {
+    pub(crate) async fn start(
+        addr: SocketAddr,
+        stats: Arc<RunStats>,
+        limit: Arc<AdaptiveLimit>,
+        retry: RetryPolicy,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("bind metrics endpoint: `{addr}`"))?;
+        info!(addr = %listener.local_addr().context("get local address")?, "serve metrics");
+
+        let task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(x) => x,
+                    Err(e) => {
+                        debug!(%e, "cannot accept metrics connection");
+                        continue;
+                    }
+                };
+
+                let body = render(&stats, &limit, &retry);
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, body).await {
+                        debug!(%e, %peer, "cannot serve metrics");
+                    }
+                });
+            }
+        });
+
+        Ok(Self { task })
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answer a single HTTP request and close the connection.
+async fn respond(mut stream: TcpStream, body: String) -> std::io::Result<()> {
+    let mut buf = vec![];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        if buf.len() > MAX_REQUEST_SIZE {
+            return stream
+                .write_all(
+                    b"HTTP/1.1 431 Request Header Fields Too Large\r\nconnection: close\r\n\r\n",
+                )
+                .await;
+        }
+
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let request_line = String::from_utf8_lossy(&buf);
+    let mut parts = request_line.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain; version=0.0.4\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len(),
+        ),
+        _ => "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_owned(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Render metrics in the Prometheus text format.
+fn render(stats: &RunStats, limit: &AdaptiveLimit, retry: &RetryPolicy) -> String {
+    let load = |counter: &AtomicU64| counter.load(Ordering::SeqCst);
+    let mut out = String::new();
+
+    metric(
+        &mut out,
+        "mails_listed_total",
+        "counter",
+        "Mails that were listed and considered for export.",
+        &[("", load(&stats.listed))],
+    );
+    metric(
+        &mut out,
+        "mails_downloaded_total",
+        "counter",
+        "Mails that were downloaded.",
+        &[("", load(&stats.downloaded))],
+    );
+    metric(
+        &mut out,
+        "mails_skipped_total",
+        "counter",
+        "Mails that were NOT exported.",
+        &[
+            (r#"reason="existing""#, load(&stats.skipped_existing)),
+            (r#"reason="duplicate""#, load(&stats.skipped_duplicate)),
+        ],
+    );
+    metric(
+        &mut out,
+        "mails_failed_total",
+        "counter",
+        "Mails that failed to export.",
+        &[("", load(&stats.failed))],
+    );
+    metric(
+        &mut out,
+        "written_bytes_total",
+        "counter",
+        "Size of the written files before compression and encryption.",
+        &[("", load(&stats.bytes))],
+    );
+    let labels = ErrorClass::ALL
+        .iter()
+        .map(|class| {
+            (
+                format!(r#"class="{}""#, class.name()),
+                retry.retries(*class),
+            )
+        })
+        .collect::<Vec<_>>();
+    metric(
+        &mut out,
+        "retries_total",
+        "counter",
+        "Retries of failed operations by error class.",
+        &labels
+            .iter()
+            .map(|(labels, value)| (labels.as_str(), *value))
+            .collect::<Vec<_>>(),
+    );
+    metric(
+        &mut out,
+        "queued_mails",
+        "gauge",
+        "Mails that were listed but are NOT exported yet.",
+        &[(
+            "",
+            load(&stats.listed).saturating_sub(load(&stats.completed)),
+        )],
+    );
+    metric(
+        &mut out,
+        "downloads_in_flight",
+        "gauge",
+        "Mails that are downloaded right now.",
+        &[("", limit.in_flight() as u64)],
+    );
+    metric(
+        &mut out,
+        "downloads_limit",
+        "gauge",
+        "Current limit of concurrent downloads.",
+        &[("", limit.limit() as u64)],
+    );
+    metric(
+        &mut out,
+        "last_written_timestamp_seconds",
+        "gauge",
+        "Time of the last written file, 0 if none was written yet.",
+        &[("", load(&stats.last_written))],
+    );
+
+    out
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, u64)]) {
+    writeln!(out, "# HELP {PREFIX}_{name} {help}").expect("write to string");
+    writeln!(out, "# TYPE {PREFIX}_{name} {kind}").expect("write to string");
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            writeln!(out, "{PREFIX}_{name} {value}").expect("write to string");
+        } else {
+            writeln!(out, "{PREFIX}_{name}{{{labels}}} {value}").expect("write to string");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let stats = RunStats::new();
+        stats.listed.fetch_add(10, Ordering::SeqCst);
+        stats.downloaded.fetch_add(6, Ordering::SeqCst);
+        stats.skipped_existing.fetch_add(3, Ordering::SeqCst);
+        stats.completed.fetch_add(9, Ordering::SeqCst);
+        stats.bytes.fetch_add(1024, Ordering::SeqCst);
+        stats.last_written.store(1_700_000_000, Ordering::SeqCst);
+        let limit = AdaptiveLimit::new(5);
+        let _permit = futures::executor::block_on(limit.acquire());
+
+        let out = render(&stats, &limit, &RetryPolicy::default());
+        insta::assert_snapshot!(out, @r###"
+        # HELP tatutanatata_mails_listed_total Mails that were listed and considered for export.
+        # TYPE tatutanatata_mails_listed_total counter
+        tatutanatata_mails_listed_total 10
+        # HELP tatutanatata_mails_downloaded_total Mails that were downloaded.
+        # TYPE tatutanatata_mails_downloaded_total counter
+        tatutanatata_mails_downloaded_total 6
+        # HELP tatutanatata_mails_skipped_total Mails that were NOT exported.
+        # TYPE tatutanatata_mails_skipped_total counter
+        tatutanatata_mails_skipped_total{reason="existing"} 3
+        tatutanatata_mails_skipped_total{reason="duplicate"} 0
+        # HELP tatutanatata_mails_failed_total Mails that failed to export.
+        # TYPE tatutanatata_mails_failed_total counter
+        tatutanatata_mails_failed_total 0
+        # HELP tatutanatata_written_bytes_total Size of the written files before compression and encryption.
+        # TYPE tatutanatata_written_bytes_total counter
+        tatutanatata_written_bytes_total 1024
+        # HELP tatutanatata_retries_total Retries of failed operations by error class.
+        # TYPE tatutanatata_retries_total counter
+        tatutanatata_retries_total{class="network"} 0
+        tatutanatata_retries_total{class="client_error"} 0
+        tatutanatata_retries_total{class="server_error"} 0
+        tatutanatata_retries_total{class="filesystem"} 0
+        # HELP tatutanatata_queued_mails Mails that were listed but are NOT exported yet.
+        # TYPE tatutanatata_queued_mails gauge
+        tatutanatata_queued_mails 1
+        # HELP tatutanatata_downloads_in_flight Mails that are downloaded right now.
+        # TYPE tatutanatata_downloads_in_flight gauge
+        tatutanatata_downloads_in_flight 1
+        # HELP tatutanatata_downloads_limit Current limit of concurrent downloads.
+        # TYPE tatutanatata_downloads_limit gauge
+        tatutanatata_downloads_limit 5
+        # HELP tatutanatata_last_written_timestamp_seconds Time of the last written file, 0 if none was written yet.
+        # TYPE tatutanatata_last_written_timestamp_seconds gauge
+        tatutanatata_last_written_timestamp_seconds 1700000000
+        "###);
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let stats = Arc::new(RunStats::new());
+        let limit = Arc::new(AdaptiveLimit::new(5));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let _server = MetricsServer::start(addr, stats, limit, RetryPolicy::default())
+            .await
+            .unwrap();
+
+        let get = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(format!("GET {path} HTTP/1.1\r\nhost: localhost\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+            let mut resp = String::new();
+            stream.read_to_string(&mut resp).await.unwrap();
+            resp
+        };
+
+        let resp = get("/metrics").await;
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{resp}");
+        assert!(resp.contains("\r\n\r\n# HELP tatutanatata_mails_listed_total "));
+
+        let resp = get("/").await;
+        assert!(resp.starts_with("HTTP/1.1 404 Not Found\r\n"), "{resp}");
+    }
+}
@@ -0,0 +1,167 @@
+//! Tags in the format of `notmuch dump`, so an export can be tagged via `notmuch restore`.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use tatutanatata_core::{mails::Mail, proto::enums::ReplyType, retry::RetryPolicy};
+
+use crate::file_output::write_to_file;
+
+/// Bytes that are NOT hex-encoded in tags and message IDs.
+///
+/// This matches the output charset of `notmuch dump`.
+const SAFE_CHARS: &[u8] = b"+-_@=.,";
+
+/// Tags of exported mails, keyed by `Message-ID`, that is shared between concurrent downloads.
+#[derive(Debug)]
+pub(crate) struct NotmuchDump {
+    /// Output file.
+    path: PathBuf,
+
+    /// Tags that are added to every mail.
+    common_tags: Vec<String>,
+
+    tags: Mutex<BTreeMap<String, BTreeSet<String>>>,
+}
+
+impl NotmuchDump {
+    /// Start empty dump for mails of the given folder.
+    ///
+    /// The folder name is used as a tag.
+    pub(crate) fn new(path: PathBuf, folder_name: &str) -> Self {
+        Self {
+            path,
+            common_tags: vec![folder_name.to_lowercase()],
+            tags: Mutex::default(),
+        }
+    }
+
+    /// Record tags of the mail with the given `Message-ID`.
+    ///
+    /// Angle brackets around the `Message-ID` are removed, like notmuch does.
+    pub(crate) fn insert(&self, message_id: &str, tags: impl IntoIterator<Item = String>) {
+        let message_id = message_id
+            .trim()
+            .trim_start_matches('<')
+            .trim_end_matches('>')
+            .to_owned();
+        self.tags
+            .lock()
+            .expect("not poisoned")
+            .entry(message_id)
+            .or_default()
+            .extend(self.common_tags.iter().cloned().chain(tags));
+    }
+
+    /// Serialize in the batch-tag format of `notmuch dump`.
+    pub(crate) fn to_dump(&self) -> String {
+        let tags = self.tags.lock().expect("not poisoned");
+
+        let mut out = String::new();
+        for (message_id, tags) in tags.iter() {
+            for tag in tags {
+                write!(out, "+{} ", hex_encode(tag)).expect("write to string");
+            }
+            writeln!(out, "-- id:{}", hex_encode(message_id)).expect("write to string");
+        }
+        out
+    }
+
+    /// Write dump to the output file, replacing earlier versions.
+    pub(crate) async fn write(&self, retry: &RetryPolicy) -> Result<()> {
+        let s = self.to_dump();
+
+        write_to_file(s.as_bytes(), &self.path, None, retry)
+            .await
+            .with_context(|| format!("write notmuch dump: `{}`", self.path.display()))
+    }
+}
+
+/// State tags of the given mail, following the naming of notmuch for maildir flags.
+pub(crate) fn notmuch_tags(mail: &Mail) -> Vec<String> {
+    let mut tags = vec![];
+    if mail.unread {
+        tags.push("unread".to_owned());
+    }
+    if matches!(mail.reply_type, ReplyType::Reply | ReplyType::ReplyForward) {
+        tags.push("replied".to_owned());
+    }
+    if matches!(
+        mail.reply_type,
+        ReplyType::Forward | ReplyType::ReplyForward
+    ) {
+        tags.push("passed".to_owned());
+    }
+    tags
+}
+
+/// Hex-encode all bytes except alphanumerics and [`SAFE_CHARS`] as `%XX`.
+fn hex_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || SAFE_CHARS.contains(&b) {
+            out.push(b as char);
+        } else {
+            write!(out, "%{b:02x}").expect("write to string");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use tatutanatata_core::{
+        mails::Address,
+        proto::{enums::MailState, keys::Key},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_to_dump() {
+        let dump = NotmuchDump::new(PathBuf::new(), "My Inbox");
+        dump.insert("<b@example.com>", []);
+        dump.insert("<a/1@example.com>", ["unread".to_owned()]);
+        dump.insert("b@example.com", ["foo".to_owned()]);
+
+        insta::assert_snapshot!(dump.to_dump(), @r###"
+        +my%20inbox +unread -- id:a%2f1@example.com
+        +foo +my%20inbox -- id:b@example.com
+        "###);
+    }
+
+    #[test]
+    fn test_notmuch_tags() {
+        fn tags(unread: bool, reply_type: ReplyType) -> Vec<String> {
+            notmuch_tags(&Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread,
+                state: MailState::Received,
+                reply_type,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: Default::default(),
+                subject: "".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "".to_owned(),
+                },
+                attachments: vec![],
+            })
+        }
+
+        assert_eq!(tags(false, ReplyType::NoReply), Vec::<String>::new());
+        assert_eq!(tags(true, ReplyType::Reply), ["unread", "replied"]);
+        assert_eq!(tags(false, ReplyType::Forward), ["passed"]);
+        assert_eq!(tags(false, ReplyType::ReplyForward), ["replied", "passed"]);
+    }
+}
@@ -0,0 +1,200 @@
+//! Targets that exported files are written to.
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use tatutanatata_core::retry::RetryPolicy;
+
+use crate::{
+    file_output::{maybe_compress, write_to_file, FileCompression},
+    s3::{is_s3_path, S3CLIConfig, S3Output},
+    tar_output::{ArchiveFormat, TarOutput},
+    zip_output::{is_zip_path, ZipOutput},
+};
+
+/// Path that selects [`Output::Stdout`].
+const STDOUT_PATH: &str = "-";
+
+/// Check if the output path refers to stdout.
+pub(crate) fn is_stdout_path(path: &Path) -> bool {
+    path == Path::new(STDOUT_PATH)
+}
+
+/// Output target, selected by the output path.
+#[derive(Debug)]
+pub(crate) enum Output {
+    /// One file per mail in a directory, renames are retried with the given policy.
+    Dir(PathBuf, RetryPolicy),
+
+    /// Single ZIP archive, see [`ZipOutput`].
+    Zip(Box<ZipOutput>),
+
+    /// Objects in an S3 bucket, see [`S3Output`].
+    S3(Box<S3Output>),
+
+    /// Raw content on stdout, only sensible for a single mail.
+    Stdout,
+
+    /// Archive streamed to stdout, see [`TarOutput`].
+    Tar(Box<TarOutput>),
+}
+
+impl Output {
+    pub(crate) fn try_new(
+        path: &Path,
+        s3_cfg: &S3CLIConfig,
+        archive: Option<ArchiveFormat>,
+        retry: RetryPolicy,
+    ) -> Result<Self> {
+        if is_stdout_path(path) {
+            match archive {
+                Some(ArchiveFormat::Tar) => Ok(Self::Tar(Box::new(TarOutput::new(Box::new(
+                    std::io::stdout(),
+                ))))),
+                None => Ok(Self::Stdout),
+            }
+        } else if is_s3_path(path) {
+            Ok(Self::S3(Box::new(
+                S3Output::try_new(path, s3_cfg, retry).context("set up S3 output")?,
+            )))
+        } else if is_zip_path(path) {
+            Ok(Self::Zip(Box::new(
+                ZipOutput::create(path).context("create ZIP archive")?,
+            )))
+        } else {
+            Ok(Self::Dir(path.to_owned(), retry))
+        }
+    }
+
+    /// Human-readable location of the given file, used for logging.
+    pub(crate) fn describe(&self, name: &str) -> String {
+        match self {
+            Self::Dir(dir, _) => dir.join(name).display().to_string(),
+            Self::Zip(zip) => format!("{}:{name}", zip.path().display()),
+            Self::S3(s3) => s3.describe(name),
+            Self::Stdout => STDOUT_PATH.to_owned(),
+            Self::Tar(_) => format!("{STDOUT_PATH}:{name}"),
+        }
+    }
+
+    /// Check if a file with the given name was written already.
+    ///
+    /// This is always `false` for stdout.
+    pub(crate) async fn exists(&self, name: &str) -> Result<bool> {
+        match self {
+            Self::Dir(dir, _) => tokio::fs::try_exists(dir.join(name))
+                .await
+                .context("check file existence"),
+            Self::Zip(zip) => Ok(zip.contains(name)),
+            Self::S3(s3) => s3.exists(name).await,
+            Self::Stdout => Ok(false),
+            Self::Tar(tar) => Ok(tar.contains(name)),
+        }
+    }
+
+    /// The output may contain files of earlier runs, see [`exists`](Self::exists).
+    pub(crate) fn keeps_files(&self) -> bool {
+        !matches!(self, Self::Stdout | Self::Tar(_))
+    }
+
+    /// Write file.
+    ///
+    /// Returns `false` if the output already contains a file with the given name and cannot replace it. The
+    /// compression suffix must already be part of `name`.
+    pub(crate) async fn write(
+        &self,
+        name: &str,
+        data: impl Read + Send,
+        compression: Option<FileCompression>,
+    ) -> Result<bool> {
+        match self {
+            Self::Dir(dir, retry) => {
+                let path = dir.join(name);
+                // names may contain sub-directories, see `Layout`
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .with_context(|| format!("create output dir: `{}`", parent.display()))?;
+                }
+                write_to_file(data, &path, compression, retry)
+                    .await
+                    .with_context(|| format!("write output file: `{}`", path.display()))?;
+                Ok(true)
+            }
+            Self::Zip(zip) => zip.add(name, maybe_compress(data, compression)?),
+            Self::Tar(tar) => tar.add(name, maybe_compress(data, compression)?),
+            Self::S3(s3) => {
+                // uploads are not streamed, so the object is buffered in memory
+                let mut buf = vec![];
+                maybe_compress(data, compression)?
+                    .read_to_end(&mut buf)
+                    .context("read content")?;
+                s3.put(name, &buf).await?;
+                Ok(true)
+            }
+            Self::Stdout => {
+                let mut data = maybe_compress(data, compression)?;
+                tokio::task::block_in_place(|| {
+                    let mut stdout = std::io::stdout().lock();
+                    std::io::copy(&mut data, &mut stdout).context("write to stdout")?;
+                    stdout.flush().context("flush stdout")
+                })?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Finish output, e.g. move a ZIP archive to its final location.
+    pub(crate) fn finish(self) -> Result<()> {
+        match self {
+            Self::Dir(..) | Self::S3(_) | Self::Stdout => Ok(()),
+            Self::Zip(zip) => (*zip).finish(),
+            Self::Tar(tar) => (*tar).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_is_stdout_path() {
+        assert!(is_stdout_path(Path::new("-")));
+        assert!(!is_stdout_path(Path::new("./-")));
+        assert!(!is_stdout_path(Path::new("out")));
+    }
+
+    #[tokio::test]
+    async fn test_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let out = Output::try_new(
+            dir.path(),
+            &S3CLIConfig::parse_from([""]),
+            None,
+            RetryPolicy::default(),
+        )
+        .unwrap();
+        assert!(matches!(out, Output::Dir(..)));
+
+        assert!(!out.exists("a.eml").await.unwrap());
+        assert!(out.write("a.eml", b"foo".as_slice(), None).await.unwrap());
+        assert!(out.exists("a.eml").await.unwrap());
+        assert_eq!(std::fs::read(dir.path().join("a.eml")).unwrap(), b"foo");
+
+        assert!(out
+            .write("2024/01/b.eml", b"bar".as_slice(), None)
+            .await
+            .unwrap());
+        assert!(out.exists("2024/01/b.eml").await.unwrap());
+        assert_eq!(
+            std::fs::read(dir.path().join("2024").join("01").join("b.eml")).unwrap(),
+            b"bar"
+        );
+        out.finish().unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+//! Removal of exported mails that no longer exist on the server, see [`run`].
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use tatutanatata_core::{client::Client, folders::Folder, mails::Mail, session::Session};
+use tracing::{debug, info};
+
+use crate::{
+    compare::local_mails,
+    db::Database,
+    lock::LockCLIConfig,
+    manifest::{Manifest, MANIFEST_FILE},
+    LINK_INDEX_DIR,
+};
+
+/// Options of the `prune` command.
+#[derive(Debug, Parser)]
+pub(crate) struct PruneCLIConfig {
+    /// Folder name.
+    #[clap(long, action)]
+    folder: String,
+
+    /// Directory of the export.
+    ///
+    /// The `manifest.json` in this directory is used to find the exported mails, see `download --manifest`.
+    #[clap(long, action)]
+    path: PathBuf,
+
+    /// Database of the export, see `download --db`.
+    #[clap(long, action)]
+    db: Option<PathBuf>,
+
+    /// Only print the files that would be deleted.
+    #[clap(long, action)]
+    dry_run: bool,
+
+    /// Run lock config.
+    #[clap(flatten)]
+    lock_cfg: LockCLIConfig,
+}
+
+impl PruneCLIConfig {
+    /// Lock config and target path, see [`RunLock`](crate::lock::RunLock).
+    pub(crate) fn lock_target(&self) -> (&LockCLIConfig, &Path) {
+        (&self.lock_cfg, &self.path)
+    }
+}
+
+/// Delete exported files of mails that were deleted on the server or moved to another folder.
+pub(crate) async fn run(
+    client: &Client,
+    session: &Session,
+    group: Option<&str>,
+    cfg: PruneCLIConfig,
+) -> Result<()> {
+    let folder = Folder::find(client, session, group, &cfg.folder).await?;
+    let pruned = prune(
+        client,
+        session,
+        &folder,
+        &cfg.path,
+        cfg.db.as_deref(),
+        cfg.dry_run,
+    )
+    .await?;
+
+    for file_name in &pruned {
+        println!("{file_name}");
+    }
+    eprintln!(
+        "{} exported mails {}",
+        pruned.len(),
+        if cfg.dry_run {
+            "would be pruned"
+        } else {
+            "pruned"
+        },
+    );
+    Ok(())
+}
+
+/// Delete exported files of the given folder whose mails are NOT on the server anymore.
+///
+/// The pruned mails are also removed from the manifest and the database. Mails that cannot be decrypted yet still
+/// exist, so they are never pruned. Returns the file names of the pruned mails.
+pub(crate) async fn prune(
+    client: &Client,
+    session: &Session,
+    folder: &Folder,
+    dir: &Path,
+    db: Option<&Path>,
+    dry_run: bool,
+) -> Result<Vec<String>> {
+    let local = local_mails(dir, db, folder).await?;
+    let server = Mail::list_ids(client, session, folder).await?;
+
+    let pruned = local
+        .into_iter()
+        .filter(|(mail_id, _local)| !server.contains(mail_id))
+        .collect::<Vec<_>>();
+    let file_names = pruned
+        .iter()
+        .map(|(_mail_id, local)| local.file_name.clone())
+        .collect::<Vec<_>>();
+    if dry_run || pruned.is_empty() {
+        return Ok(file_names);
+    }
+
+    for (mail_id, local) in &pruned {
+        info!(
+            mail_id = mail_id.as_str(),
+            file_name = local.file_name.as_str(),
+            "prune",
+        );
+        remove_file(dir, &dir.join(&local.file_name)).await?;
+        remove_links(&dir.join(LINK_INDEX_DIR), mail_id).await?;
+    }
+
+    if tokio::fs::try_exists(dir.join(MANIFEST_FILE))
+        .await
+        .context("check manifest existence")?
+    {
+        let manifest = Manifest::load(dir).await.context("load manifest")?;
+        for file_name in &file_names {
+            manifest.remove(file_name);
+        }
+        manifest.write(dir, client.retry_policy()).await?;
+    }
+
+    if let Some(path) = db {
+        let db = Database::open(path).context("open database")?;
+        for (mail_id, _local) in &pruned {
+            db.remove(&folder.id, mail_id)?;
+        }
+    }
+
+    Ok(file_names)
+}
+
+/// Remove file along with the directories that become empty, e.g. those of `--layout=by-date`.
+pub(crate) async fn remove_file(root: &Path, path: &Path) -> Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            debug!(path = %path.display(), "already gone");
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("remove file: `{}`", path.display()));
+        }
+    }
+
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == root || !d.starts_with(root) {
+            break;
+        }
+        // fails for non-empty directories
+        if tokio::fs::remove_dir(d).await.is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+
+    Ok(())
+}
+
+/// Remove the entries of the given mail from the `--link-index` directory.
+async fn remove_links(dir: &Path, mail_id: &str) -> Result<()> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("read link index: `{}`", dir.display()));
+        }
+    };
+
+    let prefix = format!("{mail_id}.");
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .with_context(|| format!("read link index: `{}`", dir.display()))?
+    {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let path = entry.path();
+            tokio::fs::remove_file(&path)
+                .await
+                .with_context(|| format!("remove link: `{}`", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_remove_file() {
+        let root = tempfile::TempDir::new().unwrap();
+        let root = root.path();
+        tokio::fs::create_dir_all(root.join("2024/01"))
+            .await
+            .unwrap();
+        tokio::fs::create_dir_all(root.join("2024/02"))
+            .await
+            .unwrap();
+        tokio::fs::write(root.join("2024/01/a.eml"), b"")
+            .await
+            .unwrap();
+        tokio::fs::write(root.join("2024/02/b.eml"), b"")
+            .await
+            .unwrap();
+
+        remove_file(root, &root.join("2024/01/a.eml"))
+            .await
+            .unwrap();
+        assert!(!root.join("2024/01").exists());
+        assert!(root.join("2024/02/b.eml").exists());
+
+        // missing files are fine
+        remove_file(root, &root.join("2024/01/a.eml"))
+            .await
+            .unwrap();
+
+        remove_file(root, &root.join("2024/02/b.eml"))
+            .await
+            .unwrap();
+        assert!(!root.join("2024").exists());
+        assert!(root.exists());
+    }
+}
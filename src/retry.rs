@@ -1,115 +1,81 @@
-use std::{future::Future, time::Duration};
+//! Retry limits of this process, see [`RetryCLIConfig`].
+use std::time::Duration;
 
-use anyhow::{Context, Result};
-use rand::{rng, rngs::StdRng, Rng, RngCore, SeedableRng};
-use tracing::warn;
+use clap::Parser;
+use tatutanatata_core::retry::RetryConfig;
 
-/// Exponential backoff with jitter
-///
-/// See <https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/>
-pub(crate) async fn retry<F, Fut, R, T, E>(what: &'static str, f: F, should_retry: R) -> Result<T>
-where
-    F: Fn() -> Fut + Send,
-    Fut: Future<Output = Result<T, E>> + Send,
-    R: for<'a> Fn(&'a E) -> bool + Send,
-    T: Send,
-    E: std::error::Error + Send + Sync + 'static,
-{
-    let config = Config::default();
-    tokio::time::timeout(config.deadline, async move {
-        for sleep in Sleep::from(config) {
-            match f().await {
-                Ok(x) => {
-                    return Ok(x);
-                }
-                Err(e) if should_retry(&e) => {
-                    warn!(%e, what, sleep_sec=sleep.as_secs_f64(), "retry");
-                    tokio::time::sleep(sleep).await;
-                }
-                Err(e) => {
-                    return Err(e).context("failed");
-                }
-            }
-        }
+/// Retry CLI config.
+#[derive(Debug, Clone, Copy, Parser)]
+pub(crate) struct RetryCLIConfig {
+    /// Give up retrying an operation after this many seconds.
+    #[clap(long, default_value_t = 600)]
+    retry_deadline_secs: u64,
 
-        unreachable!("iterator never ends")
-    })
-    .await
-    .context("deadline exceeded")?
-}
+    /// Maximum delay between two attempts in seconds.
+    ///
+    /// Delays requested by the server via `Retry-After` are NOT capped.
+    #[clap(long, default_value_t = 60)]
+    retry_max_delay_secs: u64,
 
-struct Config {
-    multiplier: f64,
-    cap: Duration,
-    base: Duration,
-    deadline: Duration,
-    rng: Box<dyn RngCore + Send + Sync>,
-}
+    /// Maximum number of retries for network errors like failed connections, timeouts, and broken transfers.
+    ///
+    /// Unlimited until the deadline passes if not set.
+    #[clap(long)]
+    retry_network: Option<u32>,
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            multiplier: 3.0,
-            cap: Duration::from_secs(60),
-            base: Duration::from_millis(100),
-            deadline: Duration::from_secs(600),
-            rng: Box::new(StdRng::from_rng(&mut rng())),
-        }
-    }
-}
+    /// Maximum number of retries for `408 Request Timeout` and `429 Too Many Requests` responses.
+    ///
+    /// Other `4xx` responses are never retried. Unlimited until the deadline passes if not set.
+    #[clap(long = "retry-4xx")]
+    retry_client_error: Option<u32>,
 
-struct Sleep {
-    config: Config,
-    sleep: Duration,
-}
+    /// Maximum number of retries for `5xx` responses.
+    ///
+    /// Unlimited until the deadline passes if not set.
+    #[clap(long = "retry-5xx")]
+    retry_server_error: Option<u32>,
 
-impl From<Config> for Sleep {
-    fn from(config: Config) -> Self {
-        let sleep = config.base;
-        Self { config, sleep }
-    }
+    /// Maximum number of retries for transient file system errors, e.g. files that are not visible yet on network
+    /// file systems.
+    ///
+    /// Unlimited until the deadline passes if not set.
+    #[clap(long)]
+    retry_filesystem: Option<u32>,
 }
 
-impl Iterator for Sleep {
-    type Item = Duration;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let sleep = self.sleep;
-        self.sleep = self
-            .config
-            .cap
-            .min(Duration::from_secs_f64(self.config.rng.random_range(
-                self.config.base.as_secs_f64()..(sleep.as_secs_f64() * self.config.multiplier),
-            )));
-        Some(sleep)
+impl From<&RetryCLIConfig> for RetryConfig {
+    fn from(cfg: &RetryCLIConfig) -> Self {
+        Self {
+            deadline: Duration::from_secs(cfg.retry_deadline_secs),
+            max_delay: Duration::from_secs(cfg.retry_max_delay_secs),
+            network: cfg.retry_network,
+            client_error: cfg.retry_client_error,
+            server_error: cfg.retry_server_error,
+            filesystem: cfg.retry_filesystem,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use rand::rngs::mock::StepRng;
-
     use super::*;
 
     #[test]
-    fn test_sleep() {
-        let mut it = Sleep::from(Config {
-            rng: Box::new(StepRng::new(15397336220812216376, u64::MAX / 2 - 1)),
-            cap: Duration::from_secs(2),
-            ..Default::default()
-        });
-
-        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.1);
-        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.266938254);
-        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.334556582);
-        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.854285247);
-        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.924296313);
-        assert_approx_eq(it.next().unwrap().as_secs_f64(), 2.0);
-        assert_approx_eq(it.next().unwrap().as_secs_f64(), 2.0);
-    }
+    fn test_retry_config() {
+        assert_eq!(
+            RetryConfig::from(&RetryCLIConfig::parse_from([""])),
+            RetryConfig::default(),
+        );
 
-    #[track_caller]
-    fn assert_approx_eq(a: f64, b: f64) {
-        assert!((a - b).abs() < 0.000000001, "{a} != {b}",);
+        let cfg = RetryConfig::from(&RetryCLIConfig::parse_from([
+            "",
+            "--retry-filesystem",
+            "1",
+            "--retry-5xx",
+            "0",
+        ]));
+        assert_eq!(cfg.filesystem, Some(1));
+        assert_eq!(cfg.server_error, Some(0));
+        assert_eq!(cfg.network, None);
     }
 }
@@ -0,0 +1,378 @@
+//! Summary of an export run.
+use std::{
+    fmt::Display,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tatutanatata_core::retry::RetryPolicy;
+
+use crate::file_output::write_to_file;
+
+/// Summaries of the exports of this process, see [`Summary::record`].
+static RECORDED: Mutex<Vec<Summary>> = Mutex::new(Vec::new());
+
+/// Counters of an export run, updated by the concurrent downloads.
+#[derive(Debug)]
+pub(crate) struct RunStats {
+    start: Instant,
+
+    /// Mails that were listed and considered for export.
+    pub(crate) listed: AtomicU64,
+
+    /// Mails that were downloaded, including the ones that were only verified.
+    pub(crate) downloaded: AtomicU64,
+
+    /// Mails that were skipped because they exist in the output already.
+    pub(crate) skipped_existing: AtomicU64,
+
+    /// Mails that were skipped as duplicates, see `--dedup`.
+    pub(crate) skipped_duplicate: AtomicU64,
+
+    /// Mails that failed to export, see `--keep-going`.
+    pub(crate) failed: AtomicU64,
+
+    /// Size of the written files before compression and encryption.
+    pub(crate) bytes: AtomicU64,
+
+    /// Mails whose export finished, successfully or not.
+    pub(crate) completed: AtomicU64,
+
+    /// Time of the last written file in seconds since the UNIX epoch, `0` if none was written yet.
+    pub(crate) last_written: AtomicU64,
+
+    /// Mails to export, known once the folder was listed completely, see [`ProgressTracker`].
+    pub(crate) total: OnceLock<u64>,
+}
+
+impl RunStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            listed: AtomicU64::new(0),
+            downloaded: AtomicU64::new(0),
+            skipped_existing: AtomicU64::new(0),
+            skipped_duplicate: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            last_written: AtomicU64::new(0),
+            total: OnceLock::new(),
+        }
+    }
+
+    /// Snapshot of the counters.
+    pub(crate) fn summary(&self) -> Summary {
+        self.summary_after(self.start.elapsed())
+    }
+
+    fn summary_after(&self, elapsed: Duration) -> Summary {
+        let bytes = self.bytes.load(Ordering::SeqCst);
+        let downloaded = self.downloaded.load(Ordering::SeqCst);
+        let elapsed_secs = elapsed.as_secs_f64();
+        let per_sec = |x: u64| {
+            if elapsed_secs > 0.0 {
+                x as f64 / elapsed_secs
+            } else {
+                0.0
+            }
+        };
+
+        Summary {
+            listed: self.listed.load(Ordering::SeqCst),
+            downloaded,
+            skipped_existing: self.skipped_existing.load(Ordering::SeqCst),
+            skipped_duplicate: self.skipped_duplicate.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            bytes,
+            elapsed_secs,
+            bytes_per_sec: per_sec(bytes),
+            mails_per_sec: per_sec(downloaded),
+        }
+    }
+}
+
+/// Summary of an export run, see [`RunStats`].
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Summary {
+    listed: u64,
+    pub(crate) downloaded: u64,
+    skipped_existing: u64,
+    skipped_duplicate: u64,
+    pub(crate) failed: u64,
+    bytes: u64,
+    elapsed_secs: f64,
+    bytes_per_sec: f64,
+    mails_per_sec: f64,
+}
+
+impl Summary {
+    /// Write summary as JSON.
+    pub(crate) async fn write(&self, path: &Path, retry: &RetryPolicy) -> Result<()> {
+        let s = serde_json::to_string_pretty(self).context("serialize summary")?;
+
+        write_to_file(s.as_bytes(), path, None, retry)
+            .await
+            .context("write summary")
+    }
+
+    /// Remember summary for the report at the end of the process, see [`recorded`](Self::recorded).
+    pub(crate) fn record(&self) {
+        RECORDED.lock().expect("not poisoned").push(self.clone());
+    }
+
+    /// Summaries of all exports of this process, e.g. one per mailbox for `--all-mailboxes`.
+    pub(crate) fn recorded() -> Vec<Self> {
+        RECORDED.lock().expect("not poisoned").clone()
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "listed:              {}", self.listed)?;
+        writeln!(f, "downloaded:          {}", self.downloaded)?;
+        writeln!(f, "skipped (existing):  {}", self.skipped_existing)?;
+        writeln!(f, "skipped (duplicate): {}", self.skipped_duplicate)?;
+        writeln!(f, "failed:              {}", self.failed)?;
+        writeln!(
+            f,
+            "written:             {}",
+            format_bytes(self.bytes as f64)
+        )?;
+        writeln!(f, "elapsed:             {:.1}s", self.elapsed_secs)?;
+        write!(
+            f,
+            "throughput:          {}/s, {:.1} mails/s",
+            format_bytes(self.bytes_per_sec),
+            self.mails_per_sec
+        )
+    }
+}
+
+/// Tracks the throughput between progress reports, see [`update`](Self::update).
+#[derive(Debug)]
+pub(crate) struct ProgressTracker {
+    last_at: Instant,
+    last_completed: u64,
+    last_bytes: u64,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(stats: &RunStats) -> Self {
+        Self {
+            last_at: stats.start,
+            last_completed: 0,
+            last_bytes: 0,
+        }
+    }
+
+    /// Progress since the last update.
+    ///
+    /// The throughput only covers the time since the last update, so that the ETA adapts to changing conditions, e.g.
+    /// when the mails that exist already are skipped at the start of a resumed export.
+    pub(crate) fn update(&mut self, stats: &RunStats, total: Option<u64>) -> Progress {
+        self.update_at(stats, total, Instant::now())
+    }
+
+    fn update_at(&mut self, stats: &RunStats, total: Option<u64>, now: Instant) -> Progress {
+        let completed = stats.completed.load(Ordering::SeqCst);
+        let bytes = stats.bytes.load(Ordering::SeqCst);
+        let elapsed_secs = now.saturating_duration_since(self.last_at).as_secs_f64();
+        let per_sec = |x: u64| {
+            if elapsed_secs > 0.0 {
+                x as f64 / elapsed_secs
+            } else {
+                0.0
+            }
+        };
+        let mails_per_sec = per_sec(completed.saturating_sub(self.last_completed));
+        let bytes_per_sec = per_sec(bytes.saturating_sub(self.last_bytes));
+
+        self.last_at = now;
+        self.last_completed = completed;
+        self.last_bytes = bytes;
+
+        let eta = match total {
+            Some(total) if mails_per_sec > 0.0 => Some(Duration::from_secs_f64(
+                total.saturating_sub(completed) as f64 / mails_per_sec,
+            )),
+            _ => None,
+        };
+        Progress {
+            completed,
+            total,
+            mails_per_sec,
+            bytes_per_sec,
+            eta,
+        }
+    }
+}
+
+/// Progress of a running export, see [`ProgressTracker`].
+#[derive(Debug)]
+pub(crate) struct Progress {
+    completed: u64,
+
+    /// Mails to export, if known.
+    total: Option<u64>,
+
+    mails_per_sec: f64,
+    bytes_per_sec: f64,
+
+    /// Estimated time until the export finishes, if the total is known and mails were exported recently.
+    eta: Option<Duration>,
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.total {
+            Some(total) if total > 0 => write!(
+                f,
+                "{}/{} mails ({:.1}%)",
+                self.completed,
+                total,
+                self.completed as f64 * 100.0 / total as f64
+            )?,
+            _ => write!(f, "{} mails", self.completed)?,
+        }
+        write!(
+            f,
+            ", {:.1} mails/s, {}/s",
+            self.mails_per_sec,
+            format_bytes(self.bytes_per_sec)
+        )?;
+        match (self.total, self.eta) {
+            (Some(_), Some(eta)) => write!(f, ", ETA {}", format_duration(eta)),
+            (Some(_), None) => write!(f, ", ETA unknown"),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+/// Format duration with the two most significant units, e.g. `1h 23m`.
+pub(crate) fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (days, hours, mins, secs) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60, secs % 60);
+
+    if days > 0 {
+        format!("{days}d {hours}h")
+    } else if hours > 0 {
+        format!("{hours}h {mins}m")
+    } else if mins > 0 {
+        format!("{mins}m {secs}s")
+    } else {
+        format!("{secs}s")
+    }
+}
+
+/// Format bytes with binary unit prefixes.
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{value:.0} {}", UNITS[unit])
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let stats = RunStats::new();
+        stats.listed.fetch_add(10, Ordering::SeqCst);
+        stats.downloaded.fetch_add(6, Ordering::SeqCst);
+        stats.skipped_existing.fetch_add(3, Ordering::SeqCst);
+        stats.failed.fetch_add(1, Ordering::SeqCst);
+        stats.bytes.fetch_add(3 * 1024 * 1024, Ordering::SeqCst);
+
+        insta::assert_snapshot!(stats.summary_after(Duration::from_secs(4)), @r###"
+        listed:              10
+        downloaded:          6
+        skipped (existing):  3
+        skipped (duplicate): 0
+        failed:              1
+        written:             3.0 MiB
+        elapsed:             4.0s
+        throughput:          768.0 KiB/s, 1.5 mails/s
+        "###);
+        insta::assert_snapshot!(
+            serde_json::to_string(&stats.summary_after(Duration::ZERO)).unwrap(),
+            @r###"
+            {"listed":10,"downloaded":6,"skippedExisting":3,"skippedDuplicate":0,"failed":1,"bytes":3145728,"elapsedSecs":0.0,"bytesPerSec":0.0,"mailsPerSec":0.0}
+            "###
+        );
+    }
+
+    #[test]
+    fn test_progress() {
+        let stats = RunStats::new();
+        let mut tracker = ProgressTracker::new(&stats);
+        let start = stats.start;
+
+        stats.completed.fetch_add(100, Ordering::SeqCst);
+        stats.bytes.fetch_add(10 * 1024 * 1024, Ordering::SeqCst);
+        let progress = tracker.update_at(&stats, Some(60_100), start + Duration::from_secs(10));
+        assert_eq!(
+            progress.to_string(),
+            "100/60100 mails (0.2%), 10.0 mails/s, 1.0 MiB/s, ETA 1h 40m",
+        );
+
+        // throughput only covers the time since the last update
+        stats.completed.fetch_add(10, Ordering::SeqCst);
+        let progress = tracker.update_at(&stats, Some(60_100), start + Duration::from_secs(20));
+        assert_eq!(
+            progress.to_string(),
+            "110/60100 mails (0.2%), 1.0 mails/s, 0 B/s, ETA 16h 39m",
+        );
+
+        let progress = tracker.update_at(&stats, Some(60_100), start + Duration::from_secs(30));
+        assert_eq!(
+            progress.to_string(),
+            "110/60100 mails (0.2%), 0.0 mails/s, 0 B/s, ETA unknown",
+        );
+
+        let progress = tracker.update_at(&stats, None, start + Duration::from_secs(40));
+        assert_eq!(progress.to_string(), "110 mails, 0.0 mails/s, 0 B/s");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_secs(59)), "59s");
+        assert_eq!(format_duration(Duration::from_secs(61)), "1m 1s");
+        assert_eq!(
+            format_duration(Duration::from_secs(2 * 3_600 + 5 * 60)),
+            "2h 5m"
+        );
+        assert_eq!(
+            format_duration(Duration::from_secs(3 * 86_400 + 3_600)),
+            "3d 1h"
+        );
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0.0), "0 B");
+        assert_eq!(format_bytes(1023.0), "1023 B");
+        assert_eq!(format_bytes(1536.0), "1.5 KiB");
+        assert_eq!(format_bytes(5.0 * 1024.0 * 1024.0 * 1024.0), "5.0 GiB");
+    }
+}
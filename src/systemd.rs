@@ -0,0 +1,277 @@
+//! Integration with systemd services of `Type=notify`, see [`SystemdService`].
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+use crate::summary::RunStats;
+
+/// Interval of status updates if the watchdog is disabled.
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Socket of the service manager, see `sd_notify(3)`.
+#[derive(Debug)]
+struct Notifier {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+
+    #[cfg(unix)]
+    addr: std::os::unix::net::SocketAddr,
+}
+
+impl Notifier {
+    /// Connect to the socket given by `$NOTIFY_SOCKET`, if any.
+    #[cfg(unix)]
+    fn from_env() -> Result<Option<Self>> {
+        match std::env::var_os("NOTIFY_SOCKET") {
+            Some(path) if !path.is_empty() => Self::connect(&path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn from_env() -> Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// Connect to the socket at the given path, which starts with `@` for abstract sockets.
+    #[cfg(unix)]
+    fn connect(path: &std::ffi::OsStr) -> Result<Self> {
+        use std::os::unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        };
+
+        let addr = match path.as_bytes().strip_prefix(b"@") {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name)
+            }
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            Some(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "abstract sockets are only supported on Linux",
+            )),
+            None => SocketAddr::from_pathname(path),
+        }
+        .with_context(|| format!("notify socket address: `{}`", path.to_string_lossy()))?;
+
+        let socket = UnixDatagram::unbound().context("create notify socket")?;
+        // never block the runtime, messages are only informational
+        socket
+            .set_nonblocking(true)
+            .context("set notify socket to non-blocking")?;
+
+        Ok(Self { socket, addr })
+    }
+
+    /// Send the given newline-separated assignments like `READY=1`.
+    ///
+    /// Errors are only logged, since the service manager may be restarting.
+    fn notify(&self, state: &str) {
+        #[cfg(unix)]
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            debug!(%e, "cannot notify service manager");
+        }
+
+        #[cfg(not(unix))]
+        let _ = state;
+    }
+}
+
+/// Status of a `watch` run, as shown by `systemctl status`.
+#[derive(Debug)]
+struct Status {
+    folder: String,
+    stats: Arc<RunStats>,
+
+    /// Whether the catch-up finished and new mails are awaited.
+    waiting: AtomicBool,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} folder `{}`: {} mails downloaded, {} failed",
+            if self.waiting.load(Ordering::SeqCst) {
+                "waiting for new mails in"
+            } else {
+                "catching up with"
+            },
+            self.folder,
+            self.stats.downloaded.load(Ordering::SeqCst),
+            self.stats.failed.load(Ordering::SeqCst),
+        )
+    }
+}
+
+/// Notifications of a systemd service of `Type=notify`.
+///
+/// Sends `READY=1` once the catch-up finished, a status string, and keep-alive pings if `WatchdogSec=` is configured.
+/// The pings are sent by a background task, so they only detect a stalled process, NOT an export that waits forever.
+/// The task is stopped on drop.
+#[derive(Debug)]
+pub(crate) struct SystemdService {
+    notifier: Arc<Notifier>,
+    status: Arc<Status>,
+    task: JoinHandle<()>,
+}
+
+impl SystemdService {
+    /// Start notifications if the process runs as a systemd service, i.e. if `$NOTIFY_SOCKET` is set.
+    pub(crate) fn start(folder: &str, stats: Arc<RunStats>) -> Result<Option<Self>> {
+        let Some(notifier) = Notifier::from_env()? else {
+            return Ok(None);
+        };
+        let notifier = Arc::new(notifier);
+        let status = Arc::new(Status {
+            folder: folder.to_owned(),
+            stats,
+            waiting: AtomicBool::new(false),
+        });
+
+        let watchdog = watchdog_interval(
+            std::env::var("WATCHDOG_USEC").ok().as_deref(),
+            std::env::var("WATCHDOG_PID").ok().as_deref(),
+        )?;
+        info!(watchdog = ?watchdog, "notify service manager");
+
+        notifier.notify(&format!("STATUS={status}"));
+        let task = tokio::spawn({
+            let notifier = Arc::clone(&notifier);
+            let status = Arc::clone(&status);
+            async move {
+                // ping twice per watchdog interval as recommended by `sd_watchdog_enabled(3)`
+                let interval = watchdog.map(|d| d / 2).unwrap_or(STATUS_INTERVAL);
+                loop {
+                    tokio::time::sleep(interval).await;
+                    if watchdog.is_some() {
+                        notifier.notify(&format!("WATCHDOG=1\nSTATUS={status}"));
+                    } else {
+                        notifier.notify(&format!("STATUS={status}"));
+                    }
+                }
+            }
+        });
+
+        Ok(Some(Self {
+            notifier,
+            status,
+            task,
+        }))
+    }
+
+    /// Signal that the catch-up finished and new mails are awaited.
+    ///
+    /// This is repeated after reconnects, which systemd ignores.
+    pub(crate) fn ready(&self) {
+        self.status.waiting.store(true, Ordering::SeqCst);
+        self.notifier
+            .notify(&format!("READY=1\nSTATUS={}", self.status));
+    }
+
+    /// Signal that a catch-up started, e.g. after a reconnect.
+    pub(crate) fn catching_up(&self) {
+        self.status.waiting.store(false, Ordering::SeqCst);
+        self.update();
+    }
+
+    /// Refresh the status, e.g. after a mail was exported.
+    pub(crate) fn update(&self) {
+        self.notifier.notify(&format!("STATUS={}", self.status));
+    }
+}
+
+impl Drop for SystemdService {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.notifier.notify("STOPPING=1");
+    }
+}
+
+/// Watchdog interval given by `$WATCHDOG_USEC`, if it applies to this process, see `sd_watchdog_enabled(3)`.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>) -> Result<Option<Duration>> {
+    let Some(usec) = usec else {
+        return Ok(None);
+    };
+    if let Some(pid) = pid {
+        let pid = pid
+            .parse::<u32>()
+            .with_context(|| format!("invalid `WATCHDOG_PID`: `{pid}`"))?;
+        if pid != std::process::id() {
+            debug!(pid, "watchdog is meant for another process");
+            return Ok(None);
+        }
+    }
+
+    let usec = usec
+        .parse::<u64>()
+        .with_context(|| format!("invalid `WATCHDOG_USEC`: `{usec}`"))?;
+    Ok((usec > 0).then(|| Duration::from_micros(usec)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(watchdog_interval(None, None).unwrap(), None);
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None).unwrap(),
+            Some(Duration::from_secs(30)),
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some(&std::process::id().to_string())).unwrap(),
+            Some(Duration::from_secs(30)),
+        );
+        assert_eq!(
+            watchdog_interval(
+                Some("30000000"),
+                Some(&(std::process::id() + 1).to_string())
+            )
+            .unwrap(),
+            None,
+        );
+        assert_eq!(watchdog_interval(Some("0"), None).unwrap(), None);
+        assert_eq!(
+            watchdog_interval(Some("30s"), None)
+                .unwrap_err()
+                .to_string(),
+            "invalid `WATCHDOG_USEC`: `30s`",
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notify() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("notify.sock");
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        let notifier = Notifier::connect(path.as_os_str()).unwrap();
+        let status = Status {
+            folder: "Inbox".to_owned(),
+            stats: Arc::new(RunStats::new()),
+            waiting: AtomicBool::new(true),
+        };
+        notifier.notify(&format!("READY=1\nSTATUS={status}"));
+
+        let mut buf = [0; 1024];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "READY=1\nSTATUS=waiting for new mails in folder `Inbox`: 0 mails downloaded, 0 failed",
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Stream exported files as a tar archive.
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use tar::{Builder, EntryType, Header};
+use tatutanatata_core::spool::Spool;
+
+/// Archive format for streamed exports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum ArchiveFormat {
+    /// POSIX tar archive.
+    Tar,
+}
+
+struct State {
+    builder: Builder<Box<dyn Write + Send>>,
+    names: HashSet<String>,
+}
+
+impl std::fmt::Debug for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("names", &self.names)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Tar archive that is streamed to a writer, usually stdout.
+///
+/// Entries are written one after another, so the archive can be consumed while the export is still running. An
+/// aborted export results in a truncated archive.
+#[derive(Debug)]
+pub(crate) struct TarOutput {
+    /// Modification time of all entries in seconds since the UNIX epoch.
+    mtime: u64,
+
+    state: Mutex<State>,
+}
+
+impl TarOutput {
+    pub(crate) fn new(w: Box<dyn Write + Send>) -> Self {
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            mtime,
+            state: Mutex::new(State {
+                builder: Builder::new(w),
+                names: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Check if the archive contains a file with the given name.
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.state
+            .lock()
+            .expect("not poisoned")
+            .names
+            .contains(name)
+    }
+
+    /// Add file to archive.
+    ///
+    /// Returns `false` if the archive already contains a file with the given name. The archive is NOT modified in
+    /// this case.
+    pub(crate) fn add(&self, name: &str, mut data: impl Read) -> Result<bool> {
+        let mut state = self.state.lock().expect("not poisoned");
+        if state.names.contains(name) {
+            return Ok(false);
+        }
+
+        tokio::task::block_in_place(|| {
+            // tar headers contain the size, so the data is buffered first
+            let mut spool = Spool::new();
+            std::io::copy(&mut data, &mut spool).context("buffer archive entry")?;
+
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(spool.len());
+            header.set_mode(0o644);
+            header.set_mtime(self.mtime);
+            state
+                .builder
+                .append_data(&mut header, name, spool.reader()?)
+                .context("write archive entry")?;
+            state.builder.get_mut().flush().context("flush archive")
+        })
+        .with_context(|| format!("add to archive: `{name}`"))?;
+        state.names.insert(name.to_owned());
+
+        Ok(true)
+    }
+
+    /// Write the end-of-archive marker.
+    pub(crate) fn finish(self) -> Result<()> {
+        let state = self.state.into_inner().expect("not poisoned");
+        let mut w = state.builder.into_inner().context("finish archive")?;
+        w.flush().context("flush archive")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// Writer that can be inspected after it was moved into the archive.
+    #[derive(Debug, Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_write() {
+        let buf = SharedBuf::default();
+
+        let out = TarOutput::new(Box::new(buf.clone()));
+        assert!(out.add("a.eml", b"foo".as_slice()).unwrap());
+        assert!(out.add("2024/01/b.eml", b"bar".as_slice()).unwrap());
+        assert!(!out.add("a.eml", b"baz".as_slice()).unwrap());
+        assert!(out.contains("a.eml"));
+        assert!(!out.contains("c.eml"));
+        out.finish().unwrap();
+
+        let data = buf.0.lock().unwrap().clone();
+        let mut archive = tar::Archive::new(data.as_slice());
+        let entries = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let name = entry.path().unwrap().display().to_string();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (name, content)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                ("a.eml".to_owned(), "foo".to_owned()),
+                ("2024/01/b.eml".to_owned(), "bar".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! [RFC 3161] timestamps of the manifest, see `--timestamp-url`.
+//!
+//! [RFC 3161]: https://www.rfc-editor.org/rfc/rfc3161
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use cmpv2::status::PkiStatus;
+use cms::{cert::x509::spki::AlgorithmIdentifier, signed_data::SignedData};
+use der::{
+    asn1::{GeneralizedTime, Int, OctetString, Uint},
+    oid::ObjectIdentifier,
+    Any, Decode, Encode,
+};
+use reqwest::{header::CONTENT_TYPE, Url};
+use sha2::{Digest, Sha256};
+use x509_tsp::{MessageImprint, TimeStampReq, TimeStampResp, TspVersion, TstInfo};
+
+/// File name of the timestamp, next to the [manifest](crate::manifest::MANIFEST_FILE).
+pub(crate) const TIMESTAMP_FILE: &str = "manifest.json.tsr";
+
+/// Timeout of the TSA request, so that an unreachable TSA does not block the process.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Object identifier of SHA-256, see RFC 5754.
+const ID_SHA_256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+
+/// Request a timestamp for the given data from the time-stamping authority (TSA) at `url`.
+///
+/// Returns the DER-encoded response, which is what `openssl ts -verify -in` expects, along with the time that the TSA
+/// attested. The response is checked to match the request, but the signature is NOT verified, since this requires
+/// the certificate of the TSA.
+pub(crate) async fn timestamp(url: &Url, data: &[u8]) -> Result<(Vec<u8>, GeneralizedTime)> {
+    let imprint = Sha256::digest(data);
+    let nonce = Int::from(Uint::new(&rand::random::<[u8; 8]>()).context("create nonce")?);
+    let req = request(&imprint, &nonce)?;
+
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .context("set up TSA client")?;
+    let resp = client
+        .post(url.clone())
+        .header(CONTENT_TYPE, "application/timestamp-query")
+        .body(req)
+        .send()
+        .await
+        .context("request timestamp")?
+        .error_for_status()
+        .context("request timestamp")?
+        .bytes()
+        .await
+        .context("read timestamp response")?;
+
+    let time = check_response(&resp, &imprint, &nonce)?;
+    Ok((resp.to_vec(), time))
+}
+
+/// Encode `TimeStampReq` for the given SHA-256 hash.
+fn request(imprint: &[u8], nonce: &Int) -> Result<Vec<u8>> {
+    TimeStampReq {
+        version: TspVersion::V1,
+        message_imprint: message_imprint(imprint)?,
+        req_policy: None,
+        nonce: Some(nonce.clone()),
+        // the token shall contain the TSA certificate, so that it can be verified without further files
+        cert_req: true,
+        extensions: None,
+    }
+    .to_der()
+    .context("encode timestamp request")
+}
+
+fn message_imprint(imprint: &[u8]) -> Result<MessageImprint> {
+    Ok(MessageImprint {
+        hash_algorithm: AlgorithmIdentifier {
+            oid: ID_SHA_256,
+            parameters: Some(Any::null()),
+        },
+        hashed_message: OctetString::new(imprint).context("encode hash")?,
+    })
+}
+
+/// Decode `TimeStampResp` and check that the token belongs to the request.
+///
+/// Returns the time that the TSA attested.
+fn check_response(resp: &[u8], imprint: &[u8], nonce: &Int) -> Result<GeneralizedTime> {
+    let resp = TimeStampResp::from_der(resp).context("decode timestamp response")?;
+    ensure!(
+        matches!(
+            resp.status.status,
+            PkiStatus::Accepted | PkiStatus::GrantedWithMods
+        ),
+        "TSA rejected timestamp request: {:?} {:?}",
+        resp.status.status,
+        resp.status.fail_info,
+    );
+
+    let token = resp
+        .time_stamp_token
+        .context("timestamp response contains no token")?;
+    let signed_data = SignedData::from_der(&token.content.to_der().context("encode token")?)
+        .context("decode token")?;
+    let tst_info = signed_data
+        .encap_content_info
+        .econtent
+        .context("token contains no timestamp info")?;
+    let tst_info = TstInfo::from_der(tst_info.value()).context("decode timestamp info")?;
+
+    ensure!(
+        tst_info.message_imprint == message_imprint(imprint)?,
+        "timestamp is for different data"
+    );
+    ensure!(
+        tst_info.nonce.as_ref() == Some(nonce),
+        "timestamp does not match the request nonce"
+    );
+    Ok(tst_info.gen_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `TimeStampResp` for SHA-256 of `abc`, created via `openssl ts -reply`.
+    ///
+    /// Taken from the tests of the `x509-tsp` crate.
+    const RESPONSE: &str = "3082028430030201003082027B06092A864886F70D010702A082026C30820268020103310F300D060960864801650304020105003081C9060B2A864886F70D0109100104A081B90481B63081B302010106042A0304013031300D060960864801650304020105000420BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD020104180F32303233303630373131323632365A300A020101800201F48101640101FF0208314CFCE4E0651827A048A4463044310B30090603550406130255533113301106035504080C0A536F6D652D5374617465310D300B060355040A0C04546573743111300F06035504030C0854657374205453413182018430820180020101305C3044310B30090603550406130255533113301106035504080C0A536F6D652D5374617465310D300B060355040A0C04546573743111300F06035504030C08546573742054534102146A0DCC59137C11D1C2B092042B4BC51C0D634D24300D06096086480165030402010500A08198301A06092A864886F70D010903310D060B2A864886F70D0109100104301C06092A864886F70D010905310F170D3233303630373131323632365A302B060B2A864886F70D010910020C311C301A3018301604142F36B1B52456F5AC3A1CA09794AE3D0D64AD38C2302F06092A864886F70D01090431220420BAF4CCF82E9B5B3956EADCC87346B407684F26D82B68D0E7DE0D31EA79AF648C300A06082A8648CE3D0403020467306502305A6E1C175B20A93FAB25D14CC5F5A2836D726D6D4A964B66FFBFFCE46276A96475F1408728B3385DCA37C2BA46BE17E1023100C46B7F08D03409A8ECCFD7637765412C3C5EC050E0D39CF48F0F5015950342CB18D8434FF331BA4463C086297C37D07B";
+
+    const NONCE: &str = "314CFCE4E0651827";
+
+    #[test]
+    fn test_request() {
+        // same as `openssl ts -query -sha256 -cert` for `abc`, with the nonce of the response above
+        let req = request(&Sha256::digest(b"abc"), &nonce(NONCE)).unwrap();
+        assert_eq!(
+            hex(&req),
+            "30430201013031300D060960864801650304020105000420BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD0208314CFCE4E06518270101FF",
+        );
+    }
+
+    #[test]
+    fn test_check_response() {
+        let resp = unhex(RESPONSE);
+        let imprint = Sha256::digest(b"abc");
+
+        let time = check_response(&resp, &imprint, &nonce(NONCE)).unwrap();
+        assert_eq!(time.to_date_time().to_string(), "2023-06-07T11:26:26Z");
+
+        let err = check_response(&resp, &Sha256::digest(b"abd"), &nonce(NONCE)).unwrap_err();
+        assert_eq!(err.to_string(), "timestamp is for different data");
+
+        let err = check_response(&resp, &imprint, &nonce("01")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "timestamp does not match the request nonce"
+        );
+
+        // status `rejection` without token
+        let err = check_response(&unhex("30053003020102"), &imprint, &nonce(NONCE)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "TSA rejected timestamp request: Rejection None"
+        );
+    }
+
+    fn nonce(s: &str) -> Int {
+        Int::new(&unhex(s)).unwrap()
+    }
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{b:02X}")).collect()
+    }
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+}
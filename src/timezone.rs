@@ -0,0 +1,94 @@
+//! Time zone handling for user-facing timestamps.
+use std::str::FromStr;
+
+use chrono::{DateTime, Local, Utc};
+
+/// Time zone used to render timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Timezone {
+    /// Coordinated Universal Time.
+    Utc,
+
+    /// Local time zone of the system.
+    Local,
+
+    /// Named time zone from the IANA database, e.g. `Europe/Berlin`.
+    Named(chrono_tz::Tz),
+}
+
+impl Timezone {
+    /// Format timestamp in this time zone.
+    ///
+    /// See [`chrono::format::strftime`] for the format syntax.
+    pub(crate) fn format(&self, dt: DateTime<Utc>, fmt: &str) -> String {
+        match self {
+            Self::Utc => dt.format(fmt).to_string(),
+            Self::Local => dt.with_timezone(&Local).format(fmt).to_string(),
+            Self::Named(tz) => dt.with_timezone(tz).format(fmt).to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Timezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Utc => write!(f, "utc"),
+            Self::Local => write!(f, "local"),
+            Self::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("utc") {
+            Ok(Self::Utc)
+        } else if s.eq_ignore_ascii_case("local") {
+            Ok(Self::Local)
+        } else {
+            chrono_tz::Tz::from_str(s)
+                .map(Self::Named)
+                .map_err(|_| format!("unknown time zone: `{s}`"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Timezone::from_str("utc").unwrap(), Timezone::Utc);
+        assert_eq!(Timezone::from_str("UTC").unwrap(), Timezone::Utc);
+        assert_eq!(Timezone::from_str("local").unwrap(), Timezone::Local);
+        assert_eq!(
+            Timezone::from_str("Europe/Berlin").unwrap(),
+            Timezone::Named(chrono_tz::Europe::Berlin),
+        );
+        assert_eq!(
+            Timezone::from_str("Mars/Olympus").unwrap_err(),
+            "unknown time zone: `Mars/Olympus`",
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let dt = DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+            .unwrap()
+            .to_utc();
+        let fmt = "%Y-%m-%d-%Hh%Mm%Ss";
+
+        assert_eq!(Timezone::Utc.format(dt, fmt), "2020-03-04-11h22m33s");
+        assert_eq!(
+            Timezone::Named(chrono_tz::Europe::Berlin).format(dt, fmt),
+            "2020-03-04-12h22m33s",
+        );
+        assert_eq!(
+            Timezone::Named(chrono_tz::America::New_York).format(dt, fmt),
+            "2020-03-04-06h22m33s",
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Verification of existing files, see `--verify`.
+use std::{io::Read, path::Path};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use tatutanatata_core::{eml::find_boundaries, spool::Spool};
+use tracing::{debug, warn};
+
+use crate::file_output::FileCompression;
+
+/// Number of bytes at the start of an existing EML file that are searched for MIME boundaries.
+const BOUNDARY_SCAN_BYTES: u64 = 1024 * 1024;
+
+/// Read MIME boundaries of an existing EML file.
+///
+/// Only the start of the file is read. Unreadable files yield no boundaries, so they fail verification later.
+pub(crate) fn existing_boundaries(
+    path: &Path,
+    compression: Option<FileCompression>,
+) -> Vec<String> {
+    let read = || -> Result<String> {
+        let file = std::fs::File::open(path).context("open file")?;
+        let reader = match compression {
+            Some(compression) => compression.decompress(file)?,
+            None => Box::new(file),
+        };
+        let mut buf = vec![];
+        reader
+            .take(BOUNDARY_SCAN_BYTES)
+            .read_to_end(&mut buf)
+            .context("read file")?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    };
+
+    match tokio::task::block_in_place(read) {
+        Ok(eml) => find_boundaries(&eml),
+        Err(e) => {
+            debug!(path = %path.display(), %e, "cannot read boundaries");
+            vec![]
+        }
+    }
+}
+
+/// Compare existing file with the expected content.
+///
+/// Returns `false` and logs a warning if the file differs.
+pub(crate) fn verify_file(
+    expected: &Spool,
+    path: &Path,
+    compression: Option<FileCompression>,
+) -> Result<bool> {
+    tokio::task::block_in_place(|| {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("read existing file: `{}`", path.display()))?;
+        let mut actual_hash = Sha256::new();
+        let actual_len = match compression {
+            Some(compression) => {
+                match compression
+                    .decompress(file)
+                    .and_then(|mut r| Ok(std::io::copy(&mut r, &mut actual_hash)?))
+                {
+                    Ok(len) => len,
+                    Err(e) => {
+                        warn!(path = %path.display(), %e, "verification failed: cannot decompress");
+                        return Ok(false);
+                    }
+                }
+            }
+            None => std::io::copy(&mut &file, &mut actual_hash)
+                .with_context(|| format!("read existing file: `{}`", path.display()))?,
+        };
+        let actual_hash = actual_hash.finalize();
+
+        if actual_hash.as_slice() == expected.sha256() {
+            debug!(path = %path.display(), "verified");
+            return Ok(true);
+        }
+
+        let truncated = actual_len < expected.len() && {
+            let mut prefix_hash = Sha256::new();
+            std::io::copy(
+                &mut Read::take(expected.reader()?, actual_len),
+                &mut prefix_hash,
+            )
+            .context("hash expected data")?;
+            prefix_hash.finalize() == actual_hash
+        };
+        let problem = if truncated {
+            "truncated"
+        } else {
+            "content differs"
+        };
+        warn!(
+            path = %path.display(),
+            actual_len,
+            expected_len = expected.len(),
+            "verification failed: {problem}",
+        );
+
+        Ok(false)
+    })
+}
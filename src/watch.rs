@@ -0,0 +1,217 @@
+//! Continuous export of new mails, see [`run`].
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use anyhow::{ensure, Context, Result};
+use futures::{future::ready, TryStreamExt};
+use tatutanatata_core::{
+    client::Client, failure::Failure, folders::Folder, mails::Mail, proto::enums::OperationType,
+    session::Session,
+};
+use tracing::{info, warn};
+
+use crate::{
+    export::{
+        download_folder, export_listing, export_mail, find_collisions, prepare_output, queue_mails,
+    },
+    export_state::ExportState,
+    metrics::MetricsServer,
+    systemd::SystemdService,
+    zip_output::is_zip_path,
+    DownloadCLIConfig,
+};
+
+/// Delay before reconnecting to the event channel in watch mode.
+const WATCH_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Set up the export and run [`watch_events`] until a permanent failure occurs.
+///
+/// Other failures reconnect after [`WATCH_RECONNECT_DELAY`].
+pub(crate) async fn run(
+    client: &Client,
+    session: &Session,
+    group: Option<&str>,
+    cfg: DownloadCLIConfig,
+) -> Result<()> {
+    ensure!(
+        !is_zip_path(&cfg.path),
+        "`watch` cannot write into ZIP archives"
+    );
+    ensure!(
+        cfg.archive.is_none(),
+        "`watch` does not support `--archive`"
+    );
+    ensure!(
+        cfg.mail_id.is_empty(),
+        "`watch` does not support `--mail-id`"
+    );
+    ensure!(
+        !cfg.all_mailboxes,
+        "`watch` does not support `--all-mailboxes`"
+    );
+    ensure!(
+        cfg.timestamp_url.is_none(),
+        "`watch` does not support `--timestamp-url`, its manifest is never final"
+    );
+    let target = cfg.path.clone();
+    prepare_output(&cfg).await?;
+    let folder = Folder::find(client, session, group, &cfg.folder).await?;
+    let state = ExportState::try_new(&cfg, &folder, &target, client.retry_policy().clone()).await?;
+    let client = &client
+        .clone()
+        .with_concurrency_limit(Arc::clone(&state.concurrency_limit));
+    let _metrics_server = match cfg.metrics_addr {
+        Some(addr) => Some(
+            MetricsServer::start(
+                addr,
+                Arc::clone(&state.stats),
+                Arc::clone(&state.concurrency_limit),
+                state.retry.clone(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let systemd = SystemdService::start(&cfg.folder, Arc::clone(&state.stats))?;
+
+    loop {
+        match watch_events(client, session, &cfg, &folder, &state, systemd.as_ref()).await {
+            Ok(()) => warn!("event channel closed, reconnecting"),
+            Err(e) if Failure::of(&e).is_some_and(Failure::is_permanent) => return Err(e),
+            Err(e) => warn!(error = format!("{e:#}"), "watch failed, reconnecting"),
+        }
+        tokio::time::sleep(WATCH_RECONNECT_DELAY).await;
+    }
+}
+
+/// Catch up with the given folder and export new mails as they arrive, see [`Command::Watch`](crate::Command::Watch).
+///
+/// Returns when the event channel is closed. The caller reconnects after errors, since the catch-up also exports the
+/// mails that failed before.
+async fn watch_events(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    folder: &Folder,
+    state: &ExportState,
+    systemd: Option<&SystemdService>,
+) -> Result<()> {
+    if let Some(systemd) = systemd {
+        systemd.catching_up();
+    }
+
+    // subscribe BEFORE the catch-up so we do not miss mails that arrive in between
+    let updates = tatutanatata_core::events::entity_updates(client, session)
+        .await
+        .context("subscribe to events")?;
+
+    let mut resumable = catch_up(client, session, cfg, folder, state)
+        .await
+        .context("catch up")?;
+    state.write_manifest().await?;
+    state.write_notmuch_dump().await?;
+    state.write_failure_report().await?;
+    info!(folder = cfg.folder.as_str(), "waiting for new mails");
+    if let Some(systemd) = systemd {
+        systemd.ready();
+    }
+
+    let mut updates = std::pin::pin!(updates);
+    while let Some(update) = updates.try_next().await.context("event channel")? {
+        // Updates are relevant as well, since new mails may only become decodable after the official app processed
+        // them.
+        if update.application != "tutanota"
+            || update.instance_list_id != folder.mails
+            || update.operation == OperationType::Delete
+        {
+            continue;
+        }
+
+        match Mail::get(client, session, folder, &update.instance_id).await? {
+            Some(mail) => {
+                let failed = state.stats.failed.load(Ordering::SeqCst);
+                let mails =
+                    find_collisions(client, session, cfg, folder, vec![Arc::clone(&mail)], state)
+                        .await?;
+                for queued in queue_mails(client, session, cfg, mails, state).await? {
+                    export_mail(client, session, cfg, queued, state).await?;
+                }
+                // a failed mail must be listed again by the next catch-up
+                resumable &= state.stats.failed.load(Ordering::SeqCst) == failed;
+                if let (true, Some(manifest)) = (resumable, &state.manifest) {
+                    manifest.set_watch_progress(&folder.id, &mail.mail_id);
+                }
+                state.write_manifest().await?;
+                state.write_notmuch_dump().await?;
+                state.write_failure_report().await?;
+                if let Some(systemd) = systemd {
+                    systemd.update();
+                }
+            }
+            None => {
+                resumable = false;
+                warn!(
+                    mail_id = update.instance_id.as_str(),
+                    "cannot decode new mail yet, view it in the official app",
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Export the mails of the given folder that `watch` did not export before.
+///
+/// With a manifest, only the mails after the [recorded progress](crate::manifest::Manifest::watch_progress) are
+/// listed, and the progress is moved to the last listed mail afterwards. It stops before mails that could not be
+/// decoded yet and is kept if a mail failed, so that these are listed again.
+///
+/// Returns whether the progress covers all mails of the folder, i.e. whether new mails may move it further.
+async fn catch_up(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    folder: &Folder,
+    state: &ExportState,
+) -> Result<bool> {
+    let Some(manifest) = &state.manifest else {
+        download_folder(client, session, cfg, folder, state).await?;
+        return Ok(false);
+    };
+
+    let after = manifest.watch_progress(&folder.id);
+    if let Some(after) = &after {
+        info!(mail_id = after.as_str(), "resume after last exported mail");
+    }
+    let failed = state.stats.failed.load(Ordering::SeqCst);
+    let mut last = None;
+    let mut complete = true;
+    let mails = Mail::list_resumable(
+        client,
+        session,
+        folder,
+        cfg.ignore_new_mails,
+        cfg.since,
+        after.as_deref(),
+    )
+    .try_filter_map(|(mail_id, mail)| {
+        match &mail {
+            Some(_) if complete => last = Some(mail_id),
+            Some(_) => {}
+            None => complete = false,
+        }
+        ready(Ok(mail))
+    });
+    export_listing(client, session, cfg, mails, state).await?;
+
+    if state.stats.failed.load(Ordering::SeqCst) != failed {
+        return Ok(false);
+    }
+    if let Some(last) = last {
+        manifest.set_watch_progress(&folder.id, &last);
+    }
+    Ok(complete)
+}
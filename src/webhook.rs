@@ -0,0 +1,124 @@
+//! Report of the finished run that is posted to a webhook, see [`RunReport`].
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::Url;
+use serde::Serialize;
+use tracing::{debug, warn};
+
+use crate::{
+    exit_code::exit_code_of,
+    summary::{format_duration, Summary},
+};
+
+/// Timeout of the webhook request, so that an unreachable endpoint does not block the process.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Report of the finished run, see `--notify-url`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RunReport {
+    /// Human-readable summary, e.g. for Slack.
+    text: String,
+
+    command: String,
+    success: bool,
+
+    /// Error chain of a failed run.
+    error: Option<String>,
+
+    exit_code: u8,
+    duration_secs: f64,
+
+    /// Summaries of the exports of this run, e.g. one per mailbox for `--all-mailboxes`.
+    exports: Vec<Summary>,
+}
+
+impl RunReport {
+    pub(crate) fn new(
+        command: &str,
+        res: &Result<()>,
+        duration: Duration,
+        exports: Vec<Summary>,
+    ) -> Self {
+        let mut text = format!(
+            "tatutanatata {command} {} after {}",
+            if res.is_ok() { "succeeded" } else { "failed" },
+            format_duration(duration),
+        );
+        if !exports.is_empty() {
+            let downloaded = exports.iter().map(|s| s.downloaded).sum::<u64>();
+            let failed = exports.iter().map(|s| s.failed).sum::<u64>();
+            text.push_str(&format!(": {downloaded} mails downloaded, {failed} failed"));
+        }
+        let error = res.as_ref().err().map(|e| format!("{e:#}"));
+        if let Some(error) = &error {
+            text.push_str(&format!("\n{error}"));
+        }
+
+        Self {
+            text,
+            command: command.to_owned(),
+            success: res.is_ok(),
+            error,
+            exit_code: res.as_ref().err().map(exit_code_of).unwrap_or_default(),
+            duration_secs: duration.as_secs_f64(),
+            exports,
+        }
+    }
+
+    /// Post the report as JSON to the given URL.
+    ///
+    /// Failures are only logged, since they must NOT change the outcome of the run.
+    pub(crate) async fn send(&self, url: &Url) {
+        match self.try_send(url).await {
+            Ok(()) => debug!("sent run report"),
+            Err(e) => warn!(error = format!("{e:#}"), "cannot send run report"),
+        }
+    }
+
+    async fn try_send(&self, url: &Url) -> Result<()> {
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .context("set up webhook client")?;
+
+        // the URL usually contains a secret token, so it is NOT logged
+        client
+            .post(url.clone())
+            .json(self)
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)
+            .context("post run report")?
+            .error_for_status()
+            .map_err(reqwest::Error::without_url)
+            .context("post run report")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use tatutanatata_core::failure::Failure;
+
+    use super::*;
+
+    #[test]
+    fn test_run_report() {
+        let report = RunReport::new("prune", &Ok(()), Duration::from_secs(83), vec![]);
+        insta::assert_snapshot!(serde_json::to_string(&report).unwrap(), @r###"
+        {"text":"tatutanatata prune succeeded after 1m 23s","command":"prune","success":true,"error":null,"exitCode":0,"durationSecs":83.0,"exports":[]}
+        "###);
+
+        let res = Err(anyhow!("boom")
+            .context(Failure::Network)
+            .context("perform login"));
+        let report = RunReport::new("download", &res, Duration::from_secs(2), vec![]);
+        insta::assert_snapshot!(serde_json::to_string(&report).unwrap(), @r###"
+        {"text":"tatutanatata download failed after 2s\nperform login: network failure: boom","command":"download","success":false,"error":"perform login: network failure: boom","exitCode":6,"durationSecs":2.0,"exports":[]}
+        "###);
+    }
+}
@@ -0,0 +1,143 @@
+//! Write exported files into a single ZIP archive.
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+/// Check if the output path refers to a ZIP archive.
+pub(crate) fn is_zip_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+#[derive(Debug)]
+struct State {
+    writer: ZipWriter<File>,
+    names: HashSet<String>,
+}
+
+/// ZIP archive that is written incrementally.
+///
+/// The archive is written to a temporary file that is only moved to the final location by [`finish`](Self::finish),
+/// so an aborted export does not leave a seemingly complete archive behind.
+#[derive(Debug)]
+pub(crate) struct ZipOutput {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl ZipOutput {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let tmp_path = path.with_extension("zip.part");
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("create archive: `{}`", tmp_path.display()))?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            tmp_path,
+            state: Mutex::new(State {
+                writer: ZipWriter::new(file),
+                names: HashSet::new(),
+            }),
+        })
+    }
+
+    /// Final location of the archive.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check if the archive contains a file with the given name.
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.state
+            .lock()
+            .expect("not poisoned")
+            .names
+            .contains(name)
+    }
+
+    /// Add file to archive.
+    ///
+    /// Returns `false` if the archive already contains a file with the given name. The archive is NOT modified in
+    /// this case.
+    pub(crate) fn add(&self, name: &str, mut data: impl Read) -> Result<bool> {
+        let mut state = self.state.lock().expect("not poisoned");
+        if state.names.contains(name) {
+            return Ok(false);
+        }
+
+        tokio::task::block_in_place(|| {
+            state
+                .writer
+                .start_file(
+                    name,
+                    SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+                )
+                .context("start archive entry")?;
+            std::io::copy(&mut data, &mut state.writer).context("write archive entry")
+        })
+        .with_context(|| format!("add to archive: `{name}`"))?;
+        state.names.insert(name.to_owned());
+
+        Ok(true)
+    }
+
+    /// Finish archive and move it to its final location.
+    pub(crate) fn finish(self) -> Result<()> {
+        let state = self.state.into_inner().expect("not poisoned");
+        let file = state.writer.finish().context("finish archive")?;
+        file.sync_all().context("sync archive")?;
+        drop(file);
+
+        std::fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("move archive to `{}`", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_zip_path() {
+        assert!(is_zip_path(Path::new("out.zip")));
+        assert!(is_zip_path(Path::new("foo/out.ZIP")));
+        assert!(!is_zip_path(Path::new("out")));
+        assert!(!is_zip_path(Path::new("out.zip/foo")));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_write() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("out.zip");
+
+        let out = ZipOutput::create(&path).unwrap();
+        assert!(out.add("a.eml", b"foo".as_slice()).unwrap());
+        assert!(out.add("b.eml", b"bar".as_slice()).unwrap());
+        assert!(!out.add("a.eml", b"baz".as_slice()).unwrap());
+        assert!(out.contains("a.eml"));
+        assert!(!out.contains("c.eml"));
+        assert!(!path.exists());
+        out.finish().unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut names = archive.file_names().collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, ["a.eml", "b.eml"]);
+        let mut content = String::new();
+        archive
+            .by_name("b.eml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "bar");
+        assert!(!dir.path().join("out.zip.part").exists());
+    }
+}
@@ -0,0 +1,338 @@
+//! Blobs that hold mail details and attachments.
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::{rng, seq::IteratorRandom};
+use reqwest::Method;
+use tracing::debug;
+
+use crate::{
+    client::{Client, Prefix, Request},
+    proto::{
+        enums::ArchiveDataType,
+        messages::{
+            BlobAccessTokenServiceRequest, BlobAccessTokenServiceResponse, BlobReadRequest,
+            BlobReadRequestInstanceId, BlobServiceRequest, MailDetailsBlob,
+        },
+    },
+    session::Session,
+};
+
+pub(crate) async fn get_mail_blob(
+    client: &Client,
+    session: &Session,
+    archive_id: &str,
+    blob_id: &str,
+) -> Result<MailDetailsBlob> {
+    let resp = get_mail_blobs(client, session, archive_id, &[blob_id.to_owned()]).await?;
+
+    if resp.len() != 1 {
+        bail!("invalid reponse length")
+    }
+
+    Ok(resp.into_iter().next().expect("checked length"))
+}
+
+/// Get details of multiple mails that are stored in the same archive with a single request.
+///
+/// The response order is NOT guaranteed to match `blob_ids`, use [`MailDetailsBlob::id`] to match them.
+pub async fn get_mail_blobs(
+    client: &Client,
+    session: &Session,
+    archive_id: &str,
+    blob_ids: &[String],
+) -> Result<Vec<MailDetailsBlob>> {
+    let access = get_access(
+        client,
+        session,
+        archive_id,
+        ArchiveDataType::MailDetails,
+        None,
+    )
+    .await
+    .context("get blob access")?;
+
+    let resp: Vec<MailDetailsBlob> = client
+        .do_json(Request {
+            method: Method::GET,
+            host: Some(&access.server_url),
+            prefix: Prefix::Tutanota,
+            path: &format!("maildetailsblob/{archive_id}"),
+            data: &(),
+            access_token: None,
+            query: &[
+                ("accessToken", &session.access_token.to_string()),
+                ("ids", &blob_ids.join(",")),
+                ("blobAccessToken", &access.blob_access_token),
+            ],
+        })
+        .await
+        .context("blob download")?;
+
+    if resp.len() != blob_ids.len() {
+        bail!(
+            "invalid reponse length: expected {}, got {}",
+            blob_ids.len(),
+            resp.len()
+        )
+    }
+
+    Ok(resp)
+}
+
+pub(crate) async fn get_mail_draft_blob(
+    client: &Client,
+    session: &Session,
+    archive_id: &str,
+    blob_id: &str,
+) -> Result<MailDetailsBlob> {
+    let resp: Vec<MailDetailsBlob> = client
+        .do_json(Request {
+            method: Method::GET,
+            host: None,
+            prefix: Prefix::Tutanota,
+            path: &format!("maildetailsdraft/{archive_id}"),
+            data: &(),
+            access_token: Some(&session.access_token),
+            query: &[("ids", &[blob_id].join(","))],
+        })
+        .await
+        .context("blob download")?;
+
+    if resp.len() != 1 {
+        bail!("invalid reponse length")
+    }
+
+    Ok(resp.into_iter().next().expect("checked length"))
+}
+
+/// Download an attachment blob and pass it to `sink` chunk by chunk, see [`Client::do_bytes`].
+pub(crate) async fn get_attachment_blob(
+    client: &Client,
+    session: &Session,
+    archive_id: &str,
+    blob_id: &str,
+    instance_list_id: &str,
+    instance_id: &str,
+    sink: impl FnMut(&[u8]) -> Result<()> + Send,
+) -> Result<()> {
+    let access = get_access(
+        client,
+        session,
+        archive_id,
+        ArchiveDataType::Attachments,
+        Some((instance_list_id, instance_id)),
+    )
+    .await
+    .context("get blob access")?;
+
+    client
+        .do_bytes(
+            Request {
+                method: Method::GET,
+                host: Some(&access.server_url),
+                prefix: Prefix::Storage,
+                path: "blobservice",
+                data: &(),
+                access_token: None,
+                query: &[
+                    ("accessToken", &session.access_token.to_string()),
+                    ("blobAccessToken", &access.blob_access_token),
+                    (
+                        "_body",
+                        &serde_json::to_string(&BlobServiceRequest {
+                            format: Default::default(),
+                            archive_id: archive_id.to_owned(),
+                            blob_id: blob_id.to_owned(),
+                            blob_ids: vec![],
+                        })
+                        .expect("serde should always work"),
+                    ),
+                ],
+            },
+            sink,
+        )
+        .await
+        .context("blob download")
+}
+
+/// Tokens are considered expired this long before the server-provided expiry.
+const ACCESS_EXPIRY_MARGIN: TimeDelta = TimeDelta::seconds(60);
+
+/// Lifetime of tokens if the server does not provide an expiry.
+const ACCESS_DEFAULT_TTL: TimeDelta = TimeDelta::seconds(60);
+
+/// Archive and data type that a blob access token was requested for.
+///
+/// Tokens for single instances, i.e. attachments, are NOT cached, since they cannot be reused for other blobs.
+type AccessKey = (String, ArchiveDataType);
+
+/// Cache for blob access tokens, see [`get_access`].
+#[derive(Debug, Default)]
+pub(crate) struct BlobAccessCache {
+    entries: Mutex<HashMap<AccessKey, (BlobAccess, DateTime<Utc>)>>,
+}
+
+impl BlobAccessCache {
+    fn get(&self, key: &AccessKey, now: DateTime<Utc>) -> Option<BlobAccess> {
+        let mut entries = self.entries.lock().expect("not poisoned");
+        match entries.get(key) {
+            Some((access, expires)) if *expires > now => Some(access.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Insert token and drop expired ones, so that the cache does not grow in long-running processes.
+    fn insert(
+        &self,
+        key: AccessKey,
+        access: BlobAccess,
+        expires: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) {
+        let mut entries = self.entries.lock().expect("not poisoned");
+        entries.retain(|_key, (_access, expires)| *expires > now);
+        entries.insert(key, (access, expires));
+    }
+}
+
+/// Get blob access token.
+///
+/// Tokens for whole archives are reused until they expire, see [`BlobAccessCache`].
+async fn get_access(
+    client: &Client,
+    session: &Session,
+    archive_id: &str,
+    archive_data_type: ArchiveDataType,
+    instance: Option<(&str, &str)>,
+) -> Result<BlobAccess> {
+    if instance.is_some() {
+        let (access, _expires) =
+            request_access(client, session, archive_id, archive_data_type, instance).await?;
+        return Ok(access);
+    }
+
+    let key = (archive_id.to_owned(), archive_data_type);
+    if let Some(access) = session.blob_access.get(&key, Utc::now()) {
+        debug!(archive_id, "reuse blob access token");
+        return Ok(access);
+    }
+
+    let (access, expires) =
+        request_access(client, session, archive_id, archive_data_type, None).await?;
+    session
+        .blob_access
+        .insert(key, access.clone(), expires, Utc::now());
+    Ok(access)
+}
+
+/// Request new blob access token, returns the token and its expiry.
+async fn request_access(
+    client: &Client,
+    session: &Session,
+    archive_id: &str,
+    archive_data_type: ArchiveDataType,
+    instance: Option<(&str, &str)>,
+) -> Result<(BlobAccess, DateTime<Utc>)> {
+    let req = access_request(archive_id, archive_data_type, instance);
+    let resp: BlobAccessTokenServiceResponse = client
+        .do_json(Request {
+            method: Method::POST,
+            host: None,
+            prefix: Prefix::Storage,
+            path: "blobaccesstokenservice",
+            data: &req,
+            access_token: Some(&session.access_token),
+            query: &[],
+        })
+        .await
+        .context("blob service access request")?;
+
+    let Some(server) = resp.blob_access_info.servers.into_iter().choose(&mut rng()) else {
+        bail!("no blob servers provided")
+    };
+
+    let expires = match resp.blob_access_info.expires {
+        Some(expires) => expires.0 - ACCESS_EXPIRY_MARGIN,
+        None => Utc::now() + ACCESS_DEFAULT_TTL,
+    };
+
+    Ok((
+        BlobAccess {
+            server_url: server.url.clone(),
+            blob_access_token: resp.blob_access_info.blob_access_token,
+        },
+        expires,
+    ))
+}
+
+/// Request body of [`request_access`].
+pub(crate) fn access_request(
+    archive_id: &str,
+    archive_data_type: ArchiveDataType,
+    instance: Option<(&str, &str)>,
+) -> BlobAccessTokenServiceRequest {
+    BlobAccessTokenServiceRequest {
+        format: Default::default(),
+        archive_data_type,
+        read: BlobReadRequest {
+            id: "MR9cbw".to_owned(),
+            archive_id: archive_id.to_owned(),
+            instance_ids: instance
+                .iter()
+                .map(|(_l, i)| BlobReadRequestInstanceId {
+                    id: "MR9cbw".to_owned(),
+                    instance_id: (*i).to_owned(),
+                })
+                .collect(),
+            instance_list_id: instance.as_ref().map(|(l, _i)| (*l).to_owned()),
+        },
+        write: Default::default(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BlobAccess {
+    pub(crate) server_url: String,
+    pub(crate) blob_access_token: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_cache() {
+        let cache = BlobAccessCache::default();
+        let now = Utc::now();
+        let key = |archive: &str| (archive.to_owned(), ArchiveDataType::MailDetails);
+        let access = BlobAccess {
+            server_url: "https://example.com".to_owned(),
+            blob_access_token: "token".to_owned(),
+        };
+
+        assert!(cache.get(&key("a"), now).is_none());
+        cache.insert(key("a"), access.clone(), now + TimeDelta::seconds(10), now);
+        assert_eq!(
+            cache.get(&key("a"), now).unwrap().blob_access_token,
+            "token"
+        );
+        assert!(cache.get(&key("b"), now).is_none());
+        assert!(cache.get(&key("a"), now + TimeDelta::seconds(10)).is_none());
+        assert!(cache.get(&key("a"), now).is_none());
+
+        // expired entries are dropped when inserting, even if they are never looked up again
+        cache.insert(key("a"), access.clone(), now + TimeDelta::seconds(10), now);
+        let later = now + TimeDelta::seconds(20);
+        cache.insert(key("b"), access, later + TimeDelta::seconds(10), later);
+        assert_eq!(
+            cache.entries.lock().unwrap().keys().collect::<Vec<_>>(),
+            [&key("b")],
+        );
+    }
+}
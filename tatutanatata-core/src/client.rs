@@ -0,0 +1,1182 @@
+//! HTTP client for the Tuta API, see [`Client`].
+use std::{
+    collections::HashSet,
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use base64::{
+    alphabet::Alphabet,
+    engine::{general_purpose::NO_PAD, GeneralPurpose},
+    Engine,
+};
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use reqwest::{
+    header::{
+        HeaderMap, ACCEPT_ENCODING, CONNECTION, CONTENT_RANGE, DATE, RANGE, RETRY_AFTER,
+        SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE,
+    },
+    Method, RequestBuilder, Response, StatusCode, Upgraded,
+};
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::{client::generate_key, derive_accept_key},
+        protocol::Role,
+    },
+    WebSocketStream,
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::{debug, warn};
+
+use crate::{
+    concurrency::AdaptiveLimit,
+    constants::APP_USER_AGENT,
+    dump::{Dump, Replay, RequestKey},
+    failure::Failure,
+    proto::{binary::Base64Url, messages::Entity},
+    retry::{ErrorClass, Retry, RetryPolicy},
+    tls::{build_tls_config, CertFingerprint},
+};
+
+pub(crate) const STREAM_BATCH_SIZE: u64 = 1000;
+
+/// Smallest and largest generated element ID, used as start for forward and reverse pagination.
+pub(crate) const GENERATED_MIN_ID: &str = "------------";
+const GENERATED_MAX_ID: &str = "zzzzzzzzzzzz";
+
+/// Sort-preserving base64 alphabet of generated element IDs.
+const GENERATED_ID_ALPHABET: Alphabet =
+    match Alphabet::new("-0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz") {
+        Ok(alphabet) => alphabet,
+        Err(_) => panic!("invalid alphabet"),
+    };
+const GENERATED_ID_ENGINE: GeneralPurpose = GeneralPurpose::new(&GENERATED_ID_ALPHABET, NO_PAD);
+/// Server of the official service.
+pub const DEFAULT_HOST: &str = "https://app.tuta.com";
+
+/// HTTP client config, see [`Client::try_new`].
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Server URL, e.g. [`DEFAULT_HOST`] or the one of a self-hosted or whitelabel deployment.
+    pub host: String,
+
+    /// Proxy URL, e.g. `http://proxy:3128` or `socks5://proxy:1080`.
+    ///
+    /// If not set, the standard `HTTPS_PROXY`/`ALL_PROXY` environment variables are used.
+    pub proxy: Option<String>,
+
+    /// Use HTTP/1.1 instead of HTTP/2.
+    pub http1: bool,
+
+    /// Additionally trust the CA certificates in the given PEM file.
+    pub ca_cert: Option<PathBuf>,
+
+    /// Require a certificate with one of the given fingerprints in the server certificate chain.
+    pub pin_cert: Vec<CertFingerprint>,
+
+    /// Allow requests that modify data on the server.
+    ///
+    /// Otherwise, the client refuses to issue any request that could modify the mailbox (logging in and out is always
+    /// permitted).
+    pub allow_writes: bool,
+
+    /// Dump responses of the server to the given folder, so that they can be replayed via
+    /// [`replay_from`](Self::replay_from).
+    pub debug_dump_json_to: Option<PathBuf>,
+
+    /// Serve responses from a folder that was written via [`debug_dump_json_to`](Self::debug_dump_json_to) instead
+    /// of contacting the server.
+    pub replay_from: Option<PathBuf>,
+
+    /// Log fields of server responses that are NOT part of the protocol model.
+    pub strict_proto: bool,
+
+    /// Number of list pages that are fetched ahead while the current page is processed, must be at least 1.
+    pub prefetch_pages: u64,
+
+    /// Fetch list pages from both ends concurrently until they meet.
+    pub parallel_pagination: bool,
+}
+
+impl ClientConfig {
+    /// Config for the given server, e.g. [`DEFAULT_HOST`], with the defaults of all other options.
+    pub fn new(host: &str) -> Self {
+        Self {
+            host: host.to_owned(),
+            proxy: None,
+            http1: false,
+            ca_cert: None,
+            pin_cert: vec![],
+            allow_writes: false,
+            debug_dump_json_to: None,
+            replay_from: None,
+            strict_proto: false,
+            prefetch_pages: 4,
+            parallel_pagination: false,
+        }
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_HOST)
+    }
+}
+
+/// HTTP client for the Tuta API.
+///
+/// Cheap to clone, clones share the connection pool.
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: reqwest::Client,
+    host: Arc<str>,
+    allow_writes: bool,
+    dump: Option<Arc<Dump>>,
+    replay: Option<Arc<Replay>>,
+
+    /// Fields that were reported already, see [`ClientConfig::strict_proto`].
+    unknown_fields: Option<Arc<UnknownFields>>,
+
+    prefetch_pages: u64,
+    parallel_pagination: bool,
+
+    /// Client for websocket connections, which require HTTP/1.1, see [`websocket`](Self::websocket).
+    websocket: reqwest::Client,
+
+    /// Limit that is informed about overload signals of the server.
+    ///
+    /// See [`with_concurrency_limit`](Self::with_concurrency_limit).
+    concurrency_limit: Option<Arc<AdaptiveLimit>>,
+
+    /// Retries of failed requests, see [`with_retry_policy`](Self::with_retry_policy).
+    retry: RetryPolicy,
+}
+
+impl Client {
+    /// Set up client from the given config.
+    pub async fn try_new(config: ClientConfig) -> Result<Self> {
+        let ClientConfig {
+            host,
+            proxy,
+            http1,
+            ca_cert,
+            pin_cert,
+            allow_writes,
+            debug_dump_json_to,
+            replay_from,
+            strict_proto,
+            prefetch_pages,
+            parallel_pagination,
+        } = config;
+        ensure!(prefetch_pages > 0, "must prefetch at least one page");
+
+        let base_builder = || -> Result<reqwest::ClientBuilder> {
+            let mut builder = reqwest::Client::builder()
+                .hickory_dns(true)
+                .https_only(true)
+                .min_tls_version(reqwest::tls::Version::TLS_1_3)
+                .user_agent(APP_USER_AGENT);
+            if let Some(proxy) = &proxy {
+                // this disables the proxy detection via environment variables
+                builder = builder
+                    .proxy(reqwest::Proxy::all(proxy.as_str()).context("invalid proxy URL")?);
+            }
+            Ok(builder)
+        };
+        let websocket = base_builder()?
+            .http1_only()
+            .use_preconfigured_tls(
+                build_tls_config(ca_cert.as_deref(), &pin_cert, true)
+                    .context("set up websocket TLS config")?,
+            )
+            .build()
+            .context("set up websocket client")?;
+        let mut builder = base_builder()?;
+        builder = if http1 {
+            builder.http1_only()
+        } else {
+            builder.http2_adaptive_window(true).http2_prior_knowledge()
+        };
+        if ca_cert.is_some() || !pin_cert.is_empty() {
+            builder = builder.use_preconfigured_tls(
+                build_tls_config(ca_cert.as_deref(), &pin_cert, http1)
+                    .context("set up TLS config")?,
+            );
+        }
+        let inner = builder.build().context("set up HTTPs client")?;
+
+        let dump = match debug_dump_json_to {
+            Some(path) => Some(Arc::new(Dump::try_new(path).await?)),
+            None => None,
+        };
+        let replay = replay_from
+            .map(|path| Replay::try_new(path).context("load replay data"))
+            .transpose()?
+            .map(Arc::new);
+
+        Ok(Self {
+            inner,
+            host: host.trim_end_matches('/').into(),
+            allow_writes,
+            dump,
+            replay,
+            unknown_fields: strict_proto.then(Default::default),
+            prefetch_pages,
+            parallel_pagination,
+            websocket,
+            concurrency_limit: None,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Report response status codes to the given limit, so it can adapt to the load of the server.
+    pub fn with_concurrency_limit(self, limit: Arc<AdaptiveLimit>) -> Self {
+        Self {
+            concurrency_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Retry failed requests with the given policy instead of the default one.
+    pub fn with_retry_policy(self, retry: RetryPolicy) -> Self {
+        Self { retry, ..self }
+    }
+
+    /// Policy that failed requests are retried with.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Server URL, e.g. `https://app.tuta.com`.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Contact the server once, without retries, and return its `Date` header.
+    ///
+    /// This checks connectivity and TLS, e.g. for diagnostics.
+    pub async fn server_date(&self) -> Result<Option<DateTime<Utc>>> {
+        if self.replay.is_some() {
+            bail!("the server is not contacted during replay");
+        }
+
+        let resp = self
+            .inner
+            .get(&*self.host)
+            .send()
+            .await
+            .context("connect to server")?;
+        debug!(status = %resp.status(), "server responded");
+
+        Ok(resp
+            .headers()
+            .get(DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.to_utc()))
+    }
+
+    /// Open websocket connection, e.g. to the `event` endpoint.
+    ///
+    /// Like all other requests, this uses the configured proxy.
+    pub async fn websocket(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<WebSocketStream<Upgraded>> {
+        if self.replay.is_some() {
+            bail!("websocket connections are not supported during replay");
+        }
+
+        let url = reqwest::Url::parse_with_params(&format!("{}/{path}", self.host), query)
+            .context("build websocket URL")?;
+        debug!(path, "open websocket");
+
+        let key = generate_key();
+        let resp = self
+            .websocket
+            .get(url)
+            .header(CONNECTION, "Upgrade")
+            .header(UPGRADE, "websocket")
+            .header(SEC_WEBSOCKET_VERSION, "13")
+            .header(SEC_WEBSOCKET_KEY, &key)
+            .send()
+            .await
+            .context("connect websocket")?;
+        ensure!(
+            resp.status() == StatusCode::SWITCHING_PROTOCOLS,
+            "server refused websocket: {}",
+            resp.status()
+        );
+        ensure!(
+            resp.headers().get(SEC_WEBSOCKET_ACCEPT).is_some_and(
+                |accept| accept.as_bytes() == derive_accept_key(key.as_bytes()).as_bytes()
+            ),
+            "invalid websocket handshake"
+        );
+        let upgraded = resp.upgrade().await.context("upgrade to websocket")?;
+
+        Ok(WebSocketStream::from_raw_socket(upgraded, Role::Client, None).await)
+    }
+
+    /// Stream all elements of the given list in ascending ID order.
+    ///
+    /// If `start` is given, only elements with a larger ID are returned.
+    pub(crate) fn stream<Resp>(
+        &self,
+        path: &str,
+        access_token: Option<&Base64Url>,
+        start: Option<&str>,
+    ) -> impl Stream<Item = Result<Resp>>
+    where
+        Resp: DeserializeOwned + Entity + Send + 'static,
+    {
+        let (tx, rx) = channel((self.prefetch_pages * STREAM_BATCH_SIZE) as usize);
+
+        let path = Arc::new(path.to_owned());
+        let start = start.unwrap_or(GENERATED_MIN_ID).to_owned();
+        let access_token = Arc::new(access_token.cloned());
+        let this = self.clone();
+        let cancel = CancellationToken::new();
+        let cancel_captured = cancel.clone();
+        // The task is NOT aborted when the stream is dropped. Instead it checks the cancellation token between pages
+        // and exits cleanly.
+        tokio::spawn(async move {
+            let cancel = cancel_captured;
+            let mut tail = vec![];
+            let mut cursor = if this.parallel_pagination {
+                Cursor::Bidirectional {
+                    lo: start,
+                    hi: GENERATED_MAX_ID.to_owned(),
+                }
+            } else {
+                Cursor::Forward(start)
+            };
+
+            loop {
+                if cancel.is_cancelled() {
+                    debug!(path = path.as_str(), "stream dropped, stop fetching");
+                    return;
+                }
+
+                let res = tokio::select! {
+                    _ = cancel.cancelled() => {
+                        debug!(path = path.as_str(), "stream dropped during fetch, stop fetching");
+                        return;
+                    }
+                    res = this.next_batch::<Resp>(&path, access_token.as_ref().as_ref(), &mut cursor, &mut tail) => res,
+                };
+
+                match res {
+                    Ok(None) => {
+                        // reached end
+                        return;
+                    }
+                    Ok(Some(elements)) => {
+                        for o in elements {
+                            if tx.send(Ok(o)).await.is_err() {
+                                // receiver gone
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        // it's OK if the receiver is gone, we exit anyways
+                        tx.send(Err(e)).await.ok();
+                        return;
+                    }
+                }
+            }
+        });
+        let state = StreamState {
+            rx,
+            _cancel_on_drop: cancel.drop_guard(),
+        };
+
+        futures::stream::unfold(state, move |mut state: StreamState<Resp>| async move {
+            state.rx.recv().await.map(|next| (next, state))
+        })
+    }
+
+    /// Fetch next batch of elements for [`stream`](Self::stream).
+    ///
+    /// Elements that were fetched backward are kept in `tail` in descending order until [`Cursor::Bidirectional`]
+    /// finished, so that all batches are in ascending order. Returns [`None`] if all elements were fetched.
+    async fn next_batch<Resp>(
+        &self,
+        path: &str,
+        access_token: Option<&Base64Url>,
+        cursor: &mut Cursor,
+        tail: &mut Vec<Resp>,
+    ) -> Result<Option<Vec<Resp>>>
+    where
+        Resp: DeserializeOwned + Entity,
+    {
+        match cursor {
+            Cursor::Forward(next_start) => {
+                let elements = self
+                    .fetch_page::<Resp>(path, access_token, next_start, false)
+                    .await?;
+                match elements.last() {
+                    None => Ok(None),
+                    Some(o) => {
+                        o.id().clone_into(next_start);
+                        Ok(Some(elements))
+                    }
+                }
+            }
+            Cursor::Bidirectional { lo, hi } => {
+                let (fwd, bwd) = futures::try_join!(
+                    self.fetch_page::<Resp>(path, access_token, lo, false),
+                    self.fetch_page::<Resp>(path, access_token, hi, true),
+                )?;
+                let (mut elements, bwd, next) = merge_pages(fwd, bwd, hi);
+                tail.extend(bwd);
+                if matches!(next, Cursor::Done) {
+                    elements.extend(tail.drain(..).rev());
+                }
+                *cursor = next;
+                Ok(Some(elements))
+            }
+            Cursor::Done => Ok(None),
+        }
+    }
+
+    async fn fetch_page<Resp>(
+        &self,
+        path: &str,
+        access_token: Option<&Base64Url>,
+        start: &str,
+        reverse: bool,
+    ) -> Result<Vec<Resp>>
+    where
+        Resp: DeserializeOwned,
+    {
+        debug!(path, start, reverse, "fetch new page");
+
+        let count = STREAM_BATCH_SIZE.to_string();
+        let query = [
+            ("start", start),
+            ("count", count.as_str()),
+            ("reverse", if reverse { "true" } else { "false" }),
+        ];
+        self.do_json::<(), Vec<Resp>>(Request {
+            method: Method::GET,
+            host: None,
+            prefix: Prefix::Tutanota,
+            path,
+            data: &(),
+            access_token,
+            query: &query,
+        })
+        .await
+        .context("fetch next page")
+    }
+
+    /// Perform request and deserialize the JSON response.
+    pub async fn do_json<Req, Resp>(&self, r: Request<'_, Req>) -> Result<Resp>
+    where
+        Req: serde::Serialize + Sync,
+        Resp: DeserializeOwned,
+    {
+        self.check_read_only(&r)?;
+        let s = match &self.replay {
+            Some(replay) => String::from_utf8(replay.get(&RequestKey::new(&r)?).await?)
+                .context("recorded response is not UTF-8")?,
+            None => {
+                retry(&self.retry, || async {
+                    Ok(self.do_request(r.clone()).await?.text().await?)
+                })
+                .await?
+            }
+        };
+
+        let json_path = match &self.dump {
+            Some(dump) => Some(dump.write(&r, "json", s.as_bytes()).await?),
+            None => None,
+        };
+
+        let type_name = std::any::type_name::<Resp>();
+        let jd = &mut serde_json::Deserializer::from_str(&s);
+        let res: Result<Resp, _> = match &self.unknown_fields {
+            Some(unknown_fields) => {
+                let mut unknown = vec![];
+                let mut callback = |path: serde_ignored::Path<'_>| unknown.push(field_path(&path));
+                let res = serde_path_to_error::deserialize(serde_ignored::Deserializer::new(
+                    jd,
+                    &mut callback,
+                ));
+                for field in unknown {
+                    if unknown_fields.insert(type_name, &field) {
+                        warn!(
+                            type_name,
+                            field = field.as_str(),
+                            "unknown field in server response"
+                        );
+                    }
+                }
+                res
+            }
+            None => serde_path_to_error::deserialize(jd),
+        };
+
+        res.with_context(|| {
+            match json_path {
+                Some(json_path) => {
+                    format!(
+                        "deserialize JSON for `{}`, data dumped to `{}`",
+                        type_name,
+                        json_path.display(),
+                    )
+                }
+                None => {
+                    format!(
+                        "deserialize JSON for `{}`, consider passing `--debug-dump-json-to=some/path` to dump the data",
+                        type_name,
+                    )
+                }
+            }
+        })
+    }
+
+    /// Request binary data and pass the response body to `sink` chunk by chunk, so that it is NOT held in memory.
+    ///
+    /// A retry resumes the download via a range request. If the server starts over instead, the data that was passed to
+    /// `sink` already is skipped. An error of `sink` aborts the download without retries.
+    pub(crate) async fn do_bytes<Req, S>(&self, r: Request<'_, Req>, mut sink: S) -> Result<()>
+    where
+        Req: serde::Serialize + Sync,
+        S: FnMut(&[u8]) -> Result<()> + Send,
+    {
+        self.check_read_only(&r)?;
+        if let Some(replay) = &self.replay {
+            return sink(&replay.get(&RequestKey::new(&r)?).await?);
+        }
+
+        // only the dump requires the whole response
+        let dumped = self.dump.as_ref().map(|_| Vec::new());
+        let state = tokio::sync::Mutex::new((0, dumped, sink));
+        retry(&self.retry, || async {
+            let mut state = state.lock().await;
+            let (received, dumped, sink) = &mut *state;
+            let offset = *received;
+            let mut skip = 0;
+            let mut resp = self.do_range_request(r.clone(), offset).await?;
+            if offset > 0 && !resumes_at(resp.status(), resp.headers(), offset) {
+                if resp.status() == StatusCode::PARTIAL_CONTENT {
+                    warn!(
+                        offset,
+                        content_range = ?resp.headers().get(CONTENT_RANGE),
+                        path = r.path,
+                        "unexpected range, start over",
+                    );
+                    resp = self.do_range_request(r.clone(), 0).await?;
+                } else {
+                    debug!(offset, path = r.path, "server ignored range, start over");
+                }
+                skip = offset;
+            }
+            while let Some(chunk) = resp.chunk().await? {
+                let skipped = skip.min(chunk.len() as u64);
+                skip -= skipped;
+                let chunk = &chunk[skipped as usize..];
+                if chunk.is_empty() {
+                    continue;
+                }
+
+                *received += chunk.len() as u64;
+                if let Some(dumped) = dumped {
+                    dumped.extend_from_slice(chunk);
+                }
+                if let Err(e) = sink(chunk) {
+                    return Ok(Err(e));
+                }
+            }
+
+            Ok(Ok(()))
+        })
+        .await??;
+
+        if let (Some(dump), (_received, Some(dumped), _sink)) = (&self.dump, state.into_inner()) {
+            dump.write(&r, "bin", &dumped).await?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn do_no_response<Req>(&self, r: Request<'_, Req>) -> Result<()>
+    where
+        Req: serde::Serialize + Sync,
+    {
+        self.check_read_only(&r)?;
+        if self.replay.is_some() {
+            debug!(method=%r.method, prefix=r.prefix.str(), path=r.path, "skip request during replay");
+            return Ok(());
+        }
+
+        retry(&self.retry, || async { self.do_request(r.clone()).await }).await?;
+
+        Ok(())
+    }
+
+    fn check_read_only<Req>(&self, r: &Request<'_, Req>) -> Result<()>
+    where
+        Req: serde::Serialize + Sync,
+    {
+        if !self.allow_writes && !is_read_only(&r.method, r.prefix, r.path) {
+            bail!(
+                "refusing to issue `{} {}/{}` in read-only mode, pass `--allow-writes` to permit modifications",
+                r.method,
+                r.prefix.str(),
+                r.path,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Request the data from the given offset on, see [`resumes_at`].
+    ///
+    /// Range requests ask for the unencoded data, since the offsets refer to it and NOT to the compressed transfer.
+    async fn do_range_request<Req>(
+        &self,
+        r: Request<'_, Req>,
+        offset: u64,
+    ) -> Result<Response, RequestError>
+    where
+        Req: serde::Serialize + Sync,
+    {
+        let mut req = self.build_request(r.clone());
+        if offset > 0 {
+            debug!(offset, path = r.path, "resume download");
+            req = req
+                .header(RANGE, format!("bytes={offset}-"))
+                .header(ACCEPT_ENCODING, "identity");
+        }
+
+        let resp = req.send().await?;
+        self.observe(resp.status());
+        check_status(resp)
+    }
+
+    async fn do_request<Req>(&self, r: Request<'_, Req>) -> Result<Response, RequestError>
+    where
+        Req: serde::Serialize + Sync,
+    {
+        let resp = self.build_request(r).send().await?;
+        self.observe(resp.status());
+        check_status(resp)
+    }
+
+    /// Report response status to the [concurrency limit](Self::with_concurrency_limit).
+    fn observe(&self, status: StatusCode) {
+        let Some(limit) = &self.concurrency_limit else {
+            return;
+        };
+
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+            limit.on_overload();
+        } else if status.is_success() {
+            limit.on_success();
+        }
+    }
+
+    fn build_request<Req>(&self, r: Request<'_, Req>) -> RequestBuilder
+    where
+        Req: serde::Serialize + Sync,
+    {
+        let Request {
+            method,
+            host,
+            prefix,
+            path,
+            data,
+            access_token,
+            query,
+        } = r;
+        debug!(%method, prefix=prefix.str(), path, "service request",);
+
+        let host = host.unwrap_or(&self.host);
+        let mut req = self
+            .inner
+            .request(method, format!("{}/rest/{}/{}", host, prefix.str(), path));
+
+        if let Some(access_token) = access_token {
+            req = req.header("accessToken", access_token.to_string());
+        }
+
+        req.json(data).query(query)
+    }
+}
+
+/// Check if the request does not modify any data.
+///
+/// Some endpoints use `POST` or `DELETE` although they only read data or manage the session.
+fn is_read_only(method: &Method, prefix: Prefix, path: &str) -> bool {
+    if method == Method::GET {
+        return true;
+    }
+
+    match prefix {
+        // login
+        Prefix::Sys if method == Method::POST => path == "sessionservice",
+        // logout
+        Prefix::Sys if method == Method::DELETE => path.starts_with("session/"),
+        // read access to blobs
+        Prefix::Storage if method == Method::POST => path == "blobaccesstokenservice",
+        _ => false,
+    }
+}
+
+struct StreamState<T> {
+    rx: Receiver<Result<T>>,
+
+    /// Cancels the fetch task when the stream is dropped.
+    _cancel_on_drop: DropGuard,
+}
+
+/// API of a request, see [`Request::prefix`].
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Prefix {
+    /// Mails and folders.
+    Tutanota,
+
+    /// Blobs, e.g. mail bodies and attachments.
+    Storage,
+
+    /// Accounts, sessions, and keys.
+    Sys,
+}
+
+impl Prefix {
+    pub(crate) fn str(&self) -> &'static str {
+        match self {
+            Self::Tutanota => "tutanota",
+            Self::Storage => "storage",
+            Self::Sys => "sys",
+        }
+    }
+}
+
+/// Fields of server responses that are NOT part of the protocol model, see [`ClientConfig::strict_proto`].
+#[derive(Debug, Default)]
+struct UnknownFields {
+    seen: Mutex<HashSet<(&'static str, String)>>,
+}
+
+impl UnknownFields {
+    /// Record field of the given response type, returns `true` if it was NOT seen before.
+    fn insert(&self, type_name: &'static str, field: &str) -> bool {
+        self.seen
+            .lock()
+            .expect("not poisoned")
+            .insert((type_name, field.to_owned()))
+    }
+}
+
+/// Path of a field within a response, e.g. `memberships[].foo`.
+///
+/// Array indices are omitted, so that a field is only reported once per response type.
+fn field_path(path: &serde_ignored::Path<'_>) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, .. } => format!("{}[]", field_path(parent)),
+        serde_ignored::Path::Map { parent, key } => match field_path(parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{parent}.{key}"),
+        },
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => field_path(parent),
+    }
+}
+
+/// API request, see [`Client::do_json`].
+#[derive(Debug)]
+pub struct Request<'a, Req>
+where
+    Req: serde::Serialize + Sync,
+{
+    /// HTTP method, defaults to `GET`.
+    pub method: Method,
+
+    /// Server URL, defaults to [`Client::host`].
+    pub host: Option<&'a str>,
+
+    /// API of the endpoint.
+    pub prefix: Prefix,
+
+    /// Endpoint within the API, e.g. `mailfolder`.
+    pub path: &'a str,
+
+    /// Request body, sent as JSON.
+    pub data: &'a Req,
+
+    /// Token of the session, see [`Session::access_token`](crate::session::Session::access_token).
+    pub access_token: Option<&'a Base64Url>,
+
+    /// Query parameters.
+    pub query: &'a [(&'a str, &'a str)],
+}
+
+impl<'a, Req> Request<'a, Req>
+where
+    Req: serde::Serialize + Sync,
+{
+    /// Unauthenticated `GET` request.
+    pub fn new(prefix: Prefix, path: &'a str, data: &'a Req) -> Self {
+        Self {
+            method: Method::GET,
+            host: None,
+            prefix,
+            path,
+            data,
+            access_token: None,
+            query: &[],
+        }
+    }
+}
+
+impl<Req> Clone for Request<'_, Req>
+where
+    Req: serde::Serialize + Sync,
+{
+    fn clone(&self) -> Self {
+        Self {
+            method: self.method.clone(),
+            host: self.host,
+            prefix: self.prefix,
+            path: self.path,
+            data: self.data,
+            access_token: self.access_token,
+            query: self.query,
+        }
+    }
+}
+
+/// Smallest generated element ID that the server could have created at the given time.
+///
+/// Generated IDs start with the creation time in milliseconds since the UNIX epoch, followed by 2 server bits and a
+/// counter, so all elements created at or after `t` sort after the returned ID.
+pub(crate) fn generated_id_at(t: DateTime<Utc>) -> String {
+    let millis = u128::try_from(t.timestamp_millis()).unwrap_or_default();
+    let id = (millis << 2) << 28;
+    GENERATED_ID_ENGINE.encode(&id.to_be_bytes()[7..])
+}
+
+/// Pagination state of [`Client::stream`].
+#[derive(Debug)]
+enum Cursor {
+    /// Fetch pages in ascending order, starting after the given ID.
+    Forward(String),
+
+    /// Fetch pages from both ends concurrently.
+    ///
+    /// All elements outside of the open interval `(lo, hi)` were already returned.
+    Bidirectional { lo: String, hi: String },
+
+    /// All elements were fetched.
+    Done,
+}
+
+/// Merge pages that were fetched forward and backward by [`Cursor::Bidirectional`].
+///
+/// Generated IDs are sortable strings of equal length. `bwd` is in descending order and only contains IDs below `hi`.
+/// Returns the new elements of both pages without duplicates, the forward ones in ascending and the backward ones in
+/// descending order.
+fn merge_pages<T>(fwd: Vec<T>, bwd: Vec<T>, hi: &str) -> (Vec<T>, Vec<T>, Cursor)
+where
+    T: Entity,
+{
+    // No element after `lo` means that everything was fetched. No element before `hi` means that the forward
+    // elements were already fetched by the backward direction.
+    let (Some(fwd_last), Some(bwd_last)) = (fwd.last(), bwd.last()) else {
+        return (vec![], vec![], Cursor::Done);
+    };
+    let fwd_last = fwd_last.id().to_owned();
+    let bwd_last = bwd_last.id().to_owned();
+
+    if fwd_last >= bwd_last {
+        // pages overlap, `(lo, fwd_last]` and `[bwd_last, hi)` cover the remaining interval
+        let fwd = fwd.into_iter().filter(|o| o.id() < hi).collect();
+        let bwd = bwd
+            .into_iter()
+            .filter(|o| o.id() > fwd_last.as_str())
+            .collect();
+        (fwd, bwd, Cursor::Done)
+    } else {
+        (
+            fwd,
+            bwd,
+            Cursor::Bidirectional {
+                lo: fwd_last,
+                hi: bwd_last,
+            },
+        )
+    }
+}
+
+/// Failed attempt of a request, see [`retry`].
+#[derive(Debug)]
+pub struct RequestError {
+    source: reqwest::Error,
+
+    /// Delay that the server requested via `Retry-After`.
+    retry_after: Option<Duration>,
+}
+
+impl RequestError {
+    /// Class of the failure, see [`Failure::of`].
+    pub(crate) fn failure(&self) -> Option<Failure> {
+        if self.source.status() == Some(StatusCode::UNAUTHORIZED) {
+            return Some(Failure::Auth);
+        }
+        ErrorClass::of_http_error(&self.source).map(|_| Failure::Network)
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl std::error::Error for RequestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.source()
+    }
+}
+
+impl From<reqwest::Error> for RequestError {
+    fn from(source: reqwest::Error) -> Self {
+        Self {
+            source,
+            retry_after: None,
+        }
+    }
+}
+
+/// Turn error status codes into errors, keeping the `Retry-After` delay of overload responses.
+pub fn check_status(resp: Response) -> Result<Response, RequestError> {
+    let retry_after = matches!(
+        resp.status(),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    )
+    .then(|| retry_after(resp.headers(), Utc::now()))
+    .flatten();
+
+    resp.error_for_status().map_err(|source| RequestError {
+        source,
+        retry_after,
+    })
+}
+
+/// Whether the response continues a download at the given offset.
+///
+/// This requires a partial response whose `Content-Range` starts at the offset, see
+/// <https://www.rfc-editor.org/rfc/rfc9110#section-14.4>.
+fn resumes_at(status: StatusCode, headers: &HeaderMap, offset: u64) -> bool {
+    if status != StatusCode::PARTIAL_CONTENT {
+        return false;
+    }
+
+    let start = headers
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("bytes "))
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(start, _rest)| start.trim().parse::<u64>().ok());
+    start == Some(offset)
+}
+
+/// Parse `Retry-After` header, which is either a number of seconds or an HTTP date.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc9110#section-10.2.3>.
+fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.to_utc() - now).to_std().unwrap_or_default())
+}
+
+/// Retry HTTP requests that failed transiently, honoring `Retry-After`.
+pub async fn retry<F, Fut, T>(policy: &RetryPolicy, action: F) -> Result<T>
+where
+    F: Fn() -> Fut + Send,
+    Fut: Future<Output = Result<T, RequestError>> + Send,
+    T: Send,
+{
+    policy
+        .retry("REST client", action, |e| {
+            match (ErrorClass::of_http_error(&e.source), e.retry_after) {
+                (None, _) => Retry::No,
+                (Some(class), None) => Retry::Backoff(class),
+                (Some(class), Some(after)) => Retry::After(class, after),
+            }
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_path() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Outer {
+            #[allow(dead_code)]
+            inner: Vec<Inner>,
+            #[allow(dead_code)]
+            opt: Option<Inner>,
+        }
+
+        #[derive(Debug, serde::Deserialize)]
+        struct Inner {
+            #[allow(dead_code)]
+            a: u8,
+        }
+
+        let jd = &mut serde_json::Deserializer::from_str(
+            r#"{"inner":[{"a":1,"b":2},{"a":1,"b":3}],"opt":{"a":1,"c":null},"d":true}"#,
+        );
+        let mut fields = vec![];
+        let _: Outer =
+            serde_ignored::deserialize(jd, |path| fields.push(field_path(&path))).unwrap();
+        assert_eq!(fields, ["inner[].b", "inner[].b", "opt.c", "d"]);
+    }
+
+    #[test]
+    fn test_is_read_only() {
+        assert!(is_read_only(&Method::GET, Prefix::Tutanota, "mail/foo"));
+        assert!(is_read_only(&Method::POST, Prefix::Sys, "sessionservice"));
+        assert!(is_read_only(&Method::DELETE, Prefix::Sys, "session/a/b"));
+        assert!(is_read_only(
+            &Method::POST,
+            Prefix::Storage,
+            "blobaccesstokenservice"
+        ));
+
+        assert!(!is_read_only(&Method::POST, Prefix::Tutanota, "mail/foo"));
+        assert!(!is_read_only(&Method::PUT, Prefix::Tutanota, "mail/foo"));
+        assert!(!is_read_only(&Method::DELETE, Prefix::Tutanota, "mail/a/b"));
+        assert!(!is_read_only(&Method::DELETE, Prefix::Sys, "user/a"));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .to_utc();
+        let parse = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, value.parse().unwrap());
+            retry_after(&headers, now)
+        };
+
+        assert_eq!(retry_after(&HeaderMap::new(), now), None);
+        assert_eq!(parse("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse("Wed, 21 Oct 2015 07:28:30 GMT"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse("Wed, 21 Oct 2015 07:27:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse("soon"), None);
+    }
+
+    #[test]
+    fn test_resumes_at() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_RANGE, value.parse().unwrap());
+            headers
+        };
+        let partial = StatusCode::PARTIAL_CONTENT;
+
+        assert!(resumes_at(partial, &headers("bytes 100-199/200"), 100));
+        assert!(resumes_at(partial, &headers("bytes 100-199/*"), 100));
+        assert!(!resumes_at(partial, &headers("bytes 0-199/200"), 100));
+        assert!(!resumes_at(partial, &headers("bytes 150-199/200"), 100));
+        assert!(!resumes_at(partial, &headers("bytes */200"), 100));
+        assert!(!resumes_at(partial, &HeaderMap::new(), 100));
+        assert!(!resumes_at(
+            StatusCode::OK,
+            &headers("bytes 100-199/200"),
+            100
+        ));
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Element(String);
+
+    impl Entity for Element {
+        fn id(&self) -> &str {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn test_generated_id_at() {
+        let at = |s: &str| generated_id_at(DateTime::parse_from_rfc3339(s).unwrap().to_utc());
+
+        assert_eq!(at("1970-01-01T00:00:00Z"), GENERATED_MIN_ID);
+        assert_eq!(at("2024-01-01T00:00:00Z"), "Nn1JUF------");
+        assert!(at("2024-01-01T00:00:00Z") < at("2024-01-01T00:00:00.001Z"));
+        assert!(at("2024-01-01T00:00:00Z") < at("2024-06-01T00:00:00Z"));
+        assert!(at("2100-01-01T00:00:00Z").as_str() < GENERATED_MAX_ID);
+    }
+
+    #[test]
+    fn test_merge_pages() {
+        for n in [0, 1, 5, 6, 7, 20] {
+            let all = (0..n)
+                .map(|i| Element(format!("{i:012}")))
+                .collect::<Vec<_>>();
+            let page_size = 3;
+
+            let mut out = vec![];
+            let mut tail = vec![];
+            let mut lo = GENERATED_MIN_ID.to_owned();
+            let mut hi = GENERATED_MAX_ID.to_owned();
+            loop {
+                let fwd = all
+                    .iter()
+                    .filter(|e| e.id() > lo.as_str())
+                    .take(page_size)
+                    .cloned()
+                    .collect();
+                let bwd = all
+                    .iter()
+                    .rev()
+                    .filter(|e| e.id() < hi.as_str())
+                    .take(page_size)
+                    .cloned()
+                    .collect();
+
+                let (elements, bwd, cursor) = merge_pages(fwd, bwd, &hi);
+                out.extend(elements);
+                tail.extend(bwd);
+                match cursor {
+                    Cursor::Bidirectional { lo: l, hi: h } => {
+                        lo = l;
+                        hi = h;
+                    }
+                    Cursor::Done => break,
+                    Cursor::Forward(_) => unreachable!(),
+                }
+            }
+
+            out.extend(tail.into_iter().rev());
+            assert_eq!(out, all, "n={n}");
+        }
+    }
+}
@@ -1,11 +1,14 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
-pub(crate) fn decompress_value(v: &[u8]) -> Result<Vec<u8>> {
+/// Decompress LZ4 block.
+///
+/// Fails if the decompressed data would exceed `max_len` bytes, which protects against decompression bombs.
+pub(crate) fn decompress_value(v: &[u8], max_len: usize) -> Result<Vec<u8>> {
     if v.is_empty() {
         return Ok(vec![]);
     }
 
-    let mut out_len = v.len() * 6;
+    let mut out_len = v.len().saturating_mul(6).min(max_len);
     loop {
         match lz4_flex::block::decompress(v, out_len) {
             Ok(out) => {
@@ -17,7 +20,10 @@ pub(crate) fn decompress_value(v: &[u8]) -> Result<Vec<u8>> {
                     expected > actual,
                     "failed: expected ({expected}) > actual ({actual})"
                 );
-                out_len = expected.max(actual.saturating_mul(2));
+                if actual >= max_len {
+                    bail!("decompressed data exceeds limit of {max_len} bytes");
+                }
+                out_len = expected.max(actual.saturating_mul(2)).min(max_len);
             }
             Err(e) => {
                 return Err(e).context("decompression");
@@ -36,17 +42,32 @@ mod tests {
         assert_compress_value_roundtrip(b"");
         assert_compress_value_roundtrip(&[0xff; 1024]);
 
-        assert_eq!(decompress_value(b"").unwrap(), b"");
+        assert_eq!(decompress_value(b"", usize::MAX).unwrap(), b"");
         assert_eq!(
-            decompress_value(b"\xff").unwrap_err().to_string(),
+            decompress_value(b"\xff", usize::MAX)
+                .unwrap_err()
+                .to_string(),
             "decompression",
         );
     }
 
+    #[test]
+    fn test_decompress_value_limit() {
+        let compressed = compress_value(&[0xff; 1024]);
+        assert_eq!(
+            decompress_value(&compressed, 1024).unwrap(),
+            [0xff; 1024].to_vec(),
+        );
+        assert_eq!(
+            decompress_value(&compressed, 1023).unwrap_err().to_string(),
+            "decompressed data exceeds limit of 1023 bytes",
+        );
+    }
+
     #[track_caller]
     fn assert_compress_value_roundtrip(v: &[u8]) {
         let compressed = compress_value(v);
-        let decompressed = decompress_value(&compressed).unwrap();
+        let decompressed = decompress_value(&compressed, usize::MAX).unwrap();
         assert_eq!(&decompressed, v);
     }
 
@@ -0,0 +1,191 @@
+//! Adaptive limit for concurrent downloads.
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+use tracing::{debug, info};
+
+/// Minimum time between two decreases of the limit.
+///
+/// Requests that were in flight when the server started to reject them fail around the same time. They should only
+/// count as a single overload signal.
+const DECREASE_COOLDOWN: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct State {
+    /// Current limit, fractional so it can grow by less than one per response.
+    limit: f64,
+
+    in_flight: usize,
+    last_decrease: Option<Instant>,
+}
+
+impl State {
+    fn current(&self) -> usize {
+        (self.limit as usize).max(1)
+    }
+}
+
+/// Concurrency limit that adapts to the load of the server.
+///
+/// Follows the AIMD scheme of TCP congestion control: the limit is halved whenever the server signals overload
+/// (`429 Too Many Requests` or `503 Service Unavailable`) and grows by roughly one per limit's worth of successful
+/// responses. It starts and is capped at the configured maximum.
+#[derive(Debug)]
+pub struct AdaptiveLimit {
+    max: usize,
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+impl AdaptiveLimit {
+    /// Create limit with the given maximum, which is at least `1`.
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            state: Mutex::new(State {
+                limit: max as f64,
+                in_flight: 0,
+                last_decrease: None,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Wait until the number of in-flight operations is below the limit.
+    pub async fn acquire(&self) -> AdaptivePermit<'_> {
+        loop {
+            // register BEFORE checking the state, so a release in between is not missed
+            let notified = self.notify.notified();
+            let mut notified = std::pin::pin!(notified);
+            notified.as_mut().enable();
+
+            {
+                let mut state = self.state.lock().expect("not poisoned");
+                if state.in_flight < state.current() {
+                    state.in_flight += 1;
+                    return AdaptivePermit { limit: self };
+                }
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Current limit.
+    pub fn limit(&self) -> usize {
+        self.state.lock().expect("not poisoned").current()
+    }
+
+    /// Number of operations that currently hold a permit.
+    pub fn in_flight(&self) -> usize {
+        self.state.lock().expect("not poisoned").in_flight
+    }
+
+    /// Record that the server signaled overload.
+    pub(crate) fn on_overload(&self) {
+        self.on_overload_at(Instant::now());
+    }
+
+    fn on_overload_at(&self, now: Instant) {
+        let mut state = self.state.lock().expect("not poisoned");
+        if state
+            .last_decrease
+            .is_some_and(|t| now.saturating_duration_since(t) < DECREASE_COOLDOWN)
+        {
+            return;
+        }
+
+        state.limit = (state.limit / 2.0).max(1.0);
+        state.last_decrease = Some(now);
+        info!(
+            limit = state.current(),
+            "server is overloaded, reduce concurrent downloads"
+        );
+    }
+
+    /// Record successful response.
+    pub(crate) fn on_success(&self) {
+        let mut state = self.state.lock().expect("not poisoned");
+        let max = self.max as f64;
+        if state.limit >= max {
+            return;
+        }
+
+        let before = state.current();
+        state.limit = (state.limit + 1.0 / state.limit).min(max);
+        if state.current() > before {
+            debug!(limit = state.current(), "increase concurrent downloads");
+            drop(state);
+            self.notify.notify_waiters();
+        }
+    }
+}
+
+/// Permit of [`AdaptiveLimit::acquire`], released on drop.
+#[derive(Debug)]
+pub struct AdaptivePermit<'a> {
+    limit: &'a AdaptiveLimit,
+}
+
+impl Drop for AdaptivePermit<'_> {
+    fn drop(&mut self) {
+        self.limit.state.lock().expect("not poisoned").in_flight -= 1;
+        self.limit.notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aimd() {
+        let limit = AdaptiveLimit::new(8);
+        assert_eq!(limit.limit(), 8);
+
+        // healthy responses do not exceed the maximum
+        limit.on_success();
+        assert_eq!(limit.limit(), 8);
+
+        // overload signals within the cooldown count once
+        let t0 = Instant::now();
+        limit.on_overload_at(t0);
+        limit.on_overload_at(t0 + Duration::from_millis(500));
+        assert_eq!(limit.limit(), 4);
+        limit.on_overload_at(t0 + DECREASE_COOLDOWN);
+        assert_eq!(limit.limit(), 2);
+        limit.on_overload_at(t0 + DECREASE_COOLDOWN * 2);
+        limit.on_overload_at(t0 + DECREASE_COOLDOWN * 3);
+        assert_eq!(limit.limit(), 1);
+
+        // additive increase: one step per limit's worth of successes
+        limit.on_success();
+        assert_eq!(limit.limit(), 2);
+        limit.on_success();
+        limit.on_success();
+        assert_eq!(limit.limit(), 2);
+        limit.on_success();
+        assert_eq!(limit.limit(), 3);
+        for _ in 0..100 {
+            limit.on_success();
+        }
+        assert_eq!(limit.limit(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let limit = AdaptiveLimit::new(2);
+        let a = limit.acquire().await;
+        let _b = limit.acquire().await;
+
+        let mut c = std::pin::pin!(limit.acquire());
+        assert!(futures::poll!(c.as_mut()).is_pending());
+
+        drop(a);
+        assert!(futures::poll!(c.as_mut()).is_ready());
+    }
+}
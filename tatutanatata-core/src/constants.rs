@@ -0,0 +1,15 @@
+//! Build information.
+
+/// User agent of all requests.
+///
+/// This names the CLI instead of this crate, since the server does not care about the difference.
+pub(crate) static APP_USER_AGENT: &str = concat!(
+    "tatutanatata/",
+    env!("CARGO_PKG_VERSION"),
+    ", revision ",
+    env!("GIT_HASH")
+);
+
+/// Version and git revision, e.g. for `--version`.
+pub static VERSION_STRING: &str =
+    concat!(env!("CARGO_PKG_VERSION"), ", revision ", env!("GIT_HASH"));
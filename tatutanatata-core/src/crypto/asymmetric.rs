@@ -0,0 +1,404 @@
+//! Asymmetric encryption.
+use anyhow::{anyhow, ensure, Context, Result};
+use hkdf::Hkdf;
+use ml_kem::{
+    ml_kem_1024::Ciphertext as KyberCiphertext, Decapsulate, DecapsulationKey1024,
+    ExpandedDecapsulationKey, KeyExport, MlKem1024,
+};
+use rsa::{BigUint, Oaep, RsaPrivateKey};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret as X25519PrivateKey};
+
+use crate::proto::{enums::CryptoProtocolVersion, keys::Key};
+
+use super::encryption::decrypt_value;
+
+/// Public exponent that is used for all RSA keys.
+const RSA_PUBLIC_EXPONENT: u32 = 65537;
+
+/// Decrypt RSA private key that is encrypted with a symmetric (group) key.
+pub(crate) fn decrypt_rsa_private_key(
+    encryption_key: Key,
+    encrypted: &[u8],
+) -> Result<RsaPrivateKey> {
+    let decrypted = decrypt_value(encryption_key, encrypted).context("decrypt private key")?;
+    parse_rsa_private_key(&decrypted)
+}
+
+/// Parse RSA private key.
+///
+/// The key consists of modulus, private exponent, the two primes, the two CRT exponents, and the CRT coefficient.
+/// Every component is prefixed with its length in hex digits as a 16 bit big-endian integer.
+fn parse_rsa_private_key(mut data: &[u8]) -> Result<RsaPrivateKey> {
+    let mut components = vec![];
+    while !data.is_empty() {
+        ensure!(data.len() >= 2, "component length missing");
+        let hex_len = u16::from_be_bytes([data[0], data[1]]) as usize;
+        data = &data[2..];
+
+        let len = hex_len.div_ceil(2);
+        ensure!(data.len() >= len, "component truncated");
+        components.push(BigUint::from_bytes_be(&data[..len]));
+        data = &data[len..];
+    }
+
+    let [n, d, p, q, _dp, _dq, _qinv] = <[BigUint; 7]>::try_from(components)
+        .map_err(|c| anyhow!("expected 7 key components, got {}", c.len()))?;
+
+    let key = RsaPrivateKey::from_components(n, RSA_PUBLIC_EXPONENT.into(), d, vec![p, q])
+        .context("assemble private key")?;
+    key.validate().context("validate private key")?;
+    Ok(key)
+}
+
+/// Decrypt symmetric key that is encrypted with RSA.
+pub(crate) fn decrypt_rsa_key(private_key: &RsaPrivateKey, encrypted: &[u8]) -> Result<Key> {
+    let decrypted = private_key
+        .decrypt(Oaep::new::<Sha256>(), encrypted)
+        .map_err(|e| anyhow!("{e}"))
+        .context("RSA decryption")?;
+
+    key_from_bytes(decrypted)
+}
+
+/// Hybrid X25519 + Kyber key pair used by TutaCrypt.
+pub(crate) struct PqKeyPair {
+    x25519: X25519PrivateKey,
+    kyber: DecapsulationKey1024,
+}
+
+impl std::fmt::Debug for PqKeyPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PqKeyPair").finish_non_exhaustive()
+    }
+}
+
+/// Decrypt TutaCrypt key pair that is encrypted with a symmetric (group) key.
+pub(crate) fn decrypt_pq_key_pair(
+    encryption_key: Key,
+    encrypted_x25519: &[u8],
+    encrypted_kyber: &[u8],
+) -> Result<PqKeyPair> {
+    let x25519 = decrypt_value(encryption_key, encrypted_x25519).context("decrypt X25519 key")?;
+    let x25519 = <[u8; 32]>::try_from(x25519)
+        .map_err(|k| anyhow!("invalid X25519 private key length: {}", k.len()))?;
+
+    let kyber = decrypt_value(encryption_key, encrypted_kyber).context("decrypt Kyber key")?;
+
+    Ok(PqKeyPair {
+        x25519: X25519PrivateKey::from(x25519),
+        kyber: parse_kyber_private_key(&kyber)?,
+    })
+}
+
+/// Parse Kyber (ML-KEM-1024) private key.
+///
+/// The components of the expanded key of FIPS 203 are stored in a different order: `s`, `H(ek)`, `z`, `t`, `rho`.
+// Tuta stores the expanded key instead of the seed that `ml_kem` prefers.
+#[allow(deprecated)]
+fn parse_kyber_private_key(data: &[u8]) -> Result<DecapsulationKey1024> {
+    let [s, hpk, z, t, rho] = <[&[u8]; 5]>::try_from(split_byte_arrays(data)?)
+        .map_err(|c| anyhow!("expected 5 Kyber key components, got {}", c.len()))?;
+
+    let expanded = [s, t, rho, hpk, z].concat();
+    let expanded = ExpandedDecapsulationKey::<MlKem1024>::try_from(expanded.as_slice())
+        .map_err(|_| anyhow!("invalid Kyber private key length: {}", expanded.len()))?;
+    DecapsulationKey1024::from_expanded(&expanded).map_err(|_| anyhow!("invalid Kyber private key"))
+}
+
+/// Decrypt symmetric key that is encrypted with TutaCrypt.
+///
+/// The message consists of the sender identity key, the ephemeral key, and the encapsulation (Kyber ciphertext and
+/// the encrypted key).
+pub(crate) fn decrypt_pq_key(key_pair: &PqKeyPair, message: &[u8]) -> Result<Key> {
+    let [sender_identity_key, ephemeral_key, encapsulation] =
+        <[&[u8]; 3]>::try_from(split_byte_arrays(message).context("decode message")?)
+            .map_err(|c| anyhow!("expected 3 message components, got {}", c.len()))?;
+    let [kyber_ciphertext, kek_enc_key] =
+        <[&[u8]; 2]>::try_from(split_byte_arrays(encapsulation).context("decode encapsulation")?)
+            .map_err(|c| anyhow!("expected 2 encapsulation components, got {}", c.len()))?;
+
+    let x25519_public = |k: &[u8]| {
+        <[u8; 32]>::try_from(k)
+            .map(X25519PublicKey::from)
+            .map_err(|_| anyhow!("invalid X25519 public key length: {}", k.len()))
+    };
+    let ephemeral_secret = key_pair
+        .x25519
+        .diffie_hellman(&x25519_public(ephemeral_key)?);
+    let auth_secret = key_pair
+        .x25519
+        .diffie_hellman(&x25519_public(sender_identity_key)?);
+    let kyber_secret =
+        key_pair
+            .kyber
+            .decapsulate(&KyberCiphertext::try_from(kyber_ciphertext).map_err(|_| {
+                anyhow!(
+                    "invalid Kyber ciphertext length: {}",
+                    kyber_ciphertext.len()
+                )
+            })?);
+
+    let context = Sha256::new()
+        .chain_update(sender_identity_key)
+        .chain_update(ephemeral_key)
+        .chain_update(X25519PublicKey::from(&key_pair.x25519))
+        .chain_update(key_pair.kyber.encapsulation_key().to_bytes())
+        .chain_update(kyber_ciphertext)
+        .chain_update([protocol_version_byte(CryptoProtocolVersion::TutaCrypt)])
+        .finalize();
+    let ikm = [
+        ephemeral_secret.as_bytes().as_slice(),
+        auth_secret.as_bytes(),
+        kyber_secret.as_slice(),
+    ]
+    .concat();
+    let mut kek = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&context), &ikm)
+        .expand(b"kek", &mut kek)
+        .expect("valid length");
+
+    let key = decrypt_value(Key::Aes256(kek), kek_enc_key).context("decrypt key with KEK")?;
+    key_from_bytes(key)
+}
+
+fn protocol_version_byte(version: CryptoProtocolVersion) -> u8 {
+    match version {
+        CryptoProtocolVersion::Rsa => 0,
+        CryptoProtocolVersion::SecureExternal => 1,
+        CryptoProtocolVersion::TutaCrypt => 2,
+    }
+}
+
+/// Split data into byte arrays that are each prefixed with their length as a 16 bit big-endian integer.
+fn split_byte_arrays(mut data: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut out = vec![];
+    while !data.is_empty() {
+        ensure!(data.len() >= 2, "array length missing");
+        let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+        data = &data[2..];
+
+        ensure!(data.len() >= len, "array truncated");
+        out.push(&data[..len]);
+        data = &data[len..];
+    }
+    Ok(out)
+}
+
+/// Interpret raw bytes as symmetric key.
+pub(crate) fn key_from_bytes(data: Vec<u8>) -> Result<Key> {
+    match data.len() {
+        16 => Ok(Key::Aes128(data.try_into().expect("checked length"))),
+        32 => Ok(Key::Aes256(data.try_into().expect("checked length"))),
+        n => Err(anyhow!("invalid key length: {n}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+    use ml_kem::Seed;
+
+    use super::*;
+
+    const PRIVATE_KEY: [u8; 590] = hex!("0100b3a29aa587fa7e20adfb0d8290fe99b79ec8e061e983b58da62b54fec1d3150c206e668b5e4e79c0c121851b4e2845ec37aca14a385b7eb30a5a179977ef3861d5de85aeb4d9e0394f60b78a5fe5fb041058210a2c4a4ce923e588da88fc3d40d749ccd59e24e8a10d915ee05a5d56257818a5f9f5f94992240e4918f126e1810100071691779aa7fdb61867619f174d948f344ac4dfd8310446f4999d1ef852f5efa68ff4a7e72ebd8b2793c506d0ccb205a224c3b0840cc83114657dace6989fe6db6dbf1116530a88131a7b7532e6438a6b588feb92fe34e84b6ccec4c64dd893b37ad5479cf8a5c7e5b25cd613e89865c322a010774b5fef60f3dd7ab16d71810080e501ad54522b432f3f877f0cf9735df72a6cf01df81b4e57563f9d76aa1a36ed57158cde8442b70e76e2db39380f081c30065ff6a314943e1d1c9abdc9b384690080c8cf228438b217aea08602786a4340203699e3e431a1a3d743bd10a7475c18c8b46b9c947a474aa9ce17caaff843faa5bd3e1bbbb7177845e1cc0537d27ff15900804b38d4cd68bc2b325ee4cbf7e00c642626fa679900813fa7727e2ce3e895f9cad61b0f0d9c5629b9079616ba61ce9634641603f988b1decbd4562c02013a055900804a620fd3127d45b075ddae0494b81c84d234e6e99c48d7bbe167a347937355eae28a0a1225c94aaae3a0847ff2629bf1d0cbb4d22fdc0dbd350ab1d41fb12ce10080a4ef2101effb25c31de4729b5929d47c9ddae345c0bc50cc9b1fed3604562d6a1317ccbcdb3a3925104ff360f329c43e114a11bf1f7caa02d8132b52ba34eb19");
+
+    #[test]
+    fn test_decrypt_rsa_key() {
+        let private_key = parse_rsa_private_key(&PRIVATE_KEY).unwrap();
+
+        let encrypted = hex!("6c555693cfcd89b2086d2dfb573353ff8b05562cee7c9a810cf88f4733c9c1c87743ca71b63797f7a4d952c86844fab2c94a920574097049766df31e47ea1508ea1065cd2524777ce5c8b350ddc44bf1cea4a7765a02c7d6b72ade37a574830a58cf07217fb457ca05a59b33f92590a9e34b4ff811c120d381b0829c0e43a67a");
+        assert_eq!(
+            decrypt_rsa_key(&private_key, &encrypted).unwrap(),
+            Key::Aes128(hex!("c547a0ef919bbe29e5abaeeb6ac75264")),
+        );
+
+        let mut encrypted_broken = encrypted;
+        encrypted_broken[0] ^= 1;
+        assert_eq!(
+            decrypt_rsa_key(&private_key, &encrypted_broken)
+                .unwrap_err()
+                .to_string(),
+            "RSA decryption",
+        );
+    }
+
+    const KYBER_SEED: [u8; 64] = hex!(
+        "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f"
+    );
+    const KYBER_CIPHERTEXT: [u8; 1568] = hex!(
+        "3f3aee255b6d3f5faa90349b05a0af82dd344291f15d735f6ad1762c2092fb001f790b75ce52a051a32f6e4891a6b915cfc7971ab58bb7b2e749f83fc30f1610"
+        "41d39663f69434f976a1533a621005623c10da4bfea0bc715b471e116ecc9e4e342e5bfd123e670d1b12f9f9b435ec7ea54d7d5d788ea2ed42987cb492bd7a84"
+        "d14a8a0b03d33d9bde2a226f57dbb4e250a0602cff2f545212762fb0b8ad21dd80eb0812b4306c533bceaf9294fa9a38232d3a1cc9eb8d47cad63c5f83648bc0"
+        "23933b256044ae1b65d67eb69ebbb9e4b95616d6d327fc12b207d59e92554e64d7772e19d119888b5778ebf94a5c98a7c8ae6f6f71b2b211a945072e75a05002"
+        "d90fbc42bf5475c1c56d3e309b9eeb6a3ce68dd0d1bc176ec4b08775d418646263511ec5f05370e75b1f7bf991aa88848f5e2142d779ac60cc0955578b0741b2"
+        "719f58346fc29de6037bbfb4f3e7b90d3db520b7ff682bcdb971258457b928a03f765f2d7398808fa1d33a38a1bfa0bfe93d045f46a68288c9aa0df2872615f2"
+        "069545533ecc4b22cb6b2c39c51a57b92c37c5a08cf0291ac4bd2970a903be67c848dc4773e2c8b0e415cf7d8994ef587001637688a521ba4766ef16188fa671"
+        "f272dac9271271d71c4977c77fa1582d85af6c84b75fcc7d67863fb7cf581cf6949b5988ceb99a1121a81bb5fa921376177955cac148d894ee3116f3c93fc609"
+        "b2e556a4abe202b4ee41cfad164181ce494f68dab1b9739a54a21127d46731a0ca031cdf8243069d2c91b28d44d582746d1bdcc8b7ae3124ecdc21b1fbb4f2ea"
+        "c06a367bc472aec6e4ebc54880e1d4eb667a6996f080e8c20167d217e55bb83646633b55bbba2f389c3f1baf788c8f11a9cd301aa5cc64c12c2bf79684369d08"
+        "7957cdaf365fa2c6773f44a0f40eefbf2a5aff264a22aa5e62e1622b6731b7667beab77c571675ae0b5d60f84fbeb5b5bc3516a7f3778834a6e64d4e19071dc2"
+        "4bcc328291ca3f250b8599e0842bf09d86dbc81c8319c9171dd0614c71d96c37b2602da4f14a658ab95765f0987bd0ad1f6bb11ccb47289da9084f8e4c277345"
+        "b5c6a27b49197f66f5bc8223b5ec5601234ab241c67c1c8594e9a99b8a35d72bfb61b17f088b1ef1eac44fd268e578b6c28a07ca95a12a5196feffc318e2a17e"
+        "8d1bd0ccf4bec435bc8aa1b2ad3dd4d859e62e76ad19b4bd65fe6478b9283caf975f6d62a5f764179fcdde5b25cab92d2ee04b450fc85b2da774a236f50dd06e"
+        "d20e7b395587b06d059eeef61306774b38401dbbc39301f2261123562136616245eec3d3d8e4006110c2b067855fe22f5d54bdd4c2527d86a44f19bca0f1109c"
+        "8d4b7764abbf66e2508e02d5acfd10d9f9deec387fc0884836ac6122a64f67d8c778f9b8e6ba46159c4591d6bfc4c3aee73b9fb48e92fe4d3b45ca4fc04a0461"
+        "2b91df667d844692c0d38dca447762025fc0deeb6e908976c37a8dd97721a49a51d8a608be5a335dc12a300574999d56197c62f465876b445ea40885c7d09b85"
+        "c809985c7293afd2017735602c512bb13f96741e645ad7958945475386cd62523e325a02e9a663fcb8cdfe8bc5e6ff0cfc3a0906be094e796a4eec36238f41e1"
+        "65e0680af8fdbb097c9192ea4578f37cc6815c8e36d28f0b5d59126ce9d2e25f29f29c89b18870ad2fde3ebb96cb13c2396da44d475af7cd6003f9ccba94de91"
+        "383504829caf3a376fdbbcf53189d15f27bf9a5d6325bc6c7e3bb63a109dbb1ece152215a14a457cf0b750c7b1a011d2a6ac72aa84f6cb9ee0857b09f230bb9d"
+        "c83e578cfabf4f682c0e02aa762852f662f5ecbb128b6dfc48a57aee0bca99b1969d7a4cbc96e572cbc5e3d7eebf64e2a400e5260caca604b002dbdaf9f6560b"
+        "9ea68463825955c173a290a0f29095ecb1b1c5df94de48558e41ea679f8c06ec32eacda0f6b0cfab8e156717c8578a6610328c71f436d78235ed8b50adf15060"
+        "b298247b88a2cef44a269d769b9396861179da8659c8370f868914cd6fa571ac349da81cf53140f45aa8a582c6cc74cd423e9ec0d3cbc2eab03333bb0d548885"
+        "bdee0eacab027dfc3e03acf881ab9b299a593bc928b1722f8b739baaa0153fe3e7fff5c94d029ce9b0745a319468847d465e536a872a4eb2a2374e3296297584"
+        "97257836a02ebacdba079fcc2485640754c4912bb442b71a8cb85f44beb52d76"
+    );
+
+    /// NIST ACVP vectors for ML-KEM-1024.
+    ///
+    /// Taken from the `internalProjection.json` files of `ML-KEM-keyGen-FIPS203` (first two tests) and
+    /// `ML-KEM-encapDecap-FIPS203` (decapsulation tests) of ACVP-Server v1.1.0.38.
+    const ACVP_VECTORS: &str = include_str!("../../testdata/acvp-ml-kem-1024.json");
+
+    #[test]
+    fn test_decrypt_pq_key() {
+        let key_pair = PqKeyPair {
+            x25519: X25519PrivateKey::from([1; 32]),
+            kyber: DecapsulationKey1024::from_seed(Seed::from(KYBER_SEED)),
+        };
+        let sender_identity_key =
+            hex!("ce8d3ad1ccb633ec7b70c17814a5c76ecd029685050d344745ba05870e587d59");
+        let ephemeral_key =
+            hex!("5dfedd3b6bd47f6fa28ee15d969d5bb0ea53774d488bdaf9df1c6e0124b3ef22");
+        let kek_enc_key = hex!("010707070707070707070707070707070790aa62ccbcaa07954f09da09ce4e2f4bcd7a1e4d45c397617544046c80db41a50c5611a8c6b12fff5f4e8b133a33b6b4a12e99fdc877088cc5167a55afedd56b");
+
+        let encapsulation = byte_arrays(&[&KYBER_CIPHERTEXT, &kek_enc_key]);
+        let message = byte_arrays(&[&sender_identity_key, &ephemeral_key, &encapsulation]);
+        assert_eq!(
+            decrypt_pq_key(&key_pair, &message).unwrap(),
+            Key::Aes128(hex!("c547a0ef919bbe29e5abaeeb6ac75264")),
+        );
+
+        // wrong sender
+        let message = byte_arrays(&[&ephemeral_key, &ephemeral_key, &encapsulation]);
+        assert_eq!(
+            decrypt_pq_key(&key_pair, &message).unwrap_err().to_string(),
+            "decrypt key with KEK",
+        );
+
+        let encapsulation = byte_arrays(&[&KYBER_CIPHERTEXT[1..], &kek_enc_key]);
+        let message = byte_arrays(&[&sender_identity_key, &ephemeral_key, &encapsulation]);
+        assert_eq!(
+            decrypt_pq_key(&key_pair, &message).unwrap_err().to_string(),
+            "invalid Kyber ciphertext length: 1567",
+        );
+
+        assert_eq!(
+            decrypt_pq_key(&key_pair, &message[..10])
+                .unwrap_err()
+                .to_string(),
+            "decode message",
+        );
+    }
+
+    #[test]
+    fn test_parse_kyber_private_key_acvp() {
+        let vectors: serde_json::Value = serde_json::from_str(ACVP_VECTORS).unwrap();
+
+        for test in vectors["keyGen"].as_array().unwrap() {
+            let seed = [hex_field(&test["d"]), hex_field(&test["z"])].concat();
+            let ek = hex_field(&test["ek"]);
+            let key =
+                parse_kyber_private_key(&tuta_kyber_private_key(&hex_field(&test["dk"]))).unwrap();
+            assert_eq!(key.encapsulation_key().to_bytes().as_slice(), ek);
+            assert_eq!(
+                key,
+                DecapsulationKey1024::from_seed(Seed::try_from(seed.as_slice()).unwrap()),
+            );
+        }
+
+        let decapsulation = &vectors["decapsulation"];
+        let key =
+            parse_kyber_private_key(&tuta_kyber_private_key(&hex_field(&decapsulation["dk"])))
+                .unwrap();
+        for test in decapsulation["tests"].as_array().unwrap() {
+            let ciphertext = KyberCiphertext::try_from(hex_field(&test["c"]).as_slice()).unwrap();
+            // modified ciphertexts are rejected implicitly, i.e. yield a pseudo-random key
+            assert_eq!(
+                key.decapsulate(&ciphertext).as_slice(),
+                hex_field(&test["k"]),
+                "test {}: {}",
+                test["tcId"],
+                test["reason"],
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_kyber_private_key_errors() {
+        let vectors: serde_json::Value = serde_json::from_str(ACVP_VECTORS).unwrap();
+        let dk = hex_field(&vectors["decapsulation"]["dk"]);
+
+        let mut broken = dk.clone();
+        // last byte of `H(ek)`
+        broken[3135] ^= 1;
+        assert_eq!(
+            parse_kyber_private_key(&tuta_kyber_private_key(&broken))
+                .unwrap_err()
+                .to_string(),
+            "invalid Kyber private key",
+        );
+
+        let truncated = tuta_kyber_private_key(&dk[..dk.len() - 1]);
+        assert_eq!(
+            parse_kyber_private_key(&truncated).unwrap_err().to_string(),
+            "invalid Kyber private key length: 3167",
+        );
+        assert_eq!(
+            parse_kyber_private_key(&byte_arrays(&[&dk]))
+                .unwrap_err()
+                .to_string(),
+            "expected 5 Kyber key components, got 1",
+        );
+    }
+
+    /// Reorder expanded FIPS 203 key into the layout of Tuta, see [`parse_kyber_private_key`].
+    fn tuta_kyber_private_key(dk: &[u8]) -> Vec<u8> {
+        let (s, rest) = dk.split_at(1536);
+        let (t, rest) = rest.split_at(1536);
+        let (rho, rest) = rest.split_at(32);
+        let (hpk, z) = rest.split_at(32);
+        byte_arrays(&[s, hpk, z, t, rho])
+    }
+
+    fn hex_field(value: &serde_json::Value) -> Vec<u8> {
+        let s = value.as_str().unwrap();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn byte_arrays(arrays: &[&[u8]]) -> Vec<u8> {
+        let mut out = vec![];
+        for a in arrays {
+            out.extend_from_slice(&(a.len() as u16).to_be_bytes());
+            out.extend_from_slice(a);
+        }
+        out
+    }
+
+    #[test]
+    fn test_parse_rsa_private_key_errors() {
+        assert_eq!(
+            parse_rsa_private_key(&PRIVATE_KEY[..PRIVATE_KEY.len() - 1])
+                .unwrap_err()
+                .to_string(),
+            "component truncated",
+        );
+        assert_eq!(
+            parse_rsa_private_key(&[0, 2, 1]).unwrap_err().to_string(),
+            "expected 7 key components, got 1",
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use std::ops::Deref;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, ensure, Context, Result};
 use argon2::PasswordHasher;
 use base64::prelude::*;
 use sha2::{Digest, Sha256};
@@ -80,9 +80,33 @@ pub(crate) fn derive_passkey(
     }
 }
 
-pub(crate) fn encode_auth_verifier(passkey: &UserPassphraseKey) -> Base64Url {
+/// Parse account recovery code.
+///
+/// The code is a hex-encoded AES-256 key, usually displayed in groups of four characters.
+pub(crate) fn parse_recovery_code(code: &str) -> Result<Key> {
+    let code = code
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<_>>();
+    ensure!(
+        code.len() == 64,
+        "recovery code must have 64 hex characters but has {}",
+        code.len()
+    );
+
+    let mut key = [0u8; 32];
+    for (k, chunk) in key.iter_mut().zip(code.chunks(2)) {
+        let chunk = chunk.iter().collect::<String>();
+        *k = u8::from_str_radix(&chunk, 16)
+            .with_context(|| format!("invalid hex in recovery code: `{chunk}`"))?;
+    }
+
+    Ok(Key::Aes256(key))
+}
+
+pub(crate) fn encode_auth_verifier(key: &Key) -> Base64Url {
     let mut hasher = Sha256::new();
-    hasher.update(passkey.0);
+    hasher.update(key);
     let hashed = hasher.finalize().to_vec();
 
     Base64Url::from(hashed)
@@ -111,4 +135,27 @@ mod tests {
             "4PBZ1KNEu2DB1OpKjqz8lRTIxq55ESO05TYmjLAQvM8",
         );
     }
+
+    #[test]
+    fn test_parse_recovery_code() {
+        let code =
+            "0102 0304 0506 0708 090a 0b0c 0d0e 0f10 1112 1314 1516 1718 191a 1b1c 1d1e 1f20";
+        let key = parse_recovery_code(code).unwrap();
+        assert_eq!(key, Key::Aes256(std::array::from_fn(|i| i as u8 + 1)),);
+        assert_eq!(
+            encode_auth_verifier(&key).to_string(),
+            "riFsLvUkejeCwTXvonmj5M3GEJQnD10r5YxiBLemEsk",
+        );
+
+        assert_eq!(
+            parse_recovery_code("0102").unwrap_err().to_string(),
+            "recovery code must have 64 hex characters but has 4",
+        );
+        assert_eq!(
+            parse_recovery_code(&"xx".repeat(32))
+                .unwrap_err()
+                .to_string(),
+            "invalid hex in recovery code: `xx`",
+        );
+    }
 }
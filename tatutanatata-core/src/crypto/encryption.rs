@@ -0,0 +1,820 @@
+use std::{io::Write, ops::Deref};
+
+use aes_gcm::{aead::Aead, Aes128Gcm, Aes256Gcm, Nonce};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use cbc::cipher::{
+    block_padding::{NoPadding, Pkcs7},
+    generic_array::GenericArray,
+    BlockDecryptMut, BlockEncrypt, KeyIvInit, StreamCipher,
+};
+use ghash::{universal_hash::UniversalHash, GHash};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::{
+    proto::keys::{EncryptedKey, Key},
+    spool::Spool,
+};
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+type Aes128Ctr = ctr::Ctr32BE<aes::Aes128>;
+type Aes256Ctr = ctr::Ctr32BE<aes::Aes256>;
+type HmacSha256 = Hmac<Sha256>;
+
+const IV_LEN: usize = 16;
+const MAC_LEN: usize = 32;
+
+/// Version byte that prefixes AES-GCM payloads.
+const GCM_VERSION: u8 = 2;
+const GCM_NONCE_LEN: usize = 12;
+const GCM_TAG_LEN: usize = 16;
+
+pub(crate) fn decrypt_key(encryption_key: Key, key_to_be_decrypted: EncryptedKey) -> Result<Key> {
+    let encrypted = match key_to_be_decrypted {
+        EncryptedKey::Aes128NoMac(_) | EncryptedKey::Aes256NoMac(_) => {
+            // add constant IV to encrypted data
+            let iv: [u8; IV_LEN] = [128u8 + 8; IV_LEN];
+            let mut data = Vec::with_capacity(key_to_be_decrypted.len() + iv.len());
+            data.extend_from_slice(&iv);
+            data.extend_from_slice(key_to_be_decrypted.as_ref());
+            data
+        }
+        EncryptedKey::Aes128WithMac(_)
+        | EncryptedKey::Aes256WithMac(_)
+        | EncryptedKey::Aes128Gcm(_)
+        | EncryptedKey::Aes256Gcm(_) => key_to_be_decrypted.deref().to_vec(),
+    };
+
+    let decrypted = decrypt(encryption_key, &encrypted, false)?;
+
+    match key_to_be_decrypted {
+        EncryptedKey::Aes128NoMac(_)
+        | EncryptedKey::Aes128WithMac(_)
+        | EncryptedKey::Aes128Gcm(_) => Ok(Key::Aes128(
+            decrypted
+                .try_into()
+                .map_err(|_| anyhow!("invalid decrypted key length"))?,
+        )),
+        EncryptedKey::Aes256NoMac(_)
+        | EncryptedKey::Aes256WithMac(_)
+        | EncryptedKey::Aes256Gcm(_) => Ok(Key::Aes256(
+            decrypted
+                .try_into()
+                .map_err(|_| anyhow!("invalid decrypted key length"))?,
+        )),
+    }
+}
+
+pub(crate) fn decrypt_value(encryption_key: Key, value: &[u8]) -> Result<Vec<u8>> {
+    if value.is_empty() {
+        return Ok(vec![]);
+    }
+
+    decrypt(encryption_key, value, true)
+}
+
+fn decrypt(encryption_key: Key, value: &[u8], padding: bool) -> Result<Vec<u8>> {
+    if value.first() != Some(&GCM_VERSION) || value.len() < 1 + GCM_NONCE_LEN + GCM_TAG_LEN {
+        return decrypt_cbc(encryption_key, value, padding);
+    }
+
+    match decrypt_gcm(encryption_key, &value[1..]) {
+        Ok(decrypted) => Ok(decrypted),
+        // Unauthenticated legacy data (IV + whole blocks) may start with the version byte by chance. The GCM tag check
+        // is sufficient to tell them apart.
+        Err(e) if value.len().is_multiple_of(IV_LEN) => {
+            decrypt_cbc(encryption_key, value, padding).map_err(|_| e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn decrypt_cbc(encryption_key: Key, value: &[u8], padding: bool) -> Result<Vec<u8>> {
+    let (encryption_key, value) = if value.len() % 2 == 1 {
+        // use mac
+        if value.len() < MAC_LEN + 1 {
+            bail!("mac missing")
+        }
+        let payload = &value[1..(value.len() - MAC_LEN)];
+        let mac = &value[value.len() - MAC_LEN..];
+        let subkeys = Subkeys::from(encryption_key);
+
+        // check mac
+        let mut m = HmacSha256::new_from_slice(&subkeys.mac_key).expect("checked length");
+        m.update(payload);
+        m.verify_slice(mac)
+            .map_err(|e| anyhow!("{e}"))
+            .context("HMAC verification")?;
+
+        (subkeys.encryption_key, payload)
+    } else {
+        (encryption_key, value)
+    };
+
+    // get IV
+    if value.len() < IV_LEN {
+        bail!("IV missing")
+    }
+    let iv: [u8; IV_LEN] = value[..IV_LEN].try_into().expect("checked length");
+    let value = &value[IV_LEN..];
+
+    match encryption_key {
+        Key::Aes128(k) => {
+            if padding {
+                Aes128CbcDec::new(&k.into(), &iv.into())
+                    .decrypt_padded_vec_mut::<Pkcs7>(value)
+                    .map_err(|e| anyhow!("{e}"))
+                    .context("AES decryption")
+            } else {
+                Aes128CbcDec::new(&k.into(), &iv.into())
+                    .decrypt_padded_vec_mut::<NoPadding>(value)
+                    .map_err(|e| anyhow!("{e}"))
+                    .context("AES decryption")
+            }
+        }
+        Key::Aes256(k) => {
+            if padding {
+                Aes256CbcDec::new(&k.into(), &iv.into())
+                    .decrypt_padded_vec_mut::<Pkcs7>(value)
+                    .map_err(|e| anyhow!("{e}"))
+                    .context("AES decryption")
+            } else {
+                Aes256CbcDec::new(&k.into(), &iv.into())
+                    .decrypt_padded_vec_mut::<NoPadding>(value)
+                    .map_err(|e| anyhow!("{e}"))
+                    .context("AES decryption")
+            }
+        }
+    }
+}
+
+/// Decrypt AES-GCM payload that consists of nonce, ciphertext, and tag.
+fn decrypt_gcm(encryption_key: Key, value: &[u8]) -> Result<Vec<u8>> {
+    let (nonce, value) = value.split_at(GCM_NONCE_LEN);
+    let nonce = Nonce::from_slice(nonce);
+
+    match encryption_key {
+        Key::Aes128(k) => <Aes128Gcm as aes_gcm::KeyInit>::new(&k.into()).decrypt(nonce, value),
+        Key::Aes256(k) => <Aes256Gcm as aes_gcm::KeyInit>::new(&k.into()).decrypt(nonce, value),
+    }
+    .map_err(|e| anyhow!("{e}"))
+    .context("AES-GCM decryption")
+}
+
+/// Decrypts a value chunk by chunk, the streaming counterpart of [`decrypt_value`].
+///
+/// The length of the encrypted value has to be known upfront, since it determines the format. Plaintext is written as
+/// soon as it is decrypted, but it is only authenticated by [`finish`](Self::finish), so it must NOT be used unless
+/// that succeeds.
+pub(crate) struct ValueDecryptor {
+    state: DecryptorState,
+}
+
+enum DecryptorState {
+    /// The format is decided by the first byte.
+    Start {
+        key: Key,
+        len: usize,
+    },
+
+    Single(Box<Framed>),
+
+    /// Legacy AES-CBC data that starts with the AES-GCM version byte by chance, see [`decrypt`].
+    ///
+    /// Only the GCM tag at the end tells them apart, so both are decrypted into separate spools.
+    Ambiguous {
+        gcm: Box<(Framed, Spool)>,
+        cbc: Box<(Framed, Spool)>,
+    },
+}
+
+impl ValueDecryptor {
+    /// Decryptor for an encrypted value of `len` bytes.
+    pub(crate) fn new(encryption_key: Key, len: usize) -> Self {
+        Self {
+            state: DecryptorState::Start {
+                key: encryption_key,
+                len,
+            },
+        }
+    }
+
+    /// Decrypt the next chunk of the encrypted value and write the plaintext that is available so far.
+    pub(crate) fn update(&mut self, chunk: &[u8], out: &mut impl Write) -> Result<()> {
+        if let (DecryptorState::Start { key, len }, Some(first)) = (&self.state, chunk.first()) {
+            let (key, len) = (*key, *len);
+            self.state = if *first != GCM_VERSION || len < 1 + GCM_NONCE_LEN + GCM_TAG_LEN {
+                DecryptorState::Single(Box::new(Framed::cbc(key, len)?))
+            } else if len.is_multiple_of(IV_LEN) {
+                DecryptorState::Ambiguous {
+                    gcm: Box::new((Framed::gcm(key, len), Spool::new())),
+                    cbc: Box::new((Framed::cbc(key, len)?, Spool::new())),
+                }
+            } else {
+                DecryptorState::Single(Box::new(Framed::gcm(key, len)))
+            };
+        }
+
+        match &mut self.state {
+            DecryptorState::Start { .. } => Ok(()),
+            DecryptorState::Single(framed) => framed.update(chunk, out),
+            DecryptorState::Ambiguous { gcm, cbc } => {
+                let (framed, spool) = gcm.as_mut();
+                framed.update(chunk, spool)?;
+                let (framed, spool) = cbc.as_mut();
+                framed.update(chunk, spool)
+            }
+        }
+    }
+
+    /// Check that the value was complete and authentic, and write the remaining plaintext.
+    pub(crate) fn finish(self, out: &mut impl Write) -> Result<()> {
+        match self.state {
+            DecryptorState::Start { len, .. } => {
+                ensure!(len == 0, "encrypted value is truncated");
+                Ok(())
+            }
+            DecryptorState::Single(framed) => framed.finish(out),
+            DecryptorState::Ambiguous { gcm, cbc } => {
+                let (gcm, mut gcm_out) = *gcm;
+                let (cbc, mut cbc_out) = *cbc;
+                let spool = match gcm.finish(&mut gcm_out) {
+                    Ok(()) => gcm_out,
+                    Err(e) => match cbc.finish(&mut cbc_out) {
+                        Ok(()) => cbc_out,
+                        Err(_) => return Err(e),
+                    },
+                };
+                std::io::copy(&mut spool.reader()?, out).context("copy decrypted data")?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Encrypted value that consists of a header, IV or nonce, ciphertext, and authentication tag.
+struct Framed {
+    /// Number of bytes of the encrypted value that were processed.
+    pos: usize,
+
+    iv_start: usize,
+    ciphertext_start: usize,
+    tag_start: usize,
+    len: usize,
+
+    iv: Vec<u8>,
+    tag: Vec<u8>,
+
+    /// Ciphertext that is not decrypted yet, because it does not fill a block or may be the last one.
+    pending: Vec<u8>,
+
+    cipher: StreamingCipher,
+}
+
+enum StreamingCipher {
+    Cbc {
+        key: Key,
+        mac: Option<HmacSha256>,
+        dec: Option<CbcDec>,
+    },
+    Gcm {
+        key: Key,
+        state: Option<GcmState>,
+    },
+}
+
+enum CbcDec {
+    Aes128(Box<Aes128CbcDec>),
+    Aes256(Box<Aes256CbcDec>),
+}
+
+struct GcmState {
+    ctr: GcmCtr,
+    ghash: GHash,
+    mask: [u8; GCM_TAG_LEN],
+}
+
+enum GcmCtr {
+    Aes128(Box<Aes128Ctr>),
+    Aes256(Box<Aes256Ctr>),
+}
+
+impl Framed {
+    /// AES-CBC value, optionally with version byte and HMAC, see [`decrypt_cbc`].
+    fn cbc(encryption_key: Key, len: usize) -> Result<Self> {
+        if len % 2 == 1 {
+            if len < MAC_LEN + 1 {
+                bail!("mac missing")
+            }
+            if len - MAC_LEN - 1 < IV_LEN {
+                bail!("IV missing")
+            }
+            let subkeys = Subkeys::from(encryption_key);
+            Ok(Self::new(
+                1,
+                1 + IV_LEN,
+                len - MAC_LEN,
+                len,
+                StreamingCipher::Cbc {
+                    key: subkeys.encryption_key,
+                    mac: Some(
+                        HmacSha256::new_from_slice(&subkeys.mac_key).expect("checked length"),
+                    ),
+                    dec: None,
+                },
+            ))
+        } else {
+            if len < IV_LEN {
+                bail!("IV missing")
+            }
+            Ok(Self::new(
+                0,
+                IV_LEN,
+                len,
+                len,
+                StreamingCipher::Cbc {
+                    key: encryption_key,
+                    mac: None,
+                    dec: None,
+                },
+            ))
+        }
+    }
+
+    /// AES-GCM value with version byte, see [`decrypt_gcm`].
+    fn gcm(encryption_key: Key, len: usize) -> Self {
+        Self::new(
+            1,
+            1 + GCM_NONCE_LEN,
+            len - GCM_TAG_LEN,
+            len,
+            StreamingCipher::Gcm {
+                key: encryption_key,
+                state: None,
+            },
+        )
+    }
+
+    fn new(
+        iv_start: usize,
+        ciphertext_start: usize,
+        tag_start: usize,
+        len: usize,
+        cipher: StreamingCipher,
+    ) -> Self {
+        Self {
+            pos: 0,
+            iv_start,
+            ciphertext_start,
+            tag_start,
+            len,
+            iv: Vec::with_capacity(ciphertext_start - iv_start),
+            tag: Vec::with_capacity(len - tag_start),
+            pending: Vec::new(),
+            cipher,
+        }
+    }
+
+    /// Take the part of `chunk` up to position `end` of the encrypted value.
+    fn take<'a>(&mut self, chunk: &mut &'a [u8], end: usize) -> &'a [u8] {
+        let n = end.saturating_sub(self.pos).min(chunk.len());
+        let (head, tail) = chunk.split_at(n);
+        *chunk = tail;
+        self.pos += n;
+        head
+    }
+
+    fn update(&mut self, mut chunk: &[u8], out: &mut impl Write) -> Result<()> {
+        // version byte
+        self.take(&mut chunk, self.iv_start);
+
+        let iv = self.take(&mut chunk, self.ciphertext_start);
+        self.iv.extend_from_slice(iv);
+        if let StreamingCipher::Cbc { mac: Some(mac), .. } = &mut self.cipher {
+            mac.update(iv);
+        }
+        if self.pos == self.ciphertext_start {
+            self.cipher.init(&self.iv);
+        }
+
+        let ciphertext = self.take(&mut chunk, self.tag_start);
+        if !ciphertext.is_empty() {
+            self.pending.extend_from_slice(ciphertext);
+            self.cipher.decrypt(&mut self.pending, ciphertext, out)?;
+        }
+
+        let tag = self.take(&mut chunk, self.len);
+        self.tag.extend_from_slice(tag);
+
+        ensure!(chunk.is_empty(), "encrypted value is longer than expected");
+        Ok(())
+    }
+
+    fn finish(mut self, out: &mut impl Write) -> Result<()> {
+        ensure!(self.pos == self.len, "encrypted value is truncated");
+
+        match self.cipher {
+            StreamingCipher::Cbc { mac, dec, .. } => {
+                if let Some(mac) = mac {
+                    mac.verify_slice(&self.tag)
+                        .map_err(|e| anyhow!("{e}"))
+                        .context("HMAC verification")?;
+                }
+
+                let dec = dec.context("AES decryption")?;
+                let plain = match dec {
+                    CbcDec::Aes128(dec) => dec.decrypt_padded_mut::<Pkcs7>(&mut self.pending),
+                    CbcDec::Aes256(dec) => dec.decrypt_padded_mut::<Pkcs7>(&mut self.pending),
+                }
+                .map_err(|e| anyhow!("{e}"))
+                .context("AES decryption")?;
+                out.write_all(plain).context("write decrypted data")?;
+            }
+            StreamingCipher::Gcm { state, .. } => {
+                let GcmState {
+                    mut ctr,
+                    mut ghash,
+                    mask,
+                } = state.context("AES-GCM decryption")?;
+
+                ghash.update_padded(&self.pending);
+                let ciphertext_bits = ((self.tag_start - self.ciphertext_start) as u64) * 8;
+                let mut lengths = ghash::Block::default();
+                lengths[8..].copy_from_slice(&ciphertext_bits.to_be_bytes());
+                ghash.update(&[lengths]);
+
+                let mut expected = ghash::Block::default();
+                for ((e, t), m) in expected.iter_mut().zip(&self.tag).zip(&mask) {
+                    *e = t ^ m;
+                }
+                ghash
+                    .verify(&expected)
+                    .map_err(|e| anyhow!("{e}"))
+                    .context("AES-GCM decryption")?;
+
+                ctr.apply_keystream(&mut self.pending);
+                out.write_all(&self.pending)
+                    .context("write decrypted data")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StreamingCipher {
+    /// Set up the cipher once the IV or nonce is complete.
+    fn init(&mut self, iv: &[u8]) {
+        match self {
+            Self::Cbc { key, dec, .. } => {
+                let iv = GenericArray::from_slice(iv);
+                *dec = Some(match key {
+                    Key::Aes128(k) => CbcDec::Aes128(Box::new(Aes128CbcDec::new(&(*k).into(), iv))),
+                    Key::Aes256(k) => CbcDec::Aes256(Box::new(Aes256CbcDec::new(&(*k).into(), iv))),
+                });
+            }
+            Self::Gcm { key, state } => {
+                // same as `aes_gcm` for 96-bit nonces, but the tag is checked after decryption
+                let mut j0 = [0u8; GCM_TAG_LEN];
+                j0[..GCM_NONCE_LEN].copy_from_slice(iv);
+                j0[GCM_TAG_LEN - 1] = 1;
+
+                let mut h = ghash::Block::default();
+                let mut ctr = match key {
+                    Key::Aes128(k) => {
+                        <aes::Aes128 as aes::cipher::KeyInit>::new(&(*k).into())
+                            .encrypt_block(&mut h);
+                        GcmCtr::Aes128(Box::new(Aes128Ctr::new(&(*k).into(), &j0.into())))
+                    }
+                    Key::Aes256(k) => {
+                        <aes::Aes256 as aes::cipher::KeyInit>::new(&(*k).into())
+                            .encrypt_block(&mut h);
+                        GcmCtr::Aes256(Box::new(Aes256Ctr::new(&(*k).into(), &j0.into())))
+                    }
+                };
+
+                // the first block of the key stream masks the tag
+                let mut mask = [0u8; GCM_TAG_LEN];
+                ctr.apply_keystream(&mut mask);
+
+                *state = Some(GcmState {
+                    ctr,
+                    ghash: <GHash as ghash::universal_hash::KeyInit>::new(&h),
+                    mask,
+                });
+            }
+        }
+    }
+
+    /// Decrypt the complete blocks of `pending` and write them to `out`.
+    ///
+    /// `ciphertext` is the part of `pending` that was just added.
+    fn decrypt(
+        &mut self,
+        pending: &mut Vec<u8>,
+        ciphertext: &[u8],
+        out: &mut impl Write,
+    ) -> Result<()> {
+        match self {
+            Self::Cbc { mac, dec, .. } => {
+                if let Some(mac) = mac {
+                    mac.update(ciphertext);
+                }
+
+                // the last block is unpadded by `finish`
+                let n = pending.len().saturating_sub(1) / IV_LEN * IV_LEN;
+                let dec = dec.as_mut().expect("initialized after IV");
+                for block in pending[..n].chunks_exact_mut(IV_LEN) {
+                    let block = GenericArray::from_mut_slice(block);
+                    match dec {
+                        CbcDec::Aes128(dec) => dec.decrypt_block_mut(block),
+                        CbcDec::Aes256(dec) => dec.decrypt_block_mut(block),
+                    }
+                }
+                out.write_all(&pending[..n])
+                    .context("write decrypted data")?;
+                pending.drain(..n);
+            }
+            Self::Gcm { state, .. } => {
+                let GcmState { ctr, ghash, .. } = state.as_mut().expect("initialized after nonce");
+
+                let n = pending.len() / GCM_TAG_LEN * GCM_TAG_LEN;
+                ghash.update_padded(&pending[..n]);
+                ctr.apply_keystream(&mut pending[..n]);
+                out.write_all(&pending[..n])
+                    .context("write decrypted data")?;
+                pending.drain(..n);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GcmCtr {
+    fn apply_keystream(&mut self, data: &mut [u8]) {
+        match self {
+            Self::Aes128(ctr) => ctr.apply_keystream(data),
+            Self::Aes256(ctr) => ctr.apply_keystream(data),
+        }
+    }
+}
+
+/// Encrypt key with AES-GCM, the inverse of [`decrypt_key`].
+#[cfg(any(test, feature = "test-harness"))]
+pub(crate) fn encrypt_key(encryption_key: Key, key: Key) -> EncryptedKey {
+    let encrypted = encrypt_value(encryption_key, &key);
+    match key {
+        Key::Aes128(_) => EncryptedKey::Aes128Gcm(encrypted.try_into().expect("GCM length")),
+        Key::Aes256(_) => EncryptedKey::Aes256Gcm(encrypted.try_into().expect("GCM length")),
+    }
+}
+
+/// Encrypt value with AES-GCM and a random nonce, the inverse of [`decrypt_value`].
+#[cfg(any(test, feature = "test-harness"))]
+pub(crate) fn encrypt_value(encryption_key: Key, value: &[u8]) -> Vec<u8> {
+    let nonce: [u8; GCM_NONCE_LEN] = rand::random();
+    let encrypted = match encryption_key {
+        Key::Aes128(k) => {
+            <Aes128Gcm as aes_gcm::KeyInit>::new(&k.into()).encrypt(&nonce.into(), value)
+        }
+        Key::Aes256(k) => {
+            <Aes256Gcm as aes_gcm::KeyInit>::new(&k.into()).encrypt(&nonce.into(), value)
+        }
+    }
+    .expect("AES-GCM encryption does not fail for in-memory data");
+
+    let mut out = vec![GCM_VERSION];
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&encrypted);
+    out
+}
+
+struct Subkeys {
+    encryption_key: Key,
+    mac_key: Key,
+}
+
+impl From<Key> for Subkeys {
+    fn from(k: Key) -> Self {
+        match k {
+            Key::Aes128(k) => {
+                let mut hasher = Sha256::new();
+                hasher.update(k);
+                let hashed = hasher.finalize().to_vec();
+
+                Self {
+                    encryption_key: Key::Aes128(hashed[..16].try_into().expect("check length")),
+                    mac_key: Key::Aes128(hashed[16..].try_into().expect("check length")),
+                }
+            }
+            Key::Aes256(k) => {
+                let mut hasher = Sha512::new();
+                hasher.update(k);
+                let hashed = hasher.finalize().to_vec();
+
+                Self {
+                    encryption_key: Key::Aes256(hashed[..32].try_into().expect("check length")),
+                    mac_key: Key::Aes256(hashed[32..].try_into().expect("check length")),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_decrypt_key() {
+        assert_eq!(
+            decrypt_key(
+                Key::Aes128(hex!("0102030405060708090a0b0c0d0e0f10")),
+                EncryptedKey::Aes128NoMac(hex!("0a141e28323c46505a646e78828c96a0")),
+            )
+            .unwrap(),
+            Key::Aes128(hex!("b10b0b75204b020f6be6f85e1a0b8f00")),
+        );
+
+        assert_eq!(
+            decrypt_key(
+                Key::Aes128(hex!("0102030405060708090a0b0c0d0e0f10")),
+                EncryptedKey::Aes256NoMac(hex!(
+                    "2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a"
+                )),
+            )
+            .unwrap(),
+            Key::Aes256(hex!(
+                "a7e4f05300dda8d576d20ce2f818e3c3054652f1a27f0a77d470ae405aba4161"
+            )),
+        );
+
+        assert_eq!(
+            decrypt_key(
+                Key::Aes256(hex!("a812fd92b4a09011b51799477e8c057abd6de8d9021a8289bfe4210d6812dcc0")),
+                EncryptedKey::Aes128WithMac(hex!("011155a44089b3b56c809d1fd7d1a922476a5c13de555b78a7258b8b3f37c5ba839e10bbe06529a35bffaa6b2582d9b8a77b1f75247e2a7ca238202abe2f3ff55f")),
+            )
+            .unwrap(),
+            Key::Aes128(hex!("c547a0ef919bbe29e5abaeeb6ac75264")),
+        );
+
+        assert_eq!(
+            decrypt_key(
+                Key::Aes256(hex!("a812fd92b4a09011b51799477e8c057abd6de8d9021a8289bfe4210d6812dcc0")),
+                EncryptedKey::Aes256WithMac(hex!("01000102030405060708090a0b0c0d0e0f3a065df63b937d265dd65639208e4481a57f40a542242206f96a12a791175b2450641737c1754467d632711c17ef338219b9183993a5e3bdcc76d592d8b7be18")),
+            )
+            .unwrap(),
+            Key::Aes256(hex!(
+                "a7e4f05300dda8d576d20ce2f818e3c3054652f1a27f0a77d470ae405aba4161"
+            )),
+        );
+    }
+
+    #[test]
+    fn test_decrypt_key_gcm() {
+        let k = Key::Aes256(hex!(
+            "a334e6864cc70d3d7c453a500301c6dbd7332a083b4c37bc65a5d1a76fcd803c"
+        ));
+        let key_128 = hex!("c547a0ef919bbe29e5abaeeb6ac75264");
+        let key_256 = hex!("a7e4f05300dda8d576d20ce2f818e3c3054652f1a27f0a77d470ae405aba4161");
+
+        assert_eq!(
+            decrypt_key(k, encrypt_key(k, Key::Aes128(key_128))).unwrap(),
+            Key::Aes128(key_128),
+        );
+        assert_eq!(
+            decrypt_key(k, encrypt_key(k, Key::Aes256(key_256))).unwrap(),
+            Key::Aes256(key_256),
+        );
+    }
+
+    #[test]
+    fn test_decrypt_value_gcm() {
+        for k in [
+            Key::Aes128(hex!("0102030405060708090a0b0c0d0e0f10")),
+            Key::Aes256(hex!(
+                "a334e6864cc70d3d7c453a500301c6dbd7332a083b4c37bc65a5d1a76fcd803c"
+            )),
+        ] {
+            // cover lengths that are a multiple of the CBC block size as well
+            for plain in [b"fooooo".as_slice(), b"foo", b""] {
+                let v = encrypt_value(k, plain);
+                assert_eq!(decrypt_value(k, &v).unwrap(), plain);
+
+                let mut v_broken = v;
+                *v_broken.last_mut().unwrap() ^= 1;
+                assert_eq!(
+                    decrypt_value(k, &v_broken).unwrap_err().to_string(),
+                    "AES-GCM decryption",
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_decrypt_value() {
+        let k = Key::Aes256(hex!(
+            "a334e6864cc70d3d7c453a500301c6dbd7332a083b4c37bc65a5d1a76fcd803c"
+        ));
+
+        let v = [
+            1, 1, 221, 88, 186, 70, 178, 125, 28, 66, 245, 102, 7, 214, 121, 162, 88, 138, 118,
+            208, 12, 173, 154, 251, 201, 68, 94, 254, 228, 178, 138, 73, 52, 118, 21, 143, 248,
+            117, 32, 158, 29, 154, 194, 98, 55, 215, 5, 129, 18, 13, 32, 165, 44, 185, 129, 14, 78,
+            146, 134, 10, 134, 81, 50, 252, 212,
+        ];
+        assert_eq!(decrypt_value(k, &v,).unwrap(), b"fooooo".to_owned(),);
+
+        assert_eq!(decrypt_value(k, &[]).unwrap(), b"".to_owned());
+
+        let mut v_broken = v;
+        v_broken[1] = 0;
+        assert_eq!(
+            decrypt_value(k, &v_broken).unwrap_err().to_string(),
+            "HMAC verification",
+        );
+    }
+
+    #[test]
+    fn test_value_decryptor() {
+        let k = Key::Aes256(hex!(
+            "a334e6864cc70d3d7c453a500301c6dbd7332a083b4c37bc65a5d1a76fcd803c"
+        ));
+        let plain = (0..1000).map(|i| i as u8).collect::<Vec<_>>();
+
+        // AES-CBC with HMAC, see `test_decrypt_value`
+        let cbc = [
+            1, 1, 221, 88, 186, 70, 178, 125, 28, 66, 245, 102, 7, 214, 121, 162, 88, 138, 118,
+            208, 12, 173, 154, 251, 201, 68, 94, 254, 228, 178, 138, 73, 52, 118, 21, 143, 248,
+            117, 32, 158, 29, 154, 194, 98, 55, 215, 5, 129, 18, 13, 32, 165, 44, 185, 129, 14, 78,
+            146, 134, 10, 134, 81, 50, 252, 212,
+        ];
+        // legacy AES-CBC that starts with the AES-GCM version byte
+        let legacy = encrypt_cbc(k, [GCM_VERSION; IV_LEN], &plain);
+
+        for v in [
+            cbc.to_vec(),
+            legacy,
+            encrypt_value(k, &plain),
+            encrypt_value(k, &plain[..13]),
+            encrypt_value(k, b""),
+            vec![],
+        ] {
+            for chunk_size in [1, 7, 16, 100, 10_000] {
+                assert_eq!(
+                    decrypt_chunked(k, &v, chunk_size).unwrap(),
+                    decrypt_value(k, &v).unwrap(),
+                );
+            }
+        }
+
+        let mut cbc_broken = cbc;
+        cbc_broken[1] = 0;
+        assert_eq!(
+            decrypt_chunked(k, &cbc_broken, 7).unwrap_err().to_string(),
+            "HMAC verification",
+        );
+
+        let mut gcm_broken = encrypt_value(k, &plain);
+        gcm_broken[100] ^= 1;
+        assert_eq!(
+            decrypt_chunked(k, &gcm_broken, 7).unwrap_err().to_string(),
+            "AES-GCM decryption",
+        );
+
+        let v = encrypt_value(k, &plain);
+        let mut decryptor = ValueDecryptor::new(k, v.len() + 1);
+        decryptor.update(&v, &mut Vec::new()).unwrap();
+        assert_eq!(
+            decryptor.finish(&mut Vec::new()).unwrap_err().to_string(),
+            "encrypted value is truncated",
+        );
+    }
+
+    fn decrypt_chunked(k: Key, value: &[u8], chunk_size: usize) -> Result<Vec<u8>> {
+        let mut decryptor = ValueDecryptor::new(k, value.len());
+        let mut out = vec![];
+        for chunk in value.chunks(chunk_size) {
+            decryptor.update(chunk, &mut out)?;
+        }
+        decryptor.finish(&mut out)?;
+        Ok(out)
+    }
+
+    /// Encrypt value with AES-CBC without HMAC, as done by old clients.
+    fn encrypt_cbc(k: Key, iv: [u8; IV_LEN], value: &[u8]) -> Vec<u8> {
+        use cbc::cipher::BlockEncryptMut;
+
+        let Key::Aes256(k) = k else {
+            unreachable!("test key")
+        };
+        let mut out = iv.to_vec();
+        out.extend(
+            cbc::Encryptor::<aes::Aes256>::new(&k.into(), &iv.into())
+                .encrypt_padded_vec_mut::<Pkcs7>(value),
+        );
+        out
+    }
+}
@@ -1,4 +1,5 @@
 //! Crypto methods.
 
+pub(crate) mod asymmetric;
 pub(crate) mod auth;
 pub(crate) mod encryption;
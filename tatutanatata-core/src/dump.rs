@@ -0,0 +1,343 @@
+//! Dump server responses to disk and replay them later.
+//!
+//! Every dumped response is stored in its own file. An index file (see [`INDEX_FILE`]) maps the requests to these
+//! files, which allows [`Replay`] to serve the responses without network access. The index also records the order of
+//! the responses and request details that are NOT needed for the replay, so that protocol issues can be reconstructed.
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::client::Request;
+
+/// Name of the index file within the dump directory.
+const INDEX_FILE: &str = "index.jsonl";
+
+/// Query parameters and body fields that change between sessions or builds and are therefore NOT part of the
+/// [`RequestKey`].
+const VOLATILE_FIELDS: &[&str] = &["accessToken", "blobAccessToken", "clientIdentifier"];
+
+/// Identifies a request independently of the session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RequestKey {
+    method: String,
+    prefix: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: serde_json::Value,
+}
+
+impl RequestKey {
+    pub(crate) fn new<Req>(r: &Request<'_, Req>) -> Result<Self>
+    where
+        Req: serde::Serialize + Sync,
+    {
+        let mut body = serde_json::to_value(r.data).context("serialize request body")?;
+        if let Some(body) = body.as_object_mut() {
+            body.retain(|k, _v| !VOLATILE_FIELDS.contains(&k.as_str()));
+        }
+
+        Ok(Self {
+            method: r.method.to_string(),
+            prefix: r.prefix.str().to_owned(),
+            path: r.path.to_owned(),
+            query: r
+                .query
+                .iter()
+                .filter(|(k, _v)| !VOLATILE_FIELDS.contains(k))
+                .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+                .collect(),
+            body,
+        })
+    }
+
+    /// Canonical string representation, used for lookups.
+    fn canonical(&self) -> String {
+        serde_json::to_string(self).expect("serialization always works")
+    }
+}
+
+/// Request details that are NOT part of the [`RequestKey`].
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestMeta {
+    /// Position of the response within the dump, starting at `0`.
+    seq: u64,
+
+    /// Time the response was dumped.
+    time: DateTime<Utc>,
+
+    /// Server URL if the request was NOT sent to the default server, e.g. for blobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+
+    /// Names of the headers, query parameters, and body fields that were set but are NOT recorded, e.g. access tokens.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    redacted: Vec<String>,
+}
+
+impl RequestMeta {
+    fn new<Req>(r: &Request<'_, Req>, seq: u64, time: DateTime<Utc>) -> Result<Self>
+    where
+        Req: serde::Serialize + Sync,
+    {
+        let mut redacted = vec![];
+        if r.access_token.is_some() {
+            redacted.push("accessToken".to_owned());
+        }
+        redacted.extend(
+            r.query
+                .iter()
+                .filter(|(k, _v)| VOLATILE_FIELDS.contains(k))
+                .map(|(k, _v)| format!("query.{k}")),
+        );
+        let body = serde_json::to_value(r.data).context("serialize request body")?;
+        if let Some(body) = body.as_object() {
+            redacted.extend(
+                body.keys()
+                    .filter(|k| VOLATILE_FIELDS.contains(&k.as_str()))
+                    .map(|k| format!("body.{k}")),
+            );
+        }
+
+        Ok(Self {
+            seq,
+            time,
+            host: r.host.map(ToOwned::to_owned),
+            redacted,
+        })
+    }
+}
+
+/// Entry of the [index file](INDEX_FILE).
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexEntry {
+    /// Response file, relative to the dump directory.
+    file: String,
+
+    #[serde(flatten)]
+    key: RequestKey,
+
+    /// [`None`] for dumps that were written by older versions.
+    #[serde(flatten)]
+    meta: Option<RequestMeta>,
+}
+
+/// Writes server responses to a directory.
+#[derive(Debug)]
+pub(crate) struct Dump {
+    path: PathBuf,
+    index: Mutex<tokio::fs::File>,
+
+    /// Sequence number of the next response, see [`RequestMeta::seq`].
+    next_seq: AtomicU64,
+}
+
+impl Dump {
+    pub(crate) async fn try_new(path: PathBuf) -> Result<Self> {
+        tokio::fs::create_dir_all(&path)
+            .await
+            .context("creating directories to dump JSON data")?;
+
+        let index_path = path.join(INDEX_FILE);
+        // continue the sequence of an existing dump
+        let n_existing = match tokio::fs::read_to_string(&index_path).await {
+            Ok(index) => index.lines().filter(|l| !l.trim().is_empty()).count() as u64,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e).context("read dump index"),
+        };
+        let index = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&index_path)
+            .await
+            .context("open dump index")?;
+
+        Ok(Self {
+            path,
+            index: Mutex::new(index),
+            next_seq: AtomicU64::new(n_existing),
+        })
+    }
+
+    /// Dump response to the given request and return the path of the written file.
+    ///
+    /// `extension` should be `json` for JSON data and `bin` for everything else.
+    pub(crate) async fn write<Req>(
+        &self,
+        r: &Request<'_, Req>,
+        extension: &str,
+        data: &[u8],
+    ) -> Result<PathBuf>
+    where
+        Req: serde::Serialize + Sync,
+    {
+        let key = RequestKey::new(r)?;
+        let uuid = Uuid::new_v4();
+        let file = format!("{uuid}.{extension}");
+        let path = self.path.join(&file);
+        debug!(%uuid, path=%path.display(), "dumping response");
+        tokio::fs::write(&path, data)
+            .await
+            .context("dumping response")?;
+
+        // assign the sequence number under the lock, so the index is ordered
+        let mut index = self.index.lock().await;
+        let meta = RequestMeta::new(r, self.next_seq.fetch_add(1, Ordering::SeqCst), Utc::now())?;
+        let mut line = serde_json::to_vec(&IndexEntry {
+            file,
+            key,
+            meta: Some(meta),
+        })
+        .context("serialize index")?;
+        line.push(b'\n');
+        index.write_all(&line).await.context("write index")?;
+        index.flush().await.context("flush index")?;
+
+        Ok(path)
+    }
+}
+
+/// Serves responses from a directory that was written by [`Dump`].
+#[derive(Debug)]
+pub(crate) struct Replay {
+    path: PathBuf,
+    files: HashMap<String, String>,
+}
+
+impl Replay {
+    pub(crate) fn try_new(path: PathBuf) -> Result<Self> {
+        let index = std::fs::read_to_string(path.join(INDEX_FILE)).context("read dump index")?;
+
+        let mut files = HashMap::new();
+        for (idx, line) in index.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: IndexEntry = serde_json::from_str(line)
+                .with_context(|| format!("parse dump index line {}", idx + 1))?;
+
+            // the first response wins, that's also the one that the original run acted upon
+            files.entry(entry.key.canonical()).or_insert(entry.file);
+        }
+        debug!(path=%path.display(), n_responses=files.len(), "loaded replay data");
+
+        Ok(Self { path, files })
+    }
+
+    /// Get recorded response for the given request.
+    pub(crate) async fn get(&self, key: &RequestKey) -> Result<Vec<u8>> {
+        let file = self.files.get(&key.canonical()).with_context(|| {
+            format!(
+                "no recorded response for `{} {}/{}`",
+                key.method, key.prefix, key.path
+            )
+        })?;
+
+        tokio::fs::read(self.path.join(file))
+            .await
+            .with_context(|| format!("read recorded response: `{file}`"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::Prefix;
+
+    use super::*;
+
+    #[test]
+    fn test_index_entry_without_meta() {
+        let entry: IndexEntry = serde_json::from_str(
+            r#"{"file":"a.json","method":"GET","prefix":"tutanota","path":"mail/foo","query":[],"body":null}"#,
+        )
+        .unwrap();
+        assert_eq!(entry.file, "a.json");
+        assert!(entry.meta.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dump_replay_roundtrip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let token = crate::proto::binary::Base64Url::from(b"token");
+
+        let dump = Dump::try_new(dir.path().to_owned()).await.unwrap();
+        let request_1 = Request {
+            access_token: Some(&token),
+            query: &[("start", "a"), ("accessToken", "secret")],
+            ..Request::new(Prefix::Tutanota, "mail/foo", &())
+        };
+        let request_2 = Request {
+            query: &[("start", "b")],
+            ..Request::new(Prefix::Tutanota, "mail/foo", &())
+        };
+        dump.write(&request_1, "json", b"1").await.unwrap();
+        drop(dump);
+
+        // continue existing dump
+        let dump = Dump::try_new(dir.path().to_owned()).await.unwrap();
+        dump.write(&request_2, "json", b"2").await.unwrap();
+        drop(dump);
+
+        let index = std::fs::read_to_string(dir.path().join(INDEX_FILE)).unwrap();
+        let index = index
+            .lines()
+            .map(|l| serde_json::from_str::<IndexEntry>(l).unwrap())
+            .collect::<Vec<_>>();
+        let meta = index
+            .iter()
+            .map(|e| {
+                let meta = e.meta.as_ref().unwrap();
+                (meta.seq, meta.redacted.clone())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            meta,
+            [
+                (
+                    0,
+                    vec!["accessToken".to_owned(), "query.accessToken".to_owned()]
+                ),
+                (1, vec![]),
+            ]
+        );
+        assert!(!index[0].key.canonical().contains("secret"));
+
+        let replay = Replay::try_new(dir.path().to_owned()).unwrap();
+
+        // volatile fields are ignored
+        let key = RequestKey::new(&Request {
+            query: &[("accessToken", "other"), ("start", "a")],
+            ..Request::new(Prefix::Tutanota, "mail/foo", &())
+        })
+        .unwrap();
+        assert_eq!(replay.get(&key).await.unwrap(), b"1");
+
+        let key = RequestKey::new(&Request {
+            query: &[("start", "b")],
+            ..Request::new(Prefix::Tutanota, "mail/foo", &())
+        })
+        .unwrap();
+        assert_eq!(replay.get(&key).await.unwrap(), b"2");
+
+        let key = RequestKey::new(&Request {
+            query: &[("start", "c")],
+            ..Request::new(Prefix::Tutanota, "mail/foo", &())
+        })
+        .unwrap();
+        assert_eq!(
+            replay.get(&key).await.unwrap_err().to_string(),
+            "no recorded response for `GET tutanota/mail/foo`",
+        );
+    }
+}
@@ -0,0 +1,2048 @@
+//! EML rendering of downloaded mails, see [`write_eml`].
+use std::{
+    io::{Read, Write},
+    sync::OnceLock,
+};
+
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    html::html_to_text,
+    mails::{Address, Attachment, DownloadedMail},
+    proto::binary::Base64String,
+};
+
+static LINE_ENDING_RE: OnceLock<regex::Regex> = OnceLock::new();
+static CONTENT_TYPE_RE: OnceLock<regex::Regex> = OnceLock::new();
+static CONTENT_TRANSFER_ENCODING_RE: OnceLock<regex::Regex> = OnceLock::new();
+static START_WITH_SPACES_RE: OnceLock<regex::Regex> = OnceLock::new();
+static MULTIPART_BOUNDARY_RE: OnceLock<regex::Regex> = OnceLock::new();
+static STATE_HEADER_RE: OnceLock<regex::Regex> = OnceLock::new();
+const NEWLINE: &str = "\r\n";
+
+/// Folding whitespace that continues a header on the next line.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc5322#section-2.2.3>.
+const FOLD: &str = "\r\n ";
+
+/// Number of generated characters in MIME boundaries.
+const BOUNDARY_CHARS: usize = 32;
+
+/// Alphabet of the generated characters in MIME boundaries.
+const BOUNDARY_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Preferred maximum of characters per header line, excluding the line break.
+///
+/// Longer headers are folded where possible. See <https://www.rfc-editor.org/rfc/rfc5322#section-2.1.1>.
+const HEADER_LINE_CHARS: usize = 78;
+
+/// Maximum number of input bytes per RFC 2047 encoded word.
+///
+/// This is encoded to 60 base64 characters, so the whole word is 72 characters long and stays below the limit of 75.
+const ENCODED_WORD_BYTES: usize = 45;
+
+/// Characters per line of base64-encoded content.
+const CHUNK_CHARS: usize = 78;
+
+/// Number of input bytes that are encoded to exactly two lines, see [`CHUNK_CHARS`].
+const CHUNK_BYTES: usize = CHUNK_CHARS * 2 / 4 * 3;
+
+/// Maximum characters per quoted-printable line, excluding the line break.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc2045#section-6.7>.
+const QP_LINE_CHARS: usize = 76;
+
+/// Maximum octets per line, excluding the line break.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc5322#section-2.1.1>.
+const MAX_LINE_OCTETS: usize = 998;
+
+/// Transfer encoding of the mail body.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BodyEncoding {
+    /// base64, opaque but robust.
+    #[default]
+    Base64,
+
+    /// Quoted-printable, keeps ASCII text readable.
+    QuotedPrintable,
+
+    /// Raw 8bit text.
+    ///
+    /// Falls back to quoted-printable if the body cannot be represented as 8bit, e.g. because lines are too long.
+    #[value(name = "8bit")]
+    EightBit,
+}
+
+/// Options for [`write_eml`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmlOptions {
+    /// Transfer encoding of the mail body.
+    pub body_encoding: BodyEncoding,
+
+    /// Add a plain-text rendering of the HTML body as `multipart/alternative`.
+    pub text_alternative: bool,
+
+    /// Keep the original single-part content type if the body matches it, see `preserved_content_type`.
+    pub preserve_content_type: bool,
+
+    /// Add `X-Tuta-*` headers with the read and reply state, see `state_headers`.
+    pub state_headers: bool,
+}
+
+/// Write mail as EML.
+///
+/// Attachments are encoded incrementally, so they are never held in memory as a whole.
+///
+/// The output is deterministic: the same mail data and options always produce the same bytes. MIME boundaries are
+/// derived from the mail ID. The given boundaries are used in order before new ones are derived, unless they occur in
+/// the content. This allows to reproduce an existing file that was written with random boundaries by an older
+/// version, see [`find_boundaries`].
+pub fn write_eml(
+    mail: &DownloadedMail,
+    options: EmlOptions,
+    boundaries: Vec<String>,
+    w: &mut impl Write,
+) -> Result<()> {
+    write_eml_impl(
+        mail,
+        options,
+        &mut Boundaries::new(boundaries, &mail.mail.mail_id),
+        w,
+    )
+}
+
+fn write_eml_impl(
+    mail: &DownloadedMail,
+    options: EmlOptions,
+    boundaries: &mut Boundaries,
+    w: &mut impl Write,
+) -> Result<()> {
+    let mut out = LineWriter::new(w);
+    let mut lines = Vec::new();
+
+    let text = options
+        .text_alternative
+        .then(|| html_to_text(&String::from_utf8_lossy(&mail.body)));
+
+    // headers
+    let preserved = options
+        .preserve_content_type
+        .then(|| preserved_content_type(mail))
+        .flatten();
+    if let Some(headers) = &mail.headers {
+        let headers = split_header_lines(headers);
+        let mut headers =
+            remove_header(headers, content_type_re()).context("filter content type header")?;
+        if preserved.is_some() {
+            headers = remove_header(headers, content_transfer_encoding_re())
+                .context("filter content transfer encoding header")?;
+        }
+        if options.state_headers {
+            // do NOT trust headers that the sender may have set
+            headers = remove_header(headers, state_header_re()).context("filter state headers")?;
+        }
+
+        lines.append(&mut headers);
+    } else {
+        synthesize_headers(mail, &mut lines);
+    }
+    if options.state_headers {
+        state_headers(mail, &mut lines);
+    }
+    omitted_attachment_headers(mail, &mut lines);
+
+    if let Some(content_type) = preserved {
+        write_text_part(
+            &mut out,
+            &mut lines,
+            &content_type,
+            &mail.body,
+            options.body_encoding,
+        )
+        .context("write body")?;
+        return Ok(());
+    }
+
+    let mut text_parts = vec![mail.body.as_slice()];
+    if let Some(text) = &text {
+        text_parts.push(text.as_bytes());
+    }
+    // Inline attachments are related to the body, all others are just mixed in. Clients may hide regular attachments
+    // of `multipart/related` mails.
+    let (inline, regular): (Vec<_>, Vec<_>) =
+        mail.attachments.iter().partition(|a| a.cid.is_some());
+    let boundary = boundaries.next(&text_parts);
+    lines.push(format!(
+        "Content-Type: multipart/{}; boundary=\"{}\"",
+        if regular.is_empty() {
+            "related"
+        } else {
+            "mixed"
+        },
+        boundary
+    ));
+    text_parts.push(boundary.as_bytes());
+
+    // body
+    write_intermediate_delimiter(&mut lines, &boundary);
+    let related_boundary = (!inline.is_empty() && !regular.is_empty()).then(|| {
+        let related_boundary = boundaries.next(&text_parts);
+        lines.push(format!(
+            "Content-Type: multipart/related; boundary=\"{}\"",
+            related_boundary
+        ));
+        write_intermediate_delimiter(&mut lines, &related_boundary);
+        related_boundary
+    });
+    if let Some(related_boundary) = &related_boundary {
+        text_parts.push(related_boundary.as_bytes());
+    }
+    if let Some(text) = &text {
+        let alternative_boundary = boundaries.next(&text_parts);
+        lines.push(format!(
+            "Content-Type: multipart/alternative; boundary=\"{}\"",
+            alternative_boundary
+        ));
+
+        write_intermediate_delimiter(&mut lines, &alternative_boundary);
+        write_text_part(
+            &mut out,
+            &mut lines,
+            "text/plain; charset=UTF-8",
+            text.as_bytes(),
+            options.body_encoding,
+        )
+        .context("write text body")?;
+
+        // the preferred alternative comes last
+        write_intermediate_delimiter(&mut lines, &alternative_boundary);
+        write_text_part(
+            &mut out,
+            &mut lines,
+            "text/html; charset=UTF-8",
+            &mail.body,
+            options.body_encoding,
+        )
+        .context("write body")?;
+
+        write_final_delimiter(&mut lines, &alternative_boundary);
+    } else {
+        write_text_part(
+            &mut out,
+            &mut lines,
+            "text/html; charset=UTF-8",
+            &mail.body,
+            options.body_encoding,
+        )
+        .context("write body")?;
+    }
+
+    // attachments
+    for attachment in inline {
+        write_intermediate_delimiter(&mut lines, related_boundary.as_deref().unwrap_or(&boundary));
+        write_attachment(&mut out, &mut lines, attachment)?;
+    }
+    if let Some(related_boundary) = &related_boundary {
+        write_final_delimiter(&mut lines, related_boundary);
+    }
+    for attachment in regular {
+        write_intermediate_delimiter(&mut lines, &boundary);
+        write_attachment(&mut out, &mut lines, attachment)?;
+    }
+
+    write_final_delimiter(&mut lines, &boundary);
+    out.lines(&mut lines).context("write final delimiter")?;
+    Ok(())
+}
+
+/// Write headers and content of an attachment.
+///
+/// Pending `lines` are written first.
+fn write_attachment<W>(
+    out: &mut LineWriter<'_, W>,
+    lines: &mut Vec<String>,
+    attachment: &Attachment,
+) -> Result<()>
+where
+    W: Write,
+{
+    lines.push(format!(
+        "Content-Type: {}; name={}",
+        attachment.mime_type,
+        utf8_param_value(&attachment.name)
+    ));
+    lines.push("Content-Transfer-Encoding: base64".to_owned());
+    lines.push(format!(
+        "Content-Disposition: attachment; filename={}",
+        utf8_param_value(&attachment.name)
+    ));
+    if let Some(cid) = &attachment.cid {
+        lines.push(format!("Content-Id: <{}>", cid));
+    }
+    lines.push("".to_owned());
+    out.lines(lines).context("write attachment headers")?;
+    write_chunked(out, attachment.data.reader()?).context("write attachment")?;
+    Ok(())
+}
+
+/// Write headers and content of a text part.
+///
+/// Pending `lines` are written first. The content type must include the charset.
+fn write_text_part<W>(
+    out: &mut LineWriter<'_, W>,
+    lines: &mut Vec<String>,
+    content_type: &str,
+    data: &[u8],
+    encoding: BodyEncoding,
+) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let encoding = match encoding {
+        BodyEncoding::EightBit if !is_8bit_safe(data) => BodyEncoding::QuotedPrintable,
+        encoding => encoding,
+    };
+
+    lines.push(format!("Content-Type: {content_type}"));
+    lines.push(format!(
+        "Content-Transfer-Encoding: {}",
+        match encoding {
+            BodyEncoding::Base64 => "base64",
+            BodyEncoding::QuotedPrintable => "quoted-printable",
+            BodyEncoding::EightBit => "8bit",
+        }
+    ));
+    lines.push("".to_owned());
+    out.lines(lines)?;
+
+    match encoding {
+        BodyEncoding::Base64 => write_chunked(out, data),
+        BodyEncoding::QuotedPrintable => write_quoted_printable(out, data),
+        BodyEncoding::EightBit => write_8bit(out, data),
+    }
+}
+
+/// Get original content type if the mail can be written as a single part of that type.
+///
+/// This is only possible for `text/plain` and `text/html` mails without attachments. Since the body is always stored
+/// as UTF-8, the charset is replaced. Plain-text mails that contain HTML tags were converted by the sender or the
+/// server and do NOT match anymore.
+fn preserved_content_type(mail: &DownloadedMail) -> Option<String> {
+    if !mail.attachments.is_empty() {
+        return None;
+    }
+    let content_type = header_value(mail.headers.as_deref()?, "content-type")?;
+
+    let mut params = content_type.split(';').map(|p| p.trim());
+    let media_type = params.next()?.to_ascii_lowercase();
+    match media_type.as_str() {
+        "text/html" => {}
+        "text/plain" if !contains_html_tags(&mail.body) => {}
+        _ => return None,
+    }
+
+    let params = params
+        .filter(|p| {
+            !p.is_empty()
+                && !p
+                    .split_once('=')
+                    .is_some_and(|(k, _)| k.trim().eq_ignore_ascii_case("charset"))
+        })
+        .map(|p| format!("; {p}"))
+        .join("");
+    Some(format!("{media_type}; charset=UTF-8{params}"))
+}
+
+/// Check if data contains common HTML tags.
+fn contains_html_tags(data: &[u8]) -> bool {
+    let data = String::from_utf8_lossy(data).to_ascii_lowercase();
+    [
+        "<br", "<div", "<p>", "<p ", "<html", "<body", "<span", "<a ",
+    ]
+    .iter()
+    .any(|tag| data.contains(tag))
+}
+
+/// Writes lines separated by [`NEWLINE`].
+struct LineWriter<'a, W> {
+    w: &'a mut W,
+    first: bool,
+}
+
+impl<'a, W> LineWriter<'a, W>
+where
+    W: Write,
+{
+    fn new(w: &'a mut W) -> Self {
+        Self { w, first: true }
+    }
+
+    fn line(&mut self, line: &str) -> std::io::Result<()> {
+        self.line_bytes(line.as_bytes())
+    }
+
+    fn line_bytes(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if !std::mem::take(&mut self.first) {
+            self.w.write_all(NEWLINE.as_bytes())?;
+        }
+        self.w.write_all(line)
+    }
+
+    /// Write and drain lines.
+    fn lines(&mut self, lines: &mut Vec<String>) -> std::io::Result<()> {
+        for line in lines.drain(..) {
+            self.line(&line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Create headers from metadata.
+fn synthesize_headers(mail: &DownloadedMail, lines: &mut Vec<String>) {
+    lines.push(address_header("From", [&mail.mail.sender]));
+    lines.push(format!("Date: {}", mail.mail.date.to_rfc2822()));
+    lines.push("MIME-Version: 1.0".to_owned());
+
+    if mail.mail.subject.is_empty() {
+        lines.push("Subject: ".to_owned());
+    } else {
+        lines.push(format!(
+            "Subject: {}",
+            utf8_header_value(&mail.mail.subject),
+        ));
+    };
+
+    if !mail.bcc.is_empty() {
+        lines.push(address_header("BCC", &mail.bcc));
+    }
+    if !mail.cc.is_empty() {
+        lines.push(address_header("CC", &mail.cc));
+    }
+    if !mail.to.is_empty() {
+        lines.push(address_header("To", &mail.to));
+    }
+}
+
+/// Headers with the read and reply state of the mail.
+///
+/// These are NOT part of the original mail but allow to restore the state after importing it elsewhere.
+fn state_headers(mail: &DownloadedMail, lines: &mut Vec<String>) {
+    lines.push(format!(
+        "X-Tuta-Unread: {}",
+        if mail.mail.unread { "1" } else { "0" }
+    ));
+    lines.push(format!("X-Tuta-State: {}", mail.mail.state.name()));
+    lines.push(format!(
+        "X-Tuta-Reply-Type: {}",
+        mail.mail.reply_type.name()
+    ));
+}
+
+/// Stub headers for attachments that were NOT downloaded, see [`DownloadedMail::omitted_attachments`].
+///
+/// The name comes last since it may contain any character.
+fn omitted_attachment_headers(mail: &DownloadedMail, lines: &mut Vec<String>) {
+    for attachment in &mail.omitted_attachments {
+        lines.push(format!(
+            "X-Tuta-Omitted-Attachment: type={}; size={}; name={}",
+            attachment.mime_type,
+            attachment.size,
+            utf8_header_value(&attachment.name),
+        ));
+    }
+}
+
+/// Create address headers
+fn address_header<'a>(
+    header: &'static str,
+    addrs: impl IntoIterator<Item = &'a Address>,
+) -> String {
+    let mut out = format!("{}:", header);
+    let mut line_len = out.len();
+
+    for (i, addr) in addrs.into_iter().enumerate() {
+        let item = format!("{} <{}>", utf8_header_value(&addr.name), addr.mail);
+        let first_line_len = item.find(FOLD).unwrap_or(item.len());
+
+        if i > 0 {
+            out.push(',');
+            line_len += 1;
+        }
+        if i > 0 && line_len + 1 + first_line_len > HEADER_LINE_CHARS {
+            out.push_str(FOLD);
+            line_len = FOLD.len() - NEWLINE.len();
+        } else {
+            out.push(' ');
+            line_len += 1;
+        }
+
+        out.push_str(&item);
+        line_len = match item.rfind(FOLD) {
+            Some(pos) => item.len() - pos - NEWLINE.len(),
+            None => line_len + item.len(),
+        };
+    }
+
+    out
+}
+
+fn line_ending_re() -> &'static regex::Regex {
+    LINE_ENDING_RE.get_or_init(|| regex::Regex::new(r#"\r?\n"#).expect("valid regex"))
+}
+
+fn content_type_re() -> &'static regex::Regex {
+    CONTENT_TYPE_RE.get_or_init(|| {
+        regex::RegexBuilder::new(r#"^Content-Type: .*"#)
+            .case_insensitive(true)
+            .build()
+            .expect("valid regex")
+    })
+}
+
+fn content_transfer_encoding_re() -> &'static regex::Regex {
+    CONTENT_TRANSFER_ENCODING_RE.get_or_init(|| {
+        regex::RegexBuilder::new(r#"^Content-Transfer-Encoding: .*"#)
+            .case_insensitive(true)
+            .build()
+            .expect("valid regex")
+    })
+}
+
+fn multipart_boundary_re() -> &'static regex::Regex {
+    MULTIPART_BOUNDARY_RE.get_or_init(|| {
+        regex::Regex::new(r#"(?m)^Content-Type: multipart/[a-z]+; boundary="([^"\r\n]+)"\r?$"#)
+            .expect("valid regex")
+    })
+}
+
+fn state_header_re() -> &'static regex::Regex {
+    STATE_HEADER_RE.get_or_init(|| {
+        regex::RegexBuilder::new(r#"^X-Tuta-(Unread|State|Reply-Type):.*"#)
+            .case_insensitive(true)
+            .build()
+            .expect("valid regex")
+    })
+}
+
+fn start_with_spaces_re() -> &'static regex::Regex {
+    START_WITH_SPACES_RE.get_or_init(|| regex::Regex::new(r#"^\s+.*"#).expect("valid regex"))
+}
+
+/// Upstream provides `\n` line endings for headers but we need `\r\n`
+fn split_header_lines(headers: &str) -> Vec<String> {
+    line_ending_re()
+        .split(headers)
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+/// Remove header that matches the given regex, including its continuation lines.
+fn remove_header(headers: Vec<String>, header_re: &regex::Regex) -> Result<Vec<String>> {
+    let start_with_spaces_re = start_with_spaces_re();
+
+    let mut out = Vec::with_capacity(headers.len());
+    let mut in_header = false;
+    for header in headers {
+        if header_re.is_match(&header) {
+            in_header = true;
+            // skip
+        } else if in_header && start_with_spaces_re.is_match(&header) {
+            // skip
+        } else {
+            // keep
+            in_header = false;
+            out.push(header);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Extract `Message-ID` from raw headers.
+///
+/// Folded header lines are supported. Returns [`None`] if the header is missing or empty.
+pub fn message_id(headers: &str) -> Option<String> {
+    header_value(headers, "message-id")
+}
+
+/// Extract value of the first header with the given name from raw headers.
+///
+/// Folded header lines are supported. Returns [`None`] if the header is missing or empty.
+fn header_value(headers: &str, header: &str) -> Option<String> {
+    let start_with_spaces_re = start_with_spaces_re();
+
+    let mut lines = split_header_lines(headers).into_iter();
+    while let Some(line) = lines.next() {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        if !name.trim().eq_ignore_ascii_case(header) {
+            continue;
+        }
+
+        let mut value = value.trim().to_owned();
+        for line in lines.take_while(|l| start_with_spaces_re.is_match(l)) {
+            value.push_str(line.trim());
+        }
+        return (!value.is_empty()).then_some(value);
+    }
+
+    None
+}
+
+/// Source of MIME boundaries.
+struct Boundaries {
+    /// Boundaries that are used before new ones are derived.
+    preset: std::vec::IntoIter<String>,
+
+    /// Input for the derived boundaries, so they are stable across runs.
+    seed: String,
+
+    /// Number of boundaries derived so far.
+    counter: u64,
+}
+
+impl Boundaries {
+    fn new(preset: Vec<String>, seed: &str) -> Self {
+        Self {
+            preset: preset.into_iter(),
+            seed: seed.to_owned(),
+            counter: 0,
+        }
+    }
+
+    /// Get boundary that does not occur in any of the given parts.
+    ///
+    /// Only parts that are NOT base64-encoded need to be checked, since base64 never produces the leading `--` of a
+    /// delimiter line.
+    fn next(&mut self, parts: &[&[u8]]) -> String {
+        let is_unique = |boundary: &str| {
+            !parts.iter().any(|part| {
+                part.windows(boundary.len())
+                    .any(|w| w == boundary.as_bytes())
+            })
+        };
+
+        if let Some(boundary) = self.preset.next().filter(|b| is_unique(b)) {
+            return boundary;
+        }
+
+        loop {
+            let boundary = self.derive();
+            if is_unique(&boundary) {
+                return boundary;
+            }
+        }
+    }
+
+    /// Derive next boundary from the seed.
+    fn derive(&mut self) -> String {
+        let hash = Sha256::new()
+            .chain_update(self.seed.as_bytes())
+            .chain_update([0])
+            .chain_update(self.counter.to_le_bytes())
+            .finalize();
+        self.counter += 1;
+
+        let chars = hash
+            .iter()
+            .take(BOUNDARY_CHARS)
+            .map(|b| char::from(BOUNDARY_ALPHABET[usize::from(*b) % BOUNDARY_ALPHABET.len()]))
+            .collect::<String>();
+        format!("----------{chars}")
+    }
+}
+
+/// Find MIME boundaries of an EML file written by [`write_eml`], in the order they were used.
+///
+/// `eml` may be truncated, since the boundaries are declared before the content.
+pub fn find_boundaries(eml: &str) -> Vec<String> {
+    multipart_boundary_re()
+        .captures_iter(eml)
+        .map(|c| c[1].to_owned())
+        .collect()
+}
+
+/// See <https://www.w3.org/Protocols/rfc1341/7_2_Multipart.html>.
+fn write_intermediate_delimiter(lines: &mut Vec<String>, boundary: &str) {
+    lines.push("".to_owned());
+    lines.push(format!("--{}", boundary));
+}
+
+/// See <https://www.w3.org/Protocols/rfc1341/7_2_Multipart.html>.
+fn write_final_delimiter(lines: &mut Vec<String>, boundary: &str) {
+    lines.push("".to_owned());
+    lines.push(format!("--{}--", boundary));
+}
+
+/// Write base64-encoded data in lines of [`CHUNK_CHARS`] characters.
+///
+/// Data is encoded block-wise into a reusable buffer, so the output is written with few large writes.
+fn write_chunked<W>(out: &mut LineWriter<'_, W>, mut data: impl Read) -> std::io::Result<()>
+where
+    W: Write,
+{
+    const BLOCKS: usize = 256;
+
+    // Every full buffer is encoded to complete lines, so the output is identical to encoding everything at once.
+    let mut buf = vec![0; CHUNK_BYTES * BLOCKS];
+    let mut encoded = Vec::with_capacity(BLOCKS * 2 * (CHUNK_CHARS + NEWLINE.len()));
+    let mut block = [0; CHUNK_CHARS * 2];
+    loop {
+        let n = read_full(&mut data, &mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        encoded.clear();
+        for input in buf[..n].chunks(CHUNK_BYTES) {
+            let len = BASE64_STANDARD
+                .encode_slice(input, &mut block)
+                .expect("block fits two lines");
+            for line in block[..len].chunks(CHUNK_CHARS) {
+                if !encoded.is_empty() {
+                    encoded.extend_from_slice(NEWLINE.as_bytes());
+                }
+                encoded.extend_from_slice(line);
+            }
+        }
+        out.line_bytes(&encoded)?;
+
+        if n < buf.len() {
+            return Ok(());
+        }
+    }
+}
+
+/// Split text into lines, accepting both `\r\n` and `\n` line endings.
+fn text_lines(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    data.split(|b| *b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+/// Check if text can be transferred as 8bit.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc2045#section-2.8>.
+fn is_8bit_safe(data: &[u8]) -> bool {
+    text_lines(data).all(|line| {
+        line.len() <= MAX_LINE_OCTETS && !line.iter().any(|b| matches!(b, b'\0' | b'\r'))
+    })
+}
+
+/// Write text as 8bit with normalized line endings.
+///
+/// The text must be [8bit safe](is_8bit_safe).
+fn write_8bit<W>(out: &mut LineWriter<'_, W>, data: &[u8]) -> std::io::Result<()>
+where
+    W: Write,
+{
+    for line in text_lines(data) {
+        out.line_bytes(line)?;
+    }
+    Ok(())
+}
+
+/// Write text as quoted-printable.
+///
+/// Line breaks of the input are kept as hard line breaks. Long lines are wrapped using soft line breaks.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc2045#section-6.7>.
+fn write_quoted_printable<W>(out: &mut LineWriter<'_, W>, data: &[u8]) -> std::io::Result<()>
+where
+    W: Write,
+{
+    for line in text_lines(data) {
+        let mut encoded = String::with_capacity(QP_LINE_CHARS + 1);
+        for (i, b) in line.iter().enumerate() {
+            let is_last = i + 1 == line.len();
+            let literal = match b {
+                b'=' => false,
+                b' ' | b'\t' => !is_last,
+                b'!'..=b'~' => true,
+                _ => false,
+            };
+            let len = if literal { 1 } else { 3 };
+
+            // keep room for the `=` of a soft line break, unless this is the end of the line
+            let limit = if is_last {
+                QP_LINE_CHARS
+            } else {
+                QP_LINE_CHARS - 1
+            };
+            if encoded.len() + len > limit {
+                encoded.push('=');
+                out.line(&encoded)?;
+                encoded.clear();
+            }
+
+            if literal {
+                encoded.push(*b as char);
+            } else {
+                encoded.push_str(&format!("={b:02X}"));
+            }
+        }
+        out.line(&encoded)?;
+    }
+    Ok(())
+}
+
+/// Fill buffer unless the reader is exhausted, returns number of bytes read.
+fn read_full(r: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..])? {
+            0 => break,
+            k => n += k,
+        }
+    }
+    Ok(n)
+}
+
+/// Encode header value as one or more encoded words.
+///
+/// Long values are split into multiple words that are separated by folding whitespace, so no encoded word exceeds
+/// the limit of 75 characters. Words are only split at character boundaries, since each word must be decodable on
+/// its own.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc2047>.
+fn utf8_header_value(s: &str) -> String {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if i + c.len_utf8() - start > ENCODED_WORD_BYTES {
+            words.push(&s[start..i]);
+            start = i;
+        }
+    }
+    words.push(&s[start..]);
+
+    words
+        .into_iter()
+        .map(|word| format!("=?UTF-8?B?{}?=", Base64String::from(word.as_bytes())))
+        .join(FOLD)
+}
+
+/// Encode parameter value like [`utf8_header_value`].
+///
+/// Values that are split into multiple words are quoted, because parameters cannot contain unquoted whitespace.
+fn utf8_param_value(s: &str) -> String {
+    let value = utf8_header_value(s);
+    if value.contains(FOLD) {
+        format!("\"{value}\"")
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::DateTime;
+
+    use crate::{
+        mails::{Attachment, Mail, OmittedAttachment},
+        proto::{
+            enums::{MailState, ReplyType},
+            keys::Key,
+        },
+    };
+
+    use super::*;
+
+    fn emit_eml(mail: &DownloadedMail) -> Result<String> {
+        emit_eml_with_options(mail, EmlOptions::default())
+    }
+
+    fn emit_eml_with_options(mail: &DownloadedMail, options: EmlOptions) -> Result<String> {
+        let mut buf = vec![];
+        write_eml(mail, options, vec![], &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_simple() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some(
+                "From: foo@example.com\nContent-Type: multipart/related; boundary=\"myboundary\""
+                    .to_owned(),
+            ),
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        aGVsbG8gd29ybGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_plain_email() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some("From: foo@example.com\nContent-Type: text/plain".to_owned()),
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        aGVsbG8gd29ybGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_content_type_lower_case() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some("From: foo@example.com\ncontent-type: text/plain".to_owned()),
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        aGVsbG8gd29ybGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_content_type_multi_line() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some(
+                "From: foo@example.com\nContent-Type: multipart/related;\n\tboundary=\"myboundary\"\nFoo: bar\nContent-Type: text/plain\nFoo2: bar2"
+                    .to_owned(),
+            ),
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Foo: bar
+        Foo2: bar2
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        aGVsbG8gd29ybGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_content_type_missing() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some("From: foo@example.com\nFoo: bar".to_owned()),
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Foo: bar
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        aGVsbG8gd29ybGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_attachments() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![
+                    ["a".to_owned(), "b".to_owned()],
+                    ["c".to_owned(), "d".to_owned()],
+                    ["e".to_owned(), "f".to_owned()],
+                ],
+            }),
+            headers: Some(
+                "From: foo@example.com\nContent-Type: multipart/related; boundary=\"myboundary\""
+                    .to_owned(),
+            ),
+            body: b"hello world".to_vec(),
+            attachments: vec![
+                Attachment {
+                    cid: Some("cid001".to_owned()),
+                    mime_type: "image/jpeg".to_owned(),
+                    name: "föo.jpg".to_owned(),
+                    data: b"foobar".to_vec().into(),
+                },
+                Attachment {
+                    cid: Some("cid002".to_owned()),
+                    mime_type: "image/new".to_owned(),
+                    name: "å".to_owned(),
+                    data: b"x".to_vec().into(),
+                },
+                Attachment {
+                    cid: None,
+                    mime_type: "x/y".to_owned(),
+                    name: "something".to_owned(),
+                    data: b"xcddd".to_vec().into(),
+                },
+            ],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Content-Type: multipart/mixed; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: multipart/related; boundary="----------lM1tDFKBwTcJmWIViNHnzQDpM75671zE"
+
+        ------------lM1tDFKBwTcJmWIViNHnzQDpM75671zE
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        aGVsbG8gd29ybGQ=
+
+        ------------lM1tDFKBwTcJmWIViNHnzQDpM75671zE
+        Content-Type: image/jpeg; name==?UTF-8?B?ZsO2by5qcGc=?=
+        Content-Transfer-Encoding: base64
+        Content-Disposition: attachment; filename==?UTF-8?B?ZsO2by5qcGc=?=
+        Content-Id: <cid001>
+
+        Zm9vYmFy
+
+        ------------lM1tDFKBwTcJmWIViNHnzQDpM75671zE
+        Content-Type: image/new; name==?UTF-8?B?w6U=?=
+        Content-Transfer-Encoding: base64
+        Content-Disposition: attachment; filename==?UTF-8?B?w6U=?=
+        Content-Id: <cid002>
+
+        eA==
+
+        ------------lM1tDFKBwTcJmWIViNHnzQDpM75671zE--
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: x/y; name==?UTF-8?B?c29tZXRoaW5n?=
+        Content-Transfer-Encoding: base64
+        Content-Disposition: attachment; filename==?UTF-8?B?c29tZXRoaW5n?=
+
+        eGNkZGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_attachments_inline_only() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![["a".to_owned(), "b".to_owned()]],
+            }),
+            headers: Some("From: foo@example.com".to_owned()),
+            body: b"hello world".to_vec(),
+            attachments: vec![Attachment {
+                cid: Some("cid001".to_owned()),
+                mime_type: "image/jpeg".to_owned(),
+                name: "foo.jpg".to_owned(),
+                data: b"foobar".to_vec().into(),
+            }],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        aGVsbG8gd29ybGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: image/jpeg; name==?UTF-8?B?Zm9vLmpwZw==?=
+        Content-Transfer-Encoding: base64
+        Content-Disposition: attachment; filename==?UTF-8?B?Zm9vLmpwZw==?=
+        Content-Id: <cid001>
+
+        Zm9vYmFy
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_synthesize_headers_minimal() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Mé".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: None,
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: =?UTF-8?B?TcOp?= <foo@example.com>
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        MIME-Version: 1.0
+        Subject: =?UTF-8?B?SMOkbGzDtg==?=
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        aGVsbG8gd29ybGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_synthesize_headers_to_all() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Mé".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: None,
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![
+                Address {
+                    mail: "bar1@example.com".to_owned(),
+                    name: "Óther 1".to_owned(),
+                },
+                Address {
+                    mail: "bar2@example.com".to_owned(),
+                    name: "Óther 2".to_owned(),
+                },
+            ],
+            cc: vec![
+                Address {
+                    mail: "bar3@example.com".to_owned(),
+                    name: "Óther 3".to_owned(),
+                },
+                Address {
+                    mail: "bar4@example.com".to_owned(),
+                    name: "Óther 4".to_owned(),
+                },
+            ],
+            to: vec![
+                Address {
+                    mail: "bar5@example.com".to_owned(),
+                    name: "Óther 5".to_owned(),
+                },
+                Address {
+                    mail: "bar6@example.com".to_owned(),
+                    name: "Óther 6".to_owned(),
+                },
+            ],
+            omitted_attachments: vec![],
+            memory: None,
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: =?UTF-8?B?TcOp?= <foo@example.com>
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        MIME-Version: 1.0
+        Subject: =?UTF-8?B?SMOkbGzDtg==?=
+        BCC: =?UTF-8?B?w5N0aGVyIDE=?= <bar1@example.com>,
+         =?UTF-8?B?w5N0aGVyIDI=?= <bar2@example.com>
+        CC: =?UTF-8?B?w5N0aGVyIDM=?= <bar3@example.com>,
+         =?UTF-8?B?w5N0aGVyIDQ=?= <bar4@example.com>
+        To: =?UTF-8?B?w5N0aGVyIDU=?= <bar5@example.com>,
+         =?UTF-8?B?w5N0aGVyIDY=?= <bar6@example.com>
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        aGVsbG8gd29ybGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_write_chunked_large() {
+        for len in [
+            1,
+            CHUNK_BYTES - 1,
+            CHUNK_BYTES,
+            CHUNK_BYTES + 1,
+            CHUNK_BYTES * 256,
+            CHUNK_BYTES * 256 * 2 + 5,
+        ] {
+            let data = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+
+            let mut buf = vec![];
+            write_chunked(&mut LineWriter::new(&mut buf), data.as_slice()).unwrap();
+
+            let expected = BASE64_STANDARD
+                .encode(&data)
+                .as_bytes()
+                .chunks(CHUNK_CHARS)
+                .map(|c| std::str::from_utf8(c).unwrap())
+                .join(NEWLINE);
+            assert_eq!(String::from_utf8(buf).unwrap(), expected, "len={len}");
+        }
+    }
+
+    #[test]
+    fn test_body_encoding() {
+        let mail = DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some("From: foo@example.com".to_owned()),
+            body: "<p>Hällö</p>\n<p>a=b</p>".as_bytes().to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        };
+
+        let eml = emit_eml_with_options(
+            &mail,
+            EmlOptions {
+                body_encoding: BodyEncoding::QuotedPrintable,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: quoted-printable
+
+        <p>H=C3=A4ll=C3=B6</p>
+        <p>a=3Db</p>
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+
+        let eml = emit_eml_with_options(
+            &mail,
+            EmlOptions {
+                body_encoding: BodyEncoding::EightBit,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: 8bit
+
+        <p>Hällö</p>
+        <p>a=b</p>
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+
+        // lines that are too long fall back to quoted-printable
+        let mail = DownloadedMail {
+            body: vec![b'x'; MAX_LINE_OCTETS + 1],
+            ..mail
+        };
+        let eml = emit_eml_with_options(
+            &mail,
+            EmlOptions {
+                body_encoding: BodyEncoding::EightBit,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(eml.contains("Content-Transfer-Encoding: quoted-printable"));
+    }
+
+    #[test]
+    fn test_text_alternative() {
+        let eml = emit_eml_with_options(
+            &DownloadedMail {
+                mail: Arc::new(Mail {
+                    folder_id: "folder_id".to_owned(),
+                    mail_id: "mail_id".to_owned(),
+                    archive_id: "archive_id".to_owned(),
+                    blob_id: "blob_id".to_owned(),
+                    is_draft: false,
+                    unread: false,
+                    state: MailState::Received,
+                    reply_type: ReplyType::NoReply,
+                    conversation_id: None,
+                    session_key: Key::Aes256([0; 32]),
+                    bucket_session_keys: Default::default(),
+                    date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                        .unwrap()
+                        .to_utc(),
+                    subject: "Hällö".to_owned(),
+                    sender: Address {
+                        mail: "foo@example.com".to_owned(),
+                        name: "Me".to_owned(),
+                    },
+                    attachments: vec![],
+                }),
+                headers: Some("From: foo@example.com".to_owned()),
+                body: b"<p>hello</p><p>world</p>".to_vec(),
+                attachments: vec![Attachment {
+                    cid: None,
+                    mime_type: "x/y".to_owned(),
+                    name: "something".to_owned(),
+                    data: b"xcddd".to_vec().into(),
+                }],
+                bcc: vec![],
+                cc: vec![],
+                to: vec![],
+                omitted_attachments: vec![],
+                memory: None,
+            },
+            EmlOptions {
+                body_encoding: BodyEncoding::EightBit,
+                text_alternative: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Content-Type: multipart/mixed; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: multipart/alternative; boundary="----------lM1tDFKBwTcJmWIViNHnzQDpM75671zE"
+
+        ------------lM1tDFKBwTcJmWIViNHnzQDpM75671zE
+        Content-Type: text/plain; charset=UTF-8
+        Content-Transfer-Encoding: 8bit
+
+        hello
+
+        world
+
+        ------------lM1tDFKBwTcJmWIViNHnzQDpM75671zE
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: 8bit
+
+        <p>hello</p><p>world</p>
+
+        ------------lM1tDFKBwTcJmWIViNHnzQDpM75671zE--
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: x/y; name==?UTF-8?B?c29tZXRoaW5n?=
+        Content-Transfer-Encoding: base64
+        Content-Disposition: attachment; filename==?UTF-8?B?c29tZXRoaW5n?=
+
+        eGNkZGQ=
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_preserve_content_type() {
+        fn mail(headers: &str, body: &str, attachments: Vec<Attachment>) -> DownloadedMail {
+            DownloadedMail {
+                mail: Arc::new(Mail {
+                    folder_id: "folder_id".to_owned(),
+                    mail_id: "mail_id".to_owned(),
+                    archive_id: "archive_id".to_owned(),
+                    blob_id: "blob_id".to_owned(),
+                    is_draft: false,
+                    unread: false,
+                    state: MailState::Received,
+                    reply_type: ReplyType::NoReply,
+                    conversation_id: None,
+                    session_key: Key::Aes256([0; 32]),
+                    bucket_session_keys: Default::default(),
+                    date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                        .unwrap()
+                        .to_utc(),
+                    subject: "Hällö".to_owned(),
+                    sender: Address {
+                        mail: "foo@example.com".to_owned(),
+                        name: "Me".to_owned(),
+                    },
+                    attachments: vec![],
+                }),
+                headers: Some(headers.to_owned()),
+                body: body.as_bytes().to_vec(),
+                attachments,
+                bcc: vec![],
+                cc: vec![],
+                to: vec![],
+                omitted_attachments: vec![],
+                memory: None,
+            }
+        }
+        let options = EmlOptions {
+            body_encoding: BodyEncoding::EightBit,
+            preserve_content_type: true,
+            ..Default::default()
+        };
+        let plain_headers = "From: foo@example.com\nContent-Type: text/plain;\n\tcharset=ISO-8859-1; format=flowed\nContent-Transfer-Encoding: quoted-printable\nTo: bar@example.com";
+
+        let eml =
+            emit_eml_with_options(&mail(plain_headers, "hello\nworld", vec![]), options).unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        To: bar@example.com
+        Content-Type: text/plain; charset=UTF-8; format=flowed
+        Content-Transfer-Encoding: 8bit
+
+        hello
+        world
+        "###);
+
+        let eml = emit_eml_with_options(
+            &mail(
+                "From: foo@example.com\nCONTENT-TYPE: Text/HTML",
+                "<p>hello</p>",
+                vec![],
+            ),
+            options,
+        )
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: 8bit
+
+        <p>hello</p>
+        "###);
+
+        // body was converted to HTML
+        let eml =
+            emit_eml_with_options(&mail(plain_headers, "hello<br>world", vec![]), options).unwrap();
+        assert!(eml.contains("Content-Type: multipart/related;"));
+
+        // attachments require multipart
+        let attachments = vec![Attachment {
+            cid: None,
+            mime_type: "x/y".to_owned(),
+            name: "something".to_owned(),
+            data: b"xcddd".to_vec().into(),
+        }];
+        let eml =
+            emit_eml_with_options(&mail(plain_headers, "hello", attachments), options).unwrap();
+        assert!(eml.contains("Content-Type: multipart/mixed;"));
+
+        let eml = emit_eml_with_options(
+            &mail(
+                "From: foo@example.com\nContent-Type: multipart/mixed; boundary=foo",
+                "hello",
+                vec![],
+            ),
+            options,
+        )
+        .unwrap();
+        assert!(eml.contains("Content-Type: multipart/related;"));
+    }
+
+    #[test]
+    fn test_state_headers() {
+        let mail = DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: true,
+                state: MailState::Received,
+                reply_type: ReplyType::ReplyForward,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some(
+                "From: foo@example.com\nx-tuta-unread: 0\nX-Tuta-State:\n Sent\nContent-Type: text/html"
+                    .to_owned(),
+            ),
+            body: b"<p>hello</p>".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![],
+            memory: None,
+        };
+        let options = EmlOptions {
+            body_encoding: BodyEncoding::EightBit,
+            preserve_content_type: true,
+            state_headers: true,
+            ..Default::default()
+        };
+
+        let eml = emit_eml_with_options(&mail, options).unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        X-Tuta-Unread: 1
+        X-Tuta-State: Received
+        X-Tuta-Reply-Type: ReplyForward
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: 8bit
+
+        <p>hello</p>
+        "###);
+    }
+
+    #[test]
+    fn test_omitted_attachment_headers() {
+        let mail = DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hello".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: Some("From: foo@example.com".to_owned()),
+            body: b"<p>hello</p>".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            omitted_attachments: vec![
+                OmittedAttachment {
+                    name: "report; final.pdf".to_owned(),
+                    mime_type: "application/pdf".to_owned(),
+                    size: 1337,
+                },
+                OmittedAttachment {
+                    name: "Bäume.jpg".to_owned(),
+                    mime_type: "image/jpeg".to_owned(),
+                    size: 42,
+                },
+            ],
+            memory: None,
+        };
+        let options = EmlOptions {
+            body_encoding: BodyEncoding::EightBit,
+            preserve_content_type: true,
+            ..Default::default()
+        };
+
+        let eml = emit_eml_with_options(&mail, options).unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        X-Tuta-Omitted-Attachment: type=application/pdf; size=1337; name==?UTF-8?B?cmVwb3J0OyBmaW5hbC5wZGY=?=
+        X-Tuta-Omitted-Attachment: type=image/jpeg; size=42; name==?UTF-8?B?QsOkdW1lLmpwZw==?=
+        Content-Type: multipart/related; boundary="----------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA"
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: 8bit
+
+        <p>hello</p>
+
+        ------------8SXP4129I7U0zBGqTkgpoPMZNXQruJgA--
+        "###);
+    }
+
+    #[test]
+    fn test_write_quoted_printable() {
+        fn qp(data: &[u8]) -> String {
+            let mut buf = vec![];
+            write_quoted_printable(&mut LineWriter::new(&mut buf), data).unwrap();
+            String::from_utf8(buf).unwrap()
+        }
+
+        assert_eq!(qp(b""), "");
+        assert_eq!(qp(b"foo bar"), "foo bar");
+        assert_eq!(qp(b"a\r\nb\nc\n"), "a\r\nb\r\nc\r\n");
+        assert_eq!(qp(b"foo \nbar\t"), "foo=20\r\nbar=09");
+        assert_eq!(qp(b"a=b\x00\xff"), "a=3Db=00=FF");
+
+        // exactly fits into a single line
+        let line = "x".repeat(QP_LINE_CHARS);
+        assert_eq!(qp(line.as_bytes()), line);
+
+        // soft line breaks never split escape sequences
+        let data = format!("{}ä", "x".repeat(QP_LINE_CHARS - 4));
+        assert_eq!(
+            qp(data.as_bytes()),
+            format!("{}=C3=\r\n=A4", "x".repeat(QP_LINE_CHARS - 4)),
+        );
+
+        for line in qp(&[b'='; 1000]).split(NEWLINE) {
+            assert!(line.len() <= QP_LINE_CHARS);
+        }
+    }
+
+    #[test]
+    fn test_address_header_folding() {
+        let addrs = (0..1_000)
+            .map(|i| Address {
+                mail: format!("user{i}@example.com"),
+                name: format!("Üser {i}"),
+            })
+            .collect::<Vec<_>>();
+
+        let header = address_header("To", &addrs);
+        let lines = header.split(NEWLINE).collect::<Vec<_>>();
+        assert!(lines.len() > 1);
+        for (i, line) in lines.iter().enumerate() {
+            assert!(line.len() <= HEADER_LINE_CHARS, "line too long: {line}");
+            assert!(line.len() <= MAX_LINE_OCTETS);
+            assert_eq!(line.starts_with(' '), i > 0);
+        }
+
+        // unfolding restores the original value
+        assert_eq!(
+            header.replace(NEWLINE, ""),
+            format!(
+                "To: {}",
+                addrs
+                    .iter()
+                    .map(|addr| format!("{} <{}>", utf8_header_value(&addr.name), addr.mail))
+                    .join(", ")
+            ),
+        );
+
+        // single long names are folded as well
+        let header = address_header(
+            "To",
+            &[Address {
+                mail: "foo@example.com".to_owned(),
+                name: "ö".repeat(500),
+            }],
+        );
+        for line in header.split(NEWLINE) {
+            assert!(line.len() <= HEADER_LINE_CHARS, "line too long: {line}");
+        }
+    }
+
+    #[test]
+    fn test_utf8_header_value() {
+        assert_eq!(utf8_header_value(""), "=?UTF-8?B??=");
+        assert_eq!(utf8_header_value("Hällö"), "=?UTF-8?B?SMOkbGzDtg==?=");
+
+        let s = "ä".repeat(50);
+        let value = utf8_header_value(&s);
+        let words = value.split(FOLD).collect::<Vec<_>>();
+        assert_eq!(words.len(), 3);
+        let mut decoded = String::new();
+        for word in words {
+            assert!(word.len() <= 75);
+            let encoded = word
+                .strip_prefix("=?UTF-8?B?")
+                .unwrap()
+                .strip_suffix("?=")
+                .unwrap();
+            // every word is valid UTF-8 on its own
+            decoded
+                .push_str(std::str::from_utf8(&BASE64_STANDARD.decode(encoded).unwrap()).unwrap());
+        }
+        assert_eq!(decoded, s);
+
+        assert_eq!(utf8_param_value("å"), "=?UTF-8?B?w6U=?=");
+        assert_eq!(
+            utf8_param_value(&"x".repeat(50)),
+            format!(
+                "\"=?UTF-8?B?{}?=\r\n =?UTF-8?B?eHh4eHg=?=\"",
+                "eHh4".repeat(15)
+            ),
+        );
+    }
+
+    #[test]
+    fn test_boundaries() {
+        let first = Boundaries::new(vec![], "a").next(&[]);
+        assert_eq!(first.len(), 10 + BOUNDARY_CHARS);
+        assert_eq!(first, Boundaries::new(vec![], "a").next(&[]));
+        assert_ne!(first, Boundaries::new(vec![], "b").next(&[]));
+
+        // boundaries of the same mail differ
+        let mut boundaries = Boundaries::new(vec![], "a");
+        assert_eq!(boundaries.next(&[]), first);
+        assert_ne!(boundaries.next(&[]), first);
+
+        // boundary that occurs in the content is skipped
+        let part = format!("foo\r\n--{first}\r\nbar");
+        let boundary = Boundaries::new(vec![], "a").next(&[b"x".as_slice(), part.as_bytes()]);
+        assert_ne!(boundary, first);
+        assert!(!part.contains(&boundary));
+
+        // presets are used first, unless they occur in the content
+        let mut boundaries =
+            Boundaries::new(vec!["a".to_owned(), "b".to_owned(), "c".to_owned()], "a");
+        assert_eq!(boundaries.next(&[b"xyz".as_slice()]), "a");
+        assert_ne!(boundaries.next(&[b"xbz".as_slice()]), "b");
+        assert_eq!(boundaries.next(&[]), "c");
+        assert_ne!(boundaries.next(&[]), "c");
+    }
+
+    #[test]
+    fn test_find_boundaries() {
+        let mut buf = vec![];
+        write_eml(
+            &DownloadedMail {
+                mail: Arc::new(Mail {
+                    folder_id: "folder_id".to_owned(),
+                    mail_id: "mail_id".to_owned(),
+                    archive_id: "archive_id".to_owned(),
+                    blob_id: "blob_id".to_owned(),
+                    is_draft: false,
+                    unread: false,
+                    state: MailState::Received,
+                    reply_type: ReplyType::NoReply,
+                    conversation_id: None,
+                    session_key: Key::Aes256([0; 32]),
+                    bucket_session_keys: Default::default(),
+                    date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                        .unwrap()
+                        .to_utc(),
+                    subject: "Hällö".to_owned(),
+                    sender: Address {
+                        mail: "foo@example.com".to_owned(),
+                        name: "Me".to_owned(),
+                    },
+                    attachments: vec![],
+                }),
+                headers: None,
+                body: b"<p>hello</p>".to_vec(),
+                attachments: vec![],
+                bcc: vec![],
+                cc: vec![],
+                to: vec![],
+                omitted_attachments: vec![],
+                memory: None,
+            },
+            EmlOptions {
+                text_alternative: true,
+                ..Default::default()
+            },
+            vec!["foo".to_owned()],
+            &mut buf,
+        )
+        .unwrap();
+        let eml = String::from_utf8(buf).unwrap();
+
+        let boundaries = find_boundaries(&eml);
+        assert_eq!(boundaries.len(), 2);
+        assert_eq!(boundaries[0], "foo");
+        assert_ne!(boundaries[1], "foo");
+    }
+
+    #[test]
+    fn test_message_id() {
+        assert_eq!(message_id(""), None);
+        assert_eq!(message_id("Subject: foo\r\n"), None);
+        assert_eq!(message_id("Message-ID:  \r\nSubject: foo"), None);
+        assert_eq!(
+            message_id("Subject: foo\r\nMessage-ID: <a@b>\r\nTo: x@y"),
+            Some("<a@b>".to_owned()),
+        );
+        assert_eq!(
+            message_id("message-id:\r\n <a@b>\r\nTo: x@y"),
+            Some("<a@b>".to_owned()),
+        );
+    }
+}
@@ -0,0 +1,98 @@
+//! Server events that are pushed via websocket.
+use anyhow::{Context, Result};
+use futures::{Stream, StreamExt, TryStreamExt};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::debug;
+
+use crate::{
+    client::Client,
+    proto::messages::{EntityUpdate, WebsocketEntityData},
+    session::Session,
+};
+
+/// Subscribe to entity updates of the logged-in user.
+///
+/// The stream ends when the server closes the connection.
+pub async fn entity_updates(
+    client: &Client,
+    session: &Session,
+) -> Result<impl Stream<Item = Result<EntityUpdate>>> {
+    let access_token = session.access_token.to_string();
+    let ws = client
+        .websocket(
+            "event",
+            &[
+                ("userIds", session.user_id.as_str()),
+                ("accessToken", access_token.as_str()),
+            ],
+        )
+        .await
+        .context("open event channel")?;
+
+    Ok(ws
+        .map(|msg| match msg.context("receive websocket message")? {
+            Message::Text(text) => parse_message(&text),
+            Message::Close(frame) => {
+                debug!(?frame, "event channel closed by server");
+                Ok(vec![])
+            }
+            _ => Ok(vec![]),
+        })
+        .map_ok(|updates| futures::stream::iter(updates.into_iter().map(Ok)))
+        .try_flatten())
+}
+
+/// Parse websocket message of the form `<type>;<JSON>`.
+///
+/// Only `entityUpdate` messages are considered, all other types are ignored.
+fn parse_message(msg: &str) -> Result<Vec<EntityUpdate>> {
+    let (msg_type, data) = msg
+        .split_once(';')
+        .context("websocket message without type")?;
+
+    match msg_type {
+        "entityUpdate" => {
+            let data: WebsocketEntityData =
+                serde_json::from_str(data).context("parse entity update")?;
+            debug!(
+                event_batch_id = data.event_batch_id.as_str(),
+                n_updates = data.event_batch.len(),
+                "entity update",
+            );
+            Ok(data.event_batch)
+        }
+        _ => {
+            debug!(msg_type, "ignore websocket message");
+            Ok(vec![])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::enums::OperationType;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_message() {
+        let updates = parse_message(
+            r#"entityUpdate;{"_format":"0","eventBatchId":"b","eventBatchOwner":"o","eventBatch":[{"_id":"x","application":"tutanota","type":"Mail","instanceListId":"l","instanceId":"i","operation":"0"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].application, "tutanota");
+        assert_eq!(updates[0].instance_list_id, "l");
+        assert_eq!(updates[0].instance_id, "i");
+        assert_eq!(updates[0].operation, OperationType::Create);
+
+        assert!(parse_message(r#"unreadCounterUpdate;{"counterValues":[]}"#)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            parse_message("foo").unwrap_err().to_string(),
+            "websocket message without type",
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Classes of failures, so that callers can tell "retry later" from "fix the setup".
+use std::fmt::Display;
+
+use tokio::time::error::Elapsed;
+
+use crate::client::RequestError;
+
+/// Class of failure.
+///
+/// Attach it to an error via [`anyhow::Context`] to classify it explicitly. Other errors are classified by their
+/// causes, see [`of`](Self::of).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Login failed, e.g. because of wrong credentials.
+    Auth,
+
+    /// Requested folder does not exist.
+    FolderNotFound,
+
+    /// Export finished, but some mails failed, see `--keep-going`.
+    PartialExport,
+
+    /// Server unreachable, overloaded, or retry deadline exceeded. Retrying later may help.
+    Network,
+
+    /// Reading or writing local files failed.
+    Filesystem,
+}
+
+impl Failure {
+    /// Retrying does NOT help without user intervention, e.g. because the credentials or the config are wrong.
+    pub fn is_permanent(self) -> bool {
+        matches!(self, Self::Auth | Self::FolderNotFound)
+    }
+
+    /// Classify error.
+    ///
+    /// Explicit classifications take precedence. Otherwise the outermost cause that can be classified wins.
+    pub fn of(e: &anyhow::Error) -> Option<Self> {
+        if let Some(failure) = e.downcast_ref::<Self>() {
+            return Some(*failure);
+        }
+
+        e.chain().find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<RequestError>() {
+                e.failure()
+            } else if cause.is::<Elapsed>() {
+                Some(Self::Network)
+            } else if cause.is::<std::io::Error>() {
+                Some(Self::Filesystem)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Auth => "authentication failed",
+            Self::FolderNotFound => "folder not found",
+            Self::PartialExport => "partial export",
+            Self::Network => "network failure",
+            Self::Filesystem => "file system failure",
+        };
+        f.write_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::*;
+
+    #[test]
+    fn test_of() {
+        assert_eq!(Failure::of(&anyhow!("foo")), None);
+
+        let e = anyhow!("foo")
+            .context(Failure::PartialExport)
+            .context("bar");
+        assert_eq!(Failure::of(&e), Some(Failure::PartialExport));
+        assert_eq!(format!("{e:#}"), "bar: partial export: foo");
+
+        let e = None::<()>
+            .context(Failure::FolderNotFound)
+            .context("bar")
+            .unwrap_err();
+        assert_eq!(Failure::of(&e), Some(Failure::FolderNotFound));
+
+        let e = anyhow::Error::new(std::io::Error::other("foo")).context("write file");
+        assert_eq!(Failure::of(&e), Some(Failure::Filesystem));
+
+        // explicit classification wins
+        let e = anyhow::Error::new(std::io::Error::other("foo")).context(Failure::Network);
+        assert_eq!(Failure::of(&e), Some(Failure::Network));
+    }
+}
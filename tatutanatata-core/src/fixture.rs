@@ -0,0 +1,702 @@
+//! Synthetic account for offline tests.
+//!
+//! [`generate`] writes the server responses for a small, made-up account into a directory in the format of `--debug-dump-json-to`, so
+//! that commands can run end-to-end via `--replay-from` without real credentials. The data is encrypted with keys that
+//! are derived from the given password, so the regular login and decryption code paths are exercised.
+//!
+//! Only the requests of a forward listing are recorded, i.e. `--parallel-pagination` is NOT supported. Besides whole
+//! folders, the listings of [`Mail::list_same_second`](crate::mails::Mail::list_same_second) are recorded. Mail details
+//! are recorded for whole folders and for single mails.
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use chrono::{DateTime, SubsecRound};
+use reqwest::Method;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    blob::access_request,
+    client::{generated_id_at, Prefix, Request, GENERATED_MIN_ID, STREAM_BATCH_SIZE},
+    constants::APP_USER_AGENT,
+    crypto::{
+        auth::{derive_passkey, encode_auth_verifier},
+        encryption::{encrypt_key, encrypt_value},
+    },
+    dump::Dump,
+    mails::SINCE_ID_SLACK,
+    proto::{
+        binary::{Base64String, Base64Url},
+        boolean::Boolean,
+        constants::Format,
+        date::UnixDate,
+        enums::{ArchiveDataType, GroupType, KdfVersion, MailFolderType, MailState, ReplyType},
+        keys::Key,
+        messages::{BlobServiceRequest, SaltServiceRequest, SessionServiceRequest},
+        numbers::Number,
+    },
+    session::LoginConfig,
+};
+
+const USER_ID: &str = "fixtureUser";
+const USER_GROUP_ID: &str = "fixtureUserGroup";
+const MAIL_GROUP_ID: &str = "fixtureMailGroup";
+const SESSIONS_ID: &str = "fixtureSessions";
+const MAILBOX_GROUP_ROOT_ID: &str = "fixtureMailboxGroupRoot";
+const MAILBOX_ID: &str = "fixtureMailbox";
+const FOLDER_LIST_ID: &str = "fixtureFolders";
+const DETAILS_ARCHIVE_ID: &str = "fixtureDetailsArchive";
+const FILE_LIST_ID: &str = "fixtureFiles";
+const FILE_ARCHIVE_ID: &str = "fixtureFileArchive";
+const BLOB_SERVER: &str = "https://blob.fixture.invalid";
+const SALT: [u8; 16] = *b"fixture-salt-16b";
+
+/// Folders of the account.
+const FOLDERS: &[FixtureFolder] = &[
+    FixtureFolder {
+        id: "fixtureInbox",
+        folder_type: MailFolderType::Inbox,
+        name: "",
+        parent: None,
+        mails: &[],
+    },
+    FixtureFolder {
+        id: "fixtureSent",
+        folder_type: MailFolderType::Sent,
+        name: "",
+        parent: None,
+        mails: &[],
+    },
+    FixtureFolder {
+        id: "fixtureTrash",
+        folder_type: MailFolderType::Trash,
+        name: "",
+        parent: None,
+        mails: &[],
+    },
+    FixtureFolder {
+        id: "fixtureArchive",
+        folder_type: MailFolderType::Archive,
+        name: "",
+        parent: None,
+        // Same subject and second, so their default file names collide. Unlike the other mails, the IDs encode the
+        // creation time like real ones, see `Mail::list_same_second`.
+        mails: &[
+            FixtureMail {
+                id: "NxWZSfN----0",
+                received: "2024-05-10T08:15:00.100Z",
+                subject: "Invoice",
+                sender: ("billing@example.com", "Billing"),
+                to: &[("bob@example.com", "Bob")],
+                cc: &[],
+                headers: None,
+                body: "<p>Invoice 1</p>",
+                compressed: false,
+                unread: false,
+                attachment: None,
+            },
+            FixtureMail {
+                id: "NxWZSfN----1",
+                received: "2024-05-10T08:15:00.700Z",
+                subject: "Invoice",
+                sender: ("billing@example.com", "Billing"),
+                to: &[("bob@example.com", "Bob")],
+                cc: &[],
+                headers: None,
+                body: "<p>Invoice 2</p>",
+                compressed: false,
+                unread: false,
+                attachment: None,
+            },
+        ],
+    },
+    FixtureFolder {
+        id: "fixtureSpam",
+        folder_type: MailFolderType::Spam,
+        name: "",
+        parent: None,
+        mails: &[],
+    },
+    FixtureFolder {
+        id: "fixtureDraft",
+        folder_type: MailFolderType::Draft,
+        name: "",
+        parent: None,
+        mails: &[],
+    },
+    FixtureFolder {
+        id: "fixtureCustom",
+        folder_type: MailFolderType::Custom,
+        name: "fooooo",
+        parent: None,
+        mails: &[
+            FixtureMail {
+                id: "fixtureMail1",
+                received: "2024-01-15T09:30:00Z",
+                subject: "Hello",
+                sender: ("alice@example.com", "Alice"),
+                to: &[("bob@example.com", "Bob")],
+                cc: &[],
+                headers: Some(
+                    "Message-ID: <fixture-1@example.com>\r\nFrom: Alice <alice@example.com>\r\nTo: Bob <bob@example.com>\r\nSubject: Hello",
+                ),
+                body: "<p>Hello Bob!</p>",
+                compressed: true,
+                unread: false,
+                attachment: None,
+            },
+            FixtureMail {
+                id: "fixtureMail2",
+                received: "2024-03-02T18:00:00Z",
+                subject: "Grüße mit Anhang",
+                sender: ("carol@example.org", "Carol"),
+                to: &[("bob@example.com", "Bob")],
+                cc: &[("alice@example.com", "")],
+                headers: None,
+                body: "<p>See the attached notes.</p>",
+                compressed: false,
+                unread: true,
+                attachment: Some(FixtureAttachment {
+                    id: "fixtureFile1",
+                    blob_id: "fixtureFileBlob1",
+                    name: "notes.txt",
+                    mime_type: "text/plain",
+                    data: "first line\nsecond line\n",
+                }),
+            },
+            FixtureMail {
+                id: "fixtureMail3",
+                received: "2023-11-20T07:45:00Z",
+                subject: "Old news",
+                sender: ("alice@example.com", "Alice"),
+                to: &[("bob@example.com", "Bob")],
+                cc: &[],
+                headers: None,
+                body: "<p>This happened last year.</p>",
+                compressed: true,
+                unread: false,
+                attachment: None,
+            },
+        ],
+    },
+    FixtureFolder {
+        id: "fixtureNested",
+        folder_type: MailFolderType::Custom,
+        name: "baaar",
+        parent: Some("fixtureCustom"),
+        mails: &[],
+    },
+];
+
+#[derive(Debug)]
+struct FixtureFolder {
+    id: &'static str,
+    folder_type: MailFolderType,
+
+    /// Name of custom folders, system folders are named by their type.
+    name: &'static str,
+
+    /// ID of the parent folder of nested folders.
+    parent: Option<&'static str>,
+
+    mails: &'static [FixtureMail],
+}
+
+impl FixtureFolder {
+    fn mail_list_id(&self) -> String {
+        format!("{}Mails", self.id)
+    }
+}
+
+#[derive(Debug)]
+struct FixtureMail {
+    id: &'static str,
+
+    /// RFC 3339 timestamp.
+    received: &'static str,
+
+    subject: &'static str,
+    sender: (&'static str, &'static str),
+    to: &'static [(&'static str, &'static str)],
+    cc: &'static [(&'static str, &'static str)],
+
+    /// Headers of true emails, internal messages have none.
+    headers: Option<&'static str>,
+
+    body: &'static str,
+
+    /// Body and headers are LZ4-compressed.
+    compressed: bool,
+
+    unread: bool,
+    attachment: Option<FixtureAttachment>,
+}
+
+impl FixtureMail {
+    fn blob_id(&self) -> String {
+        format!("{}Details", self.id)
+    }
+}
+
+#[derive(Debug)]
+struct FixtureAttachment {
+    id: &'static str,
+    blob_id: &'static str,
+    name: &'static str,
+    mime_type: &'static str,
+    data: &'static str,
+}
+
+/// Write responses for the synthetic account to the given directory.
+///
+/// The account can be accessed with the username and password of the login config.
+pub async fn generate(login_cfg: &LoginConfig, path: &Path) -> Result<()> {
+    let (username, password) = login_cfg.username_and_password()?;
+    let writer = Writer {
+        dump: Dump::try_new(path.to_owned()).await?,
+        access_token: Base64Url::from(rand::random::<[u8; 32]>()),
+    };
+
+    // login
+    let passkey = derive_passkey(KdfVersion::Bcrypt, password, &SALT).context("derive passkey")?;
+    let user_key = random_key();
+    let mail_group_key = random_key();
+    writer
+        .json(
+            Request::new(
+                Prefix::Sys,
+                "saltservice",
+                &SaltServiceRequest {
+                    format: Default::default(),
+                    mail_address: username.to_owned(),
+                },
+            ),
+            &json!({
+                "_format": Format::<0>,
+                "kdfVersion": KdfVersion::Bcrypt,
+                "salt": Base64String::from(SALT),
+            }),
+        )
+        .await?;
+    writer
+        .json(
+            Request {
+                method: Method::POST,
+                ..Request::new(
+                    Prefix::Sys,
+                    "sessionservice",
+                    &SessionServiceRequest {
+                        format: Default::default(),
+                        access_key: Default::default(),
+                        auth_token: Default::default(),
+                        auth_verifier: Some(encode_auth_verifier(&passkey)),
+                        client_identifier: APP_USER_AGENT.to_owned(),
+                        mail_address: username.to_owned(),
+                        recover_code_verifier: None,
+                        user: Default::default(),
+                    },
+                )
+            },
+            &json!({
+                "_format": Format::<0>,
+                "accessToken": writer.access_token,
+                "challenges": [],
+                "user": USER_ID,
+            }),
+        )
+        .await?;
+    writer
+        .json(
+            Request::new(Prefix::Sys, &format!("user/{USER_ID}"), &()),
+            &json!({
+                "_format": Format::<0>,
+                "memberships": [{
+                    "groupType": GroupType::Mail,
+                    "group": MAIL_GROUP_ID,
+                    "symEncGKey": encrypt_key(user_key, mail_group_key),
+                }],
+                "auth": {
+                    "sessions": SESSIONS_ID,
+                    "recoverCode": null,
+                },
+                "userGroup": {
+                    "groupType": GroupType::User,
+                    "group": USER_GROUP_ID,
+                    "symEncGKey": encrypt_key(*passkey, user_key),
+                },
+            }),
+        )
+        .await?;
+    for group in [USER_GROUP_ID, MAIL_GROUP_ID] {
+        writer
+            .json(
+                Request::new(Prefix::Sys, &format!("group/{group}"), &()),
+                &json!({
+                    "_format": Format::<0>,
+                    "currentKeys": null,
+                    "admin": null,
+                    "adminGroupEncGKey": null,
+                }),
+            )
+            .await?;
+    }
+
+    // folders
+    writer
+        .json(
+            Request::new(
+                Prefix::Tutanota,
+                &format!("mailboxgrouproot/{MAIL_GROUP_ID}"),
+                &(),
+            ),
+            &json!({
+                "_format": Format::<0>,
+                "_id": MAILBOX_GROUP_ROOT_ID,
+                "mailbox": MAILBOX_ID,
+            }),
+        )
+        .await?;
+    writer
+        .json(
+            Request::new(Prefix::Tutanota, &format!("mailbox/{MAILBOX_ID}"), &()),
+            &json!({
+                "_format": Format::<0>,
+                "folders": {
+                    "folders": FOLDER_LIST_ID,
+                },
+            }),
+        )
+        .await?;
+    let folders = FOLDERS
+        .iter()
+        .map(|folder| {
+            let session_key = random_key();
+            json!({
+                "_format": Format::<0>,
+                "_id": [FOLDER_LIST_ID, folder.id],
+                "_ownerEncSessionKey": encrypt_key(mail_group_key, session_key),
+                "_ownerGroup": MAIL_GROUP_ID,
+                "folderType": folder.folder_type,
+                "name": Base64String::from(encrypt_value(session_key, folder.name.as_bytes())),
+                "mails": folder.mail_list_id(),
+                "parentFolder": folder.parent.map(|parent| [FOLDER_LIST_ID, parent]),
+            })
+        })
+        .collect::<Vec<_>>();
+    writer
+        .list(
+            &format!("mailfolder/{FOLDER_LIST_ID}"),
+            GENERATED_MIN_ID,
+            &folders,
+        )
+        .await?;
+
+    // mails
+    for folder in FOLDERS {
+        let mail_list_id = folder.mail_list_id();
+        let mut mails = Vec::with_capacity(folder.mails.len());
+        let mut details = Vec::with_capacity(folder.mails.len());
+        for mail in folder.mails {
+            let session_key = random_key();
+            let (m, d) = mail_entities(mail, &mail_list_id, mail_group_key, session_key)?;
+            writer
+                .json(
+                    Request::new(
+                        Prefix::Tutanota,
+                        &format!("mail/{mail_list_id}/{}", mail.id),
+                        &(),
+                    ),
+                    &m,
+                )
+                .await?;
+            writer
+                .mail_details(&[mail.blob_id()], std::slice::from_ref(&d))
+                .await?;
+            if let Some(attachment) = &mail.attachment {
+                writer.attachment(attachment, mail_group_key).await?;
+            }
+            mails.push(m);
+            details.push(d);
+        }
+
+        writer
+            .list(&format!("mail/{mail_list_id}"), GENERATED_MIN_ID, &mails)
+            .await?;
+        for mail in folder.mails {
+            let received = DateTime::parse_from_rfc3339(mail.received)
+                .context("parse received date")?
+                .to_utc();
+            let start = generated_id_at(received.trunc_subsecs(0) - SINCE_ID_SLACK);
+            let window = mails
+                .iter()
+                .filter(|m| m["_id"][1].as_str().is_some_and(|id| id > start.as_str()))
+                .cloned()
+                .collect::<Vec<_>>();
+            writer
+                .list(&format!("mail/{mail_list_id}"), &start, &window)
+                .await?;
+        }
+        if !folder.mails.is_empty() {
+            let blob_ids = folder
+                .mails
+                .iter()
+                .map(FixtureMail::blob_id)
+                .collect::<Vec<_>>();
+            writer.mail_details(&blob_ids, &details).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Mail and mail details blob.
+fn mail_entities(
+    mail: &FixtureMail,
+    mail_list_id: &str,
+    mail_group_key: Key,
+    session_key: Key,
+) -> Result<(serde_json::Value, serde_json::Value)> {
+    let encrypt = |s: &str| Base64String::from(encrypt_value(session_key, s.as_bytes()));
+    let address = |(address, name): (&str, &str)| {
+        json!({
+            "address": address,
+            "name": encrypt(name),
+        })
+    };
+    let compressible = |s: &str| {
+        let data = if mail.compressed {
+            lz4_flex::block::compress(s.as_bytes())
+        } else {
+            s.as_bytes().to_vec()
+        };
+        Base64String::from(encrypt_value(session_key, &data))
+    };
+    let received = DateTime::parse_from_rfc3339(mail.received)
+        .context("parse received date")?
+        .to_utc();
+
+    let m = json!({
+        "_format": Format::<0>,
+        "_ownerEncSessionKey": encrypt_key(mail_group_key, session_key),
+        "_ownerGroup": MAIL_GROUP_ID,
+        "_id": [mail_list_id, mail.id],
+        "mailDetails": [DETAILS_ARCHIVE_ID, mail.blob_id()],
+        "mailDetailsDraft": null,
+        "receivedDate": UnixDate(received),
+        "subject": encrypt(mail.subject),
+        "sender": address(mail.sender),
+        "attachments": mail
+            .attachment
+            .iter()
+            .map(|a| [FILE_LIST_ID, a.id])
+            .collect::<Vec<_>>(),
+        "unread": Boolean(mail.unread),
+        "state": MailState::Received,
+        "replyType": ReplyType::NoReply,
+        "conversationEntry": [format!("{}Conversation", mail.id), mail.id],
+        "bucketKey": null,
+    });
+
+    let (text_field, headers_field) = if mail.compressed {
+        ("compressedText", "compressedHeaders")
+    } else {
+        ("text", "headers")
+    };
+    let d = json!({
+        "_format": Format::<0>,
+        "_id": [DETAILS_ARCHIVE_ID, mail.blob_id()],
+        "details": {
+            "body": {
+                text_field: compressible(mail.body),
+            },
+            "headers": mail.headers.map(|h| json!({headers_field: compressible(h)})),
+            "recipients": {
+                "bccRecipients": [],
+                "ccRecipients": mail.cc.iter().copied().map(address).collect::<Vec<_>>(),
+                "toRecipients": mail.to.iter().copied().map(address).collect::<Vec<_>>(),
+            },
+        },
+    });
+
+    Ok((m, d))
+}
+
+fn random_key() -> Key {
+    Key::Aes256(rand::random())
+}
+
+/// Writes responses to the dump.
+#[derive(Debug)]
+struct Writer {
+    dump: Dump,
+    access_token: Base64Url,
+}
+
+impl Writer {
+    async fn json<Req, Resp>(&self, r: Request<'_, Req>, resp: &Resp) -> Result<()>
+    where
+        Req: Serialize + Sync,
+        Resp: Serialize + Sync,
+    {
+        let data = serde_json::to_vec(resp).context("serialize response")?;
+        self.dump
+            .write(
+                &Request {
+                    access_token: Some(&self.access_token),
+                    ..r
+                },
+                "json",
+                &data,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Pages of a list that starts after the given ID, see [`Client::stream`](crate::client::Client::stream).
+    async fn list<'a>(
+        &self,
+        path: &str,
+        mut start: &'a str,
+        elements: &'a [serde_json::Value],
+    ) -> Result<()> {
+        ensure!(
+            elements.len() < STREAM_BATCH_SIZE as usize,
+            "list does not fit into a single page"
+        );
+
+        let count = STREAM_BATCH_SIZE.to_string();
+        let mut page = elements;
+        loop {
+            self.json(
+                Request {
+                    query: &[
+                        ("start", start),
+                        ("count", count.as_str()),
+                        ("reverse", "false"),
+                    ],
+                    ..Request::new(Prefix::Tutanota, path, &())
+                },
+                &page,
+            )
+            .await?;
+
+            let Some(last) = page.last() else {
+                return Ok(());
+            };
+            start = last["_id"][1].as_str().context("element ID")?;
+            page = &[];
+        }
+    }
+
+    /// Blob access token and mail details, see [`get_mail_blobs`](crate::blob::get_mail_blobs).
+    async fn mail_details(&self, blob_ids: &[String], details: &[serde_json::Value]) -> Result<()> {
+        self.blob_access(DETAILS_ARCHIVE_ID, ArchiveDataType::MailDetails, None)
+            .await?;
+        let access_token = self.access_token.to_string();
+        self.json(
+            Request {
+                host: Some(BLOB_SERVER),
+                query: &[
+                    ("accessToken", access_token.as_str()),
+                    ("ids", blob_ids.join(",").as_str()),
+                    ("blobAccessToken", "fixture"),
+                ],
+                ..Request::new(
+                    Prefix::Tutanota,
+                    &format!("maildetailsblob/{DETAILS_ARCHIVE_ID}"),
+                    &(),
+                )
+            },
+            &details,
+        )
+        .await
+    }
+
+    /// File, blob access token, and data, see [`get_attachment_blob`](crate::blob::get_attachment_blob).
+    async fn attachment(&self, attachment: &FixtureAttachment, mail_group_key: Key) -> Result<()> {
+        let session_key = random_key();
+        let data = encrypt_value(session_key, attachment.data.as_bytes());
+        let encrypt = |s: &str| Base64String::from(encrypt_value(session_key, s.as_bytes()));
+
+        self.json(
+            Request {
+                query: &[("ids", attachment.id)],
+                ..Request::new(Prefix::Tutanota, &format!("file/{FILE_LIST_ID}"), &())
+            },
+            &[json!({
+                "_format": Format::<0>,
+                "_ownerEncSessionKey": encrypt_key(mail_group_key, session_key),
+                "_ownerGroup": MAIL_GROUP_ID,
+                "cid": null,
+                "mimeType": encrypt(attachment.mime_type),
+                "name": encrypt(attachment.name),
+                "size": Number(attachment.data.len() as u64),
+                "blobs": [{
+                    "archiveId": FILE_ARCHIVE_ID,
+                    "blobId": attachment.blob_id,
+                    "size": Number(data.len() as u64),
+                }],
+            })],
+        )
+        .await?;
+
+        self.blob_access(
+            FILE_ARCHIVE_ID,
+            ArchiveDataType::Attachments,
+            Some((FILE_LIST_ID, attachment.id)),
+        )
+        .await?;
+        let access_token = self.access_token.to_string();
+        let body = serde_json::to_string(&BlobServiceRequest {
+            format: Default::default(),
+            archive_id: FILE_ARCHIVE_ID.to_owned(),
+            blob_id: attachment.blob_id.to_owned(),
+            blob_ids: vec![],
+        })
+        .context("serialize blob request")?;
+        self.dump
+            .write(
+                &Request {
+                    host: Some(BLOB_SERVER),
+                    query: &[
+                        ("accessToken", access_token.as_str()),
+                        ("blobAccessToken", "fixture"),
+                        ("_body", body.as_str()),
+                    ],
+                    ..Request::new(Prefix::Storage, "blobservice", &())
+                },
+                "bin",
+                &data,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn blob_access(
+        &self,
+        archive_id: &str,
+        archive_data_type: ArchiveDataType,
+        instance: Option<(&str, &str)>,
+    ) -> Result<()> {
+        self.json(
+            Request {
+                method: Method::POST,
+                ..Request::new(
+                    Prefix::Storage,
+                    "blobaccesstokenservice",
+                    &access_request(archive_id, archive_data_type, instance),
+                )
+            },
+            &json!({
+                "_format": Format::<0>,
+                "blobAccessInfo": {
+                    "blobAccessToken": "fixture",
+                    "servers": [{"url": BLOB_SERVER}],
+                    "expires": null,
+                },
+            }),
+        )
+        .await
+    }
+}
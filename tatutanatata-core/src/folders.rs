@@ -0,0 +1,391 @@
+//! Mail folders and the mail groups that own them.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use futures::{Stream, TryStreamExt};
+use reqwest::Method;
+use tracing::{debug, warn};
+
+use crate::{
+    client::{Client, Prefix, Request},
+    crypto::encryption::{decrypt_key, decrypt_value},
+    failure::Failure,
+    proto::{
+        enums::{GroupType, MailFolderType},
+        messages::{FolderResponse, MailboxGroupRootResponse, MailboxResponse, UserMembership},
+    },
+    session::{GroupKeys, Session},
+};
+
+/// Mail folder, see [`Folder::list`].
+#[derive(Debug)]
+pub struct Folder {
+    /// Decrypted name, or the type of system folders like `Inbox`.
+    pub name: String,
+
+    /// ID of the list that contains the mails of this folder.
+    pub mails: String,
+
+    /// Folder ID.
+    pub id: String,
+
+    /// System folder type, [`MailFolderType::Custom`] for user-created folders.
+    pub folder_type: MailFolderType,
+
+    /// The key of the group that owns this folder is known.
+    ///
+    /// If this is `false`, neither the folder nor its mails can be decrypted. The name of custom folders is then
+    /// replaced by the folder ID.
+    pub accessible: bool,
+
+    /// ID of the parent folder, if this is a nested folder.
+    pub parent: Option<String>,
+}
+
+impl Folder {
+    /// List folders of the given mail group, see `get_mail_membership`.
+    pub async fn list(
+        client: &Client,
+        session: &Session,
+        group: Option<&str>,
+    ) -> Result<impl Stream<Item = Result<Self>>> {
+        let mail_group =
+            get_mail_membership(&session.user_data.memberships, group).context("get mail group")?;
+
+        let resp: MailboxGroupRootResponse = client
+            .do_json(Request {
+                method: Method::GET,
+                host: None,
+                prefix: Prefix::Tutanota,
+                path: &format!("mailboxgrouproot/{}", mail_group.group),
+                data: &(),
+                access_token: Some(&session.access_token),
+                query: &[],
+            })
+            .await
+            .context("get mailbox group root")?;
+        let mailbox = resp.mailbox;
+
+        debug!(mailbox = mailbox.as_str(), "mailbox found");
+
+        let resp: MailboxResponse = client
+            .do_json(Request {
+                method: Method::GET,
+                host: None,
+                prefix: Prefix::Tutanota,
+                path: &format!("mailbox/{mailbox}"),
+                data: &(),
+                access_token: Some(&session.access_token),
+                query: &[],
+            })
+            .await
+            .context("get mailbox")?;
+        let folders = resp.folders.folders;
+
+        debug!(folders = folders.as_str(), "folders found");
+
+        let group_keys = Arc::clone(&session.group_keys);
+        let stream = client
+            .stream::<FolderResponse>(
+                &format!("mailfolder/{folders}"),
+                Some(&session.access_token),
+                None,
+            )
+            .and_then(move |f| {
+                let group_keys = Arc::clone(&group_keys);
+                async move { Self::decode(f, &group_keys) }
+            });
+
+        Ok(stream)
+    }
+
+    /// Find folder by name.
+    pub async fn find(
+        client: &Client,
+        session: &Session,
+        group: Option<&str>,
+        name: &str,
+    ) -> Result<Self> {
+        Self::find_by(client, session, group, name, |f| f.name == name).await
+    }
+
+    /// Find folder by ID, see [`Mail::ui_url`](crate::mails::Mail::ui_url).
+    pub async fn find_by_id(
+        client: &Client,
+        session: &Session,
+        group: Option<&str>,
+        id: &str,
+    ) -> Result<Self> {
+        Self::find_by(client, session, group, id, |f| f.id == id).await
+    }
+
+    async fn find_by(
+        client: &Client,
+        session: &Session,
+        group: Option<&str>,
+        name: &str,
+        predicate: impl Fn(&Self) -> bool + Send + Sync,
+    ) -> Result<Self> {
+        let folders = Self::list(client, session, group)
+            .await
+            .context("get folders")?
+            .try_filter(|f| futures::future::ready(predicate(f)));
+        let mut folders = std::pin::pin!(folders);
+        let folder = folders
+            .try_next()
+            .await
+            .context("search folder")?
+            .context(Failure::FolderNotFound)?;
+        ensure!(
+            folder.accessible,
+            "folder `{name}` is inaccessible: no key for its owner group"
+        );
+        debug!(mails = folder.mails.as_str(), "found folder");
+
+        Ok(folder)
+    }
+
+    fn decode(resp: FolderResponse, group_keys: &GroupKeys) -> Result<Self> {
+        let id = resp.id[1].clone();
+        let parent = resp.parent_folder.map(|[_list, id]| id);
+
+        if !group_keys.contains(&resp.owner_group) {
+            warn!(
+                folder_id = id.as_str(),
+                owner_group = resp.owner_group.as_str(),
+                "no key for folder owner group, folder is inaccessible",
+            );
+
+            let name = if resp.folder_type == MailFolderType::Custom {
+                id.clone()
+            } else {
+                resp.folder_type.name().to_owned()
+            };
+            return Ok(Self {
+                name,
+                mails: resp.mails,
+                id,
+                folder_type: resp.folder_type,
+                accessible: false,
+                parent,
+            });
+        }
+
+        let session_key = decrypt_key(
+            group_keys
+                .get(&resp.owner_group)
+                .context("getting owner group key")?,
+            resp.owner_enc_session_key,
+        )
+        .context("decrypting session key")?;
+
+        let name = if resp.folder_type == MailFolderType::Custom {
+            String::from_utf8(
+                decrypt_value(session_key, resp.name.as_ref()).context("decrypt folder name")?,
+            )
+            .context("invalid UTF8 string")?
+        } else {
+            resp.folder_type.name().to_owned()
+        };
+
+        Ok(Self {
+            name,
+            mails: resp.mails,
+            id,
+            folder_type: resp.folder_type,
+            accessible: true,
+            parent,
+        })
+    }
+}
+
+/// Order folders depth-first, so that nested folders follow their parent.
+///
+/// Returns the nesting depth and the index of each folder. Siblings keep their order. Folders whose parent is unknown,
+/// or that are part of a cycle, are treated as top-level folders.
+pub fn tree(folders: &[Folder]) -> Vec<(usize, usize)> {
+    let index = folders
+        .iter()
+        .enumerate()
+        .map(|(idx, f)| (f.id.as_str(), idx))
+        .collect::<HashMap<_, _>>();
+    let parent = |f: &Folder| f.parent.as_deref().and_then(|p| index.get(p).copied());
+    let mut children = HashMap::<usize, Vec<usize>>::new();
+    for f in folders {
+        if let Some(p) = parent(f) {
+            children.entry(p).or_default().push(index[f.id.as_str()]);
+        }
+    }
+
+    let mut out = Vec::with_capacity(folders.len());
+    let mut seen = HashSet::with_capacity(folders.len());
+    let roots = (0..folders.len()).filter(|idx| parent(&folders[*idx]).is_none());
+    for root in roots.chain(0..folders.len()) {
+        let mut stack = vec![(0, root)];
+        while let Some((depth, idx)) = stack.pop() {
+            if !seen.insert(idx) {
+                continue;
+            }
+            out.push((depth, idx));
+            stack.extend(
+                children
+                    .get(&idx)
+                    .into_iter()
+                    .flatten()
+                    .rev()
+                    .map(|child| (depth + 1, *child)),
+            );
+        }
+    }
+
+    out
+}
+
+/// IDs of all mail groups that can be decrypted.
+pub fn mail_groups(session: &Session) -> Vec<&str> {
+    session
+        .user_data
+        .memberships
+        .iter()
+        .filter(|m| m.group_type == GroupType::Mail)
+        .filter(|m| {
+            let accessible = session.group_keys.contains(&m.group);
+            if !accessible {
+                warn!(group = m.group.as_str(), "no key for mail group, skip it");
+            }
+            accessible
+        })
+        .map(|m| m.group.as_str())
+        .collect()
+}
+
+/// Select the mail group whose folders are used.
+///
+/// If `group` is [`None`], the account must have exactly one mail group.
+fn get_mail_membership<'a>(
+    memberships: &'a [UserMembership],
+    group: Option<&str>,
+) -> Result<&'a UserMembership> {
+    debug!(group, "get mail membership");
+
+    let mut mail_memberships = memberships
+        .iter()
+        .filter(|m| m.group_type == GroupType::Mail);
+    let membership = match group {
+        Some(group) => mail_memberships
+            .find(|m| m.group == group)
+            .with_context(|| format!("no mail group `{group}`, see `list-groups`"))?,
+        None => {
+            let membership = mail_memberships.next().context("no mail group found")?;
+            if let Some(other) = mail_memberships.next() {
+                bail!(
+                    "multiple mail groups found (`{}`, `{}`, ...), select one via `--group`, see `list-groups`",
+                    membership.group,
+                    other.group,
+                );
+            }
+            membership
+        }
+    };
+
+    debug!(group = membership.group.as_str(), "got mail membership");
+
+    Ok(membership)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::keys::OptionalEncryptedKey;
+
+    use super::*;
+
+    fn membership(group_type: GroupType, group: &str) -> UserMembership {
+        UserMembership {
+            group_type,
+            group: group.to_owned(),
+            sym_enc_g_key: OptionalEncryptedKey(None),
+        }
+    }
+
+    fn folder(id: &str, parent: Option<&str>) -> Folder {
+        Folder {
+            name: id.to_owned(),
+            mails: format!("{id}Mails"),
+            id: id.to_owned(),
+            folder_type: MailFolderType::Custom,
+            accessible: true,
+            parent: parent.map(ToOwned::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_tree() {
+        let folders = [
+            folder("c", Some("a")),
+            folder("a", None),
+            folder("b", None),
+            folder("d", Some("c")),
+            folder("e", Some("a")),
+            folder("orphan", Some("unknown")),
+            folder("x", Some("y")),
+            folder("y", Some("x")),
+        ];
+
+        let tree = tree(&folders)
+            .into_iter()
+            .map(|(depth, idx)| format!("{}{}", "  ".repeat(depth), folders[idx].id))
+            .collect::<Vec<_>>()
+            .join("\n");
+        insta::assert_snapshot!(tree, @r###"
+        a
+          c
+            d
+          e
+        b
+        orphan
+        x
+          y
+        "###);
+    }
+
+    #[test]
+    fn test_get_mail_membership() {
+        let single = [
+            membership(GroupType::Contact, "contact1"),
+            membership(GroupType::Contact, "contact2"),
+            membership(GroupType::Mail, "mail1"),
+        ];
+        assert_eq!(get_mail_membership(&single, None).unwrap().group, "mail1");
+        assert_eq!(
+            get_mail_membership(&single, Some("mail1")).unwrap().group,
+            "mail1"
+        );
+        assert_eq!(
+            get_mail_membership(&single, Some("contact1"))
+                .unwrap_err()
+                .to_string(),
+            "no mail group `contact1`, see `list-groups`",
+        );
+
+        let shared = [
+            membership(GroupType::Mail, "mail1"),
+            membership(GroupType::Mail, "mail2"),
+        ];
+        assert_eq!(
+            get_mail_membership(&shared, Some("mail2")).unwrap().group,
+            "mail2"
+        );
+        assert_eq!(
+            get_mail_membership(&shared, None).unwrap_err().to_string(),
+            "multiple mail groups found (`mail1`, `mail2`, ...), select one via `--group`, see `list-groups`",
+        );
+
+        assert_eq!(
+            get_mail_membership(&[], None).unwrap_err().to_string(),
+            "no mail group found",
+        );
+    }
+}
@@ -0,0 +1,714 @@
+//! Minimal HTML processing for mail bodies.
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use base64::{prelude::BASE64_STANDARD, write::EncoderWriter};
+use itertools::Itertools;
+
+use crate::mails::{Address, Attachment, DownloadedMail};
+
+/// Prefix of references to inline attachments.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc2392>.
+const CID_PREFIX: &str = "cid:";
+
+/// Content security policy of exported HTML pages.
+///
+/// Blocks scripts and all remote content, e.g. tracking pixels. Only embedded images and inline styles are allowed.
+const CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; img-src data:; style-src 'unsafe-inline'";
+
+/// Elements whose content is never rendered.
+const HIDDEN_ELEMENTS: &[&str] = &["head", "script", "style", "template", "title"];
+
+/// Elements that start and end on their own line.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figure",
+    "footer",
+    "form",
+    "header",
+    "hr",
+    "main",
+    "nav",
+    "ol",
+    "pre",
+    "section",
+    "table",
+    "tr",
+    "ul",
+];
+
+/// Elements that are separated from their surroundings by an empty line.
+const PARAGRAPH_ELEMENTS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6", "p"];
+
+/// Render HTML as plain text.
+///
+/// This is NOT a full HTML renderer. It drops all markup, keeps the rough block structure, lists, and link targets,
+/// and decodes character references. Malformed markup never fails but may lead to odd output.
+pub fn html_to_text(html: &str) -> String {
+    let mut w = TextWriter::default();
+    let mut pre_depth = 0usize;
+    let mut links = Vec::new();
+
+    let mut rest = html;
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            w.text(&decode_entities(rest), pre_depth > 0);
+            break;
+        };
+        w.text(&decode_entities(&rest[..start]), pre_depth > 0);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment
+                .find("-->")
+                .map(|end| &comment[end + 3..])
+                .unwrap_or_default();
+            continue;
+        }
+
+        let Some(tag) = Tag::parse(rest) else {
+            // not a tag, e.g. a stray `<`
+            w.text("<", pre_depth > 0);
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[tag.len..];
+        let name = tag.name.as_str();
+
+        if HIDDEN_ELEMENTS.contains(&name) {
+            if !tag.closing && !tag.self_closing {
+                rest = skip_element(rest, name);
+            }
+            continue;
+        }
+
+        match name {
+            "br" => w.newline(),
+            "li" if !tag.closing => {
+                w.block();
+                w.text("- ", true);
+            }
+            "td" | "th" if !tag.closing => w.space(),
+            "pre" if tag.closing => {
+                pre_depth = pre_depth.saturating_sub(1);
+                w.block();
+            }
+            "pre" => {
+                pre_depth += 1;
+                w.block();
+            }
+            "a" if tag.closing => {
+                if let Some((href, start)) = links.pop().flatten() {
+                    if w.out.get(start..).unwrap_or_default().trim() != href {
+                        w.text(&format!(" <{href}>"), true);
+                    }
+                }
+            }
+            "a" => {
+                let href = tag
+                    .attr("href")
+                    .filter(|href| href.starts_with("http://") || href.starts_with("https://"))
+                    .map(|href| (href, w.out.len()));
+                links.push(href);
+            }
+            "img" => {
+                if let Some(alt) = tag.attr("alt").filter(|alt| !alt.trim().is_empty()) {
+                    w.text(&alt, false);
+                }
+            }
+            _ if PARAGRAPH_ELEMENTS.contains(&name) => w.paragraph(),
+            _ if BLOCK_ELEMENTS.contains(&name) => w.block(),
+            _ => {}
+        }
+    }
+
+    w.finish()
+}
+
+/// Write mail as self-contained HTML page.
+///
+/// The page starts with a table of the mail metadata. Inline images are embedded as data URIs and other attachments
+/// are linked as downloadable data URIs, so the page can be viewed without any other files.
+pub fn write_html(mail: &DownloadedMail, w: &mut impl Write) -> Result<()> {
+    let subject = escape_html(&mail.mail.subject);
+
+    write!(
+        w,
+        "<!DOCTYPE html>\n\
+        <html>\n\
+        <head>\n\
+        <meta charset=\"utf-8\">\n\
+        <meta http-equiv=\"Content-Security-Policy\" content=\"{CONTENT_SECURITY_POLICY}\">\n\
+        <title>{subject}</title>\n\
+        </head>\n\
+        <body>\n\
+        <table class=\"metadata\">\n"
+    )?;
+    write_metadata_row(w, "Subject", &subject)?;
+    write_metadata_row(w, "From", &escape_addresses([&mail.mail.sender]))?;
+    write_metadata_row(w, "Date", &escape_html(&mail.mail.date.to_rfc2822()))?;
+    for (name, addrs) in [("To", &mail.to), ("CC", &mail.cc), ("BCC", &mail.bcc)] {
+        if !addrs.is_empty() {
+            write_metadata_row(w, name, &escape_addresses(addrs))?;
+        }
+    }
+
+    let regular = mail
+        .attachments
+        .iter()
+        .filter(|a| a.cid.is_none())
+        .collect::<Vec<_>>();
+    if !regular.is_empty() {
+        write!(w, "<tr><th>Attachments</th><td>")?;
+        for (i, attachment) in regular.into_iter().enumerate() {
+            if i > 0 {
+                write!(w, "<br>")?;
+            }
+            let name = escape_html(&attachment.name);
+            write!(w, "<a download=\"{name}\" href=\"")?;
+            write_data_uri(w, attachment).context("write attachment")?;
+            write!(w, "\">{name}</a>")?;
+        }
+        writeln!(w, "</td></tr>")?;
+    }
+    write!(w, "</table>\n<hr>\n<div class=\"body\">\n")?;
+
+    write_body(w, &String::from_utf8_lossy(&mail.body), &mail.attachments).context("write body")?;
+
+    write!(w, "\n</div>\n</body>\n</html>\n")?;
+    Ok(())
+}
+
+/// Write mail as plain text.
+///
+/// The metadata is followed by an empty line and the [text rendering](html_to_text) of the body.
+pub fn write_text(mail: &DownloadedMail, w: &mut impl Write) -> Result<()> {
+    writeln!(w, "Subject: {}", mail.mail.subject)?;
+    writeln!(w, "From: {}", format_addresses([&mail.mail.sender]))?;
+    writeln!(w, "Date: {}", mail.mail.date.to_rfc2822())?;
+    for (name, addrs) in [("To", &mail.to), ("CC", &mail.cc), ("BCC", &mail.bcc)] {
+        if !addrs.is_empty() {
+            writeln!(w, "{name}: {}", format_addresses(addrs))?;
+        }
+    }
+    if !mail.attachments.is_empty() {
+        writeln!(
+            w,
+            "Attachments: {}",
+            mail.attachments.iter().map(|a| &a.name).join(", ")
+        )?;
+    }
+
+    let body = html_to_text(&String::from_utf8_lossy(&mail.body));
+    writeln!(w)?;
+    if !body.is_empty() {
+        writeln!(w, "{body}")?;
+    }
+    Ok(())
+}
+
+fn write_metadata_row(w: &mut impl Write, name: &str, escaped_value: &str) -> std::io::Result<()> {
+    writeln!(w, "<tr><th>{name}</th><td>{escaped_value}</td></tr>")
+}
+
+/// Write body and replace references to inline attachments by data URIs.
+///
+/// References to unknown attachments are kept as they are.
+fn write_body(w: &mut impl Write, body: &str, attachments: &[Attachment]) -> Result<()> {
+    let mut rest = body;
+    while let Some(pos) = rest.find(CID_PREFIX) {
+        let (before, after) = rest.split_at(pos);
+        w.write_all(before.as_bytes())?;
+
+        let cid_len = after[CID_PREFIX.len()..]
+            .find(|c: char| matches!(c, '"' | '\'' | ')' | '>') || c.is_whitespace())
+            .unwrap_or(after.len() - CID_PREFIX.len());
+        let (reference, remaining) = after.split_at(CID_PREFIX.len() + cid_len);
+        let cid = &reference[CID_PREFIX.len()..];
+
+        match attachments.iter().find(|a| a.cid.as_deref() == Some(cid)) {
+            Some(attachment) => {
+                write_data_uri(w, attachment)
+                    .with_context(|| format!("embed attachment `{cid}`"))?;
+            }
+            None => {
+                w.write_all(reference.as_bytes())?;
+            }
+        }
+        rest = remaining;
+    }
+    w.write_all(rest.as_bytes())?;
+    Ok(())
+}
+
+/// Write attachment as data URI, the content is encoded incrementally.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc2397>.
+fn write_data_uri(w: &mut impl Write, attachment: &Attachment) -> Result<()> {
+    write!(w, "data:{};base64,", escape_html(&attachment.mime_type))?;
+    let mut encoder = EncoderWriter::new(w, &BASE64_STANDARD);
+    std::io::copy(&mut attachment.data.reader()?, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+fn format_addresses<'a>(addrs: impl IntoIterator<Item = &'a Address>) -> String {
+    addrs
+        .into_iter()
+        .map(|addr| {
+            if addr.name.is_empty() {
+                addr.mail.clone()
+            } else {
+                format!("{} <{}>", addr.name, addr.mail)
+            }
+        })
+        .join(", ")
+}
+
+fn escape_addresses<'a>(addrs: impl IntoIterator<Item = &'a Address>) -> String {
+    escape_html(&format_addresses(addrs))
+}
+
+/// Escape text for HTML content and quoted attribute values.
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Collects text and normalizes whitespace.
+#[derive(Debug, Default)]
+struct TextWriter {
+    out: String,
+
+    /// Number of line breaks that are emitted before the next text.
+    pending_newlines: usize,
+
+    /// Whitespace is emitted before the next text.
+    pending_space: bool,
+}
+
+impl TextWriter {
+    fn text(&mut self, s: &str, preformatted: bool) {
+        if preformatted {
+            if !s.is_empty() {
+                self.flush();
+                self.out.push_str(s);
+            }
+            return;
+        }
+
+        for (i, word) in s.split(char::is_whitespace).enumerate() {
+            if i > 0 {
+                self.space();
+            }
+            if !word.is_empty() {
+                self.flush();
+                self.out.push_str(word);
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.out.is_empty() {
+            // no leading whitespace
+        } else if self.pending_newlines > 0 {
+            let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+            self.out.truncate(trimmed);
+            for _ in 0..self.pending_newlines {
+                self.out.push('\n');
+            }
+        } else if self.pending_space && !self.out.ends_with(char::is_whitespace) {
+            self.out.push(' ');
+        }
+        self.pending_newlines = 0;
+        self.pending_space = false;
+    }
+
+    fn space(&mut self) {
+        self.pending_space = true;
+    }
+
+    fn newline(&mut self) {
+        // consecutive line breaks produce empty lines, but never more than one
+        self.pending_newlines = (self.pending_newlines + 1).min(2);
+    }
+
+    fn block(&mut self) {
+        self.pending_newlines = self.pending_newlines.max(1);
+    }
+
+    fn paragraph(&mut self) {
+        self.pending_newlines = 2;
+    }
+
+    fn finish(self) -> String {
+        let mut out = self
+            .out
+            .lines()
+            .map(|line| line.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let trimmed = out.trim_end().len();
+        out.truncate(trimmed);
+        out
+    }
+}
+
+/// Opening or closing tag.
+#[derive(Debug)]
+struct Tag<'a> {
+    /// Lower-case element name.
+    name: String,
+    closing: bool,
+    self_closing: bool,
+
+    /// Raw attribute part.
+    attrs: &'a str,
+
+    /// Length of the tag in the input, including `<` and `>`.
+    len: usize,
+}
+
+impl<'a> Tag<'a> {
+    /// Parse tag at the start of `s`.
+    ///
+    /// Returns [`None`] if `s` does not start with a tag.
+    fn parse(s: &'a str) -> Option<Self> {
+        let inner = s.strip_prefix('<')?;
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let name_len = inner
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '!' || c == '-'))
+            .unwrap_or(inner.len());
+        if name_len == 0 {
+            return None;
+        }
+        let name = inner[..name_len].to_ascii_lowercase();
+
+        // find end of tag, `>` may occur within quoted attribute values
+        let mut quote = None;
+        let mut end = None;
+        for (i, c) in inner.char_indices().skip(name_len) {
+            match (quote, c) {
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), c) if q == c => quote = None,
+                (None, '>') => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        // unterminated tags swallow the rest of the input
+        let end = end.unwrap_or(inner.len());
+        let attrs = &inner[name_len..end];
+
+        Some(Self {
+            name,
+            closing,
+            self_closing: attrs.trim_end().ends_with('/'),
+            attrs,
+            len: s.len() - inner.len() + (end + 1).min(inner.len()),
+        })
+    }
+
+    /// Get decoded attribute value.
+    fn attr(&self, name: &str) -> Option<String> {
+        let mut rest = self.attrs;
+        loop {
+            rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+            if rest.is_empty() {
+                return None;
+            }
+
+            let key_len = rest
+                .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+                .unwrap_or(rest.len());
+            let key = &rest[..key_len];
+            rest = rest[key_len..].trim_start();
+
+            let value = match rest.strip_prefix('=') {
+                Some(after) => {
+                    let after = after.trim_start();
+                    let (value, remaining) = match after.chars().next() {
+                        Some(q @ ('"' | '\'')) => {
+                            let after = &after[1..];
+                            let end = after.find(q).unwrap_or(after.len());
+                            (&after[..end], after.get(end + 1..).unwrap_or_default())
+                        }
+                        _ => {
+                            let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                            (&after[..end], &after[end..])
+                        }
+                    };
+                    rest = remaining;
+                    value
+                }
+                None => "",
+            };
+
+            if key.eq_ignore_ascii_case(name) {
+                return Some(decode_entities(value));
+            }
+        }
+    }
+}
+
+/// Skip content of the given element, returns the input after the closing tag.
+fn skip_element<'a>(s: &'a str, name: &str) -> &'a str {
+    let mut rest = s;
+    while let Some(pos) = rest.find("</") {
+        rest = &rest[pos..];
+        match Tag::parse(rest) {
+            Some(tag) if tag.name == name => return &rest[tag.len..],
+            _ => rest = &rest[2..],
+        }
+    }
+    ""
+}
+
+/// Decode character references.
+///
+/// Only numeric and the most common named references are supported, unknown references are kept as they are.
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        let decoded = rest.find(';').filter(|end| *end <= 32).and_then(|end| {
+            let decoded = match &rest[1..end] {
+                "amp" => Some('&'),
+                "apos" => Some('\''),
+                "bull" => Some('•'),
+                "copy" => Some('©'),
+                "euro" => Some('€'),
+                "gt" => Some('>'),
+                "hellip" => Some('…'),
+                "laquo" => Some('«'),
+                "ldquo" => Some('“'),
+                "lsquo" => Some('‘'),
+                "lt" => Some('<'),
+                "mdash" => Some('—'),
+                "nbsp" => Some('\u{a0}'),
+                "ndash" => Some('–'),
+                "quot" => Some('"'),
+                "raquo" => Some('»'),
+                "rdquo" => Some('”'),
+                "reg" => Some('®'),
+                "rsquo" => Some('’'),
+                "shy" => Some('\u{ad}'),
+                "trade" => Some('™'),
+                "zwnj" => Some('\u{200c}'),
+                reference => reference
+                    .strip_prefix('#')
+                    .and_then(|num| match num.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => num.parse().ok(),
+                    })
+                    .and_then(char::from_u32),
+            };
+            decoded.map(|c| (c, end))
+        });
+
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::DateTime;
+
+    use crate::{
+        mails::Mail,
+        proto::{
+            enums::{MailState, ReplyType},
+            keys::Key,
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_html_to_text() {
+        assert_eq!(html_to_text(""), "");
+        assert_eq!(html_to_text("hello  \n world"), "hello world");
+        assert_eq!(
+            html_to_text(
+                "<html><head><title>x</title><style>p { color: red; }</style></head>\
+                <body><h1>Title</h1><p>First<br>line</p><div>a &amp; b &lt;c&gt; &#252;&#xfc; &foo;</div>\
+                <!-- comment --><script>if (a < b) {}</script>\
+                <ul><li>one</li><li>two</li></ul>\
+                <p>See <a href=\"https://example.com/?a=1&amp;b=2\">this</a> \
+                and <a href='https://example.com'>https://example.com</a>.</p>\
+                <table><tr><td>1</td><td>2</td></tr><tr><td>3</td><td>4</td></tr></table>\
+                <pre>  keep\n    this</pre><img src=\"cid:foo\" alt=\"[logo]\"></body></html>"
+            ),
+            "Title\n\
+            \n\
+            First\n\
+            line\n\
+            \n\
+            a & b <c> üü &foo;\n\
+            - one\n\
+            - two\n\
+            \n\
+            See this <https://example.com/?a=1&b=2> and https://example.com.\n\
+            \n\
+            1 2\n\
+            3 4\n\
+            \x20 keep\n\
+            \x20   this\n\
+            [logo]",
+        );
+    }
+
+    #[test]
+    fn test_html_to_text_malformed() {
+        assert_eq!(html_to_text("a < b"), "a < b");
+        assert_eq!(html_to_text("a <b"), "a");
+        assert_eq!(html_to_text("<p>a<!-- b"), "a");
+        assert_eq!(html_to_text("<style>a"), "");
+        assert_eq!(html_to_text("<p title=\"a>b\">c</p>"), "c");
+        assert_eq!(html_to_text("a&amp"), "a&amp");
+        assert_eq!(html_to_text("<br><br>a<br><br><br>b"), "a\n\nb");
+    }
+
+    fn test_mail() -> DownloadedMail {
+        DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                unread: false,
+                state: MailState::Received,
+                reply_type: ReplyType::NoReply,
+                conversation_id: None,
+                session_key: Key::Aes256([0; 32]),
+                bucket_session_keys: Default::default(),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö <world>".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+            }),
+            headers: None,
+            body: b"<p>hi</p><img src=\"cid:img1\"><img src='cid:unknown'>".to_vec(),
+            attachments: vec![
+                Attachment {
+                    cid: Some("img1".to_owned()),
+                    mime_type: "image/png".to_owned(),
+                    name: "a.png".to_owned(),
+                    data: b"foo".to_vec().into(),
+                },
+                Attachment {
+                    cid: None,
+                    mime_type: "text/plain".to_owned(),
+                    name: "b \"c\".txt".to_owned(),
+                    data: b"bar".to_vec().into(),
+                },
+            ],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![
+                Address {
+                    mail: "bar@example.com".to_owned(),
+                    name: "".to_owned(),
+                },
+                Address {
+                    mail: "baz@example.com".to_owned(),
+                    name: "Baz".to_owned(),
+                },
+            ],
+            omitted_attachments: vec![],
+            memory: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_write_html() {
+        let mut buf = vec![];
+        write_html(&test_mail(), &mut buf).unwrap();
+        insta::assert_snapshot!(String::from_utf8(buf).unwrap(), @r###"
+        <!DOCTYPE html>
+        <html>
+        <head>
+        <meta charset="utf-8">
+        <meta http-equiv="Content-Security-Policy" content="default-src 'none'; img-src data:; style-src 'unsafe-inline'">
+        <title>Hällö &lt;world&gt;</title>
+        </head>
+        <body>
+        <table class="metadata">
+        <tr><th>Subject</th><td>Hällö &lt;world&gt;</td></tr>
+        <tr><th>From</th><td>Me &lt;foo@example.com&gt;</td></tr>
+        <tr><th>Date</th><td>Wed, 4 Mar 2020 11:22:33 +0000</td></tr>
+        <tr><th>To</th><td>bar@example.com, Baz &lt;baz@example.com&gt;</td></tr>
+        <tr><th>Attachments</th><td><a download="b &quot;c&quot;.txt" href="data:text/plain;base64,YmFy">b &quot;c&quot;.txt</a></td></tr>
+        </table>
+        <hr>
+        <div class="body">
+        <p>hi</p><img src="data:image/png;base64,Zm9v"><img src='cid:unknown'>
+        </div>
+        </body>
+        </html>
+        "###);
+    }
+
+    #[test]
+    fn test_write_text() {
+        let mut buf = vec![];
+        write_text(&test_mail(), &mut buf).unwrap();
+        insta::assert_snapshot!(String::from_utf8(buf).unwrap(), @r###"
+        Subject: Hällö <world>
+        From: Me <foo@example.com>
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        To: bar@example.com, Baz <baz@example.com>
+        Attachments: a.png, b "c".txt
+
+        hi
+        "###);
+    }
+}
@@ -0,0 +1,71 @@
+//! Client for the [Tuta](https://tuta.com) mail API.
+//!
+//! This crate logs into an account, lists mail folders, and downloads and decrypts mails, which can then be rendered
+//! as EML files. It powers the `tatutanatata` CLI and can be embedded into other tools, e.g. backup daemons.
+//!
+//! ```no_run
+//! use futures::TryStreamExt;
+//! use tatutanatata_core::{
+//!     client::{Client, ClientConfig},
+//!     eml::{write_eml, EmlOptions},
+//!     folders::Folder,
+//!     mails::{AttachmentFilter, DownloadOptions, Mail},
+//!     session::{LoginConfig, Session},
+//! };
+//!
+//! # async fn export() -> anyhow::Result<()> {
+//! let client = Client::try_new(ClientConfig::default()).await?;
+//! let session = Session::login(LoginConfig::new("alice@tuta.com", "secret")?, &client).await?;
+//!
+//! let folders: Vec<Folder> = Folder::list(&client, &session, None).await?.try_collect().await?;
+//! let inbox = folders.iter().find(|f| f.name == "Inbox").expect("inbox exists");
+//!
+//! let mut mails = std::pin::pin!(Mail::list(&client, &session, inbox, false, None));
+//! while let Some(mail) = mails.try_next().await? {
+//!     let options = DownloadOptions {
+//!         max_decompressed_size: 100 * 1024 * 1024,
+//!         memory_budget: None,
+//!         attachment_filter: &AttachmentFilter::default(),
+//!     };
+//!     let mail = mail.download(&client, &session, None, options).await?;
+//!
+//!     let mut eml = vec![];
+//!     write_eml(&mail, EmlOptions::default(), vec![], &mut eml)?;
+//! }
+//!
+//! session.logout(&client).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Retries of transient errors can be tuned per client via [`Client::with_retry_policy`](client::Client::with_retry_policy).
+#![warn(missing_docs)]
+
+// Workaround for "unused crate" lint false positives.
+#[cfg(test)]
+use hex_literal as _;
+#[cfg(test)]
+use insta as _;
+
+pub mod blob;
+pub mod client;
+mod compression;
+pub mod concurrency;
+pub mod constants;
+mod crypto;
+mod dump;
+pub mod eml;
+pub mod events;
+pub mod failure;
+#[cfg(feature = "test-harness")]
+pub mod fixture;
+pub mod folders;
+pub mod html;
+pub mod mails;
+pub mod memory;
+mod non_empty_string;
+pub mod proto;
+pub mod retry;
+pub mod session;
+pub mod spool;
+pub mod tls;
@@ -0,0 +1,960 @@
+//! Listing, downloading, and decrypting mails.
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use chrono::{DateTime, SubsecRound, TimeDelta, Utc};
+use futures::{future::ready, Stream, TryStreamExt};
+use reqwest::Method;
+use tracing::{debug, warn};
+
+use crate::{
+    blob::{get_attachment_blob, get_mail_blob, get_mail_draft_blob},
+    client::{generated_id_at, Client, Prefix, Request},
+    compression::decompress_value,
+    crypto::encryption::{decrypt_key, decrypt_value, ValueDecryptor},
+    folders::Folder,
+    memory::{MemoryBudget, MemoryReservation},
+    proto::{
+        enums::{MailState, ReplyType},
+        keys::Key,
+        messages::{FileReponse, MailAddress, MailDetails, MailReponse},
+    },
+    session::{GroupKeys, Session},
+    spool::{Spool, MEMORY_LIMIT as SPOOL_MEMORY_LIMIT},
+};
+
+/// Margin between the creation time encoded in a mail ID and its received date, see [`Mail::list`].
+pub(crate) const SINCE_ID_SLACK: TimeDelta = TimeDelta::hours(1);
+
+/// Margin for clocks of different servers, see [`Mail::same_second_id_bound`].
+const ID_CLOCK_SKEW: TimeDelta = TimeDelta::minutes(1);
+
+/// Maximum ratio between decompressed and compressed size of an LZ4 block.
+const LZ4_MAX_RATIO: u64 = 255;
+
+/// Mail address of a sender or recipient.
+#[derive(Debug)]
+pub struct Address {
+    /// Address like `alice@example.com`.
+    pub mail: String,
+
+    /// Display name, may be empty.
+    pub name: String,
+}
+
+impl Address {
+    fn decode(addr: MailAddress, session_key: Key) -> Result<Self> {
+        let name = decrypt_value(session_key, &addr.name).context("decrypt name")?;
+        let name = String::from_utf8(name).context("decode name string")?;
+
+        Ok(Self {
+            mail: addr.address,
+            name,
+        })
+    }
+}
+
+/// Reference to a single mail, either a plain mail ID or a [UI URL](Mail::ui_url).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailRef {
+    /// Folder ID, only known for UI URLs.
+    pub folder_id: Option<String>,
+
+    /// Mail ID.
+    pub mail_id: String,
+}
+
+impl FromStr for MailRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().trim_end_matches('/');
+        let is_id = |s: &str| !s.is_empty() && !s.contains(['/', ':', '?', '#']);
+
+        if let Some((_, path)) = s.rsplit_once("/mail/") {
+            match path.split_once('/') {
+                Some((folder_id, mail_id)) if is_id(folder_id) && is_id(mail_id) => Ok(Self {
+                    folder_id: Some(folder_id.to_owned()),
+                    mail_id: mail_id.to_owned(),
+                }),
+                _ => Err(format!(
+                    "invalid mail URL, expected `<host>/mail/<folder_id>/<mail_id>`: `{s}`"
+                )),
+            }
+        } else if is_id(s) {
+            Ok(Self {
+                folder_id: None,
+                mail_id: s.to_owned(),
+            })
+        } else {
+            Err(format!("neither a mail ID nor a mail URL: `{s}`"))
+        }
+    }
+}
+
+/// Number of mails in a folder, see [`Mail::count`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MailCounts {
+    /// All mails.
+    pub total: u64,
+
+    /// Mails that were NOT read yet.
+    pub unread: u64,
+}
+
+/// Metadata of a mail, see [`Mail::list`].
+///
+/// Body, recipients, and attachments are fetched via [`Mail::download`].
+#[derive(Debug)]
+pub struct Mail {
+    /// ID of the folder that the mail was listed in.
+    #[allow(dead_code)]
+    pub folder_id: String,
+
+    /// Mail ID, unique within the folder.
+    pub mail_id: String,
+
+    /// Archive of the blob that holds the mail details.
+    pub archive_id: String,
+
+    /// Blob that holds the mail details.
+    pub blob_id: String,
+
+    /// The mail is a draft, whose details are stored differently.
+    pub is_draft: bool,
+
+    /// Key that the mail is encrypted with.
+    pub session_key: Key,
+
+    /// Session keys of the attachments if the mail was encrypted via a bucket key.
+    pub bucket_session_keys: HashMap<String, Key>,
+
+    /// Received date, as set by the server.
+    pub date: DateTime<Utc>,
+
+    /// Decrypted subject.
+    pub subject: String,
+
+    /// Decrypted sender.
+    pub sender: Address,
+
+    /// IDs of the attachment files as `[list_id, element_id]`.
+    pub attachments: Vec<[String; 2]>,
+
+    /// The mail was NOT read yet.
+    pub unread: bool,
+
+    /// Whether the mail was received or sent, or is a draft.
+    pub state: MailState,
+
+    /// Whether the mail was replied to or forwarded.
+    pub reply_type: ReplyType,
+
+    /// ID that is shared by all mails of the same conversation.
+    pub conversation_id: Option<String>,
+}
+
+impl Mail {
+    /// List mails of the given folder.
+    ///
+    /// If `since` is given, only mails received at or after that time are returned. Older mails are skipped by the
+    /// server, so they are NOT paged through.
+    pub fn list(
+        client: &Client,
+        session: &Session,
+        folder: &Folder,
+        ignore_new_mails: bool,
+        since: Option<DateTime<Utc>>,
+    ) -> impl Stream<Item = Result<Arc<Self>>> {
+        Self::list_resumable(client, session, folder, ignore_new_mails, since, None)
+            .try_filter_map(|(_mail_id, mail)| ready(Ok(mail)))
+    }
+
+    /// List mails of the given folder like [`list`](Self::list), but only the ones with an ID greater than `after`,
+    /// e.g. to resume an earlier export.
+    ///
+    /// Yields the ID of every mail along with the mail itself. Mails that cannot be decoded yet are yielded as
+    /// [`None`] if `ignore_new_mails` is set, so that callers do NOT resume past them.
+    pub fn list_resumable(
+        client: &Client,
+        session: &Session,
+        folder: &Folder,
+        ignore_new_mails: bool,
+        since: Option<DateTime<Utc>>,
+        after: Option<&str>,
+    ) -> impl Stream<Item = Result<(String, Option<Arc<Self>>)>> {
+        let group_keys = Arc::clone(&session.group_keys);
+        let folder_id = folder.id.clone();
+        // The mail ID is generated before the received date is set, so leave some slack. The exact filtering happens
+        // below.
+        let start = since
+            .map(|since| generated_id_at(since - SINCE_ID_SLACK))
+            .into_iter()
+            .chain(after.map(str::to_owned))
+            .max();
+        client
+            .stream::<MailReponse>(
+                &format!("mail/{}", folder.mails),
+                Some(&session.access_token),
+                start.as_deref(),
+            )
+            .and_then(move |m| {
+                let group_keys = Arc::clone(&group_keys);
+                let folder_id = folder_id.clone();
+                async move {
+                    let mail_id = m.id[1].clone();
+                    Ok((mail_id, Self::decode(m, &group_keys, folder_id)?))
+                }
+            })
+            .try_filter_map(move |(mail_id, mail)| async move {
+                match mail {
+                    Some(mail) if since.is_some_and(|since| mail.date < since) => Ok(None),
+                    Some(mail) => Ok(Some((mail_id, Some(Arc::new(mail))))),
+                    None if ignore_new_mails => Ok(Some((mail_id, None))),
+                    None => bail!("Folder contains new mail that has not been decoded before. Use the official app and view the folder to decode the data, or pass --ignore-new-mails to skip new emails."),
+                }
+            })
+    }
+
+    /// Upper bound for the IDs of mails that were received in the same second as this one.
+    ///
+    /// Mails are listed by ascending ID, so once a listing passed this ID, no further mail of that second follows.
+    pub fn same_second_id_bound(&self) -> String {
+        generated_id_at(self.date.trunc_subsecs(0) + TimeDelta::seconds(1) + ID_CLOCK_SKEW)
+    }
+
+    /// List mails of the given folder that were received in the same second as this one, including this mail.
+    ///
+    /// Mails that cannot be decoded yet are skipped.
+    pub fn list_same_second(
+        &self,
+        client: &Client,
+        session: &Session,
+        folder: &Folder,
+    ) -> impl Stream<Item = Result<Arc<Self>>> {
+        let since = self.date.trunc_subsecs(0);
+        let until = since + TimeDelta::seconds(1);
+        let bound = self.same_second_id_bound();
+        Self::list(client, session, folder, true, Some(since))
+            .try_take_while(move |mail| ready(Ok(mail.mail_id < bound)))
+            .try_filter(move |mail| ready(mail.date < until))
+    }
+
+    /// Count mails of the given folder.
+    ///
+    /// This pages through the mail metadata without decrypting it, so it also works for mails that cannot be decoded
+    /// (yet) and for inaccessible folders.
+    pub async fn count(client: &Client, session: &Session, folder: &Folder) -> Result<MailCounts> {
+        client
+            .stream::<MailReponse>(
+                &format!("mail/{}", folder.mails),
+                Some(&session.access_token),
+                None,
+            )
+            .try_fold(MailCounts::default(), |counts, m| async move {
+                Ok(MailCounts {
+                    total: counts.total + 1,
+                    unread: counts.unread + u64::from(m.unread.0),
+                })
+            })
+            .await
+            .context("count mails")
+    }
+
+    /// IDs of all mails of the given folder.
+    ///
+    /// Like [`count`](Self::count), this does NOT decrypt the mails, so mails that cannot be decoded (yet) are included.
+    pub async fn list_ids(
+        client: &Client,
+        session: &Session,
+        folder: &Folder,
+    ) -> Result<HashSet<String>> {
+        client
+            .stream::<MailReponse>(
+                &format!("mail/{}", folder.mails),
+                Some(&session.access_token),
+                None,
+            )
+            .map_ok(|m| {
+                let [_, mail_id] = m.id;
+                mail_id
+            })
+            .try_collect()
+            .await
+            .context("list mail IDs")
+    }
+
+    /// Get single mail of the given folder.
+    ///
+    /// Returns [`None`] if the mail cannot be decoded yet, see `decode`.
+    pub async fn get(
+        client: &Client,
+        session: &Session,
+        folder: &Folder,
+        mail_id: &str,
+    ) -> Result<Option<Arc<Self>>> {
+        let resp: MailReponse = client
+            .do_json(Request {
+                access_token: Some(&session.access_token),
+                ..Request::new(
+                    Prefix::Tutanota,
+                    &format!("mail/{}/{}", folder.mails, mail_id),
+                    &(),
+                )
+            })
+            .await
+            .context("fetch mail")?;
+
+        Ok(Self::decode(resp, &session.group_keys, folder.id.clone())?.map(Arc::new))
+    }
+
+    /// Decode [`MailReponse`].
+    ///
+    /// Returns [`None`] if no usable encryption key is set. This usually happens when the mail was NOT
+    /// processed via the official app yet and is encrypted with a key that we cannot decrypt.
+    fn decode(
+        resp: MailReponse,
+        group_keys: &GroupKeys,
+        folder_id: String,
+    ) -> Result<Option<Self>> {
+        let (session_key, bucket_session_keys) =
+            match (resp.owner_enc_session_key, &resp.bucket_key) {
+                (Some(key), _) => {
+                    let session_key = decrypt_key(
+                        group_keys
+                            .get(&resp.owner_group)
+                            .context("getting owner group key")?,
+                        key,
+                    )
+                    .context("decrypting session key")?;
+                    (session_key, HashMap::new())
+                }
+                (None, Some(bucket_key)) => {
+                    let Some(mut keys) = group_keys
+                        .decrypt_bucket_key(bucket_key)
+                        .context("decrypting bucket key")?
+                    else {
+                        return Ok(None);
+                    };
+                    let session_key = keys
+                        .remove(&resp.id[1])
+                        .context("bucket does not contain mail session key")?;
+                    (session_key, keys)
+                }
+                (None, None) => {
+                    return Ok(None);
+                }
+            };
+
+        let subject = decrypt_value(session_key, &resp.subject).context("decrypt subject")?;
+        let subject = String::from_utf8(subject).context("decode string")?;
+
+        let sender = Address::decode(resp.sender, session_key).context("decode sender")?;
+
+        let ([archive_id, blob_id], is_draft) = match (resp.mail_details, resp.mail_details_draft) {
+            (Some(_), Some(_)) => {
+                bail!("mail as both `mailDetails` and `mailDetailsDraft`");
+            }
+            (Some(x), None) => (x, false),
+            (None, Some(x)) => (x, true),
+            (None, None) => {
+                bail!("mail has neither `mailDetails` nor `mailDetailsDraft`");
+            }
+        };
+
+        Ok(Some(Self {
+            folder_id,
+            mail_id: resp.id[1].clone(),
+            archive_id,
+            blob_id,
+            is_draft,
+            session_key,
+            bucket_session_keys,
+            date: resp.received_date.0,
+            subject,
+            sender,
+            attachments: resp.attachments,
+            unread: resp.unread.0,
+            state: resp.state,
+            reply_type: resp.reply_type,
+            conversation_id: resp.conversation_entry.map(|[list_id, _]| list_id),
+        }))
+    }
+
+    /// URL of the mail in the web app.
+    pub fn ui_url(&self, client: &Client) -> String {
+        format!("{}/mail/{}/{}", client.host(), self.folder_id, self.mail_id)
+    }
+
+    /// Download and decrypt mail.
+    ///
+    /// Mail details that were already fetched, e.g. via [`get_mail_blobs`](crate::blob::get_mail_blobs), can be
+    /// passed in to avoid another request.
+    pub async fn download(
+        self: Arc<Self>,
+        client: &Client,
+        session: &Session,
+        details: Option<MailDetails>,
+        options: DownloadOptions<'_>,
+    ) -> Result<DownloadedMail> {
+        let mail_details = if let Some(details) = details {
+            details
+        } else if self.is_draft {
+            get_mail_draft_blob(client, session, &self.archive_id, &self.blob_id)
+                .await
+                .context("download mail draft details")?
+                .details
+        } else {
+            get_mail_blob(client, session, &self.archive_id, &self.blob_id)
+                .await
+                .context("download mail details")?
+                .details
+        };
+
+        let mut files = vec![];
+        if !self.attachments.is_empty() {
+            let group = &self.attachments[0][0];
+            if self.attachments.iter().any(|[g_id, _id]| g_id != group) {
+                bail!("inconsistent attachement group IDs")
+            }
+            let ids = self
+                .attachments
+                .iter()
+                .map(|[_g_id, id]| id.as_str())
+                .collect::<Vec<_>>();
+            let resp: Vec<FileReponse> = client
+                .do_json(Request {
+                    method: Method::GET,
+                    host: None,
+                    prefix: Prefix::Tutanota,
+                    path: &format!("file/{group}"),
+                    data: &(),
+                    access_token: Some(&session.access_token),
+                    query: &[("ids", &ids.join(","))],
+                })
+                .await
+                .context("get file infos")?;
+
+            ensure!(
+                ids.len() == resp.len(),
+                "attachment IDs and files match, but got {} IDs and {} files",
+                ids.len(),
+                resp.len(),
+            );
+            files = ids
+                .into_iter()
+                .zip(resp)
+                .map(|(id, file)| (group.as_str(), id, file))
+                .collect();
+        }
+
+        let mut decoded = Vec::with_capacity(files.len());
+        let mut omitted_attachments = vec![];
+        for (idx, (group, id, file)) in files.into_iter().enumerate() {
+            let info = FileInfo::decode(session, &file, self.bucket_session_keys.get(id).copied())
+                .with_context(|| format!("decode file #{}", idx + 1))?;
+            if !options
+                .attachment_filter
+                .accepts(&info.mime_type, file.size.0)
+            {
+                debug!(
+                    mail_id = self.mail_id.as_str(),
+                    mime_type = info.mime_type.as_str(),
+                    size = file.size.0,
+                    "omit attachment",
+                );
+                omitted_attachments.push(OmittedAttachment {
+                    name: info.name,
+                    mime_type: info.mime_type,
+                    size: file.size.0,
+                });
+            } else {
+                decoded.push((idx, group, id, file, info));
+            }
+        }
+
+        // reserve before decoding, which is what allocates most of the memory
+        let memory = match options.memory_budget {
+            Some(budget) => {
+                let bytes = estimate_memory(
+                    &mail_details,
+                    options.max_decompressed_size,
+                    decoded.iter().map(|(_idx, _group, _id, file, _info)| file),
+                );
+                debug!(mail_id = self.mail_id.as_str(), bytes, "reserve memory");
+                Some(budget.reserve(bytes).await)
+            }
+            None => None,
+        };
+
+        let body = decrypt_and_decompress(
+            self.session_key,
+            mail_details.body.text.as_deref(),
+            mail_details.body.compressed_text.as_deref(),
+            options.max_decompressed_size,
+        )
+        .context("decode body")?;
+
+        let headers = if let Some(headers) = mail_details.headers {
+            let headers = decrypt_and_decompress(
+                self.session_key,
+                headers.headers.as_deref(),
+                headers.compressed_headers.as_deref(),
+                options.max_decompressed_size,
+            )
+            .context("decode headers")?;
+            let headers = String::from_utf8(headers).context("decode headers string")?;
+
+            Some(headers)
+        } else {
+            None
+        };
+
+        let bcc = mail_details
+            .recipients
+            .bcc_recipients
+            .into_iter()
+            .map(|addr| Address::decode(addr, self.session_key))
+            .collect::<Result<Vec<_>>>()
+            .context("decode BCC")?;
+        let cc = mail_details
+            .recipients
+            .cc_recipients
+            .into_iter()
+            .map(|addr| Address::decode(addr, self.session_key))
+            .collect::<Result<Vec<_>>>()
+            .context("decode CC")?;
+        let to = mail_details
+            .recipients
+            .to_recipients
+            .into_iter()
+            .map(|addr| Address::decode(addr, self.session_key))
+            .collect::<Result<Vec<_>>>()
+            .context("decode To")?;
+
+        let mut attachments = vec![];
+        for (idx, group, id, file, info) in decoded {
+            attachments.push(
+                Self::download_file(client, session, group, id, file, info)
+                    .await
+                    .with_context(|| format!("download file #{}", idx + 1))?,
+            );
+        }
+
+        Ok(DownloadedMail {
+            mail: self,
+            headers,
+            body,
+            attachments,
+            bcc,
+            cc,
+            to,
+            omitted_attachments,
+            memory,
+        })
+    }
+
+    async fn download_file(
+        client: &Client,
+        session: &Session,
+        group: &str,
+        id: &str,
+        file: FileReponse,
+        info: FileInfo,
+    ) -> Result<Attachment> {
+        let FileInfo {
+            session_key,
+            cid,
+            mime_type,
+            name,
+        } = info;
+
+        let mut data_all = Spool::new();
+        let mut encrypted_size_sum = 0;
+        for blob in file.blobs {
+            let size = blob.size.0 as usize;
+            let mut decryptor = ValueDecryptor::new(session_key, size);
+            let mut received = 0;
+            get_attachment_blob(
+                client,
+                session,
+                &blob.archive_id,
+                &blob.blob_id,
+                group,
+                id,
+                |chunk| {
+                    // excess data is reported below
+                    let n = chunk.len().min(size.saturating_sub(received));
+                    received += chunk.len();
+                    decryptor
+                        .update(&chunk[..n], &mut data_all)
+                        .context("decrypt attachment data")
+                },
+            )
+            .await
+            .context("download attachment")?;
+            ensure!(
+                received == size,
+                "encrypted blob data size is wrong, should be {} bytes but got {} bytes",
+                blob.size.0,
+                received,
+            );
+            decryptor
+                .finish(&mut data_all)
+                .context("decrypt attachment data")?;
+            encrypted_size_sum += received;
+        }
+        if encrypted_size_sum != file.size.0 as usize {
+            warn!(
+                actual=encrypted_size_sum,
+                expected=file.size.0,
+                related_issue="https://github.com/crepererum-oss/tatutanatata/issues/278",
+                "encrypted blobs do not add up to file size, this seems to happen for some older data",
+            );
+        }
+
+        Ok(Attachment {
+            cid,
+            mime_type,
+            name,
+            data: data_all,
+        })
+    }
+}
+
+fn decrypt_and_decompress(
+    encryption_key: Key,
+    plain: Option<&[u8]>,
+    compressed: Option<&[u8]>,
+    max_decompressed_size: usize,
+) -> Result<Vec<u8>> {
+    match (plain, compressed) {
+        (Some(data), _) => {
+            let data = decrypt_value(encryption_key, data).context("decrypt")?;
+            Ok(data)
+        }
+        (None, Some(data)) => {
+            let data = decrypt_value(encryption_key, data).context("decrypt")?;
+            let data = decompress_value(&data, max_decompressed_size).context("decompress")?;
+            Ok(data)
+        }
+        (None, None) => {
+            bail!("neither compressed or uncompressed data available")
+        }
+    }
+}
+
+/// Decrypted metadata of an attachment, see [`Mail::download`].
+#[derive(Debug)]
+struct FileInfo {
+    session_key: Key,
+    cid: Option<String>,
+    mime_type: String,
+    name: String,
+}
+
+impl FileInfo {
+    fn decode(
+        session: &Session,
+        file: &FileReponse,
+        bucket_session_key: Option<Key>,
+    ) -> Result<Self> {
+        let session_key = match (file.owner_enc_session_key, bucket_session_key) {
+            (Some(key), _) => decrypt_key(
+                session
+                    .group_keys
+                    .get(&file.owner_group)
+                    .context("getting file owner group key")?,
+                key,
+            )
+            .context("decrypting file session key")?,
+            (None, Some(key)) => key,
+            (None, None) => bail!("file has no session key"),
+        };
+
+        let cid = if let Some(cid) = &file.cid {
+            let cid = decrypt_value(session_key, cid).context("decrypt file content ID")?;
+            let cid = String::from_utf8(cid).context("decode cid")?;
+            Some(cid)
+        } else {
+            None
+        };
+
+        let mime_type = decrypt_value(session_key, file.mime_type.as_ref())
+            .context("decrypt file mime type")?;
+        let mime_type = String::from_utf8(mime_type).context("decode mime_type")?;
+
+        let name = decrypt_value(session_key, file.name.as_ref()).context("decrypt file name")?;
+        let name = String::from_utf8(name).context("decode name")?;
+
+        Ok(Self {
+            session_key,
+            cid,
+            mime_type,
+            name,
+        })
+    }
+}
+
+/// Options for [`Mail::download`].
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadOptions<'a> {
+    /// Maximum size of the decompressed body and headers in bytes.
+    pub max_decompressed_size: usize,
+
+    /// Budget that the buffered mail data is accounted against.
+    pub memory_budget: Option<&'a MemoryBudget>,
+
+    /// Attachments that are downloaded, the metadata of the others is kept in
+    /// [`DownloadedMail::omitted_attachments`].
+    pub attachment_filter: &'a AttachmentFilter,
+}
+
+/// Selects the attachments whose content is downloaded, see [`DownloadOptions::attachment_filter`].
+///
+/// The default accepts all attachments.
+#[derive(Debug, Default)]
+pub struct AttachmentFilter {
+    /// Reject all attachments.
+    pub skip_all: bool,
+
+    /// Maximum size in bytes.
+    pub max_size: Option<u64>,
+
+    /// Accepted MIME types like `application/pdf` or `image/*`. Empty accepts all types.
+    pub mime_types: Vec<String>,
+}
+
+impl AttachmentFilter {
+    fn accepts(&self, mime_type: &str, size: u64) -> bool {
+        if self.skip_all || self.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+        if self.mime_types.is_empty() {
+            return true;
+        }
+
+        // ignore parameters like `; charset=UTF-8`
+        let mime_type = mime_type
+            .split_once(';')
+            .map_or(mime_type, |(t, _params)| t)
+            .trim();
+        self.mime_types
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(kind) => mime_type
+                    .split_once('/')
+                    .is_some_and(|(t, _subtype)| kind == "*" || t.eq_ignore_ascii_case(kind)),
+                None => mime_type.eq_ignore_ascii_case(pattern),
+            })
+    }
+}
+
+/// Estimate memory that a downloaded mail occupies until it is written.
+///
+/// Body and headers are held in memory, see [`decoded_size_bound`]. Attachments and the EML output are
+/// [spooled](Spool), since attachment blobs are decrypted while they are downloaded. Only legacy blobs that may also
+/// be AES-GCM are decrypted into two more spools, see [`ValueDecryptor`]. The estimate is taken before anything is
+/// decrypted, so only the encrypted mail details are held outside the budget.
+fn estimate_memory<'a>(
+    details: &MailDetails,
+    max_decompressed_size: usize,
+    files: impl Iterator<Item = &'a FileReponse> + Clone,
+) -> u64 {
+    let spooled = |size: u64| size.min(SPOOL_MEMORY_LIMIT as u64);
+
+    let attachments = files.clone().map(|file| spooled(file.size.0)).sum::<u64>();
+    let largest_blob = files
+        .flat_map(|file| file.blobs.iter().map(|blob| blob.size.0))
+        .max()
+        .unwrap_or_default();
+
+    let body = decoded_size_bound(
+        details.body.text.as_deref(),
+        details.body.compressed_text.as_deref(),
+        max_decompressed_size,
+    );
+    let headers = details
+        .headers
+        .as_ref()
+        .map(|h| {
+            decoded_size_bound(
+                h.headers.as_deref(),
+                h.compressed_headers.as_deref(),
+                max_decompressed_size,
+            )
+        })
+        .unwrap_or_default();
+
+    body + headers + attachments + 2 * spooled(largest_blob) + SPOOL_MEMORY_LIMIT as u64
+}
+
+/// Upper bound of the memory that [`decrypt_and_decompress`] allocates for the given encrypted data.
+///
+/// The size of compressed data is only known after decompression, so it is bounded by the maximum LZ4 compression
+/// ratio and the decompression limit. The decrypted but still compressed data is held alongside.
+fn decoded_size_bound(
+    plain: Option<&[u8]>,
+    compressed: Option<&[u8]>,
+    max_decompressed_size: usize,
+) -> u64 {
+    match (plain, compressed) {
+        (Some(data), _) => data.len() as u64,
+        (None, Some(data)) => {
+            let len = data.len() as u64;
+            len + len
+                .saturating_mul(LZ4_MAX_RATIO)
+                .min(max_decompressed_size as u64)
+        }
+        (None, None) => 0,
+    }
+}
+
+/// Mail including its content, see [`Mail::download`].
+#[derive(Debug)]
+pub struct DownloadedMail {
+    /// Metadata.
+    pub mail: Arc<Mail>,
+
+    /// Raw mail headers, only present for mails that were received via SMTP.
+    pub headers: Option<String>,
+
+    /// Decompressed body, usually HTML.
+    pub body: Vec<u8>,
+
+    /// Downloaded attachments.
+    pub attachments: Vec<Attachment>,
+
+    /// `Bcc` recipients.
+    pub bcc: Vec<Address>,
+
+    /// `Cc` recipients.
+    pub cc: Vec<Address>,
+
+    /// `To` recipients.
+    pub to: Vec<Address>,
+
+    /// Attachments whose content was NOT downloaded, see [`DownloadOptions::attachment_filter`].
+    pub omitted_attachments: Vec<OmittedAttachment>,
+
+    /// Memory reserved for this mail, released when the mail is dropped.
+    #[allow(dead_code)]
+    pub memory: Option<MemoryReservation>,
+}
+
+/// Decrypted attachment.
+#[derive(Debug)]
+pub struct Attachment {
+    /// Content ID for inline images referenced via `cid:` URLs.
+    pub cid: Option<String>,
+
+    /// MIME type as given by the sender.
+    pub mime_type: String,
+
+    /// File name.
+    pub name: String,
+
+    /// Content.
+    pub data: Spool,
+}
+
+/// Metadata of an attachment that was NOT downloaded.
+#[derive(Debug)]
+pub struct OmittedAttachment {
+    /// File name.
+    pub name: String,
+
+    /// MIME type as given by the sender.
+    pub mime_type: String,
+
+    /// Size as reported by the server.
+    pub size: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoded_size_bound() {
+        assert_eq!(decoded_size_bound(None, None, 1_000), 0);
+        assert_eq!(decoded_size_bound(Some(&[0; 10]), None, 1_000), 10);
+        assert_eq!(
+            decoded_size_bound(None, Some(&[0; 2]), 1_000),
+            2 + 2 * LZ4_MAX_RATIO,
+        );
+        assert_eq!(decoded_size_bound(None, Some(&[0; 10]), 1_000), 1_010);
+
+        // the bound holds for actual data
+        let data = vec![b'a'; 100_000];
+        let compressed = lz4_flex::block::compress(&data);
+        let bound = decoded_size_bound(None, Some(&compressed), usize::MAX);
+        assert!(bound >= (compressed.len() + data.len()) as u64);
+    }
+
+    #[test]
+    fn test_attachment_filter() {
+        let all = AttachmentFilter::default();
+        assert!(all.accepts("video/mp4", 200_000_000));
+
+        let skip = AttachmentFilter {
+            skip_all: true,
+            ..Default::default()
+        };
+        assert!(!skip.accepts("application/pdf", 1));
+
+        let filter = AttachmentFilter {
+            skip_all: false,
+            max_size: Some(1_000),
+            mime_types: vec!["image/*".to_owned(), "application/pdf".to_owned()],
+        };
+        assert!(filter.accepts("application/pdf", 1_000));
+        assert!(filter.accepts("Application/PDF; name=\"a.pdf\"", 10));
+        assert!(filter.accepts("image/png", 10));
+        assert!(!filter.accepts("application/pdf", 1_001));
+        assert!(!filter.accepts("application/pdfx", 10));
+        assert!(!filter.accepts("video/mp4", 10));
+        assert!(!filter.accepts("image", 10));
+
+        let any = AttachmentFilter {
+            mime_types: vec!["*/*".to_owned()],
+            ..Default::default()
+        };
+        assert!(any.accepts("video/mp4", 10));
+        assert!(!any.accepts("", 10));
+    }
+
+    #[test]
+    fn test_parse_mail_ref() {
+        assert_eq!(
+            "https://app.tuta.com/mail/O1-abc/O2_def".parse(),
+            Ok(MailRef {
+                folder_id: Some("O1-abc".to_owned()),
+                mail_id: "O2_def".to_owned(),
+            }),
+        );
+        assert_eq!(
+            " O2_def\n".parse(),
+            Ok(MailRef {
+                folder_id: None,
+                mail_id: "O2_def".to_owned(),
+            }),
+        );
+        assert_eq!(
+            "https://app.tuta.com/mail/O1-abc".parse::<MailRef>(),
+            Err(
+                "invalid mail URL, expected `<host>/mail/<folder_id>/<mail_id>`: `https://app.tuta.com/mail/O1-abc`"
+                    .to_owned()
+            ),
+        );
+        assert_eq!(
+            "https://app.tuta.com/".parse::<MailRef>(),
+            Err("neither a mail ID nor a mail URL: `https://app.tuta.com`".to_owned()),
+        );
+        assert!("".parse::<MailRef>().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! Global memory budget for concurrent downloads.
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Granularity of the budget in bytes.
+const UNIT: u64 = 1024;
+
+/// Limits the total size of mail data that is buffered in memory.
+#[derive(Debug)]
+pub struct MemoryBudget {
+    semaphore: Arc<Semaphore>,
+    units: u32,
+}
+
+/// Memory reserved via [`MemoryBudget::reserve`], released on drop.
+#[derive(Debug)]
+pub struct MemoryReservation {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl MemoryBudget {
+    /// Create budget of the given number of bytes.
+    pub fn new(limit: u64) -> Self {
+        let units = to_units(limit).max(1);
+        Self {
+            semaphore: Arc::new(Semaphore::new(units as usize)),
+            units,
+        }
+    }
+
+    /// Reserve the given number of bytes, waiting until enough memory is available.
+    ///
+    /// Reservations that exceed the entire budget are reduced to the budget, so large mails are still processed, but
+    /// only one at a time.
+    pub async fn reserve(&self, bytes: u64) -> MemoryReservation {
+        let permit = Arc::clone(&self.semaphore)
+            .acquire_many_owned(to_units(bytes).min(self.units))
+            .await
+            .expect("semaphore is never closed");
+        MemoryReservation { _permit: permit }
+    }
+}
+
+fn to_units(bytes: u64) -> u32 {
+    u32::try_from(bytes.div_ceil(UNIT)).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reserve() {
+        let budget = MemoryBudget::new(10 * 1024);
+
+        let r1 = budget.reserve(8 * 1024).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), budget.reserve(4 * 1024))
+                .await
+                .is_err()
+        );
+        let r2 = budget.reserve(2 * 1024).await;
+        drop(r1);
+        drop(r2);
+
+        // oversized reservations are capped
+        let r3 = budget.reserve(1024 * 1024 * 1024).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), budget.reserve(1))
+                .await
+                .is_err()
+        );
+        drop(r3);
+        budget.reserve(1).await;
+    }
+}
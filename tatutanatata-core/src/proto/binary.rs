@@ -83,7 +83,7 @@ impl<'de> serde::Deserialize<'de> for Base64String {
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
-pub(crate) struct Base64Url(Box<[u8]>);
+pub struct Base64Url(Box<[u8]>);
 
 impl Base64Url {
     fn url(&self) -> String {
@@ -1,18 +1,22 @@
+//! Enumerations of the API model, which are encoded as numeric strings.
 use anyhow::Result;
 use serde::{de::Error, Deserializer, Serializer};
 
 macro_rules! build_enum {
     ($name:ident, [$($element:ident = $descr:expr,)*] $(,)?) => {
+        #[doc = concat!("`", stringify!($name), "` of the API model.")]
         #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-        pub(crate) enum $name {
+        pub enum $name {
             $(
+                #[doc = concat!("Encoded as `\"", $descr, "\"`.")]
                 $element,
             )*
         }
 
         impl $name {
+            /// Name of the variant, e.g. for display.
             #[allow(dead_code)]
-            pub(crate) fn name(&self) -> &'static str {
+            pub fn name(&self) -> &'static str {
                 match self {
                     $(
                         Self::$element => stringify!($element),
@@ -89,6 +93,21 @@ build_enum!(
     ],
 );
 
+build_enum!(
+    MailState,
+    [Draft = "0", Sent = "1", Received = "2", Sending = "3",]
+);
+
+build_enum!(
+    ReplyType,
+    [
+        NoReply = "0",
+        Reply = "1",
+        Forward = "2",
+        ReplyForward = "3",
+    ]
+);
+
 build_enum!(
     ArchiveDataType,
     [
@@ -98,6 +117,13 @@ build_enum!(
     ],
 );
 
+build_enum!(OperationType, [Create = "0", Update = "1", Delete = "2",]);
+
+build_enum!(
+    CryptoProtocolVersion,
+    [Rsa = "0", SecureExternal = "1", TutaCrypt = "2",]
+);
+
 #[cfg(test)]
 mod tests {
     use crate::proto::testing::{assert_deser_error, assert_roundtrip};
@@ -112,6 +138,15 @@ mod tests {
         assert_deser_error::<KdfVersion>(r#""2""#, "unknown variant: 2");
     }
 
+    #[test]
+    fn test_roundtrip_crypto_protocol_version() {
+        assert_roundtrip(CryptoProtocolVersion::Rsa, r#""0""#);
+        assert_roundtrip(CryptoProtocolVersion::SecureExternal, r#""1""#);
+        assert_roundtrip(CryptoProtocolVersion::TutaCrypt, r#""2""#);
+
+        assert_deser_error::<CryptoProtocolVersion>(r#""3""#, "unknown variant: 3");
+    }
+
     #[test]
     fn test_roundtrip_group_type() {
         assert_roundtrip(GroupType::User, r#""0""#);
@@ -151,4 +186,13 @@ mod tests {
 
         assert_deser_error::<ArchiveDataType>(r#""20""#, "unknown variant: 20");
     }
+
+    #[test]
+    fn test_roundtrip_operation_type() {
+        assert_roundtrip(OperationType::Create, r#""0""#);
+        assert_roundtrip(OperationType::Update, r#""1""#);
+        assert_roundtrip(OperationType::Delete, r#""2""#);
+
+        assert_deser_error::<OperationType>(r#""20""#, "unknown variant: 20");
+    }
 }
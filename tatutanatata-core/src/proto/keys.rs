@@ -1,11 +1,18 @@
+//! Encryption keys.
 use serde::{de::Error, Deserializer, Serializer};
 use std::ops::Deref;
 
 use super::binary::Base64String;
 
+/// Symmetric AES key.
+///
+/// The [`Debug`] output contains the key material.
 #[derive(Clone, Copy, PartialEq, Eq)]
-pub(crate) enum Key {
+pub enum Key {
+    /// 128-bit key, used by older accounts.
     Aes128([u8; 16]),
+
+    /// 256-bit key.
     Aes256([u8; 32]),
 }
 
@@ -57,6 +64,13 @@ pub(crate) enum EncryptedKey {
     Aes128NoMac([u8; 16]),
     Aes128WithMac([u8; 65]),
     Aes256NoMac([u8; 32]),
+    Aes256WithMac([u8; 81]),
+
+    /// AES-GCM encrypted 128 bit key: version byte, nonce, key, and tag.
+    Aes128Gcm([u8; 45]),
+
+    /// AES-GCM encrypted 256 bit key: version byte, nonce, key, and tag.
+    Aes256Gcm([u8; 61]),
 }
 
 impl std::fmt::Debug for EncryptedKey {
@@ -65,6 +79,9 @@ impl std::fmt::Debug for EncryptedKey {
             Self::Aes128NoMac(k) => ("Aes128NoMac", k.as_slice()),
             Self::Aes128WithMac(k) => ("Aes128WithMac", k.as_slice()),
             Self::Aes256NoMac(k) => ("Aes256NoMac", k.as_slice()),
+            Self::Aes256WithMac(k) => ("Aes256WithMac", k.as_slice()),
+            Self::Aes128Gcm(k) => ("Aes128Gcm", k.as_slice()),
+            Self::Aes256Gcm(k) => ("Aes256Gcm", k.as_slice()),
         };
 
         write!(f, "{name}(")?;
@@ -81,6 +98,9 @@ impl AsRef<[u8]> for EncryptedKey {
             Self::Aes128NoMac(k) => k,
             Self::Aes128WithMac(k) => k,
             Self::Aes256NoMac(k) => k,
+            Self::Aes256WithMac(k) => k,
+            Self::Aes128Gcm(k) => k,
+            Self::Aes256Gcm(k) => k,
         }
     }
 }
@@ -93,6 +113,9 @@ impl Deref for EncryptedKey {
             Self::Aes128NoMac(k) => k,
             Self::Aes128WithMac(k) => k,
             Self::Aes256NoMac(k) => k,
+            Self::Aes256WithMac(k) => k,
+            Self::Aes128Gcm(k) => k,
+            Self::Aes256Gcm(k) => k,
         }
     }
 }
@@ -150,6 +173,12 @@ impl<'de> serde::Deserialize<'de> for OptionalEncryptedKey {
             Ok(Self(Some(EncryptedKey::Aes256NoMac(k))))
         } else if let Ok(k) = TryInto::<[u8; 65]>::try_into(s.deref()) {
             Ok(Self(Some(EncryptedKey::Aes128WithMac(k))))
+        } else if let Ok(k) = TryInto::<[u8; 81]>::try_into(s.deref()) {
+            Ok(Self(Some(EncryptedKey::Aes256WithMac(k))))
+        } else if let Ok(k) = TryInto::<[u8; 45]>::try_into(s.deref()) {
+            Ok(Self(Some(EncryptedKey::Aes128Gcm(k))))
+        } else if let Ok(k) = TryInto::<[u8; 61]>::try_into(s.deref()) {
+            Ok(Self(Some(EncryptedKey::Aes256Gcm(k))))
         } else {
             Err(D::Error::custom(format!(
                 "invalid key length: {}",
@@ -181,6 +210,18 @@ mod tests {
             EncryptedKey::Aes256NoMac([42; 32]),
             r#""KioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKio=""#,
         );
+        assert_roundtrip(
+            EncryptedKey::Aes256WithMac([42; 81]),
+            r#""KioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioq""#,
+        );
+        assert_roundtrip(
+            EncryptedKey::Aes128Gcm([42; 45]),
+            r#""KioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioq""#,
+        );
+        assert_roundtrip(
+            EncryptedKey::Aes256Gcm([42; 61]),
+            r#""KioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKg==""#,
+        );
 
         assert_deser_error::<EncryptedKey>(r#""""#, "key must not be empty");
         assert_deser_error::<EncryptedKey>(r#""eAo=""#, "invalid key length: 2");
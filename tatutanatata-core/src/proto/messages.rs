@@ -1,10 +1,17 @@
+//! Request and response bodies of the API.
+//!
+//! These are maintained by hand, see the `proto-gen` binary.
 use serde::{Deserialize, Serialize};
 
 use super::{
     binary::{Base64String, Base64Url},
+    boolean::Boolean,
     constants::{Format, Null},
     date::UnixDate,
-    enums::{ArchiveDataType, GroupType, KdfVersion, MailFolderType},
+    enums::{
+        ArchiveDataType, CryptoProtocolVersion, GroupType, KdfVersion, MailFolderType, MailState,
+        OperationType, ReplyType,
+    },
     keys::{EncryptedKey, OptionalEncryptedKey},
     numbers::Number,
 };
@@ -43,13 +50,13 @@ pub(crate) struct SessionServiceRequest {
 
     pub(crate) auth_token: Null,
 
-    pub(crate) auth_verifier: Base64Url,
+    pub(crate) auth_verifier: Option<Base64Url>,
 
     pub(crate) client_identifier: String,
 
     pub(crate) mail_address: String,
 
-    pub(crate) recover_code_verifier: Null,
+    pub(crate) recover_code_verifier: Option<Base64Url>,
 
     pub(crate) user: Null,
 }
@@ -79,6 +86,7 @@ pub(crate) struct UserMembership {
 #[serde(rename_all = "camelCase")]
 pub(crate) struct UserAuth {
     pub(crate) sessions: String,
+    pub(crate) recover_code: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -92,6 +100,38 @@ pub(crate) struct UserResponse {
     pub(crate) user_group: UserMembership,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RecoverCodeResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    pub(crate) recover_code_enc_user_group_key: EncryptedKey,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct KeyPair {
+    pub(crate) sym_enc_priv_rsa_key: Option<Base64String>,
+    pub(crate) sym_enc_priv_ecc_key: Option<Base64String>,
+    pub(crate) sym_enc_priv_kyber_key: Option<Base64String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GroupResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    pub(crate) current_keys: Option<KeyPair>,
+
+    /// Group that administrates this group.
+    pub(crate) admin: Option<String>,
+
+    /// Key of this group, encrypted with the key of the [admin](Self::admin) group.
+    pub(crate) admin_group_enc_g_key: Option<EncryptedKey>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct MailboxGroupRootResponse {
@@ -134,6 +174,9 @@ pub(crate) struct FolderResponse {
     pub(crate) folder_type: MailFolderType,
     pub(crate) name: Base64String,
     pub(crate) mails: String,
+
+    /// Parent of nested folders.
+    pub(crate) parent_folder: Option<[String; 2]>,
 }
 
 impl Entity for FolderResponse {
@@ -171,6 +214,38 @@ pub(crate) struct MailReponse {
     pub(crate) subject: Base64String,
     pub(crate) sender: MailAddress,
     pub(crate) attachments: Vec<[String; 2]>,
+    pub(crate) unread: Boolean,
+    pub(crate) state: MailState,
+    pub(crate) reply_type: ReplyType,
+
+    /// Entry in the conversation list, the list ID identifies the conversation.
+    pub(crate) conversation_entry: Option<[String; 2]>,
+
+    /// Set instead of [`owner_enc_session_key`](Self::owner_enc_session_key) for mails that were NOT re-encrypted
+    /// by the official app yet.
+    pub(crate) bucket_key: Option<BucketKey>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct BucketKey {
+    pub(crate) protocol_version: CryptoProtocolVersion,
+
+    /// Bucket key, encrypted with the public key of the key group.
+    pub(crate) pub_enc_bucket_key: Option<Base64String>,
+
+    /// Bucket key, encrypted with the symmetric key of the key group.
+    pub(crate) group_enc_bucket_key: Option<EncryptedKey>,
+
+    pub(crate) key_group: Option<String>,
+    pub(crate) bucket_enc_session_keys: Vec<InstanceSessionKey>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct InstanceSessionKey {
+    pub(crate) instance_id: String,
+    pub(crate) sym_enc_session_key: EncryptedKey,
 }
 
 impl Entity for MailReponse {
@@ -221,6 +296,7 @@ pub(crate) struct BlobServer {
 pub(crate) struct BlobAccessInfo {
     pub(crate) blob_access_token: String,
     pub(crate) servers: Vec<BlobServer>,
+    pub(crate) expires: Option<UnixDate>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -254,9 +330,10 @@ pub(crate) struct MailRecipients {
     pub(crate) to_recipients: Vec<MailAddress>,
 }
 
+/// Body, headers, and recipients of a mail, see [`MailDetailsBlob`].
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct MailDetails {
+pub struct MailDetails {
     pub(crate) body: MailBody,
 
     /// Mail headers.
@@ -267,13 +344,19 @@ pub(crate) struct MailDetails {
     pub(crate) recipients: MailRecipients,
 }
 
+/// Encrypted details of a mail, see [`Mail::blob_id`](crate::mails::Mail::blob_id).
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub(crate) struct MailDetailsBlob {
+pub struct MailDetailsBlob {
     #[serde(rename = "_format")]
     pub(crate) _format: Format<0>,
 
-    pub(crate) details: MailDetails,
+    /// Blob ID as `[archive_id, blob_id]`.
+    #[serde(rename = "_id")]
+    pub id: [String; 2],
+
+    /// Encrypted details.
+    pub details: MailDetails,
 }
 
 #[derive(Debug, Deserialize)]
@@ -291,7 +374,7 @@ pub(crate) struct FileReponse {
     pub(crate) _format: Format<0>,
 
     #[serde(rename = "_ownerEncSessionKey")]
-    pub(crate) owner_enc_session_key: EncryptedKey,
+    pub(crate) owner_enc_session_key: Option<EncryptedKey>,
 
     #[serde(rename = "_ownerGroup")]
     pub(crate) owner_group: String,
@@ -313,3 +396,27 @@ pub(crate) struct BlobServiceRequest {
     pub(crate) blob_id: String,
     pub(crate) blob_ids: Vec<()>,
 }
+
+/// Change of an entity, see [`entity_updates`](crate::events::entity_updates).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EntityUpdate {
+    /// API of the entity, e.g. `tutanota`.
+    pub application: String,
+
+    /// List that contains the entity, e.g. the mail list of a folder.
+    pub instance_list_id: String,
+
+    /// Entity ID.
+    pub instance_id: String,
+
+    /// Kind of change.
+    pub operation: OperationType,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct WebsocketEntityData {
+    pub(crate) event_batch_id: String,
+    pub(crate) event_batch: Vec<EntityUpdate>,
+}
@@ -1,9 +1,11 @@
+//! Wire format of the Tuta API.
 pub(crate) mod binary;
+pub(crate) mod boolean;
 pub(crate) mod constants;
 pub(crate) mod date;
-pub(crate) mod enums;
-pub(crate) mod keys;
-pub(crate) mod messages;
+pub mod enums;
+pub mod keys;
+pub mod messages;
 pub(crate) mod numbers;
 
 #[cfg(test)]
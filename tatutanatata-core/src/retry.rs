@@ -0,0 +1,323 @@
+//! Retries with exponential backoff, shared by all operations that can fail transiently.
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use rand::{rng, rngs::StdRng, Rng, RngCore, SeedableRng};
+use reqwest::StatusCode;
+use tracing::warn;
+
+/// Retry limits, see [`RetryPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryConfig {
+    /// Give up retrying an operation after this time.
+    pub deadline: Duration,
+
+    /// Maximum delay between two attempts.
+    ///
+    /// Delays requested by the server via `Retry-After` are NOT capped.
+    pub max_delay: Duration,
+
+    /// Maximum number of retries for network errors, [`None`] means unlimited until the deadline passes.
+    pub network: Option<u32>,
+
+    /// Maximum number of retries for `408 Request Timeout` and `429 Too Many Requests` responses.
+    ///
+    /// Other `4xx` responses are never retried.
+    pub client_error: Option<u32>,
+
+    /// Maximum number of retries for `5xx` responses.
+    pub server_error: Option<u32>,
+
+    /// Maximum number of retries for transient file system errors.
+    pub filesystem: Option<u32>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            deadline: Duration::from_secs(600),
+            max_delay: Duration::from_secs(60),
+            network: None,
+            client_error: None,
+            server_error: None,
+            filesystem: None,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Maximum number of retries for the given error class, [`None`] means unlimited.
+    fn max_retries(&self, class: ErrorClass) -> Option<u32> {
+        match class {
+            ErrorClass::Network => self.network,
+            ErrorClass::ClientError => self.client_error,
+            ErrorClass::ServerError => self.server_error,
+            ErrorClass::Filesystem => self.filesystem,
+        }
+    }
+}
+
+/// Retries with exponential backoff and jitter, limited by a [`RetryConfig`].
+///
+/// Cheap to clone, clones share the retry counters, see [`retries`](Self::retries).
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicy {
+    config: RetryConfig,
+    retries: Arc<[AtomicU64; ErrorClass::ALL.len()]>,
+}
+
+impl RetryPolicy {
+    /// Policy with the given limits.
+    pub fn new(config: RetryConfig) -> Self {
+        Self {
+            config,
+            retries: Default::default(),
+        }
+    }
+
+    /// Number of retries of the given error class by this policy and its clones.
+    pub fn retries(&self, class: ErrorClass) -> u64 {
+        self.retries[class as usize].load(Ordering::Relaxed)
+    }
+
+    /// Run `f` until it succeeds or `should_retry` rejects the error.
+    ///
+    /// Gives up once the retry limit of the error class is reached or the deadline passed.
+    ///
+    /// See <https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/>
+    pub async fn retry<F, Fut, R, T, E>(
+        &self,
+        what: &'static str,
+        f: F,
+        should_retry: R,
+    ) -> Result<T>
+    where
+        F: Fn() -> Fut + Send,
+        Fut: Future<Output = Result<T, E>> + Send,
+        R: for<'a> Fn(&'a E) -> Retry + Send,
+        T: Send,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let config = Config {
+            cap: self.config.max_delay,
+            deadline: self.config.deadline,
+            ..Default::default()
+        };
+        tokio::time::timeout(config.deadline, async move {
+            let mut retries = [0u32; ErrorClass::ALL.len()];
+            for sleep in Sleep::from(config) {
+                match f().await {
+                    Ok(x) => {
+                        return Ok(x);
+                    }
+                    Err(e) => {
+                        let (class, sleep) = match should_retry(&e) {
+                            Retry::No => {
+                                return Err(e).context("failed");
+                            }
+                            Retry::Backoff(class) => (class, sleep),
+                            Retry::After(class, after) => (class, after),
+                        };
+
+                        let retries = &mut retries[class as usize];
+                        if self
+                            .config
+                            .max_retries(class)
+                            .is_some_and(|max| *retries >= max)
+                        {
+                            return Err(e)
+                                .with_context(|| format!("failed after {retries} retries"));
+                        }
+                        *retries += 1;
+                        self.retries[class as usize].fetch_add(1, Ordering::Relaxed);
+
+                        warn!(%e, what, ?class, sleep_sec=sleep.as_secs_f64(), "retry");
+                        tokio::time::sleep(sleep).await;
+                    }
+                }
+            }
+
+            unreachable!("iterator never ends")
+        })
+        .await
+        .context("deadline exceeded")?
+    }
+}
+
+/// Class of transient errors, each with its own retry limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Failed connection, timeout, or broken transfer.
+    Network,
+
+    /// Retryable `4xx` response.
+    ClientError,
+
+    /// `5xx` response.
+    ServerError,
+
+    /// File system error.
+    Filesystem,
+}
+
+impl ErrorClass {
+    /// All classes, e.g. to initialize metrics.
+    pub const ALL: [Self; 4] = [
+        Self::Network,
+        Self::ClientError,
+        Self::ServerError,
+        Self::Filesystem,
+    ];
+
+    /// Short name, e.g. for metric labels.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Network => "network",
+            Self::ClientError => "client_error",
+            Self::ServerError => "server_error",
+            Self::Filesystem => "filesystem",
+        }
+    }
+
+    /// Classify HTTP error, returns [`None`] if it is NOT transient.
+    pub(crate) fn of_http_error(e: &reqwest::Error) -> Option<Self> {
+        // body errors are usually caused by connections that broke during the transfer
+        if e.is_connect() || e.is_timeout() || e.is_body() {
+            return Some(Self::Network);
+        }
+
+        match e.status()? {
+            status if status.is_server_error() => Some(Self::ServerError),
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => Some(Self::ClientError),
+            _ => None,
+        }
+    }
+}
+
+/// Decision of the retry condition of [`RetryPolicy::retry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// Fail.
+    No,
+
+    /// Retry after the next backoff delay.
+    Backoff(ErrorClass),
+
+    /// Retry after the given delay, e.g. because the server requested it.
+    After(ErrorClass, Duration),
+}
+
+struct Config {
+    multiplier: f64,
+    cap: Duration,
+    base: Duration,
+    deadline: Duration,
+    rng: Box<dyn RngCore + Send + Sync>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            multiplier: 3.0,
+            cap: Duration::from_secs(60),
+            base: Duration::from_millis(100),
+            deadline: Duration::from_secs(600),
+            rng: Box::new(StdRng::from_rng(&mut rng())),
+        }
+    }
+}
+
+struct Sleep {
+    config: Config,
+    sleep: Duration,
+}
+
+impl From<Config> for Sleep {
+    fn from(config: Config) -> Self {
+        let sleep = config.base;
+        Self { config, sleep }
+    }
+}
+
+impl Iterator for Sleep {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sleep = self.sleep;
+        self.sleep = self
+            .config
+            .cap
+            .min(Duration::from_secs_f64(self.config.rng.random_range(
+                self.config.base.as_secs_f64()..(sleep.as_secs_f64() * self.config.multiplier),
+            )));
+        Some(sleep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    #[test]
+    fn test_sleep() {
+        let mut it = Sleep::from(Config {
+            rng: Box::new(StepRng::new(15397336220812216376, u64::MAX / 2 - 1)),
+            cap: Duration::from_secs(2),
+            ..Default::default()
+        });
+
+        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.1);
+        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.266938254);
+        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.334556582);
+        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.854285247);
+        assert_approx_eq(it.next().unwrap().as_secs_f64(), 0.924296313);
+        assert_approx_eq(it.next().unwrap().as_secs_f64(), 2.0);
+        assert_approx_eq(it.next().unwrap().as_secs_f64(), 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_max_retries() {
+        let policy = RetryPolicy::new(RetryConfig {
+            filesystem: Some(1),
+            server_error: Some(0),
+            ..Default::default()
+        });
+        assert_eq!(policy.config.max_retries(ErrorClass::Filesystem), Some(1));
+        assert_eq!(policy.config.max_retries(ErrorClass::ServerError), Some(0));
+        assert_eq!(policy.config.max_retries(ErrorClass::Network), None);
+
+        let attempts = std::sync::atomic::AtomicUsize::new(0);
+        let res = policy
+            .retry(
+                "test",
+                || async {
+                    attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    Err::<(), _>(std::io::Error::other("foo"))
+                },
+                |_| Retry::Backoff(ErrorClass::Filesystem),
+            )
+            .await;
+        assert_eq!(
+            format!("{:#}", res.unwrap_err()),
+            "failed after 1 retries: foo"
+        );
+        assert_eq!(attempts.into_inner(), 2);
+        assert_eq!(policy.retries(ErrorClass::Filesystem), 1);
+        assert_eq!(policy.clone().retries(ErrorClass::Filesystem), 1);
+        assert_eq!(policy.retries(ErrorClass::ServerError), 0);
+    }
+
+    #[track_caller]
+    fn assert_approx_eq(a: f64, b: f64) {
+        assert!((a - b).abs() < 0.000000001, "{a} != {b}",);
+    }
+}
@@ -0,0 +1,563 @@
+//! Login and session keys, see [`Session`].
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Method;
+use rsa::RsaPrivateKey;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::{
+    blob::BlobAccessCache,
+    client::{Client, Prefix, Request},
+    constants::APP_USER_AGENT,
+    crypto::{
+        asymmetric::{
+            decrypt_pq_key, decrypt_pq_key_pair, decrypt_rsa_key, decrypt_rsa_private_key,
+            PqKeyPair,
+        },
+        auth::{derive_passkey, encode_auth_verifier, parse_recovery_code},
+        encryption::decrypt_key,
+    },
+    non_empty_string::NonEmptyString,
+    proto::{
+        binary::Base64Url,
+        enums::{CryptoProtocolVersion, GroupType},
+        keys::{EncryptedKey, Key},
+        messages::{
+            BucketKey, GroupResponse, RecoverCodeResponse, SaltServiceRequest, SaltServiceResponse,
+            SessionServiceRequest, SessionServiceResponse, UserResponse,
+        },
+    },
+};
+
+/// Credentials to log in with, see [`Session::login`].
+#[derive(Debug, Clone)]
+pub struct LoginConfig {
+    username: NonEmptyString,
+    password: Option<NonEmptyString>,
+    recovery_code: Option<NonEmptyString>,
+}
+
+impl LoginConfig {
+    /// Config to log in with username and password.
+    pub fn new(username: &str, password: &str) -> Result<Self> {
+        Ok(Self {
+            username: parse_non_empty("username", username)?,
+            password: Some(parse_non_empty("password", password)?),
+            recovery_code: None,
+        })
+    }
+
+    /// Config to log in with username and account recovery code, e.g. if the second factor was lost.
+    pub fn with_recovery_code(username: &str, recovery_code: &str) -> Result<Self> {
+        Ok(Self {
+            username: parse_non_empty("username", username)?,
+            password: None,
+            recovery_code: Some(parse_non_empty("recovery code", recovery_code)?),
+        })
+    }
+
+    /// Username and password, see [`fixture`](crate::fixture).
+    #[cfg(feature = "test-harness")]
+    pub(crate) fn username_and_password(&self) -> Result<(&str, &str)> {
+        let password = self
+            .password
+            .as_deref()
+            .context("password must be provided")?;
+        Ok((&self.username, password))
+    }
+}
+
+fn parse_non_empty(what: &str, s: &str) -> Result<NonEmptyString> {
+    s.parse().map_err(|e| anyhow!("invalid {what}: {e}"))
+}
+
+/// Secret used to log in.
+#[derive(Debug)]
+enum Credentials {
+    /// Key derived from the password.
+    Passphrase(Key),
+
+    /// Key encoded in the recovery code.
+    RecoveryCode(Key),
+}
+
+/// Maximum time that logout may take.
+///
+/// This is shorter than the usual retry deadline so that a hanging server does not block exit.
+const LOGOUT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// User session
+#[derive(Debug)]
+pub struct Session {
+    #[allow(dead_code)]
+    pub(crate) user_id: String,
+
+    /// Token that authenticates requests, see [`Request::access_token`](crate::client::Request::access_token).
+    pub access_token: Base64Url,
+
+    pub(crate) group_keys: Arc<GroupKeys>,
+    pub(crate) user_data: UserResponse,
+    pub(crate) blob_access: BlobAccessCache,
+}
+
+/// Group membership of the user, see [`Session::memberships`].
+#[derive(Debug, Clone)]
+pub struct Membership {
+    /// Group ID.
+    pub group: String,
+
+    /// Group type, e.g. [`GroupType::Mail`] for mailboxes.
+    pub group_type: GroupType,
+
+    /// Whether the group key could be decrypted, i.e. whether the data of the group is accessible.
+    pub accessible: bool,
+}
+
+impl Session {
+    /// Perform tutanota login.
+    pub async fn login(config: LoginConfig, client: &Client) -> Result<Self> {
+        debug!("perform login");
+
+        let credentials = match (&config.password, &config.recovery_code) {
+            (_, Some(recovery_code)) => Credentials::RecoveryCode(
+                parse_recovery_code(recovery_code).context("parse recovery code")?,
+            ),
+            (Some(password), None) => {
+                let resp = fetch_salt(client, &config.username).await?;
+
+                let pk = derive_passkey(resp.kdf_version, password, resp.salt.as_ref())
+                    .context("derive passkey")?;
+                Credentials::Passphrase(*pk)
+            }
+            (None, None) => bail!("either password or recovery code must be provided"),
+        };
+
+        let (auth_verifier, recover_code_verifier) = match &credentials {
+            Credentials::Passphrase(k) => (Some(encode_auth_verifier(k)), None),
+            Credentials::RecoveryCode(k) => (None, Some(encode_auth_verifier(k))),
+        };
+
+        let req = SessionServiceRequest {
+            format: Default::default(),
+            access_key: Default::default(),
+            auth_token: Default::default(),
+            auth_verifier,
+            client_identifier: APP_USER_AGENT.to_owned(),
+            mail_address: config.username.to_string(),
+            recover_code_verifier,
+            user: Default::default(),
+        };
+        let resp: SessionServiceResponse = client
+            .do_json(Request {
+                method: Method::POST,
+                ..Request::new(Prefix::Sys, "sessionservice", &req)
+            })
+            .await
+            .context("get session")?;
+        let user_id = resp.user;
+        let access_token = resp.access_token;
+
+        debug!(user = user_id.as_str(), "got user");
+
+        if !resp.challenges.is_empty() {
+            bail!("not implemented: challenges");
+        }
+
+        let user_data: UserResponse = client
+            .do_json(Request {
+                access_token: Some(&access_token),
+                ..Request::new(Prefix::Sys, &format!("user/{}", user_id), &())
+            })
+            .await
+            .context("get user")?;
+
+        let user_key = match credentials {
+            Credentials::Passphrase(pk) => decrypt_key(
+                pk,
+                user_data
+                    .user_group
+                    .sym_enc_g_key
+                    .0
+                    .context("user key must be set")?,
+            )
+            .context("decrypt user group key")?,
+            Credentials::RecoveryCode(k) => {
+                let recover_code = user_data
+                    .auth
+                    .recover_code
+                    .as_deref()
+                    .context("account has no recovery code")?;
+                let resp: RecoverCodeResponse = client
+                    .do_json(Request {
+                        access_token: Some(&access_token),
+                        ..Request::new(Prefix::Sys, &format!("recovercode/{}", recover_code), &())
+                    })
+                    .await
+                    .context("get recovery code")?;
+
+                decrypt_key(k, resp.recover_code_enc_user_group_key)
+                    .context("decrypt user group key with recovery code")?
+            }
+        };
+
+        let mut group_keys =
+            GroupKeys::try_new(user_key, &user_data).context("set up group keys")?;
+        group_keys
+            .resolve_nested_keys(client, &access_token, &user_data)
+            .await
+            .context("resolve nested group keys")?;
+        group_keys
+            .load_private_keys(client, &access_token, &user_data)
+            .await
+            .context("load private keys")?;
+        let group_keys = Arc::new(group_keys);
+
+        Ok(Self {
+            user_id,
+            access_token,
+            group_keys,
+            user_data,
+            blob_access: BlobAccessCache::default(),
+        })
+    }
+
+    /// Group memberships of the user.
+    pub fn memberships(&self) -> Vec<Membership> {
+        self.user_data
+            .memberships
+            .iter()
+            .map(|m| Membership {
+                group: m.group.clone(),
+                group_type: m.group_type,
+                accessible: self.group_keys.contains(&m.group),
+            })
+            .collect()
+    }
+
+    /// Log out, which invalidates the session.
+    pub async fn logout(self, client: &Client) -> Result<()> {
+        let session = &self.user_data.auth.sessions;
+
+        debug!(session = session.as_str(), "performing logout",);
+
+        let path = format!(
+            "session/{}/{}",
+            session,
+            session_element_id(&self.access_token)
+        );
+        let req = client.do_no_response(Request {
+            method: Method::DELETE,
+            host: None,
+            prefix: Prefix::Sys,
+            path: &path,
+            data: &(),
+            access_token: Some(&self.access_token),
+            query: &[],
+        });
+        tokio::time::timeout(LOGOUT_TIMEOUT, req)
+            .await
+            .context("session deletion timed out")?
+            .context("session deletion")?;
+
+        debug!("logout done");
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct GroupKeys {
+    keys: HashMap<String, Key>,
+    private_keys: HashMap<String, RsaPrivateKey>,
+    pq_key_pairs: HashMap<String, PqKeyPair>,
+}
+
+impl GroupKeys {
+    fn try_new(user_key: Key, user_data: &UserResponse) -> Result<Self> {
+        let mut group_keys = HashMap::default();
+        group_keys.insert(user_data.user_group.group.clone(), user_key);
+        for group in &user_data.memberships {
+            if let Some(enc_g_key) = group.sym_enc_g_key.0 {
+                group_keys.insert(
+                    group.group.clone(),
+                    decrypt_key(user_key, enc_g_key).context("decrypt membership group key")?,
+                );
+            }
+        }
+
+        Ok(Self {
+            keys: group_keys,
+            private_keys: HashMap::default(),
+            pq_key_pairs: HashMap::default(),
+        })
+    }
+
+    /// Resolve keys of groups that are NOT encrypted with the user group key directly.
+    ///
+    /// These are encrypted with the key of their admin group instead, which in turn may only be reachable via its own
+    /// admin group (e.g. for shared mailboxes).
+    async fn resolve_nested_keys(
+        &mut self,
+        client: &Client,
+        access_token: &Base64Url,
+        user_data: &UserResponse,
+    ) -> Result<()> {
+        let mut to_fetch = user_data
+            .memberships
+            .iter()
+            .filter(|m| !self.contains(&m.group))
+            .map(|m| m.group.clone())
+            .collect::<Vec<_>>();
+        let mut admin_enc_keys = HashMap::new();
+        let mut fetched = HashSet::new();
+        while let Some(group) = to_fetch.pop() {
+            if self.contains(&group) || !fetched.insert(group.clone()) {
+                continue;
+            }
+
+            let resp: GroupResponse = client
+                .do_json(Request {
+                    access_token: Some(access_token),
+                    ..Request::new(Prefix::Sys, &format!("group/{group}"), &())
+                })
+                .await
+                .context("get group")?;
+            if let (Some(admin), Some(enc_key)) = (resp.admin, resp.admin_group_enc_g_key) {
+                if !self.contains(&admin) {
+                    to_fetch.push(admin.clone());
+                }
+                admin_enc_keys.insert(group, (admin, enc_key));
+            }
+        }
+
+        resolve_admin_keys(&mut self.keys, &admin_enc_keys)?;
+
+        for m in &user_data.memberships {
+            if !self.contains(&m.group) {
+                debug!(group = m.group.as_str(), "cannot resolve group key");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fetch and decrypt the private keys (RSA and TutaCrypt) of the user and mail groups.
+    ///
+    /// These are required to decrypt mails that were NOT re-encrypted by the official app yet.
+    async fn load_private_keys(
+        &mut self,
+        client: &Client,
+        access_token: &Base64Url,
+        user_data: &UserResponse,
+    ) -> Result<()> {
+        let groups = std::iter::once(&user_data.user_group).chain(
+            user_data
+                .memberships
+                .iter()
+                .filter(|m| m.group_type == GroupType::Mail),
+        );
+
+        for group in groups {
+            if !self.contains(&group.group) {
+                continue;
+            }
+
+            let resp: GroupResponse = client
+                .do_json(Request {
+                    access_token: Some(access_token),
+                    ..Request::new(Prefix::Sys, &format!("group/{}", group.group), &())
+                })
+                .await
+                .context("get group")?;
+            let Some(keys) = resp.current_keys else {
+                continue;
+            };
+            let group_key = self.get(&group.group)?;
+
+            if let Some(encrypted) = keys.sym_enc_priv_rsa_key {
+                debug!(group = group.group.as_str(), "got RSA private key");
+                let private_key =
+                    decrypt_rsa_private_key(group_key, &encrypted).with_context(|| {
+                        format!("decrypt RSA private key of group `{}`", group.group)
+                    })?;
+                self.private_keys.insert(group.group.clone(), private_key);
+            }
+
+            if let (Some(encrypted_x25519), Some(encrypted_kyber)) =
+                (keys.sym_enc_priv_ecc_key, keys.sym_enc_priv_kyber_key)
+            {
+                debug!(group = group.group.as_str(), "got TutaCrypt key pair");
+                let key_pair = decrypt_pq_key_pair(group_key, &encrypted_x25519, &encrypted_kyber)
+                    .with_context(|| {
+                        format!("decrypt TutaCrypt key pair of group `{}`", group.group)
+                    })?;
+                self.pq_key_pairs.insert(group.group.clone(), key_pair);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if the key for the given group is known.
+    pub(crate) fn contains(&self, group: &str) -> bool {
+        self.keys.contains_key(group)
+    }
+
+    pub(crate) fn get(&self, group: &str) -> Result<Key> {
+        let key = self.keys.get(group).context("group key not found")?;
+        Ok(*key)
+    }
+
+    /// Decrypt bucket key and all session keys within the bucket.
+    ///
+    /// Returns [`None`] if the bucket key is encrypted with a key that is unknown.
+    pub(crate) fn decrypt_bucket_key(
+        &self,
+        bucket_key: &BucketKey,
+    ) -> Result<Option<HashMap<String, Key>>> {
+        let Some(key_group) = &bucket_key.key_group else {
+            return Ok(None);
+        };
+
+        let key = match (
+            bucket_key.protocol_version,
+            &bucket_key.pub_enc_bucket_key,
+            bucket_key.group_enc_bucket_key,
+        ) {
+            (CryptoProtocolVersion::Rsa, Some(encrypted), _) => {
+                let Some(private_key) = self.private_keys.get(key_group) else {
+                    return Ok(None);
+                };
+                decrypt_rsa_key(private_key, encrypted).context("decrypt bucket key")?
+            }
+            (CryptoProtocolVersion::TutaCrypt, Some(encrypted), _) => {
+                let Some(key_pair) = self.pq_key_pairs.get(key_group) else {
+                    return Ok(None);
+                };
+                decrypt_pq_key(key_pair, encrypted).context("decrypt bucket key")?
+            }
+            (_, None, Some(encrypted)) => {
+                let Some(group_key) = self.keys.get(key_group) else {
+                    return Ok(None);
+                };
+                decrypt_key(*group_key, encrypted).context("decrypt bucket key")?
+            }
+            _ => return Ok(None),
+        };
+
+        bucket_key
+            .bucket_enc_session_keys
+            .iter()
+            .map(|k| {
+                let session_key = decrypt_key(key, k.sym_enc_session_key)
+                    .with_context(|| format!("decrypt session key of `{}`", k.instance_id))?;
+                Ok((k.instance_id.clone(), session_key))
+            })
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+}
+
+/// Check that the account exists, without logging in.
+pub async fn check_account(config: &LoginConfig, client: &Client) -> Result<()> {
+    fetch_salt(client, &config.username).await?;
+    Ok(())
+}
+
+async fn fetch_salt(client: &Client, username: &NonEmptyString) -> Result<SaltServiceResponse> {
+    let req = SaltServiceRequest {
+        format: Default::default(),
+        mail_address: username.to_string(),
+    };
+    client
+        .do_json(Request::new(Prefix::Sys, "saltservice", &req))
+        .await
+        .context("get salt")
+}
+
+/// Decrypt group keys that are encrypted with the key of their admin group.
+///
+/// `admin_enc_keys` maps group IDs to their admin group and the encrypted key. Chains of admin groups are resolved in
+/// any order.
+fn resolve_admin_keys(
+    keys: &mut HashMap<String, Key>,
+    admin_enc_keys: &HashMap<String, (String, EncryptedKey)>,
+) -> Result<()> {
+    loop {
+        let mut progress = false;
+
+        for (group, (admin, enc_key)) in admin_enc_keys {
+            if keys.contains_key(group) {
+                continue;
+            }
+            let Some(admin_key) = keys.get(admin).copied() else {
+                continue;
+            };
+
+            let key = decrypt_key(admin_key, *enc_key)
+                .with_context(|| format!("decrypt key of group `{group}` via `{admin}`"))?;
+            debug!(
+                group = group.as_str(),
+                admin = admin.as_str(),
+                "resolved nested group key"
+            );
+            keys.insert(group.clone(), key);
+            progress = true;
+        }
+
+        if !progress {
+            return Ok(());
+        }
+    }
+}
+
+const GENERATE_ID_BYTES_LENGTH: usize = 9;
+
+fn session_element_id(access_token: &Base64Url) -> Base64Url {
+    let mut hasher = Sha256::new();
+    hasher.update(&access_token.as_ref()[GENERATE_ID_BYTES_LENGTH..]);
+    hasher.finalize().to_vec().into()
+}
+
+#[allow(dead_code)]
+fn session_list_id(access_token: &Base64Url) -> Base64Url {
+    access_token.as_ref()[..GENERATE_ID_BYTES_LENGTH].into()
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_resolve_admin_keys() {
+        let user_key = Key::Aes128(hex!("0102030405060708090a0b0c0d0e0f10"));
+        let enc_key = EncryptedKey::Aes128NoMac(hex!("0a141e28323c46505a646e78828c96a0"));
+        let admin_key = decrypt_key(user_key, enc_key).unwrap();
+        let shared_key = decrypt_key(admin_key, enc_key).unwrap();
+
+        let mut keys = HashMap::from([("user".to_owned(), user_key)]);
+        let admin_enc_keys = HashMap::from([
+            ("shared".to_owned(), ("admin".to_owned(), enc_key)),
+            ("admin".to_owned(), ("user".to_owned(), enc_key)),
+            ("orphan".to_owned(), ("unknown".to_owned(), enc_key)),
+        ]);
+        resolve_admin_keys(&mut keys, &admin_enc_keys).unwrap();
+
+        assert_eq!(
+            keys,
+            HashMap::from([
+                ("user".to_owned(), user_key),
+                ("admin".to_owned(), admin_key),
+                ("shared".to_owned(), shared_key),
+            ]),
+        );
+    }
+}
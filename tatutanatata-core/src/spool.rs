@@ -0,0 +1,145 @@
+//! Buffers for potentially large data like attachments.
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use sha2::{Digest, Sha256};
+
+/// Data up to this size is kept in memory.
+pub(crate) const MEMORY_LIMIT: usize = 1024 * 1024;
+
+#[derive(Debug)]
+enum Inner {
+    Memory(Vec<u8>),
+    File(File),
+}
+
+/// Buffer that is kept in memory while it is small and spilled to an anonymous temporary file otherwise.
+///
+/// This keeps the memory usage bounded for large mails. Writing and reading is blocking.
+///
+/// The content is hashed while it is written, so that its [digest](Self::sha256) does not require another pass.
+#[derive(Debug)]
+pub struct Spool {
+    inner: Inner,
+    len: u64,
+    hasher: Sha256,
+}
+
+impl Default for Spool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Spool {
+    /// Create empty spool.
+    pub fn new() -> Self {
+        Self {
+            inner: Inner::Memory(Vec::new()),
+            len: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Number of bytes written.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// No bytes were written yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// SHA-256 hash of the content written so far.
+    pub fn sha256(&self) -> [u8; 32] {
+        self.hasher.clone().finalize().into()
+    }
+
+    /// Read the entire content.
+    pub fn reader(&self) -> std::io::Result<Box<dyn Read + Send + '_>> {
+        match &self.inner {
+            Inner::Memory(data) => Ok(Box::new(data.as_slice())),
+            Inner::File(file) => {
+                let mut file = file;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Box::new(file))
+            }
+        }
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Inner::Memory(data) = &self.inner {
+            if data.len() + buf.len() > MEMORY_LIMIT {
+                let mut file = tempfile::tempfile()?;
+                file.write_all(data)?;
+                self.inner = Inner::File(file);
+            }
+        }
+
+        let n = match &mut self.inner {
+            Inner::Memory(data) => data.write(buf)?,
+            Inner::File(file) => file.write(buf)?,
+        };
+        self.len += n as u64;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.inner {
+            Inner::Memory(_) => Ok(()),
+            Inner::File(file) => file.flush(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Spool {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            len: data.len() as u64,
+            hasher: Sha256::new_with_prefix(&data),
+            inner: Inner::Memory(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spill() {
+        let mut spool = Spool::new();
+        spool.write_all(b"foo").unwrap();
+        assert!(matches!(spool.inner, Inner::Memory(_)));
+
+        let big = vec![42u8; MEMORY_LIMIT];
+        spool.write_all(&big).unwrap();
+        assert!(matches!(spool.inner, Inner::File(_)));
+        assert_eq!(spool.len(), MEMORY_LIMIT as u64 + 3);
+
+        // can be read multiple times
+        for _ in 0..2 {
+            let mut content = vec![];
+            spool.reader().unwrap().read_to_end(&mut content).unwrap();
+            assert_eq!(&content[..3], b"foo");
+            assert_eq!(&content[3..], big.as_slice());
+        }
+
+        let mut expected = Sha256::new();
+        expected.update(b"foo");
+        expected.update(&big);
+        assert_eq!(spool.sha256(), <[u8; 32]>::from(expected.finalize()));
+    }
+
+    #[test]
+    fn test_sha256_from_vec() {
+        let spool = Spool::from(b"foo".to_vec());
+        assert_eq!(spool.sha256(), <[u8; 32]>::from(Sha256::digest(b"foo")));
+    }
+}
@@ -0,0 +1,212 @@
+//! Custom TLS configuration.
+use std::{path::Path, str::FromStr, sync::Arc};
+
+use anyhow::{ensure, Context, Result};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::CryptoProvider,
+    pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+    DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+
+/// SHA-256 fingerprint of a DER-encoded certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertFingerprint([u8; 32]);
+
+impl CertFingerprint {
+    fn of(cert: &CertificateDer<'_>) -> Self {
+        Self(Sha256::digest(cert.as_ref()).into())
+    }
+}
+
+impl FromStr for CertFingerprint {
+    type Err = String;
+
+    /// Parse hex-encoded fingerprint, optionally separated by colons.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.chars().filter(|c| *c != ':').collect::<Vec<_>>();
+        if s.len() != 64 {
+            return Err(format!(
+                "SHA-256 fingerprint must have 64 hex characters but has {}",
+                s.len()
+            ));
+        }
+
+        let mut fp = [0u8; 32];
+        for (b, chunk) in fp.iter_mut().zip(s.chunks(2)) {
+            let chunk = chunk.iter().collect::<String>();
+            *b = u8::from_str_radix(&chunk, 16)
+                .map_err(|_| format!("invalid hex in fingerprint: `{chunk}`"))?;
+        }
+
+        Ok(Self(fp))
+    }
+}
+
+/// Build TLS config that trusts the default web PKI roots plus the certificates from `ca_cert`.
+///
+/// If `pins` is not empty, the server certificate chain must contain at least one certificate with one of the given
+/// fingerprints.
+pub(crate) fn build_tls_config(
+    ca_cert: Option<&Path>,
+    pins: &[CertFingerprint],
+    http1: bool,
+) -> Result<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(path) = ca_cert {
+        let data = std::fs::read(path)
+            .with_context(|| format!("read CA certificate: `{}`", path.display()))?;
+        let mut n = 0;
+        for cert in CertificateDer::pem_slice_iter(&data) {
+            let cert = cert.context("parse CA certificate")?;
+            roots.add(cert).context("add CA certificate")?;
+            n += 1;
+        }
+        ensure!(n > 0, "no certificates found in `{}`", path.display());
+    }
+
+    let verifier =
+        WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::clone(&provider))
+            .build()
+            .context("build certificate verifier")?;
+
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .context("set TLS versions")?;
+    let mut config = if pins.is_empty() {
+        builder.with_webpki_verifier(verifier).with_no_client_auth()
+    } else {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                inner: verifier,
+                pins: pins.to_vec(),
+                provider,
+            }))
+            .with_no_client_auth()
+    };
+    config.alpn_protocols = if http1 {
+        vec![b"http/1.1".to_vec()]
+    } else {
+        vec![b"h2".to_vec()]
+    };
+
+    Ok(config)
+}
+
+/// Verifier that performs the normal web PKI checks and additionally requires a pinned certificate in the chain.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<CertFingerprint>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .any(|cert| self.pins.contains(&CertFingerprint::of(cert)));
+        if pinned {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(
+                "server certificate chain does not match any pinned certificate".to_owned(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let expected = CertFingerprint(std::array::from_fn(|i| i as u8));
+        assert_eq!(
+            CertFingerprint::from_str(
+                "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+            )
+            .unwrap(),
+            expected,
+        );
+        assert_eq!(
+            CertFingerprint::from_str(
+                "00:01:02:03:04:05:06:07:08:09:0A:0B:0C:0D:0E:0F:10:11:12:13:14:15:16:17:18:19:1A:1B:1C:1D:1E:1F"
+            )
+            .unwrap(),
+            expected,
+        );
+
+        assert_eq!(
+            CertFingerprint::from_str("00:01").unwrap_err(),
+            "SHA-256 fingerprint must have 64 hex characters but has 4",
+        );
+        assert_eq!(
+            CertFingerprint::from_str(&"zz".repeat(32)).unwrap_err(),
+            "invalid hex in fingerprint: `zz`",
+        );
+    }
+
+    #[test]
+    fn test_build_tls_config() {
+        let config = build_tls_config(None, &[], false).unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"h2".to_vec()]);
+
+        let config = build_tls_config(None, &[CertFingerprint([0; 32])], true).unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("empty.pem");
+        std::fs::write(&path, b"").unwrap();
+        let err = build_tls_config(Some(&path), &[], false).unwrap_err();
+        assert!(err.to_string().starts_with("no certificates found in"));
+    }
+}
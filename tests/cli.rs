@@ -38,13 +38,32 @@ fn read_files(path: &Path) -> HashMap<String, String> {
         assert!(f.file_type().unwrap().is_file());
         out.insert(
             f.path().file_name().unwrap().to_str().unwrap().to_owned(),
-            std::fs::read_to_string(f.path()).unwrap(),
+            normalize_boundaries(std::fs::read_to_string(f.path()).unwrap()),
         );
     }
 
     out
 }
 
+/// Replace MIME boundaries, which depend on the mail ID, by stable placeholders.
+fn normalize_boundaries(mut eml: String) -> String {
+    const PREFIX: &str = "; boundary=\"";
+
+    let mut boundaries = vec![];
+    let mut rest = eml.as_str();
+    while let Some(pos) = rest.find(PREFIX) {
+        rest = &rest[pos + PREFIX.len()..];
+        let end = rest.find('"').unwrap();
+        boundaries.push(rest[..end].to_owned());
+        rest = &rest[end..];
+    }
+
+    for (i, boundary) in boundaries.into_iter().enumerate() {
+        eml = eml.replace(&boundary, &format!("BOUNDARY{i}"));
+    }
+    eml
+}
+
 mod integration {
     use super::*;
 
@@ -66,6 +85,32 @@ mod integration {
         assert!(std::fs::read_dir(dump_dir).unwrap().count() > 0);
     }
 
+    #[test]
+    fn test_replay() {
+        let tmp_dir = TempDir::new().unwrap();
+        let dump_dir = tmp_dir.path().join("json");
+
+        let res = cmd()
+            .arg("-vv")
+            .arg("--debug-dump-json-to")
+            .arg(&dump_dir)
+            .arg("list-folders")
+            .assert()
+            .success();
+        let stdout_live = res.get_output().stdout.clone();
+
+        let res = cmd()
+            .arg("-vv")
+            .arg("--replay-from")
+            .arg(&dump_dir)
+            .arg("list-folders")
+            .assert()
+            .success();
+        let stdout_replay = res.get_output().stdout.clone();
+
+        assert_eq!(stdout_live, stdout_replay);
+    }
+
     #[test]
     fn test_list_folders() {
         let mut cmd = cmd();
@@ -83,6 +128,45 @@ mod integration {
         "###);
     }
 
+    #[test]
+    fn test_list_folders_json() {
+        let mut cmd = cmd();
+        let res = cmd
+            .arg("-vv")
+            .arg("--output=json")
+            .arg("list-folders")
+            .assert()
+            .success();
+        let folders: Vec<serde_json::Value> =
+            serde_json::from_slice(&res.get_output().stdout).unwrap();
+
+        let names = folders
+            .iter()
+            .map(|f| f["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["Inbox", "Sent", "Trash", "Archive", "Spam", "Draft", "fooooo"],
+        );
+        assert_eq!(folders[0]["type"], "Inbox");
+        assert_eq!(folders[6]["type"], "Custom");
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut cmd = cmd();
+        let res = cmd
+            .arg("-vv")
+            .arg("stats")
+            .arg("--folder=fooooo")
+            .arg("--group-by=year")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(res.get_output().stdout.clone()).unwrap();
+
+        assert_eq!(stdout, "5\t2024\n2\t2023\n");
+    }
+
     #[test]
     fn test_download() {
         let actual_path = TempDir::new().unwrap();
@@ -116,6 +200,137 @@ mod integration {
         }
     }
 
+    #[test]
+    fn test_download_staging() {
+        let tmp_dir = TempDir::new().unwrap();
+
+        // use path that does NOT exist
+        let path = tmp_dir.path().join("out");
+
+        cmd()
+            .arg("-vv")
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--path")
+            .arg(&path)
+            .assert()
+            .success();
+
+        assert!(std::fs::read_dir(&path).unwrap().count() > 0);
+        assert!(!tmp_dir.path().join("out.partial").exists());
+    }
+
+    #[test]
+    fn test_download_verify() {
+        let path = TempDir::new().unwrap();
+
+        let download = || {
+            let mut cmd = cmd();
+            cmd.arg("-vv")
+                .arg("download")
+                .arg("--folder=fooooo")
+                .arg("--path")
+                .arg(path.path())
+                .arg("--verify");
+            cmd
+        };
+        download().assert().success();
+        download().assert().success();
+
+        let file = std::fs::read_dir(path.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let content = std::fs::read(&file).unwrap();
+        std::fs::write(&file, &content[..content.len() / 2]).unwrap();
+
+        download()
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(
+                "1 existing file(s) do not match the server data",
+            ));
+    }
+
+    #[test]
+    fn test_download_single_mail_to_stdout() {
+        let path = TempDir::new().unwrap();
+
+        cmd()
+            .arg("-vv")
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--path")
+            .arg(path.path())
+            .arg("--manifest")
+            .assert()
+            .success();
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path.path().join("manifest.json")).unwrap())
+                .unwrap();
+        let (file_name, entry) = manifest["mails"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .next()
+            .unwrap();
+
+        let res = cmd()
+            .arg("-vv")
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--mail-id")
+            .arg(entry["mailId"].as_str().unwrap())
+            .arg("--path=-")
+            .assert()
+            .success();
+
+        let expected = std::fs::read_to_string(path.path().join(file_name)).unwrap();
+        let actual = String::from_utf8(res.get_output().stdout.clone()).unwrap();
+        similar_asserts::assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_cat() {
+        let path = TempDir::new().unwrap();
+
+        cmd()
+            .arg("-vv")
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--path")
+            .arg(path.path())
+            .arg("--manifest")
+            .assert()
+            .success();
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path.path().join("manifest.json")).unwrap())
+                .unwrap();
+        let (_file_name, entry) = manifest["mails"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .next()
+            .unwrap();
+
+        let res = cmd()
+            .arg("-vv")
+            .arg("cat")
+            .arg("--folder=fooooo")
+            .arg("--mail-id")
+            .arg(entry["mailId"].as_str().unwrap())
+            .assert()
+            .success();
+
+        let stdout = String::from_utf8(res.get_output().stdout.clone()).unwrap();
+        assert!(stdout.starts_with(&format!(
+            "Subject: {}\n",
+            entry["subject"].as_str().unwrap()
+        )));
+    }
+
     #[test]
     fn test_new_mail_without_flag() {
         let path = TempDir::new().unwrap();
@@ -146,3 +361,756 @@ mod integration {
             .success();
     }
 }
+
+/// Tests that run against the synthetic account of the `generate-fixture` command instead of a real one.
+#[cfg(feature = "test-harness")]
+mod replay {
+    use super::*;
+
+    const USERNAME: &str = "fixture@example.com";
+    const PASSWORD: &str = "fixture-password";
+
+    /// Recorded responses of the synthetic account.
+    struct Fixture {
+        dir: TempDir,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = TempDir::new().unwrap();
+            credentials(cmd(), PASSWORD)
+                .arg("generate-fixture")
+                .arg("--path")
+                .arg(dir.path())
+                .assert()
+                .success();
+            Self { dir }
+        }
+
+        /// Command that replays the recorded responses.
+        fn cmd(&self) -> Command {
+            self.cmd_with_password(PASSWORD)
+        }
+
+        fn cmd_with_password(&self, password: &str) -> Command {
+            let mut cmd = credentials(cmd(), password);
+            cmd.arg("-vv").arg("--replay-from").arg(self.dir.path());
+            cmd
+        }
+    }
+
+    /// Pass credentials explicitly, so that a local `.env` file is NOT used.
+    fn credentials(mut cmd: Command, password: &str) -> Command {
+        cmd.env("TUTANOTA_CLI_USERNAME", USERNAME)
+            .env("TUTANOTA_CLI_PASSWORD", password)
+            .env_remove("TUTANOTA_CLI_RECOVERY_CODE")
+            .env_remove("TUTANOTA_CLI_NOTIFY_URL");
+        cmd
+    }
+
+    /// Local webhook that accepts a single POST request and returns its JSON body.
+    fn webhook() -> (String, std::thread::JoinHandle<serde_json::Value>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let (stream, _peer) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            assert_eq!(request_line, "POST /hook HTTP/1.1\r\n");
+
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            (&stream)
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                .unwrap();
+            serde_json::from_slice(&body).unwrap()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_list_folders() {
+        let fixture = Fixture::new();
+        let res = fixture.cmd().arg("list-folders").assert().success();
+        let stdout = String::from_utf8(res.get_output().stdout.clone()).unwrap();
+
+        insta::assert_snapshot!(stdout, @r###"
+        Inbox
+        Sent
+        Trash
+        Archive
+        Spam
+        Draft
+        fooooo
+        baaar
+        "###);
+    }
+
+    #[test]
+    fn test_list_folders_tree() {
+        let fixture = Fixture::new();
+        let res = fixture
+            .cmd()
+            .arg("list-folders")
+            .arg("--tree")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(res.get_output().stdout.clone()).unwrap();
+
+        insta::assert_snapshot!(stdout, @r###"
+        Inbox [Inbox, fixtureInbox]
+        Sent [Sent, fixtureSent]
+        Trash [Trash, fixtureTrash]
+        Archive [Archive, fixtureArchive]
+        Spam [Spam, fixtureSpam]
+        Draft [Draft, fixtureDraft]
+        fooooo [Custom, fixtureCustom]
+          baaar [Custom, fixtureNested]
+        "###);
+    }
+
+    #[test]
+    fn test_list_folders_counts() {
+        let fixture = Fixture::new();
+        let res = fixture
+            .cmd()
+            .arg("list-folders")
+            .arg("--counts")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(res.get_output().stdout.clone()).unwrap();
+
+        insta::assert_snapshot!(stdout, @r###"
+        Inbox (0 mails, 0 unread)
+        Sent (0 mails, 0 unread)
+        Trash (0 mails, 0 unread)
+        Archive (2 mails, 0 unread)
+        Spam (0 mails, 0 unread)
+        Draft (0 mails, 0 unread)
+        fooooo (3 mails, 1 unread)
+        baaar (0 mails, 0 unread)
+        "###);
+    }
+
+    #[test]
+    fn test_list_groups() {
+        let fixture = Fixture::new();
+        fixture
+            .cmd()
+            .arg("list-groups")
+            .assert()
+            .success()
+            .stdout("Mail\tfixtureMailGroup\n");
+    }
+
+    #[test]
+    fn test_group() {
+        let fixture = Fixture::new();
+        fixture
+            .cmd()
+            .arg("--group=fixtureMailGroup")
+            .arg("list-folders")
+            .assert()
+            .success()
+            .stdout(predicates::str::starts_with("Inbox\n"));
+        fixture
+            .cmd()
+            .arg("--group=fixtureUserGroup")
+            .arg("list-folders")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(
+                "no mail group `fixtureUserGroup`, see `list-groups`",
+            ));
+    }
+
+    #[test]
+    fn test_strict_proto() {
+        let fixture = Fixture::new();
+        fixture
+            .cmd()
+            .arg("--strict-proto")
+            .arg("list-folders")
+            .assert()
+            .success()
+            .stderr(predicates::str::contains(
+                "unknown field in server response",
+            ))
+            .stderr(predicates::str::contains("MailboxGroupRootResponse"));
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let fixture = Fixture::new();
+        fixture
+            .cmd_with_password("wrong")
+            .arg("list-folders")
+            .assert()
+            .code(3);
+    }
+
+    #[test]
+    fn test_notify_url() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+
+        let (url, hook) = webhook();
+        fixture
+            .cmd()
+            .arg("--notify-url")
+            .arg(url)
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--path")
+            .arg(path.path())
+            .assert()
+            .success();
+        let report = hook.join().unwrap();
+        assert_eq!(report["command"], "download");
+        assert_eq!(report["success"], true);
+        assert_eq!(report["exitCode"], 0);
+        assert_eq!(report["exports"][0]["downloaded"], 3);
+        assert!(report["text"]
+            .as_str()
+            .unwrap()
+            .ends_with(": 3 mails downloaded, 0 failed"));
+
+        // failed logins are reported as well
+        let (url, hook) = webhook();
+        fixture
+            .cmd_with_password("wrong")
+            .env("TUTANOTA_CLI_NOTIFY_URL", url)
+            .arg("list-folders")
+            .assert()
+            .code(3);
+        let report = hook.join().unwrap();
+        assert_eq!(report["command"], "list-folders");
+        assert_eq!(report["success"], false);
+        assert_eq!(report["exitCode"], 3);
+        assert!(report["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("perform login: authentication failed"));
+        assert_eq!(report["exports"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_stats() {
+        let fixture = Fixture::new();
+        let res = fixture
+            .cmd()
+            .arg("stats")
+            .arg("--folder=fooooo")
+            .arg("--group-by=year")
+            .assert()
+            .success();
+        let stdout = String::from_utf8(res.get_output().stdout.clone()).unwrap();
+
+        assert_eq!(stdout, "2\t2024\n1\t2023\n");
+    }
+
+    #[test]
+    fn test_download() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+
+        fixture
+            .cmd()
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--path")
+            .arg(path.path())
+            .assert()
+            .success();
+
+        let files = read_files(path.path());
+        let mut names = files.keys().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "2023-11-20-07h45m00s-Old news.eml",
+                "2024-01-15-09h30m00s-Hello.eml",
+                "2024-03-02-18h00m00s-Gre mit Anhang.eml",
+            ],
+        );
+
+        insta::assert_snapshot!(files["2024-03-02-18h00m00s-Gre mit Anhang.eml"], @r###"
+        From: =?UTF-8?B?Q2Fyb2w=?= <carol@example.org>
+        Date: Sat, 2 Mar 2024 18:00:00 +0000
+        MIME-Version: 1.0
+        Subject: =?UTF-8?B?R3LDvMOfZSBtaXQgQW5oYW5n?=
+        CC: =?UTF-8?B??= <alice@example.com>
+        To: =?UTF-8?B?Qm9i?= <bob@example.com>
+        Content-Type: multipart/mixed; boundary="BOUNDARY0"
+
+        --BOUNDARY0
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        PHA+U2VlIHRoZSBhdHRhY2hlZCBub3Rlcy48L3A+
+
+        --BOUNDARY0
+        Content-Type: text/plain; name==?UTF-8?B?bm90ZXMudHh0?=
+        Content-Transfer-Encoding: base64
+        Content-Disposition: attachment; filename==?UTF-8?B?bm90ZXMudHh0?=
+
+        Zmlyc3QgbGluZQpzZWNvbmQgbGluZQo=
+
+        --BOUNDARY0--
+        "###);
+    }
+
+    #[test]
+    fn test_download_mbox_to_stdout() {
+        let fixture = Fixture::new();
+
+        let res = fixture
+            .cmd()
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--format=mbox")
+            .arg("--path=-")
+            .assert()
+            .success();
+
+        let stdout = String::from_utf8(res.get_output().stdout.clone()).unwrap();
+        let mut from_lines = stdout
+            .lines()
+            .filter(|line| line.starts_with("From "))
+            .collect::<Vec<_>>();
+        from_lines.sort();
+        assert_eq!(
+            from_lines,
+            [
+                "From alice@example.com Mon Jan 15 09:30:00 2024",
+                "From alice@example.com Mon Nov 20 07:45:00 2023",
+                "From carol@example.org Sat Mar  2 18:00:00 2024",
+            ],
+        );
+        assert_eq!(stdout.matches("\r\nX-Mozilla-Status: ").count(), 3);
+    }
+
+    #[test]
+    fn test_download_skip_attachments() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+
+        fixture
+            .cmd()
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--mail-id=fixtureMail2")
+            .arg("--skip-attachments")
+            .arg("--path")
+            .arg(path.path())
+            .assert()
+            .success();
+
+        let files = read_files(path.path());
+        insta::assert_snapshot!(files["2024-03-02-18h00m00s-Gre mit Anhang.eml"], @r###"
+        From: =?UTF-8?B?Q2Fyb2w=?= <carol@example.org>
+        Date: Sat, 2 Mar 2024 18:00:00 +0000
+        MIME-Version: 1.0
+        Subject: =?UTF-8?B?R3LDvMOfZSBtaXQgQW5oYW5n?=
+        CC: =?UTF-8?B??= <alice@example.com>
+        To: =?UTF-8?B?Qm9i?= <bob@example.com>
+        X-Tuta-Omitted-Attachment: type=text/plain; size=23; name==?UTF-8?B?bm90ZXMudHh0?=
+        Content-Type: multipart/related; boundary="BOUNDARY0"
+
+        --BOUNDARY0
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: base64
+
+        PHA+U2VlIHRoZSBhdHRhY2hlZCBub3Rlcy48L3A+
+
+        --BOUNDARY0--
+        "###);
+    }
+
+    #[test]
+    fn test_download_attachment_filter() {
+        let fixture = Fixture::new();
+
+        for (arg, omitted) in [
+            ("--attachment-type=image/*,application/pdf", true),
+            ("--attachment-type=text/*", false),
+            ("--max-attachment-size=22", true),
+            ("--max-attachment-size=23", false),
+        ] {
+            let path = TempDir::new().unwrap();
+            fixture
+                .cmd()
+                .arg("download")
+                .arg("--folder=fooooo")
+                .arg("--mail-id=fixtureMail2")
+                .arg(arg)
+                .arg("--path")
+                .arg(path.path())
+                .assert()
+                .success();
+
+            let files = read_files(path.path());
+            let eml = &files["2024-03-02-18h00m00s-Gre mit Anhang.eml"];
+            assert_eq!(
+                eml.contains("X-Tuta-Omitted-Attachment: type=text/plain; size=23;"),
+                omitted,
+                "{arg}",
+            );
+            assert_eq!(
+                eml.contains("Zmlyc3QgbGluZQpzZWNvbmQgbGluZQo="),
+                !omitted,
+                "{arg}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+        let out = path.path().join("out");
+        let db = path.path().join("export.sqlite");
+
+        fixture
+            .cmd()
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--manifest")
+            .arg("--db")
+            .arg(&db)
+            .arg("--path")
+            .arg(&out)
+            .assert()
+            .success();
+
+        let compare = || {
+            let mut cmd = fixture.cmd();
+            cmd.arg("compare")
+                .arg("--folder=fooooo")
+                .arg("--db")
+                .arg(&db)
+                .arg("--path")
+                .arg(&out);
+            cmd
+        };
+        compare()
+            .assert()
+            .success()
+            .stdout("")
+            .stderr(predicates::str::contains(
+                "3 mails on server, 3 exported: 0 missing, 0 deleted on server, 0 mismatched",
+            ));
+
+        std::fs::remove_file(out.join("2024-01-15-09h30m00s-Hello.eml")).unwrap();
+        std::fs::write(out.join("2023-11-20-07h45m00s-Old news.eml"), "changed").unwrap();
+        let manifest_path = out.join("manifest.json");
+        let mut manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        let mut gone = manifest["mails"]["2024-01-15-09h30m00s-Hello.eml"].clone();
+        gone["mailId"] = "goneMail".into();
+        manifest["mails"]["gone.eml"] = gone;
+        std::fs::write(&manifest_path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        let res = compare().assert().failure();
+        let stdout = String::from_utf8(res.get_output().stdout.clone()).unwrap();
+        insta::assert_snapshot!(stdout, @r###"
+        missing	fixtureMail1	2024-01-15T09:30:00+00:00	Hello
+        deleted	goneMail	gone.eml
+        mismatch	fixtureMail3	2023-11-20-07h45m00s-Old news.eml	size differs: expected 488 bytes, found 7
+        "###);
+    }
+
+    #[test]
+    fn test_prune() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+
+        fixture
+            .cmd()
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--manifest")
+            .arg("--link-index")
+            .arg("--path")
+            .arg(path.path())
+            .assert()
+            .success();
+
+        // mail that was deleted on the server since the export
+        let manifest_path = path.path().join("manifest.json");
+        let mut manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        let mut gone = manifest["mails"]["2024-01-15-09h30m00s-Hello.eml"].clone();
+        gone["mailId"] = "goneMail".into();
+        manifest["mails"]["gone.eml"] = gone;
+        std::fs::write(&manifest_path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+        std::fs::write(path.path().join("gone.eml"), "gone").unwrap();
+        std::fs::write(path.path().join("by-id/goneMail.eml"), "gone").unwrap();
+
+        let prune = |dry_run: bool| {
+            let mut cmd = fixture.cmd();
+            cmd.arg("prune")
+                .arg("--folder=fooooo")
+                .arg("--path")
+                .arg(path.path());
+            if dry_run {
+                cmd.arg("--dry-run");
+            }
+            cmd
+        };
+        prune(true).assert().success().stdout("gone.eml\n");
+        assert!(path.path().join("gone.eml").exists());
+
+        prune(false).assert().success().stdout("gone.eml\n");
+        assert!(!path.path().join("gone.eml").exists());
+        assert!(!path.path().join("by-id/goneMail.eml").exists());
+        assert!(path.path().join("by-id/fixtureMail1.eml").exists());
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        assert_eq!(manifest["mails"].as_object().unwrap().len(), 3);
+
+        prune(false).assert().success().stdout("");
+    }
+
+    #[test]
+    fn test_run_lock() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+        let out = path.path().join("out");
+
+        // another run that is still in progress
+        let lock = std::fs::File::create(path.path().join("out.lock")).unwrap();
+        lock.lock().unwrap();
+
+        let download = || {
+            let mut cmd = fixture.cmd();
+            cmd.arg("download")
+                .arg("--folder=fooooo")
+                .arg("--path")
+                .arg(&out);
+            cmd
+        };
+        download()
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(
+                "pass `--wait` to queue behind it",
+            ));
+        assert!(!out.exists());
+
+        drop(lock);
+        download().arg("--wait").assert().success();
+        assert_eq!(read_files(&out).len(), 3);
+    }
+
+    #[test]
+    fn test_sync() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+        let out = path.path().join("out");
+
+        let sync = || {
+            let mut cmd = fixture.cmd();
+            cmd.arg("sync")
+                .arg("--folder=fooooo")
+                .arg("--db")
+                .arg(path.path().join("export.sqlite"))
+                .arg("--path")
+                .arg(&out);
+            cmd
+        };
+        sync().assert().success();
+        let mut names = read_files(&out).into_keys().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "2023-11-20-07h45m00s-Old news.eml",
+                "2024-01-15-09h30m00s-Hello.eml",
+                "2024-03-02-18h00m00s-Gre mit Anhang.eml",
+                "manifest.json",
+            ],
+        );
+
+        // mail that was deleted on the server since the last run
+        let manifest_path = out.join("manifest.json");
+        let mut manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+        let mut gone = manifest["mails"]["2024-01-15-09h30m00s-Hello.eml"].clone();
+        gone["mailId"] = "goneMail".into();
+        manifest["mails"]["gone.eml"] = gone;
+        std::fs::write(&manifest_path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+        std::fs::write(out.join("gone.eml"), "gone").unwrap();
+
+        sync()
+            .assert()
+            .success()
+            .stderr(predicates::str::contains("1 exported mails pruned"));
+        assert!(!out.join("gone.eml").exists());
+        assert_eq!(read_files(&out).len(), 4);
+
+        sync()
+            .arg("--since=2024-01-01")
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("does not support `--since`"));
+    }
+
+    #[test]
+    fn test_download_single_mail() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+
+        fixture
+            .cmd()
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--mail-id=fixtureMail1")
+            .arg("--path")
+            .arg(path.path())
+            .assert()
+            .success();
+
+        let files = read_files(path.path());
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            ["2024-01-15-09h30m00s-Hello.eml"]
+        );
+
+        fixture
+            .cmd()
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--timestamp-url=http://127.0.0.1:1/tsr")
+            .arg("--path")
+            .arg(path.path())
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(
+                "`--timestamp-url` requires `--manifest`",
+            ));
+    }
+
+    #[test]
+    fn test_download_colliding_file_names() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+        let expected = [
+            "2024-05-10-08h15m00s-Invoice-NxWZSfN0.eml",
+            "2024-05-10-08h15m00s-Invoice-NxWZSfN1.eml",
+        ];
+
+        // both mails get a unique name, no matter whether they are exported individually ...
+        for mail_id in ["NxWZSfN----1", "NxWZSfN----0"] {
+            fixture
+                .cmd()
+                .arg("download")
+                .arg("--folder=Archive")
+                .arg(format!("--mail-id={mail_id}"))
+                .arg("--path")
+                .arg(path.path())
+                .assert()
+                .success();
+        }
+        let files = read_files(path.path());
+        let mut file_names = files.keys().collect::<Vec<_>>();
+        file_names.sort();
+        assert_eq!(file_names, expected);
+        assert_ne!(files[expected[0]], files[expected[1]]);
+
+        // ... or together
+        let path = TempDir::new().unwrap();
+        fixture
+            .cmd()
+            .arg("download")
+            .arg("--folder=Archive")
+            .arg("--path")
+            .arg(path.path())
+            .assert()
+            .success();
+        assert_eq!(read_files(path.path()), files);
+    }
+
+    #[test]
+    fn test_download_all_mailboxes() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+
+        fixture
+            .cmd()
+            .arg("download")
+            .arg("--folder=fooooo")
+            .arg("--all-mailboxes")
+            .arg("--path")
+            .arg(path.path())
+            .assert()
+            .success();
+
+        let files = read_files(&path.path().join("fixtureMailGroup"));
+        assert_eq!(files.len(), 3);
+    }
+
+    #[test]
+    fn test_download_attachments() {
+        let fixture = Fixture::new();
+        let path = TempDir::new().unwrap();
+
+        for _ in 0..2 {
+            fixture
+                .cmd()
+                .arg("download-attachments")
+                .arg("--path")
+                .arg(path.path())
+                .assert()
+                .success();
+        }
+
+        assert_eq!(
+            read_files(&path.path().join("fooooo")),
+            HashMap::from([(
+                "2024-03-02-18h00m00s-notes.txt".to_owned(),
+                "first line\nsecond line\n".to_owned(),
+            )]),
+        );
+
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path.path().join("attachments.json")).unwrap())
+                .unwrap();
+        insta::assert_snapshot!(
+            serde_json::to_string_pretty(&manifest["attachments"]).unwrap(),
+            @r###"
+            {
+              "fooooo/2024-03-02-18h00m00s-notes.txt": {
+                "date": "2024-03-02T18:00:00Z",
+                "folderId": "fixtureCustom",
+                "mailId": "fixtureMail2",
+                "mimeType": "text/plain",
+                "name": "notes.txt",
+                "sender": "carol@example.org",
+                "size": 23,
+                "subject": "Grüße mit Anhang"
+              }
+            }
+            "###
+        );
+    }
+}